@@ -0,0 +1,645 @@
+//! Audio Processing Unit (APU).
+//!
+//! Four channels are mixed into a stereo output:
+//! - CH1: square wave with frequency sweep
+//! - CH2: square wave
+//! - CH3: 4-bit samples from wave RAM
+//! - CH4: noise from a linear feedback shift register
+//!
+//! Channel timers run off the T-cycle clock. Length counters, the CH1 sweep
+//! and volume envelopes are clocked by the 512 Hz frame sequencer, which
+//! steps on every falling edge of bit 12 of the timer's internal divider
+//! (bit 4 of DIV), or bit 13 in CGB double-speed mode, where DIV runs twice
+//! as fast.
+//!
+//! For debugging, each channel can be muted or soloed in the mixer. This only
+//! affects the stereo output, not the channels themselves or the register
+//! reads.
+//!
+//! Reference: [Pan Docs — Audio](https://gbdev.io/pandocs/Audio.html)
+
+mod noise;
+mod square;
+mod units;
+mod wave;
+
+use crate::save_state::{Snapshot, StateError, StateReader, StateWriter};
+use noise::NoiseChannel;
+use square::SquareChannel;
+use wave::WaveChannel;
+
+pub const APU_REGISTERS_START: u16 = 0xFF10;
+pub const APU_REGISTERS_END: u16 = 0xFF26;
+pub const NR52_REGISTER: u16 = 0xFF26;
+pub const WAVE_RAM_START: u16 = 0xFF30;
+pub const WAVE_RAM_END: u16 = 0xFF3F;
+pub const PCM12_REGISTER: u16 = 0xFF76;
+pub const PCM34_REGISTER: u16 = 0xFF77;
+
+const NR50_INDEX: usize = 0x14;
+const NR51_INDEX: usize = 0x15;
+const NR52_INDEX: usize = 0x16;
+const REGISTER_COUNT: usize = (APU_REGISTERS_END - APU_REGISTERS_START + 1) as usize;
+
+/// Bits that always read as 1 for each register in 0xFF10-0xFF26
+/// (write-only and unused bits).
+const READ_MASKS: [u8; REGISTER_COUNT] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // (unused), NR21-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // (unused), NR41-NR44
+    0x00, 0x00, 0x70, // NR50-NR52
+];
+
+/// Register values left behind by the DMG boot ROM.
+const POST_BOOT_REGISTERS: [u8; REGISTER_COUNT] = [
+    0x80, 0xBF, 0xF3, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // (unused), NR21-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // (unused), NR41-NR44
+    0x77, 0xF3, 0x80, // NR50-NR52
+];
+
+const NR52_POWER_BIT: u8 = 0b1000_0000;
+const NRX4_TRIGGER_BIT: u8 = 0b1000_0000;
+const NRX4_INDICES: [usize; 4] = [0x04, 0x09, 0x0E, 0x13];
+
+/// DIV bit whose falling edge clocks the frame sequencer.
+const FRAME_SEQUENCER_DIV_BIT: u16 = 1 << 12;
+/// Frame sequencer DIV bit in CGB double-speed mode.
+const FRAME_SEQUENCER_DIV_BIT_DOUBLE_SPEED: u16 = 1 << 13;
+
+/// The four APU channels, in register order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Channel {
+    /// CH1 - square wave with sweep (NR10-NR14)
+    Square1 = 0,
+    /// CH2 - square wave (NR21-NR24)
+    Square2 = 1,
+    /// CH3 - wave RAM playback (NR30-NR34)
+    Wave = 2,
+    /// CH4 - noise (NR41-NR44)
+    Noise = 3,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 4] = [
+        Channel::Square1,
+        Channel::Square2,
+        Channel::Wave,
+        Channel::Noise,
+    ];
+
+    /// Index into per-channel arrays such as [`APU::channel_outputs`].
+    pub const fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Return the channel from its Pan Docs number (1-4), if valid.
+    pub const fn from_number(number: u8) -> Option<Channel> {
+        match number {
+            1 => Some(Channel::Square1),
+            2 => Some(Channel::Square2),
+            3 => Some(Channel::Wave),
+            4 => Some(Channel::Noise),
+            _ => None,
+        }
+    }
+
+    /// Short name, used for file names of per-channel recordings.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Channel::Square1 => "ch1_square",
+            Channel::Square2 => "ch2_square",
+            Channel::Wave => "ch3_wave",
+            Channel::Noise => "ch4_noise",
+        }
+    }
+}
+
+pub(crate) struct APU {
+    powered: bool,
+    registers: [u8; REGISTER_COUNT], // last written values, for reads
+    square1: SquareChannel,
+    square2: SquareChannel,
+    wave: WaveChannel,
+    noise: NoiseChannel,
+    frame_step: u8,           // next frame sequencer step (0-7)
+    prev_div_bit: bool,       // previous state of the frame sequencer DIV bit
+    frame_sequencer_bit: u16, // DIV bit clocking the frame sequencer
+    muted: [bool; 4],         // channels removed from the mix
+    solo: Option<Channel>,    // when set, the only channel in the mix
+    cgb_hardware: bool,       // CGB APU, even in DMG compatibility mode
+}
+
+impl APU {
+    pub(crate) fn new() -> APU {
+        let mut apu = APU {
+            powered: true,
+            registers: [0; REGISTER_COUNT],
+            square1: SquareChannel::with_sweep(),
+            square2: SquareChannel::new(),
+            wave: WaveChannel::new(),
+            noise: NoiseChannel::new(),
+            frame_step: 0,
+            prev_div_bit: false,
+            frame_sequencer_bit: FRAME_SEQUENCER_DIV_BIT,
+            muted: [false; 4],
+            solo: None,
+            cgb_hardware: false,
+        };
+        for (index, value) in POST_BOOT_REGISTERS.into_iter().enumerate() {
+            // Replay the boot ROM's register writes without retriggering
+            let value = if NRX4_INDICES.contains(&index) {
+                value & !NRX4_TRIGGER_BIT
+            } else {
+                value
+            };
+            apu.write_register(index, value);
+        }
+        apu.registers = POST_BOOT_REGISTERS;
+        apu.square1.resume_silent();
+        apu
+    }
+
+    /// Leave CH1 stopped, as after the SGB boot ROM, which plays no start-up
+    /// chime.
+    pub fn skip_boot_chime(&mut self) {
+        self.square1.disable();
+    }
+
+    /// Behave as the CGB APU, whose length counters are cleared at power-off
+    /// and ignore writes while it is off.
+    pub fn set_cgb_hardware(&mut self, cgb_hardware: bool) {
+        self.cgb_hardware = cgb_hardware;
+    }
+
+    /// Follow a CGB speed switch: the frame sequencer moves to DIV bit 13 in
+    /// double speed so it keeps running at 512 Hz.
+    pub fn set_double_speed(&mut self, double_speed: bool) {
+        self.frame_sequencer_bit = if double_speed {
+            FRAME_SEQUENCER_DIV_BIT_DOUBLE_SPEED
+        } else {
+            FRAME_SEQUENCER_DIV_BIT
+        };
+    }
+
+    /// Advance one T-cycle. `div_counter` is the timer's internal 16-bit
+    /// divider, whose bit 12 (bit 13 in double speed) drives the frame sequencer.
+    pub fn tick(&mut self, div_counter: u16) {
+        let div_bit = div_counter & self.frame_sequencer_bit != 0;
+        let falling_edge = self.prev_div_bit && !div_bit;
+        self.prev_div_bit = div_bit;
+
+        if !self.powered {
+            return;
+        }
+        if falling_edge {
+            self.clock_frame_sequencer();
+        }
+
+        self.square1.tick();
+        self.square2.tick();
+        self.wave.tick();
+        self.noise.tick();
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            WAVE_RAM_START..=WAVE_RAM_END => self.wave.read_ram((addr - WAVE_RAM_START) as usize),
+            APU_REGISTERS_START..=APU_REGISTERS_END => {
+                let index = (addr - APU_REGISTERS_START) as usize;
+                if index == NR52_INDEX {
+                    self.read_nr52()
+                } else {
+                    self.registers[index] | READ_MASKS[index]
+                }
+            }
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            WAVE_RAM_START..=WAVE_RAM_END => {
+                self.wave.write_ram((addr - WAVE_RAM_START) as usize, value);
+            }
+            APU_REGISTERS_START..=APU_REGISTERS_END => {
+                let index = (addr - APU_REGISTERS_START) as usize;
+                if index == NR52_INDEX {
+                    self.write_nr52(value);
+                } else if self.powered {
+                    self.registers[index] = value;
+                    self.write_register(index, value);
+                } else if !self.cgb_hardware {
+                    // Powered off: only the length counters accept writes (DMG)
+                    self.write_length_while_off(index, value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// PCM12/PCM34 (CGB only, read-only): the digital output of channels 1
+    /// and 2, or 3 and 4, with the lower channel in the low nibble. A channel
+    /// whose DAC is off reads 0.
+    pub fn read_pcm(&self, addr: u16) -> u8 {
+        let (low, high) = match addr {
+            PCM12_REGISTER => (self.square1.output(), self.square2.output()),
+            PCM34_REGISTER => (self.wave.output(), self.noise.output()),
+            _ => return 0xFF,
+        };
+        low.unwrap_or(0) | high.unwrap_or(0) << 4
+    }
+
+    /// Mute or unmute a channel in the mixer.
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.muted[channel.index()] = muted;
+    }
+
+    /// Mix only `channel` (ignoring mutes), or every unmuted channel with `None`.
+    pub fn set_solo(&mut self, channel: Option<Channel>) {
+        self.solo = channel;
+    }
+
+    /// Whether a channel is currently included in the stereo mix.
+    pub fn is_audible(&self, channel: Channel) -> bool {
+        match self.solo {
+            Some(solo) => solo == channel,
+            None => !self.muted[channel.index()],
+        }
+    }
+
+    /// Stereo output (left, right), each in -1.0..=1.0.
+    pub fn output(&self) -> (f32, f32) {
+        let mut channels = self.channel_outputs();
+        for channel in Channel::ALL {
+            if !self.is_audible(channel) {
+                channels[channel.index()] = 0.0;
+            }
+        }
+        let panning = self.registers[NR51_INDEX];
+        let volume = self.registers[NR50_INDEX];
+
+        let mix = |enable_shift: u8, volume: u8| {
+            let sum: f32 = channels
+                .iter()
+                .enumerate()
+                .filter(|(channel, _)| panning & (1 << (*channel as u8 + enable_shift)) != 0)
+                .map(|(_, output)| output)
+                .sum();
+            sum / 4.0 * (volume as f32 + 1.0) / 8.0
+        };
+
+        (mix(4, (volume >> 4) & 0x07), mix(0, volume & 0x07))
+    }
+
+    /// Analog output of each channel's DAC in -1.0..=1.0 (0.0 with the DAC off),
+    /// indexed by [`Channel::index`]. Mutes and solo do not apply.
+    pub fn channel_outputs(&self) -> [f32; 4] {
+        [
+            self.square1.output(),
+            self.square2.output(),
+            self.wave.output(),
+            self.noise.output(),
+        ]
+        .map(|digital| match digital {
+            // The DAC maps 0..=15 linearly onto +1.0..=-1.0
+            Some(level) => 1.0 - level as f32 / 7.5,
+            None => 0.0,
+        })
+    }
+
+    // Dispatch a register write (index from 0xFF10) to its channel.
+    fn write_register(&mut self, index: usize, value: u8) {
+        // The next step clocks lengths on even steps
+        let extra_length_clock = self.frame_step & 1 != 0;
+        match index {
+            0x00..=0x04 => self.square1.write(index, value, extra_length_clock),
+            0x05..=0x09 => self.square2.write(index - 0x05, value, extra_length_clock),
+            0x0A..=0x0E => self.wave.write(index - 0x0A, value, extra_length_clock),
+            0x0F..=0x13 => self.noise.write(index - 0x0F, value, extra_length_clock),
+            _ => {} // NR50/NR51 are only read back by the mixer
+        }
+    }
+
+    fn write_length_while_off(&mut self, index: usize, value: u8) {
+        match index {
+            0x01 => self.square1.write_length(value),
+            0x06 => self.square2.write_length(value),
+            0x0B => self.wave.write_length(value),
+            0x10 => self.noise.write_length(value),
+            _ => {}
+        }
+    }
+
+    fn clock_frame_sequencer(&mut self) {
+        // Step: 0 1 2 3 4 5 6 7
+        // Length: x . x . x . x .
+        // Sweep:  . . x . . . x .
+        // Envelope: . . . . . . . x
+        if self.frame_step & 1 == 0 {
+            self.square1.clock_length();
+            self.square2.clock_length();
+            self.wave.clock_length();
+            self.noise.clock_length();
+        }
+        if self.frame_step == 2 || self.frame_step == 6 {
+            self.square1.clock_sweep();
+        }
+        if self.frame_step == 7 {
+            self.square1.clock_envelope();
+            self.square2.clock_envelope();
+            self.noise.clock_envelope();
+        }
+        self.frame_step = (self.frame_step + 1) % 8;
+    }
+
+    // NR52: power (bit 7) and read-only channel status (bits 3-0).
+    fn read_nr52(&self) -> u8 {
+        let mut value = READ_MASKS[NR52_INDEX];
+        if self.powered {
+            value |= NR52_POWER_BIT;
+        }
+        let channels = [
+            self.square1.is_enabled(),
+            self.square2.is_enabled(),
+            self.wave.is_enabled(),
+            self.noise.is_enabled(),
+        ];
+        for (bit, enabled) in channels.into_iter().enumerate() {
+            if enabled {
+                value |= 1 << bit;
+            }
+        }
+        value
+    }
+
+    fn write_nr52(&mut self, value: u8) {
+        let power = value & NR52_POWER_BIT != 0;
+        if self.powered && !power {
+            // Powering off clears every register; wave RAM is kept, and so
+            // are the length counters on the DMG
+            let keep_length = !self.cgb_hardware;
+            self.registers = [0; REGISTER_COUNT];
+            self.square1.power_off(keep_length);
+            self.square2.power_off(keep_length);
+            self.wave.power_off(keep_length);
+            self.noise.power_off(keep_length);
+        } else if !self.powered && power {
+            self.frame_step = 0;
+        }
+        self.powered = power;
+    }
+}
+
+/// Mute and solo are mixer settings of the host and are not saved.
+impl Snapshot for APU {
+    fn save_to(&self, state: &mut StateWriter) {
+        state.bool(self.powered);
+        state.bytes(&self.registers);
+        self.square1.save_to(state);
+        self.square2.save_to(state);
+        self.wave.save_to(state);
+        self.noise.save_to(state);
+        state.u8(self.frame_step);
+        state.bool(self.prev_div_bit);
+        state.u16(self.frame_sequencer_bit);
+    }
+
+    fn load_from(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.powered = state.bool()?;
+        state.bytes(&mut self.registers)?;
+        self.square1.load_from(state)?;
+        self.square2.load_from(state)?;
+        self.wave.load_from(state)?;
+        self.noise.load_from(state)?;
+        self.frame_step = state.u8()? % 8;
+        self.prev_div_bit = state.bool()?;
+        self.frame_sequencer_bit = match state.u16()? {
+            FRAME_SEQUENCER_DIV_BIT_DOUBLE_SPEED => FRAME_SEQUENCER_DIV_BIT_DOUBLE_SPEED,
+            _ => FRAME_SEQUENCER_DIV_BIT,
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NR10: u16 = 0xFF10;
+    const NR11: u16 = 0xFF11;
+    const NR12: u16 = 0xFF12;
+    const NR13: u16 = 0xFF13;
+    const NR14: u16 = 0xFF14;
+    const NR21: u16 = 0xFF16;
+    const NR22: u16 = 0xFF17;
+    const NR24: u16 = 0xFF19;
+    const NR30: u16 = 0xFF1A;
+    const NR42: u16 = 0xFF21;
+    const NR44: u16 = 0xFF23;
+    const NR50: u16 = 0xFF24;
+    const NR51: u16 = 0xFF25;
+    const NR52: u16 = 0xFF26;
+
+    /// Run the frame sequencer for `steps` steps (8192 T-cycles each).
+    fn run_frame_steps(apu: &mut APU, div: &mut u16, steps: u32) {
+        for _ in 0..steps * 8192 {
+            *div = div.wrapping_add(1);
+            apu.tick(*div);
+        }
+    }
+
+    #[test]
+    fn test_post_boot_register_reads() {
+        let apu = APU::new();
+        assert_eq!(apu.read(NR10), 0x80);
+        assert_eq!(apu.read(NR11), 0xBF);
+        assert_eq!(apu.read(NR12), 0xF3);
+        assert_eq!(apu.read(NR13), 0xFF);
+        assert_eq!(apu.read(NR50), 0x77);
+        assert_eq!(apu.read(NR51), 0xF3);
+        assert_eq!(apu.read(NR52), 0xF1);
+        assert_eq!(apu.read(0xFF27), 0xFF);
+    }
+
+    #[test]
+    fn test_power_off_clears_registers_and_blocks_writes() {
+        let mut apu = APU::new();
+        apu.write(0xFF30, 0x12);
+        apu.write(NR52, 0x00);
+
+        assert_eq!(apu.read(NR52), 0x70);
+        assert_eq!(apu.read(NR50), 0x00);
+        assert_eq!(apu.read(NR12), 0x00);
+        apu.write(NR50, 0x77);
+        assert_eq!(apu.read(NR50), 0x00);
+        // Wave RAM survives power-off and stays accessible
+        assert_eq!(apu.read(0xFF30), 0x12);
+
+        apu.write(NR52, 0x80);
+        apu.write(NR50, 0x77);
+        assert_eq!(apu.read(NR50), 0x77);
+    }
+
+    #[test]
+    fn test_length_writes_while_off_only_on_dmg() {
+        for cgb_hardware in [false, true] {
+            let mut apu = APU::new();
+            apu.set_cgb_hardware(cgb_hardware);
+            let mut div = 0;
+            apu.write(NR52, 0x00);
+            apu.write(NR21, 0x3E); // length 2, ignored by the CGB
+            apu.write(NR52, 0x80);
+
+            apu.write(NR22, 0xF0);
+            apu.write(NR24, 0xC0); // trigger with length enabled
+            run_frame_steps(&mut apu, &mut div, 4);
+            // The CGB's cleared counter reloads as 64 and is still running
+            let expected = if cgb_hardware { 0x02 } else { 0 };
+            assert_eq!(apu.read(NR52) & 0x02, expected);
+        }
+    }
+
+    #[test]
+    fn test_trigger_enables_channel_only_with_dac_on() {
+        let mut apu = APU::new();
+        apu.write(NR22, 0x00); // DAC off
+        apu.write(NR24, 0x80);
+        assert_eq!(apu.read(NR52) & 0x02, 0);
+
+        apu.write(NR22, 0xF0);
+        apu.write(NR24, 0x80);
+        assert_eq!(apu.read(NR52) & 0x02, 0x02);
+
+        apu.write(NR22, 0x00); // turning the DAC off disables the channel
+        assert_eq!(apu.read(NR52) & 0x02, 0);
+    }
+
+    #[test]
+    fn test_length_counter_silences_channel() {
+        let mut apu = APU::new();
+        let mut div = 0;
+        apu.write(NR22, 0xF0);
+        apu.write(NR21, 0x3E); // length 2
+        apu.write(NR24, 0xC0); // trigger with length enabled
+
+        run_frame_steps(&mut apu, &mut div, 2);
+        assert_eq!(apu.read(NR52) & 0x02, 0x02);
+        run_frame_steps(&mut apu, &mut div, 2);
+        assert_eq!(apu.read(NR52) & 0x02, 0);
+    }
+
+    #[test]
+    fn test_enabling_length_on_odd_step_clocks_extra() {
+        let mut apu = APU::new();
+        let mut div = 0;
+        run_frame_steps(&mut apu, &mut div, 1); // next step (1) won't clock length
+
+        apu.write(NR22, 0xF0);
+        apu.write(NR21, 0x3F); // length 1
+        apu.write(NR24, 0x80); // trigger, length disabled
+        apu.write(NR24, 0x40); // enabling length clocks it straight to zero
+
+        assert_eq!(apu.read(NR52) & 0x02, 0);
+    }
+
+    #[test]
+    fn test_sweep_overflow_disables_channel() {
+        let mut apu = APU::new();
+        apu.write(NR12, 0xF0);
+        apu.write(NR10, 0x01); // period 0, shift 1: overflow checked on trigger
+        apu.write(NR13, 0xFF);
+        apu.write(NR14, 0x87); // frequency 0x7FF + 0x3FF overflows
+        assert_eq!(apu.read(NR52) & 0x01, 0);
+
+        apu.write(NR14, 0x83); // 0x3FF + 0x1FF fits
+        assert_eq!(apu.read(NR52) & 0x01, 0x01);
+    }
+
+    #[test]
+    fn test_sweep_clock_checks_overflow_of_next_frequency() {
+        let mut apu = APU::new();
+        let mut div = 0;
+        apu.write(NR12, 0xF0);
+        apu.write(NR10, 0x11); // period 1, shift 1
+        apu.write(NR13, 0x00);
+        apu.write(NR14, 0x84); // frequency 0x400
+
+        // Step 2 sweeps to 0x600; the follow-up check (0x900) overflows
+        run_frame_steps(&mut apu, &mut div, 2);
+        assert_eq!(apu.read(NR52) & 0x01, 0x01);
+        run_frame_steps(&mut apu, &mut div, 1);
+        assert_eq!(apu.read(NR52) & 0x01, 0);
+    }
+
+    #[test]
+    fn test_envelope_fades_volume() {
+        let mut apu = APU::new();
+        let mut div = 0;
+        apu.write(NR42, 0x21); // volume 2, decrease, period 1
+        apu.write(NR44, 0x80);
+        assert!(apu.noise.output().is_some());
+
+        run_frame_steps(&mut apu, &mut div, 16);
+        assert_eq!(apu.noise.output(), Some(0));
+    }
+
+    #[test]
+    fn test_wave_dac_and_mixer_panning() {
+        let mut apu = APU::new();
+        apu.write(NR52, 0x00);
+        apu.write(NR52, 0x80);
+        assert_eq!(apu.output(), (0.0, 0.0));
+
+        apu.write(NR30, 0x80); // DAC on, channel idle outputs digital 0
+        apu.write(NR50, 0x70); // left at full volume, right at 1/8
+        apu.write(NR51, 0x44); // CH3 on both sides
+        let (left, right) = apu.output();
+        assert_eq!(left, 0.25);
+        assert_eq!(right, 0.25 / 8.0);
+    }
+
+    #[test]
+    fn test_mute_and_solo_only_affect_mix() {
+        let mut apu = APU::new();
+        apu.write(NR30, 0x80); // CH3 DAC on: digital 0 is +1.0
+        apu.write(NR50, 0x77);
+        apu.write(NR51, 0x44); // CH3 on both sides
+        assert_eq!(apu.output(), (0.25, 0.25));
+
+        apu.set_muted(Channel::Wave, true);
+        assert_eq!(apu.output(), (0.0, 0.0));
+        assert_eq!(apu.channel_outputs()[Channel::Wave.index()], 1.0);
+
+        // Solo overrides the mute of the soloed channel
+        apu.set_solo(Some(Channel::Wave));
+        assert_eq!(apu.output(), (0.25, 0.25));
+        apu.set_solo(Some(Channel::Square1));
+        assert!(!apu.is_audible(Channel::Wave));
+        assert_eq!(apu.output(), (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_frame_sequencer_follows_div_bit_12() {
+        let mut apu = APU::new();
+        apu.tick(0x1000);
+        assert_eq!(apu.frame_step, 0);
+        apu.tick(0x0000); // DIV reset while bit 12 is set is a falling edge
+        assert_eq!(apu.frame_step, 1);
+    }
+
+    #[test]
+    fn test_frame_sequencer_follows_div_bit_13_in_double_speed() {
+        let mut apu = APU::new();
+        apu.set_double_speed(true);
+        apu.tick(0x1000);
+        apu.tick(0x0000); // bit 12 falling edge is ignored
+        assert_eq!(apu.frame_step, 0);
+        apu.tick(0x2000);
+        apu.tick(0x0000);
+        assert_eq!(apu.frame_step, 1);
+    }
+}
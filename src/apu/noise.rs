@@ -0,0 +1,164 @@
+//! Noise channel (CH4), driven by a 15-bit linear feedback shift register.
+//!
+//! Reference: [Pan Docs — Sound Channel 4](https://gbdev.io/pandocs/Audio_Registers.html#sound-channel-4--noise)
+
+use super::units::{envelope_dac_enabled, Envelope, LengthCounter};
+use crate::save_state::{Snapshot, StateError, StateReader, StateWriter};
+
+const LFSR_RESET: u16 = 0x7FFF;
+
+pub struct NoiseChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    clock_shift: u8,
+    short_mode: bool, // 7-bit LFSR (NR43 bit 3)
+    divisor_code: u8,
+    timer: u32,
+    lfsr: u16,
+    length: LengthCounter,
+    envelope: Envelope,
+}
+
+impl NoiseChannel {
+    pub fn new() -> Self {
+        NoiseChannel {
+            enabled: false,
+            dac_enabled: false,
+            clock_shift: 0,
+            short_mode: false,
+            divisor_code: 0,
+            timer: 0,
+            lfsr: LFSR_RESET,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Write a channel register; `index` 1-4 selects NR41-NR44.
+    pub fn write(&mut self, index: usize, value: u8, extra_length_clock: bool) {
+        match index {
+            1 => self.length.load(value & 0x3F),
+            2 => {
+                self.envelope.write(value);
+                self.dac_enabled = envelope_dac_enabled(value);
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            3 => {
+                self.clock_shift = value >> 4;
+                self.short_mode = value & 0x08 != 0;
+                self.divisor_code = value & 0x07;
+            }
+            4 => {
+                let trigger = value & 0x80 != 0;
+                if self
+                    .length
+                    .write_control(value & 0x40 != 0, trigger, extra_length_clock)
+                {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.enabled = self.dac_enabled;
+                    self.timer = self.period();
+                    self.lfsr = LFSR_RESET;
+                    self.envelope.trigger();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// NR41 length writes are still accepted while the APU is powered off.
+    pub fn write_length(&mut self, value: u8) {
+        self.length.load(value & 0x3F);
+    }
+
+    /// Advance one T-cycle.
+    pub fn tick(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = self.period();
+            self.step_lfsr();
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// Current digital output (0-15), or None when the DAC is off.
+    pub fn output(&self) -> Option<u8> {
+        if !self.dac_enabled {
+            return None;
+        }
+        if !self.enabled {
+            return Some(0);
+        }
+        // The output is the inverted low bit of the LFSR
+        Some((!self.lfsr & 0x01) as u8 * self.envelope.volume())
+    }
+
+    /// Clear everything but, on the DMG, the length counter (APU power-off).
+    pub fn power_off(&mut self, keep_length: bool) {
+        let length = std::mem::replace(&mut self.length, LengthCounter::new(64));
+        *self = NoiseChannel::new();
+        if keep_length {
+            self.length = length;
+        }
+    }
+
+    fn period(&self) -> u32 {
+        let divisor = match self.divisor_code {
+            0 => 8,
+            code => code as u32 * 16,
+        };
+        divisor << self.clock_shift
+    }
+
+    fn step_lfsr(&mut self) {
+        let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 0x01;
+        self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+        if self.short_mode {
+            self.lfsr = (self.lfsr & !0x40) | (feedback << 6);
+        }
+    }
+}
+
+impl Snapshot for NoiseChannel {
+    fn save_to(&self, state: &mut StateWriter) {
+        state.bool(self.enabled);
+        state.bool(self.dac_enabled);
+        state.u8(self.clock_shift);
+        state.bool(self.short_mode);
+        state.u8(self.divisor_code);
+        state.u32(self.timer);
+        state.u16(self.lfsr);
+        self.length.save_to(state);
+        self.envelope.save_to(state);
+    }
+
+    fn load_from(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = state.bool()?;
+        self.dac_enabled = state.bool()?;
+        self.clock_shift = state.u8()? & 0x0F;
+        self.short_mode = state.bool()?;
+        self.divisor_code = state.u8()? & 0x07;
+        self.timer = state.u32()?;
+        self.lfsr = state.u16()?;
+        self.length.load_from(state)?;
+        self.envelope.load_from(state)
+    }
+}
@@ -0,0 +1,308 @@
+//! Square wave channels (CH1 with frequency sweep, CH2 without).
+//!
+//! Reference: [Pan Docs — Sound Channel 1](https://gbdev.io/pandocs/Audio_Registers.html#sound-channel-1--pulse-with-period-sweep)
+
+use super::units::{envelope_dac_enabled, Envelope, LengthCounter};
+use crate::save_state::{Snapshot, StateError, StateReader, StateWriter};
+
+const DUTY_PATTERNS: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1], // 12.5%
+    [1, 0, 0, 0, 0, 0, 0, 1], // 25%
+    [1, 0, 0, 0, 0, 1, 1, 1], // 50%
+    [0, 1, 1, 1, 1, 1, 1, 0], // 75%
+];
+
+const MAX_FREQUENCY: u16 = 2047;
+
+/// CH1 frequency sweep (NR10).
+struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+    timer: u8,
+    shadow_frequency: u16,
+    enabled: bool,
+    negate_used: bool, // a negate calculation happened since the last trigger
+}
+
+impl Sweep {
+    fn new() -> Self {
+        Sweep {
+            period: 0,
+            negate: false,
+            shift: 0,
+            timer: 0,
+            shadow_frequency: 0,
+            enabled: false,
+            negate_used: false,
+        }
+    }
+
+    fn reload_timer(&mut self) {
+        // A period of 0 is treated as 8
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+
+    fn calculate(&mut self) -> u16 {
+        let delta = self.shadow_frequency >> self.shift;
+        if self.negate {
+            self.negate_used = true;
+            self.shadow_frequency - delta
+        } else {
+            self.shadow_frequency + delta
+        }
+    }
+}
+
+pub struct SquareChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    duty: u8,
+    duty_position: u8,
+    frequency: u16,
+    timer: u16,
+    length: LengthCounter,
+    envelope: Envelope,
+    sweep: Option<Sweep>,
+}
+
+impl SquareChannel {
+    /// CH1, with a frequency sweep unit.
+    pub fn with_sweep() -> Self {
+        SquareChannel {
+            sweep: Some(Sweep::new()),
+            ..Self::new()
+        }
+    }
+
+    /// CH2.
+    pub fn new() -> Self {
+        SquareChannel {
+            enabled: false,
+            dac_enabled: false,
+            duty: 0,
+            duty_position: 0,
+            frequency: 0,
+            timer: 0,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            sweep: None,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Mark the channel as playing without retriggering it, as the boot ROM
+    /// leaves CH1 after its start-up chime has faded to silence.
+    pub fn resume_silent(&mut self) {
+        self.enabled = self.dac_enabled;
+    }
+
+    /// Stop the channel without touching its registers.
+    pub fn disable(&mut self) {
+        self.enabled = false;
+    }
+
+    /// Write a channel register; `index` 0-4 selects NRx0-NRx4.
+    pub fn write(&mut self, index: usize, value: u8, extra_length_clock: bool) {
+        match index {
+            0 => self.write_sweep(value),
+            1 => {
+                self.duty = value >> 6;
+                self.length.load(value & 0x3F);
+            }
+            2 => {
+                self.envelope.write(value);
+                self.dac_enabled = envelope_dac_enabled(value);
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            3 => self.frequency = (self.frequency & 0x0700) | value as u16,
+            4 => {
+                self.frequency = (self.frequency & 0x00FF) | ((value as u16 & 0x07) << 8);
+                let trigger = value & 0x80 != 0;
+                if self
+                    .length
+                    .write_control(value & 0x40 != 0, trigger, extra_length_clock)
+                {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.trigger();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// NRx1 length writes are still accepted while the APU is powered off.
+    pub fn write_length(&mut self, value: u8) {
+        self.length.load(value & 0x3F);
+    }
+
+    /// Advance one T-cycle.
+    pub fn tick(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = self.period();
+            self.duty_position = (self.duty_position + 1) % 8;
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+        let Some(sweep) = self.sweep.as_mut() else {
+            return;
+        };
+        sweep.timer = sweep.timer.saturating_sub(1);
+        if sweep.timer > 0 {
+            return;
+        }
+        sweep.reload_timer();
+        if !sweep.enabled || sweep.period == 0 {
+            return;
+        }
+
+        let frequency = sweep.calculate();
+        if frequency > MAX_FREQUENCY {
+            self.enabled = false;
+            return;
+        }
+        if sweep.shift != 0 {
+            sweep.shadow_frequency = frequency;
+            self.frequency = frequency;
+            // The new frequency is checked for overflow again but not written back
+            if sweep.calculate() > MAX_FREQUENCY {
+                self.enabled = false;
+            }
+        }
+    }
+
+    /// Current digital output (0-15), or None when the DAC is off.
+    pub fn output(&self) -> Option<u8> {
+        if !self.dac_enabled {
+            return None;
+        }
+        if !self.enabled {
+            return Some(0);
+        }
+        Some(
+            DUTY_PATTERNS[self.duty as usize][self.duty_position as usize] * self.envelope.volume(),
+        )
+    }
+
+    /// Clear everything but, on the DMG, the length counter (APU power-off).
+    pub fn power_off(&mut self, keep_length: bool) {
+        let length = std::mem::replace(&mut self.length, LengthCounter::new(64));
+        *self = if self.sweep.is_some() {
+            SquareChannel::with_sweep()
+        } else {
+            SquareChannel::new()
+        };
+        if keep_length {
+            self.length = length;
+        }
+    }
+
+    fn period(&self) -> u16 {
+        (2048 - self.frequency) * 4
+    }
+
+    fn write_sweep(&mut self, value: u8) {
+        let Some(sweep) = self.sweep.as_mut() else {
+            return;
+        };
+        sweep.period = (value >> 4) & 0x07;
+        sweep.negate = value & 0x08 != 0;
+        sweep.shift = value & 0x07;
+        // Leaving negate mode after a negate calculation disables the channel
+        if !sweep.negate && sweep.negate_used {
+            self.enabled = false;
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.timer = self.period();
+        self.envelope.trigger();
+
+        let Some(sweep) = self.sweep.as_mut() else {
+            return;
+        };
+        sweep.shadow_frequency = self.frequency;
+        sweep.reload_timer();
+        sweep.enabled = sweep.period != 0 || sweep.shift != 0;
+        sweep.negate_used = false;
+        if sweep.shift != 0 && sweep.calculate() > MAX_FREQUENCY {
+            self.enabled = false;
+        }
+    }
+}
+
+impl Snapshot for Sweep {
+    fn save_to(&self, state: &mut StateWriter) {
+        state.u8(self.period);
+        state.bool(self.negate);
+        state.u8(self.shift);
+        state.u8(self.timer);
+        state.u16(self.shadow_frequency);
+        state.bool(self.enabled);
+        state.bool(self.negate_used);
+    }
+
+    fn load_from(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.period = state.u8()? & 0x07;
+        self.negate = state.bool()?;
+        self.shift = state.u8()? & 0x07;
+        self.timer = state.u8()?;
+        self.shadow_frequency = state.u16()? & MAX_FREQUENCY;
+        self.enabled = state.bool()?;
+        self.negate_used = state.bool()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for SquareChannel {
+    fn save_to(&self, state: &mut StateWriter) {
+        state.bool(self.enabled);
+        state.bool(self.dac_enabled);
+        state.u8(self.duty);
+        state.u8(self.duty_position);
+        state.u16(self.frequency);
+        state.u16(self.timer);
+        self.length.save_to(state);
+        self.envelope.save_to(state);
+        if let Some(sweep) = &self.sweep {
+            sweep.save_to(state);
+        }
+    }
+
+    fn load_from(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = state.bool()?;
+        self.dac_enabled = state.bool()?;
+        self.duty = state.u8()? & 0x03;
+        self.duty_position = state.u8()? & 0x07;
+        self.frequency = state.u16()? & MAX_FREQUENCY;
+        self.timer = state.u16()?;
+        self.length.load_from(state)?;
+        self.envelope.load_from(state)?;
+        if let Some(sweep) = &mut self.sweep {
+            sweep.load_from(state)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+//! Building blocks shared by the APU channels: the length counter and the
+//! volume envelope.
+//!
+//! Reference: [Pan Docs — Audio Details](https://gbdev.io/pandocs/Audio_details.html)
+
+use crate::save_state::{Snapshot, StateError, StateReader, StateWriter};
+
+/// Silences a channel after a programmable number of frame sequencer length
+/// clocks (256 Hz).
+pub struct LengthCounter {
+    counter: u16,
+    enabled: bool,
+    max: u16, // 64 for the square and noise channels, 256 for the wave channel
+}
+
+impl LengthCounter {
+    pub fn new(max: u16) -> Self {
+        LengthCounter {
+            counter: 0,
+            enabled: false,
+            max,
+        }
+    }
+
+    /// Load the length from NRx1 (the counter counts up to `max`).
+    pub fn load(&mut self, value: u8) {
+        self.counter = self.max - value as u16;
+    }
+
+    /// Clock from the frame sequencer. Returns true if the channel must be
+    /// disabled because the counter reached zero.
+    pub fn clock(&mut self) -> bool {
+        if !self.enabled || self.counter == 0 {
+            return false;
+        }
+        self.counter -= 1;
+        self.counter == 0
+    }
+
+    /// Apply the length-enable and trigger bits of an NRx4 write.
+    ///
+    /// `extra_clock` is true when the next frame sequencer step will not clock
+    /// lengths; enabling the counter then clocks it once immediately, and a
+    /// trigger that reloads an expired counter loads one less than the maximum.
+    ///
+    /// Returns true if the extra clock expired the counter and the channel
+    /// must be disabled.
+    pub fn write_control(&mut self, enable: bool, trigger: bool, extra_clock: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = enable;
+
+        let mut expired = false;
+        if extra_clock && enable && !was_enabled && self.counter > 0 {
+            self.counter -= 1;
+            expired = self.counter == 0 && !trigger;
+        }
+
+        if trigger && self.counter == 0 {
+            self.counter = self.max;
+            if extra_clock && enable {
+                self.counter -= 1;
+            }
+        }
+        expired
+    }
+}
+
+/// Volume envelope (NRx2), clocked at 64 Hz by the frame sequencer.
+pub struct Envelope {
+    initial_volume: u8,
+    increase: bool,
+    period: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Self {
+        Envelope {
+            initial_volume: 0,
+            increase: false,
+            period: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    /// Latch NRx2: initial volume (bits 7-4), direction (bit 3), period (bits 2-0).
+    pub fn write(&mut self, value: u8) {
+        self.initial_volume = value >> 4;
+        self.increase = value & 0x08 != 0;
+        self.period = value & 0x07;
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.initial_volume;
+        self.timer = self.period;
+    }
+
+    pub fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return;
+        }
+        self.timer = self.period;
+        if self.increase && self.volume < 15 {
+            self.volume += 1;
+        } else if !self.increase && self.volume > 0 {
+            self.volume -= 1;
+        }
+    }
+
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+}
+
+/// The DAC of an envelope channel is on when NRx2 bits 7-3 are not all zero.
+pub fn envelope_dac_enabled(nrx2: u8) -> bool {
+    nrx2 & 0xF8 != 0
+}
+
+impl Snapshot for LengthCounter {
+    fn save_to(&self, state: &mut StateWriter) {
+        state.u16(self.counter);
+        state.bool(self.enabled);
+    }
+
+    fn load_from(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.counter = state.u16()?.min(self.max);
+        self.enabled = state.bool()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Envelope {
+    fn save_to(&self, state: &mut StateWriter) {
+        state.u8(self.initial_volume);
+        state.bool(self.increase);
+        state.u8(self.period);
+        state.u8(self.volume);
+        state.u8(self.timer);
+    }
+
+    fn load_from(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.initial_volume = state.u8()?;
+        self.increase = state.bool()?;
+        self.period = state.u8()?;
+        self.volume = state.u8()?;
+        self.timer = state.u8()?;
+        Ok(())
+    }
+}
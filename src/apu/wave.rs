@@ -0,0 +1,177 @@
+//! Wave channel (CH3), playing 32 4-bit samples from wave RAM (0xFF30-0xFF3F).
+//!
+//! Reference: [Pan Docs — Sound Channel 3](https://gbdev.io/pandocs/Audio_Registers.html#sound-channel-3--wave-output)
+
+use super::units::LengthCounter;
+use crate::save_state::{Snapshot, StateError, StateReader, StateWriter};
+
+pub const WAVE_RAM_SIZE: usize = 16;
+
+pub struct WaveChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    volume_shift: u8, // NR32 output level as a right shift (4 = mute)
+    frequency: u16,
+    timer: u16,
+    position: u8,      // current sample index (0-31)
+    sample_buffer: u8, // last sample read from wave RAM
+    length: LengthCounter,
+    ram: [u8; WAVE_RAM_SIZE],
+}
+
+impl WaveChannel {
+    pub fn new() -> Self {
+        WaveChannel {
+            enabled: false,
+            dac_enabled: false,
+            volume_shift: 4,
+            frequency: 0,
+            timer: 0,
+            position: 0,
+            sample_buffer: 0,
+            length: LengthCounter::new(256),
+            ram: [0; WAVE_RAM_SIZE],
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Write a channel register; `index` 0-4 selects NR30-NR34.
+    pub fn write(&mut self, index: usize, value: u8, extra_length_clock: bool) {
+        match index {
+            0 => {
+                self.dac_enabled = value & 0x80 != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            1 => self.length.load(value),
+            2 => {
+                self.volume_shift = match (value >> 5) & 0x03 {
+                    0 => 4,
+                    code => code - 1,
+                };
+            }
+            3 => self.frequency = (self.frequency & 0x0700) | value as u16,
+            4 => {
+                self.frequency = (self.frequency & 0x00FF) | ((value as u16 & 0x07) << 8);
+                let trigger = value & 0x80 != 0;
+                if self
+                    .length
+                    .write_control(value & 0x40 != 0, trigger, extra_length_clock)
+                {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.enabled = self.dac_enabled;
+                    self.timer = self.period();
+                    self.position = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// NR31 length writes are still accepted while the APU is powered off.
+    pub fn write_length(&mut self, value: u8) {
+        self.length.load(value);
+    }
+
+    /// Wave RAM access. While the channel plays, the CPU sees the byte the
+    /// channel is currently reading.
+    pub fn read_ram(&self, index: usize) -> u8 {
+        if self.enabled {
+            self.ram[self.position as usize / 2]
+        } else {
+            self.ram[index]
+        }
+    }
+
+    pub fn write_ram(&mut self, index: usize, value: u8) {
+        if self.enabled {
+            self.ram[self.position as usize / 2] = value;
+        } else {
+            self.ram[index] = value;
+        }
+    }
+
+    /// Advance one T-cycle.
+    pub fn tick(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = self.period();
+            if self.enabled {
+                self.position = (self.position + 1) % 32;
+                let byte = self.ram[self.position as usize / 2];
+                self.sample_buffer = if self.position & 1 == 0 {
+                    byte >> 4
+                } else {
+                    byte & 0x0F
+                };
+            }
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// Current digital output (0-15), or None when the DAC is off.
+    pub fn output(&self) -> Option<u8> {
+        if !self.dac_enabled {
+            return None;
+        }
+        if !self.enabled {
+            return Some(0);
+        }
+        Some(self.sample_buffer >> self.volume_shift)
+    }
+
+    /// Clear everything but wave RAM and, on the DMG, the length counter
+    /// (APU power-off).
+    pub fn power_off(&mut self, keep_length: bool) {
+        let ram = self.ram;
+        let length = std::mem::replace(&mut self.length, LengthCounter::new(256));
+        *self = WaveChannel::new();
+        self.ram = ram;
+        if keep_length {
+            self.length = length;
+        }
+    }
+
+    fn period(&self) -> u16 {
+        (2048 - self.frequency) * 2
+    }
+}
+
+impl Snapshot for WaveChannel {
+    fn save_to(&self, state: &mut StateWriter) {
+        state.bool(self.enabled);
+        state.bool(self.dac_enabled);
+        state.u8(self.volume_shift);
+        state.u16(self.frequency);
+        state.u16(self.timer);
+        state.u8(self.position);
+        state.u8(self.sample_buffer);
+        self.length.save_to(state);
+        state.bytes(&self.ram);
+    }
+
+    fn load_from(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = state.bool()?;
+        self.dac_enabled = state.bool()?;
+        self.volume_shift = state.u8()?.min(4);
+        self.frequency = state.u16()? & 0x7FF;
+        self.timer = state.u16()?;
+        self.position = state.u8()? % 32;
+        self.sample_buffer = state.u8()?;
+        self.length.load_from(state)?;
+        state.bytes(&mut self.ram)
+    }
+}
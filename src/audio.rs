@@ -0,0 +1,403 @@
+//! Audio sample output.
+//!
+//! The APU's stereo output is sampled once per M-cycle (1 MiHz) and
+//! converted to the host sample rate:
+//! 1. A box filter averages every input sample that falls within one output
+//!    period, which band-limits the signal before decimation.
+//! 2. A high-pass filter removes the DC offset, modelling the capacitor on
+//!    the real hardware's audio output.
+//! 3. Samples are queued in a ring buffer that front-ends pull from, and can
+//!    also be recorded to a 16-bit stereo WAV file.
+//!
+//! Each channel is also resampled separately for oscilloscope buffers and
+//! per-channel WAV dumps.
+//!
+//! Reference: [Pan Docs — Audio Details](https://gbdev.io/pandocs/Audio_details.html)
+
+use crate::apu::Channel;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+/// Rate at which the APU output is sampled (one sample per M-cycle).
+pub const INPUT_SAMPLE_RATE: u32 = 1_048_576;
+/// Default host sample rate.
+pub const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+
+/// High-pass capacitor charge factor per T-cycle on DMG hardware.
+const HIGH_PASS_CHARGE_PER_T_CYCLE: f64 = 0.999958;
+const T_CYCLES_PER_SECOND: f64 = 4_194_304.0;
+/// Buffered audio before the oldest frames are dropped, in seconds.
+const RING_BUFFER_SECONDS: f32 = 0.25;
+/// Samples of history kept per channel for oscilloscope views.
+pub const SCOPE_FRAMES: usize = 1024;
+
+/// Averages input samples over each output period, for frames of `N`
+/// samples (2 for the stereo mix, 4 for the individual channels).
+struct Resampler<const N: usize> {
+    output_rate: u32,
+    phase: u32, // accumulated output_rate, emits once it reaches INPUT_SAMPLE_RATE
+    sum: [f32; N],
+    count: u32,
+}
+
+impl<const N: usize> Resampler<N> {
+    fn new(output_rate: u32) -> Self {
+        Resampler {
+            output_rate,
+            phase: 0,
+            sum: [0.0; N],
+            count: 0,
+        }
+    }
+
+    /// Feed one input frame; returns an output frame when one is complete.
+    fn push(&mut self, frame: [f32; N]) -> Option<[f32; N]> {
+        for (sum, sample) in self.sum.iter_mut().zip(frame) {
+            *sum += sample;
+        }
+        self.count += 1;
+        self.phase += self.output_rate;
+        if self.phase < INPUT_SAMPLE_RATE {
+            return None;
+        }
+        self.phase -= INPUT_SAMPLE_RATE;
+
+        let count = self.count as f32;
+        let frame = self.sum.map(|sum| sum / count);
+        self.sum = [0.0; N];
+        self.count = 0;
+        Some(frame)
+    }
+}
+
+/// One-pole high-pass filter removing the DAC's DC offset.
+struct HighPassFilter {
+    charge: f32,
+    capacitor: f32,
+}
+
+impl HighPassFilter {
+    fn new(sample_rate: u32) -> Self {
+        let t_cycles_per_sample = T_CYCLES_PER_SECOND / sample_rate as f64;
+        HighPassFilter {
+            charge: HIGH_PASS_CHARGE_PER_T_CYCLE.powf(t_cycles_per_sample) as f32,
+            capacitor: 0.0,
+        }
+    }
+
+    fn apply(&mut self, input: f32) -> f32 {
+        let output = input - self.capacitor;
+        self.capacitor = input - output * self.charge;
+        output
+    }
+}
+
+/// Fixed-size queue of interleaved stereo samples; when full, the oldest
+/// frames are overwritten.
+pub struct SampleRingBuffer {
+    samples: Vec<f32>,
+    read: usize,
+    len: usize,
+}
+
+impl SampleRingBuffer {
+    pub fn new(capacity_frames: usize) -> Self {
+        SampleRingBuffer {
+            samples: vec![0.0; capacity_frames.max(1) * 2],
+            read: 0,
+            len: 0,
+        }
+    }
+
+    /// Number of stereo frames waiting to be read.
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.len / 2
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn push(&mut self, [left, right]: [f32; 2]) {
+        let capacity = self.samples.len();
+        if self.len == capacity {
+            // Drop the oldest frame
+            self.read = (self.read + 2) % capacity;
+            self.len -= 2;
+        }
+        let write = (self.read + self.len) % capacity;
+        self.samples[write] = left;
+        self.samples[write + 1] = right;
+        self.len += 2;
+    }
+
+    /// Copy up to `out.len() / 2` frames of interleaved left/right samples
+    /// into `out`, returning the number of frames read.
+    #[allow(dead_code)]
+    pub fn pull(&mut self, out: &mut [f32]) -> usize {
+        let capacity = self.samples.len();
+        let count = (out.len() / 2 * 2).min(self.len);
+        for sample in out.iter_mut().take(count) {
+            *sample = self.samples[self.read];
+            self.read = (self.read + 1) % capacity;
+        }
+        self.len -= count;
+        count / 2
+    }
+}
+
+type WavFile = hound::WavWriter<BufWriter<File>>;
+
+/// The most recent samples of one channel, for drawing oscilloscopes.
+struct ScopeBuffer {
+    samples: Vec<f32>,
+    next: usize, // index of the oldest sample, overwritten next
+}
+
+impl ScopeBuffer {
+    fn new() -> Self {
+        ScopeBuffer {
+            samples: vec![0.0; SCOPE_FRAMES],
+            next: 0,
+        }
+    }
+
+    fn push(&mut self, sample: f32) {
+        self.samples[self.next] = sample;
+        self.next = (self.next + 1) % self.samples.len();
+    }
+
+    fn snapshot(&self) -> Vec<f32> {
+        let (newest, oldest) = self.samples.split_at(self.next);
+        [oldest, newest].concat()
+    }
+}
+
+/// Resampled, filtered APU output at the host sample rate.
+///
+/// Besides the stereo mix, each channel's DAC output is resampled on its own
+/// for oscilloscope views and per-channel WAV dumps. Those taps are not
+/// high-pass filtered and ignore the APU's mute and solo settings.
+pub struct AudioOutput {
+    sample_rate: u32,
+    resampler: Resampler<2>,
+    high_pass: [HighPassFilter; 2],
+    buffer: SampleRingBuffer,
+    wav: Option<WavFile>,
+    channel_resampler: Resampler<4>,
+    scopes: [ScopeBuffer; 4],
+    channel_wavs: [Option<WavFile>; 4],
+}
+
+impl AudioOutput {
+    /// Resample to `sample_rate`, which must not exceed `INPUT_SAMPLE_RATE`.
+    pub fn new(sample_rate: u32) -> Self {
+        let capacity = (sample_rate as f32 * RING_BUFFER_SECONDS) as usize;
+        AudioOutput {
+            sample_rate,
+            resampler: Resampler::new(sample_rate),
+            high_pass: [
+                HighPassFilter::new(sample_rate),
+                HighPassFilter::new(sample_rate),
+            ],
+            buffer: SampleRingBuffer::new(capacity),
+            wav: None,
+            channel_resampler: Resampler::new(sample_rate),
+            scopes: std::array::from_fn(|_| ScopeBuffer::new()),
+            channel_wavs: [None, None, None, None],
+        }
+    }
+
+    /// Feed the APU output for one M-cycle: the stereo mix and the output of
+    /// each channel (see [`APU::channel_outputs`](crate::apu::APU::channel_outputs)).
+    pub fn push(&mut self, (left, right): (f32, f32), channels: [f32; 4]) {
+        if let Some([left, right]) = self.resampler.push([left, right]) {
+            let frame = [
+                self.high_pass[0].apply(left),
+                self.high_pass[1].apply(right),
+            ];
+            self.buffer.push(frame);
+            write_wav_frame(&mut self.wav, &frame);
+        }
+
+        if let Some(channels) = self.channel_resampler.push(channels) {
+            for (index, sample) in channels.into_iter().enumerate() {
+                self.scopes[index].push(sample);
+                write_wav_frame(&mut self.channel_wavs[index], &[sample]);
+            }
+        }
+    }
+
+    /// Ring buffer of output frames for front-ends to pull from.
+    #[allow(dead_code)]
+    pub fn buffer(&mut self) -> &mut SampleRingBuffer {
+        &mut self.buffer
+    }
+
+    /// The last `SCOPE_FRAMES` samples of a channel at the host rate,
+    /// oldest first.
+    #[allow(dead_code)]
+    pub fn oscilloscope(&self, channel: Channel) -> Vec<f32> {
+        self.scopes[channel.index()].snapshot()
+    }
+
+    /// Start recording every output frame to a 16-bit stereo WAV file.
+    pub fn start_wav_recording(&mut self, path: &Path) -> io::Result<()> {
+        self.wav = Some(create_wav(path, 2, self.sample_rate)?);
+        Ok(())
+    }
+
+    /// Start recording each channel to its own 16-bit mono WAV file in `dir`,
+    /// named after the channel (e.g. `ch1_square.wav`).
+    pub fn start_channel_wav_recording(&mut self, dir: &Path) -> io::Result<()> {
+        for channel in Channel::ALL {
+            let path = dir.join(format!("{}.wav", channel.name()));
+            self.channel_wavs[channel.index()] = Some(create_wav(&path, 1, self.sample_rate)?);
+        }
+        Ok(())
+    }
+
+    /// Finish all WAV files, writing their final headers.
+    pub fn finish_wav_recording(&mut self) -> io::Result<()> {
+        let writers = std::iter::once(&mut self.wav).chain(self.channel_wavs.iter_mut());
+        for writer in writers.filter_map(Option::take) {
+            writer.finalize().map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+}
+
+fn create_wav(path: &Path, channels: u16, sample_rate: u32) -> io::Result<WavFile> {
+    let spec = hound::WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    hound::WavWriter::create(path, spec).map_err(io::Error::other)
+}
+
+// Write one frame, stopping the recording if the file can't be written.
+fn write_wav_frame(wav: &mut Option<WavFile>, frame: &[f32]) {
+    let Some(writer) = wav.as_mut() else {
+        return;
+    };
+    for &sample in frame {
+        if let Err(e) = writer.write_sample(to_i16(sample)) {
+            eprintln!("Stopping WAV recording: {e}");
+            *wav = None;
+            return;
+        }
+    }
+}
+
+fn to_i16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resampler_produces_host_rate() {
+        let mut output = AudioOutput::new(DEFAULT_SAMPLE_RATE);
+        let mut frames = 0;
+        for _ in 0..INPUT_SAMPLE_RATE {
+            output.push((0.0, 0.0), [0.0; 4]);
+            frames += output.buffer().pull(&mut [0.0; 2]);
+        }
+        assert_eq!(frames, DEFAULT_SAMPLE_RATE as usize);
+    }
+
+    #[test]
+    fn test_resampler_averages_within_output_period() {
+        // Two inputs per output: alternating values average out
+        let mut resampler = Resampler::new(INPUT_SAMPLE_RATE / 2);
+        assert_eq!(resampler.push([1.0, -1.0]), None);
+        assert_eq!(resampler.push([0.0, 0.0]), Some([0.5, -0.5]));
+    }
+
+    #[test]
+    fn test_high_pass_removes_dc_offset() {
+        let mut filter = HighPassFilter::new(DEFAULT_SAMPLE_RATE);
+        assert_eq!(filter.apply(1.0), 1.0);
+        let mut last = 1.0;
+        for _ in 0..DEFAULT_SAMPLE_RATE {
+            last = filter.apply(1.0);
+        }
+        assert!(last.abs() < 0.01);
+    }
+
+    #[test]
+    fn test_ring_buffer_drops_oldest_when_full() {
+        let mut buffer = SampleRingBuffer::new(2);
+        buffer.push([1.0, 1.0]);
+        buffer.push([2.0, 2.0]);
+        buffer.push([3.0, 3.0]);
+        assert_eq!(buffer.len(), 2);
+
+        let mut out = [0.0; 6];
+        assert_eq!(buffer.pull(&mut out), 2);
+        assert_eq!(&out[..4], &[2.0, 2.0, 3.0, 3.0]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_wav_recording() {
+        let path = std::env::temp_dir().join(format!("gb_audio_test_{}.wav", std::process::id()));
+        let mut output = AudioOutput::new(DEFAULT_SAMPLE_RATE);
+        output.start_wav_recording(&path).unwrap();
+        for _ in 0..INPUT_SAMPLE_RATE {
+            output.push((0.5, -0.5), [0.0; 4]);
+        }
+        output.finish_wav_recording().unwrap();
+
+        let reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().channels, 2);
+        assert_eq!(reader.spec().sample_rate, DEFAULT_SAMPLE_RATE);
+        assert_eq!(reader.len(), 2 * DEFAULT_SAMPLE_RATE);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_oscilloscope_keeps_latest_channel_samples() {
+        let mut output = AudioOutput::new(INPUT_SAMPLE_RATE);
+        assert_eq!(output.oscilloscope(Channel::Wave), vec![0.0; SCOPE_FRAMES]);
+
+        for i in 0..SCOPE_FRAMES + 2 {
+            output.push((0.0, 0.0), [0.0, 0.0, i as f32, 0.0]);
+        }
+        let scope = output.oscilloscope(Channel::Wave);
+        assert_eq!(scope.len(), SCOPE_FRAMES);
+        assert_eq!(scope[0], 2.0);
+        assert_eq!(scope[SCOPE_FRAMES - 1], (SCOPE_FRAMES + 1) as f32);
+        assert!(output
+            .oscilloscope(Channel::Noise)
+            .iter()
+            .all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn test_channel_wav_recording() {
+        let dir = std::env::temp_dir().join(format!("gb_channel_wavs_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut output = AudioOutput::new(DEFAULT_SAMPLE_RATE);
+        output.start_channel_wav_recording(&dir).unwrap();
+        for _ in 0..INPUT_SAMPLE_RATE {
+            output.push((0.0, 0.0), [1.0, 0.0, 0.0, -1.0]);
+        }
+        output.finish_wav_recording().unwrap();
+
+        let mut square1 = hound::WavReader::open(dir.join("ch1_square.wav")).unwrap();
+        assert_eq!(square1.spec().channels, 1);
+        assert_eq!(square1.len(), DEFAULT_SAMPLE_RATE);
+        assert_eq!(square1.samples::<i16>().next().unwrap().unwrap(), i16::MAX);
+        let mut noise = hound::WavReader::open(dir.join("ch4_noise.wav")).unwrap();
+        assert_eq!(noise.samples::<i16>().next().unwrap().unwrap(), -i16::MAX);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -8,10 +8,28 @@ const HEADER_START: usize = 0x0100;
 const HEADER_END: usize = 0x014F;
 
 // Relevant fields we currently care about:
+const TITLE_ADDR: usize = 0x0134;
+const TITLE_LEN: usize = 16;
+const CGB_FLAG_ADDR: usize = 0x0143;
+const NEW_LICENSEE_ADDR: usize = 0x0144;
+const SGB_FLAG_ADDR: usize = 0x0146;
 const CARTRIDGE_TYPE_ADDR: usize = 0x0147;
 const ROM_SIZE_ADDR: usize = 0x0148;
 const RAM_SIZE_ADDR: usize = 0x0149;
-const HEADER_CHECKSUM_ADDR: usize = 0x014D;
+const OLD_LICENSEE_ADDR: usize = 0x014B;
+pub const HEADER_CHECKSUM_ADDR: usize = 0x014D;
+
+// Old licensee code 0x33 means the new licensee code at 0x0144 is used instead
+const USE_NEW_LICENSEE: u8 = 0x33;
+const NINTENDO_OLD_LICENSEE: u8 = 0x01;
+const NINTENDO_NEW_LICENSEE: [u8; 2] = *b"01";
+
+// SGB flag value for games with SGB functions; the SGB also requires the
+// old licensee code to be 0x33
+const SGB_FLAG_SUPPORTED: u8 = 0x03;
+
+// CGB flag bit 7: the game supports CGB functions (0x80 enhanced, 0xC0 CGB only)
+const CGB_FLAG_SUPPORTED: u8 = 0x80;
 
 // Header checksum input range: 0x0134..=0x014C
 const HEADER_CHECKSUM_RANGE_START: usize = 0x0134;
@@ -54,6 +72,18 @@ impl fmt::Display for MbcKind {
 /// Parsed subset of cartridge header information for emulator setup/logging.
 #[derive(Debug, Clone)]
 pub struct CartridgeHeader {
+    /// Raw title bytes 0x0134..=0x0143 (the last byte doubles as the CGB flag).
+    pub title: [u8; TITLE_LEN],
+    /// Raw 0x014B value (old licensee code).
+    pub old_licensee: u8,
+    /// Raw 0x0144..=0x0145 value (new licensee code, two ASCII characters).
+    pub new_licensee: [u8; 2],
+
+    /// Raw 0x0143 value (CGB flag).
+    pub cgb_flag: u8,
+    /// Raw 0x0146 value (SGB flag).
+    pub sgb_flag: u8,
+
     /// Raw 0x0147 value.
     pub cartridge_type: u8,
     /// Human readable cartridge type.
@@ -112,6 +142,31 @@ impl CartridgeHeader {
         Self::try_from(rom)
     }
 
+    /// Whether the game supports CGB functions (CGB flag 0x80 or 0xC0).
+    pub fn supports_cgb(&self) -> bool {
+        self.cgb_flag & CGB_FLAG_SUPPORTED != 0
+    }
+
+    /// Whether the game uses SGB functions. The SGB ignores the flag unless
+    /// the old licensee code is 0x33.
+    pub fn supports_sgb(&self) -> bool {
+        self.sgb_flag == SGB_FLAG_SUPPORTED && self.old_licensee == USE_NEW_LICENSEE
+    }
+
+    /// Whether the licensee code is Nintendo's, in either the old or new format.
+    pub fn is_nintendo_licensed(&self) -> bool {
+        match self.old_licensee {
+            USE_NEW_LICENSEE => self.new_licensee == NINTENDO_NEW_LICENSEE,
+            code => code == NINTENDO_OLD_LICENSEE,
+        }
+    }
+
+    /// Sum of the title bytes, used by the CGB boot ROM to pick colour
+    /// palettes for DMG games.
+    pub fn title_checksum(&self) -> u8 {
+        self.title.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+    }
+
     /// Compact one-line summary.
     pub fn summary_line(&self) -> String {
         let rom = match (self.rom_size_bytes, self.rom_banks) {
@@ -169,7 +224,15 @@ impl TryFrom<&[u8]> for CartridgeHeader {
         let computed = compute_header_checksum(rom);
         let valid = stored == computed;
 
+        let mut title = [0; TITLE_LEN];
+        title.copy_from_slice(&rom[TITLE_ADDR..TITLE_ADDR + TITLE_LEN]);
+
         Ok(Self {
+            title,
+            old_licensee: rom[OLD_LICENSEE_ADDR],
+            new_licensee: [rom[NEW_LICENSEE_ADDR], rom[NEW_LICENSEE_ADDR + 1]],
+            cgb_flag: rom[CGB_FLAG_ADDR],
+            sgb_flag: rom[SGB_FLAG_ADDR],
             cartridge_type: cart_type,
             cartridge_type_name: cartridge_type_name(cart_type),
             mbc_kind,
@@ -0,0 +1,115 @@
+//! Play-time controls for the headless runner, typed on standard input.
+//!
+//! The runner has no window to take key presses from, so commands are read
+//! a line at a time from standard input on a background thread and handed
+//! to the emulation loop, which applies them at the next frame boundary.
+//!
+//! Commands:
+//! - `press BUTTON[+BUTTON...]` (or `p`): hold buttons, e.g. `press a+right`
+//! - `release BUTTON[+BUTTON...]` (or `u`): let go of buttons
+//! - `rewind [N]` (or `r [N]`): step back N rewind snapshots (default 1)
+//!
+//! Buttons are `right`, `left`, `up`, `down`, `a`, `b`, `select` and `start`.
+
+use crate::joypad::Button;
+use std::io::{self, BufRead};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+
+/// A command for the running emulator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    /// Hold the buttons in this mask (see [`Button::mask`]).
+    Press(u8),
+    /// Let go of the buttons in this mask.
+    Release(u8),
+    /// Step back this many snapshots in the rewind buffer.
+    Rewind(u64),
+}
+
+/// Parse one command line. Blank lines parse to `None`.
+pub fn parse(line: &str) -> Result<Option<Control>, String> {
+    let mut words = line.split_whitespace();
+    let Some(command) = words.next() else {
+        return Ok(None);
+    };
+    let control = match command {
+        "press" | "p" => Control::Press(parse_buttons(words.next())?),
+        "release" | "u" => Control::Release(parse_buttons(words.next())?),
+        "rewind" | "r" => {
+            let steps = match words.next() {
+                None => 1,
+                Some(count) => match count.parse() {
+                    Ok(steps) if steps > 0 => steps,
+                    _ => return Err(format!("Invalid rewind step count: {count}")),
+                },
+            };
+            Control::Rewind(steps)
+        }
+        _ => return Err(format!("Unknown command: {command}")),
+    };
+    if words.next().is_some() {
+        return Err(format!("Too many arguments: {}", line.trim()));
+    }
+    Ok(Some(control))
+}
+
+/// Parse `BUTTON[+BUTTON...]` into a button mask.
+fn parse_buttons(names: Option<&str>) -> Result<u8, String> {
+    let names = names.ok_or("Missing button names".to_string())?;
+    names.split('+').try_fold(0, |mask, name| {
+        let button = Button::from_name(name).ok_or(format!("Unknown button: {name}"))?;
+        Ok(mask | button.mask())
+    })
+}
+
+/// Lines typed on standard input since the last call. The reader thread is
+/// started on the first call and lives for the rest of the process, so runs
+/// of several ROMs share it.
+pub fn poll_stdin() -> Vec<String> {
+    static LINES: OnceLock<Mutex<Receiver<String>>> = OnceLock::new();
+    let lines = LINES.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Mutex::new(receiver)
+    });
+    let lines = lines.lock().unwrap_or_else(|e| e.into_inner());
+    lines.try_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rewind() {
+        assert_eq!(parse("rewind"), Ok(Some(Control::Rewind(1))));
+        assert_eq!(parse("  r 30 "), Ok(Some(Control::Rewind(30))));
+        assert_eq!(parse(""), Ok(None));
+        assert!(parse("rewind 0").is_err());
+        assert!(parse("rewind x").is_err());
+        assert!(parse("rewind 1 2").is_err());
+        assert!(parse("jump").is_err());
+    }
+
+    #[test]
+    fn test_parse_buttons() {
+        let right_a = Button::Right.mask() | Button::A.mask();
+        assert_eq!(parse("press a+right"), Ok(Some(Control::Press(right_a))));
+        assert_eq!(
+            parse("u START"),
+            Ok(Some(Control::Release(Button::Start.mask())))
+        );
+        assert_eq!(parse("press"), Err("Missing button names".to_string()));
+        assert_eq!(parse("press a+x"), Err("Unknown button: x".to_string()));
+        assert!(parse("release a b").is_err());
+    }
+}
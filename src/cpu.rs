@@ -3,51 +3,148 @@
 //! This module contains the CPU struct and instruction execution logic,
 //! managing registers, memory access, and the fetch-decode-execute cycle.
 
+use crate::cartridge_header::{CartridgeHeader, HEADER_CHECKSUM_ADDR};
+use crate::debugger::call_stack::{CallStack, FrameKind};
 use crate::flag_helpers as fh;
 use crate::instructions::{
     ArithmeticTarget, IncDecTarget, Instruction, JumpTest, LoadByteSource, LoadByteTarget,
     LoadType, LoadWordSource, LoadWordTarget, StackTarget,
 };
-use crate::interrupts::INTERRUPT_CYCLES;
 use crate::memory_bus::MemoryBus;
+use crate::model::Model;
+use crate::ppu::compat::CompatPalettes;
 use crate::register::{self, Register16, Registers};
+use crate::save_state::{self, Snapshot, StateError, StateReader, StateWriter};
+use crate::sgb::Sgb;
+
+/// Duration of a CGB speed switch, during which the CPU sits in HALT mode.
+/// Reference: [Pan Docs — CGB Registers](https://gbdev.io/pandocs/CGB_Registers.html)
+const SPEED_SWITCH_M_CYCLES: u16 = 2050;
+
+/// PC after an interrupt dispatch that was cancelled by its own stack push.
+const CANCELLED_DISPATCH_ADDRESS: u16 = 0x0000;
 
 pub(crate) struct CPU {
     pub registers: register::Registers,
     pub bus: MemoryBus,
     is_halted: bool,
+    is_stopped: bool,
     pub interrupts_enabled: bool,
+    /// Print every executed instruction with the registers.
+    pub trace: bool,
+    /// Shadow call stack for debuggers and profilers, kept only when set.
+    pub call_stack: Option<CallStack>,
     ei_pending: bool,
     halt_bug: bool,
+    speed_switch_delay: u16, // remaining M-cycles of an in-progress speed switch
+    step_cycles: u16,        // T-cycles elapsed so far in the current `step`
+    model: Model,
+    rom_checksum: u32, // CRC-32 of the cartridge ROM, identifying it in save states
 }
 
 impl CPU {
-    /// Create a new CPU with the initial register state and a ROM loaded into the bus.
+    /// Create a new DMG CPU in its post-boot state with a ROM loaded into the bus.
+    #[allow(dead_code)]
     pub(crate) fn new(rom_data: Vec<u8>) -> CPU {
-        let bus = MemoryBus::new(rom_data);
+        Self::with_model(rom_data, Model::Dmg, None)
+    }
+
+    /// Create a CPU for `model` with a ROM loaded into the bus.
+    ///
+    /// With a boot ROM, execution starts at 0x0000 from the power-on state;
+    /// otherwise it starts at 0x0100 in the state the model's boot ROM leaves.
+    /// A game without the CGB flag runs on a CGB in DMG compatibility mode,
+    /// with the palettes the boot ROM would pick for its title. On an SGB,
+    /// games with the SGB flag can send SGB commands.
+    pub(crate) fn with_model(rom_data: Vec<u8>, model: Model, boot_rom: Option<Vec<u8>>) -> CPU {
+        let header_checksum = rom_data.get(HEADER_CHECKSUM_ADDR).copied().unwrap_or(0);
+        let header = CartridgeHeader::parse(&rom_data).ok();
+        let rom_checksum = save_state::crc32(&rom_data);
+        let mut bus = MemoryBus::new(rom_data);
+        if model == Model::Sgb && header.as_ref().is_some_and(CartridgeHeader::supports_sgb) {
+            bus.sgb = Some(Sgb::new());
+        }
+        let registers = match boot_rom {
+            Some(boot_rom) => {
+                bus.map_boot_rom(model, boot_rom);
+                Registers::power_on()
+            }
+            None => {
+                bus.apply_post_boot_state(model);
+                if model.is_cgb() && !header.as_ref().is_some_and(CartridgeHeader::supports_cgb) {
+                    bus.enter_dmg_compat();
+                    if let Some(header) = &header {
+                        bus.gpu
+                            .load_compat_palettes(&CompatPalettes::for_header(header));
+                    }
+                }
+                Registers::post_boot(model, header_checksum)
+            }
+        };
         CPU {
-            registers: Registers::new(),
+            registers,
             bus,
             is_halted: false,
+            is_stopped: false,
             interrupts_enabled: false,
+            trace: true,
+            call_stack: None,
             ei_pending: false,
             halt_bug: false,
+            speed_switch_delay: 0,
+            step_cycles: 0,
+            model,
+            rom_checksum,
+        }
+    }
+
+    /// Snapshot the whole machine as a save state (see [`crate::save_state`]).
+    pub(crate) fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        self.save_to(&mut state);
+        state.finish(self.model, self.rom_checksum)
+    }
+
+    pub(crate) fn model(&self) -> Model {
+        self.model
+    }
+
+    /// CRC-32 of the cartridge ROM.
+    pub(crate) fn rom_checksum(&self) -> u32 {
+        self.rom_checksum
+    }
+
+    /// Restore a save state taken on the same ROM and model.
+    ///
+    /// Values in the payload are only checked as each component loads
+    /// them, so the current state is snapshotted first and restored when
+    /// the new one is rejected; the machine is then left as it was.
+    pub(crate) fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::open(data, self.model, self.rom_checksum)?;
+        let previous = self.save_state();
+        let result = self.load_from(&mut state).and_then(|()| state.finish());
+        if result.is_err() {
+            let mut previous = StateReader::open(&previous, self.model, self.rom_checksum)
+                .expect("a machine's own state has a valid header");
+            self.load_from(&mut previous)
+                .expect("a machine's own state loads");
         }
+        result
     }
 
-    /// Execute a decoded instruction and return (next_pc, cycles_in_tstates).
+    /// Execute a decoded instruction and return the next PC.
     ///
-    /// Cycle counts here are provided so the rest of the emulator can
-    /// step timers/PPU/DMA appropriately. These values are the T-states for
-    /// the instruction.
-    fn execute(&mut self, instruction: Instruction) -> (u16, u16) {
+    /// The opcode fetch has already been performed by `step`. Every remaining
+    /// bus access goes through `read_cycle`/`write_cycle` and every internal
+    /// delay through `internal_cycle`, so the instruction's M-cycles are
+    /// emitted in hardware order and the rest of the machine is ticked between
+    /// them. No cycle counts are returned: the duration of an instruction is
+    /// simply the number of M-cycles it performed.
+    fn execute(&mut self, instruction: Instruction) -> u16 {
         match instruction {
-            Instruction::NOP => (self.registers.pc.wrapping_add(1), 4),
+            Instruction::NOP => self.registers.pc.wrapping_add(1),
             // STOP: Stops CPU and LCD execution until a button press occurs.
-            Instruction::STOP => {
-                self.is_halted = true;
-                (self.registers.pc.wrapping_add(2), 4)
-            }
+            Instruction::STOP => self.stop(),
             // HALT: Stops CPU execution until an interrupt occurs.
             // HALT bug: When IME=0 and there's a pending interrupt, PC fails to increment
             Instruction::HALT => {
@@ -57,106 +154,82 @@ impl CPU {
                 } else {
                     self.is_halted = true;
                 }
-                (self.registers.pc.wrapping_add(1), 4)
+                self.registers.pc.wrapping_add(1)
             }
             // DI/EI: Interrupt control instructions
             Instruction::DI => {
                 self.interrupts_enabled = false;
                 self.ei_pending = false;
-                (self.registers.pc.wrapping_add(1), 4)
+                self.registers.pc.wrapping_add(1)
             }
             Instruction::EI => {
                 self.ei_pending = true;
-                (self.registers.pc.wrapping_add(1), 4)
+                self.registers.pc.wrapping_add(1)
             }
             // Arithmetic operations on A register
             Instruction::ADD(target) => {
                 let value = self.get_arithmetic_target(target);
                 let new_value = self.add(value);
                 self.registers.a = new_value;
-                let (pc_inc, cycles) = match target {
-                    ArithmeticTarget::D8 => (2, 8),
-                    ArithmeticTarget::HLI => (1, 8),
-                    _ => (1, 4),
-                };
-                (self.registers.pc.wrapping_add(pc_inc), cycles)
+                self.registers
+                    .pc
+                    .wrapping_add(Self::get_arithmetic_pc_increment(target))
             }
             Instruction::ADC(target) => {
                 let value = self.get_arithmetic_target(target);
                 let new_value = self.adc(value);
                 self.registers.a = new_value;
-                let (pc_inc, cycles) = match target {
-                    ArithmeticTarget::D8 => (2, 8),
-                    ArithmeticTarget::HLI => (1, 8),
-                    _ => (1, 4),
-                };
-                (self.registers.pc.wrapping_add(pc_inc), cycles)
+                self.registers
+                    .pc
+                    .wrapping_add(Self::get_arithmetic_pc_increment(target))
             }
             Instruction::SUB(target) => {
                 let value = self.get_arithmetic_target(target);
                 let new_value = self.sub(value);
                 self.registers.a = new_value;
-                let (pc_inc, cycles) = match target {
-                    ArithmeticTarget::D8 => (2, 8),
-                    ArithmeticTarget::HLI => (1, 8),
-                    _ => (1, 4),
-                };
-                (self.registers.pc.wrapping_add(pc_inc), cycles)
+                self.registers
+                    .pc
+                    .wrapping_add(Self::get_arithmetic_pc_increment(target))
             }
             Instruction::SBC(target) => {
                 let value = self.get_arithmetic_target(target);
                 let new_value = self.sbc(value);
                 self.registers.a = new_value;
-                let (pc_inc, cycles) = match target {
-                    ArithmeticTarget::D8 => (2, 8),
-                    ArithmeticTarget::HLI => (1, 8),
-                    _ => (1, 4),
-                };
-                (self.registers.pc.wrapping_add(pc_inc), cycles)
+                self.registers
+                    .pc
+                    .wrapping_add(Self::get_arithmetic_pc_increment(target))
             }
             // Logical operations on A register
             Instruction::AND(target) => {
                 let value = self.get_arithmetic_target(target);
                 let new_value = self.and(value);
                 self.registers.a = new_value;
-                let (pc_inc, cycles) = match target {
-                    ArithmeticTarget::D8 => (2, 8),
-                    ArithmeticTarget::HLI => (1, 8),
-                    _ => (1, 4),
-                };
-                (self.registers.pc.wrapping_add(pc_inc), cycles)
+                self.registers
+                    .pc
+                    .wrapping_add(Self::get_arithmetic_pc_increment(target))
             }
             Instruction::OR(target) => {
                 let value = self.get_arithmetic_target(target);
                 let new_value = self.or(value);
                 self.registers.a = new_value;
-                let (pc_inc, cycles) = match target {
-                    ArithmeticTarget::D8 => (2, 8),
-                    ArithmeticTarget::HLI => (1, 8),
-                    _ => (1, 4),
-                };
-                (self.registers.pc.wrapping_add(pc_inc), cycles)
+                self.registers
+                    .pc
+                    .wrapping_add(Self::get_arithmetic_pc_increment(target))
             }
             Instruction::XOR(target) => {
                 let value = self.get_arithmetic_target(target);
                 let new_value = self.xor(value);
                 self.registers.a = new_value;
-                let (pc_inc, cycles) = match target {
-                    ArithmeticTarget::D8 => (2, 8),
-                    ArithmeticTarget::HLI => (1, 8),
-                    _ => (1, 4),
-                };
-                (self.registers.pc.wrapping_add(pc_inc), cycles)
+                self.registers
+                    .pc
+                    .wrapping_add(Self::get_arithmetic_pc_increment(target))
             }
             Instruction::CP(target) => {
                 let value = self.get_arithmetic_target(target);
                 self.cp(value);
-                let (pc_inc, cycles) = match target {
-                    ArithmeticTarget::D8 => (2, 8),
-                    ArithmeticTarget::HLI => (1, 8),
-                    _ => (1, 4),
-                };
-                (self.registers.pc.wrapping_add(pc_inc), cycles)
+                self.registers
+                    .pc
+                    .wrapping_add(Self::get_arithmetic_pc_increment(target))
             }
             // Increment/Decrement instructions (8-bit and 16-bit)
             Instruction::INC(target) => match target {
@@ -164,18 +237,19 @@ impl CPU {
                     let value = self.registers.read_8bit(reg);
                     let new_value = self.inc_8bit(value);
                     self.registers.write_8bit(reg, new_value);
-                    (self.registers.pc.wrapping_add(1), 4)
+                    self.registers.pc.wrapping_add(1)
                 }
                 IncDecTarget::Reg16(reg) => {
                     self.inc_16bit(reg);
-                    (self.registers.pc.wrapping_add(1), 8)
+                    self.internal_cycle();
+                    self.registers.pc.wrapping_add(1)
                 }
                 IncDecTarget::HLI => {
                     let address = self.registers.get_hl();
-                    let value = self.bus.read_byte(address);
+                    let value = self.read_cycle(address);
                     let new_value = self.inc_8bit(value);
-                    self.bus.write_byte(address, new_value);
-                    (self.registers.pc.wrapping_add(1), 12)
+                    self.write_cycle(address, new_value);
+                    self.registers.pc.wrapping_add(1)
                 }
             },
             Instruction::DEC(target) => match target {
@@ -183,53 +257,49 @@ impl CPU {
                     let value = self.registers.read_8bit(reg);
                     let new_value = self.dec_8bit(value);
                     self.registers.write_8bit(reg, new_value);
-                    (self.registers.pc.wrapping_add(1), 4)
+                    self.registers.pc.wrapping_add(1)
                 }
                 IncDecTarget::Reg16(reg) => {
                     self.dec_16bit(reg);
-                    (self.registers.pc.wrapping_add(1), 8)
+                    self.internal_cycle();
+                    self.registers.pc.wrapping_add(1)
                 }
                 IncDecTarget::HLI => {
                     let address = self.registers.get_hl();
-                    let value = self.bus.read_byte(address);
+                    let value = self.read_cycle(address);
                     let new_value = self.dec_8bit(value);
-                    self.bus.write_byte(address, new_value);
-                    (self.registers.pc.wrapping_add(1), 12)
+                    self.write_cycle(address, new_value);
+                    self.registers.pc.wrapping_add(1)
                 }
             },
             // Control flow: Jumps and relative jumps
             Instruction::JP(test) => {
                 let should = self.should_jump(&test);
-                let next_pc = self.jump(should);
-                let cycles = if should { 16 } else { 12 };
-                (next_pc, cycles)
+                self.jump(should)
             }
             Instruction::JR(test) => {
                 let should = self.should_jump(&test);
-                let next_pc = self.jump_relative(should);
-                let cycles = if should { 12 } else { 8 };
-                (next_pc, cycles)
+                self.jump_relative(should)
             }
             // Data transfers: Load byte/word operations
             Instruction::LD(load_type) => match load_type {
                 LoadType::Byte(target, source) => {
                     let source_value = self.read_byte_source(source);
                     self.write_byte_target(target, source_value);
-                    let cycles = self.get_load_byte_cycles(target, source);
-
-                    (
-                        self.registers
-                            .pc
-                            .wrapping_add(self.get_load_byte_pc_increment(target, source)),
-                        cycles,
-                    )
+                    self.registers
+                        .pc
+                        .wrapping_add(self.get_load_byte_pc_increment(target, source))
                 }
                 // 16-bit load
                 LoadType::Word(target, source) => {
                     let source_value = match source {
                         LoadWordSource::D16 => self.read_next_word(),
                         LoadWordSource::SP => self.registers.sp,
-                        LoadWordSource::HL => self.registers.get_hl(),
+                        LoadWordSource::HL => {
+                            // LD SP, HL: 16-bit transfer takes an extra internal M-cycle
+                            self.internal_cycle();
+                            self.registers.get_hl()
+                        }
                     };
 
                     match target {
@@ -239,61 +309,52 @@ impl CPU {
                         LoadWordTarget::SP => self.registers.sp = source_value,
                         LoadWordTarget::A16I => {
                             let address = self.read_next_word();
-                            self.bus.write_byte(address, (source_value & 0xFF) as u8);
-                            self.bus
-                                .write_byte(address.wrapping_add(1), (source_value >> 8) as u8);
+                            self.write_cycle(address, (source_value & 0xFF) as u8);
+                            self.write_cycle(address.wrapping_add(1), (source_value >> 8) as u8);
                         }
                     };
-                    let (length, cycles) = match (target, source) {
-                        (LoadWordTarget::A16I, _) => (3, 20), // Opcode 0x08
-                        (_, LoadWordSource::D16) => (3, 12),  // LD rr, d16
-                        _ => (1, 8),                          // LD SP, HL, etc.
+                    let length = match (target, source) {
+                        (LoadWordTarget::A16I, _) => 3, // Opcode 0x08
+                        (_, LoadWordSource::D16) => 3,  // LD rr, d16
+                        _ => 1,                         // LD SP, HL
                     };
-                    (self.registers.pc.wrapping_add(length), cycles)
+                    self.registers.pc.wrapping_add(length)
                 }
             },
             // Stack operations: Push/Pop/Call/Return
             Instruction::PUSH(target) => {
                 let value = self.read_stack_target(target);
                 self.push(value);
-                (self.registers.pc.wrapping_add(1), 16)
+                self.registers.pc.wrapping_add(1)
             }
             Instruction::POP(target) => {
                 let result = self.pop();
                 self.write_stack_target(target, result);
-                (self.registers.pc.wrapping_add(1), 12)
+                self.registers.pc.wrapping_add(1)
             }
             Instruction::CALL(test) => {
                 let should = self.should_jump(&test);
-                let next_pc = self.call(should);
-                let cycles = if should { 24 } else { 12 };
-                (next_pc, cycles)
+                self.call(should)
             }
             Instruction::RET(test) => {
+                // Conditional RET spends an extra M-cycle evaluating the condition
+                if !matches!(test, JumpTest::Always) {
+                    self.internal_cycle();
+                }
                 let should = self.should_jump(&test);
-                let next_pc = self.return_(should);
-                let cycles = match test {
-                    JumpTest::Always => 16,
-                    _ => {
-                        if should {
-                            20
-                        } else {
-                            8
-                        }
-                    }
-                };
-                (next_pc, cycles)
+                self.return_(should)
             }
             // RETI: Return from interrupt handler (pops PC and enables interrupts)
             Instruction::RETI => {
                 self.interrupts_enabled = true;
-                (self.pop(), 16)
+                self.return_(true)
             }
             // RST: Restart (push next PC and jump to reset vector)
             Instruction::RST(vec) => {
                 let next_pc = self.registers.pc.wrapping_add(1);
                 self.push(next_pc);
-                (vec as u16, 16)
+                self.enter_function(FrameKind::Rst, vec as u16, next_pc);
+                vec as u16
             }
             // Rotate accumulator instructions (A register only)
             Instruction::RLCA => {
@@ -304,7 +365,7 @@ impl CPU {
                 self.registers.f.subtract = false;
                 self.registers.f.half_carry = false;
                 self.registers.f.carry = carry == 1;
-                (self.registers.pc.wrapping_add(1), 4)
+                self.registers.pc.wrapping_add(1)
             }
             Instruction::RRCA => {
                 // RRCA: Rotate A right (bit 0 wraps to bit 7)
@@ -314,7 +375,7 @@ impl CPU {
                 self.registers.f.subtract = false;
                 self.registers.f.half_carry = false;
                 self.registers.f.carry = carry == 1;
-                (self.registers.pc.wrapping_add(1), 4)
+                self.registers.pc.wrapping_add(1)
             }
             Instruction::RLA => {
                 // RLA: Rotate A left through carry flag
@@ -325,7 +386,7 @@ impl CPU {
                 self.registers.f.subtract = false;
                 self.registers.f.half_carry = false;
                 self.registers.f.carry = new_carry == 1;
-                (self.registers.pc.wrapping_add(1), 4)
+                self.registers.pc.wrapping_add(1)
             }
             Instruction::RRA => {
                 // RRA: Rotate A right through carry flag
@@ -336,7 +397,7 @@ impl CPU {
                 self.registers.f.subtract = false;
                 self.registers.f.half_carry = false;
                 self.registers.f.carry = new_carry == 1;
-                (self.registers.pc.wrapping_add(1), 4)
+                self.registers.pc.wrapping_add(1)
             }
             // Miscellaneous special arithmetic operations
             Instruction::DAA => {
@@ -368,28 +429,28 @@ impl CPU {
                 self.registers.f.zero = a == 0;
                 self.registers.f.half_carry = false;
                 self.registers.f.carry = carry;
-                (self.registers.pc.wrapping_add(1), 4)
+                self.registers.pc.wrapping_add(1)
             }
             Instruction::CPL => {
                 // Complement A (bitwise NOT)
                 self.registers.a = !self.registers.a;
                 self.registers.f.subtract = true;
                 self.registers.f.half_carry = true;
-                (self.registers.pc.wrapping_add(1), 4)
+                self.registers.pc.wrapping_add(1)
             }
             Instruction::SCF => {
                 // Set Carry Flag
                 self.registers.f.subtract = false;
                 self.registers.f.half_carry = false;
                 self.registers.f.carry = true;
-                (self.registers.pc.wrapping_add(1), 4)
+                self.registers.pc.wrapping_add(1)
             }
             Instruction::CCF => {
                 // Complement Carry Flag
                 self.registers.f.subtract = false;
                 self.registers.f.half_carry = false;
                 self.registers.f.carry = !self.registers.f.carry;
-                (self.registers.pc.wrapping_add(1), 4)
+                self.registers.pc.wrapping_add(1)
             }
             // 16-bit arithmetic: ADD HL and SP operations
             Instruction::ADDHL(reg) => {
@@ -409,7 +470,8 @@ impl CPU {
                 self.registers.f.subtract = false;
                 self.registers.f.half_carry = half_carry;
                 self.registers.f.carry = carry;
-                (self.registers.pc.wrapping_add(1), 8)
+                self.internal_cycle();
+                self.registers.pc.wrapping_add(1)
             }
             Instruction::ADDSP => {
                 // ADDSP: Add signed 8-bit immediate to SP (flags set from lower 8 bits)
@@ -423,7 +485,9 @@ impl CPU {
                 self.registers.f.carry = fh::carry_add_sp(sp, offset_signed);
 
                 self.registers.sp = result;
-                (self.registers.pc.wrapping_add(2), 16)
+                self.internal_cycle();
+                self.internal_cycle();
+                self.registers.pc.wrapping_add(2)
             }
             Instruction::LDHLSP => {
                 // LDHLSP: Load HL with SP + signed 8-bit immediate (flags set from lower 8 bits)
@@ -437,10 +501,11 @@ impl CPU {
                 self.registers.f.carry = fh::carry_add_sp(sp, offset_signed);
 
                 self.registers.set_hl(result);
-                (self.registers.pc.wrapping_add(2), 12)
+                self.internal_cycle();
+                self.registers.pc.wrapping_add(2)
             }
             // JP_HL: Jump to address stored in HL
-            Instruction::JP_HL => (self.registers.get_hl(), 4),
+            Instruction::JP_HL => self.registers.get_hl(),
             // CB prefix instructions: Rotations, Shifts, and Bit operations
             Instruction::RLC(target) => {
                 let value = self.read_prefix_target(target);
@@ -451,8 +516,7 @@ impl CPU {
                 self.registers.f.subtract = false;
                 self.registers.f.half_carry = false;
                 self.registers.f.carry = carry == 1;
-                let cycles = Self::get_prefix_cycles(&target);
-                (self.registers.pc.wrapping_add(2), cycles)
+                self.registers.pc.wrapping_add(2)
             }
             Instruction::RRC(target) => {
                 let value = self.read_prefix_target(target);
@@ -463,8 +527,7 @@ impl CPU {
                 self.registers.f.subtract = false;
                 self.registers.f.half_carry = false;
                 self.registers.f.carry = carry == 1;
-                let cycles = Self::get_prefix_cycles(&target);
-                (self.registers.pc.wrapping_add(2), cycles)
+                self.registers.pc.wrapping_add(2)
             }
             Instruction::RL(target) => {
                 let value = self.read_prefix_target(target);
@@ -476,8 +539,7 @@ impl CPU {
                 self.registers.f.subtract = false;
                 self.registers.f.half_carry = false;
                 self.registers.f.carry = new_carry == 1;
-                let cycles = Self::get_prefix_cycles(&target);
-                (self.registers.pc.wrapping_add(2), cycles)
+                self.registers.pc.wrapping_add(2)
             }
             Instruction::RR(target) => {
                 let value = self.read_prefix_target(target);
@@ -489,8 +551,7 @@ impl CPU {
                 self.registers.f.subtract = false;
                 self.registers.f.half_carry = false;
                 self.registers.f.carry = new_carry == 1;
-                let cycles = Self::get_prefix_cycles(&target);
-                (self.registers.pc.wrapping_add(2), cycles)
+                self.registers.pc.wrapping_add(2)
             }
             Instruction::SLA(target) => {
                 let value = self.read_prefix_target(target);
@@ -501,8 +562,7 @@ impl CPU {
                 self.registers.f.subtract = false;
                 self.registers.f.half_carry = false;
                 self.registers.f.carry = carry == 1;
-                let cycles = Self::get_prefix_cycles(&target);
-                (self.registers.pc.wrapping_add(2), cycles)
+                self.registers.pc.wrapping_add(2)
             }
             Instruction::SRA(target) => {
                 let value = self.read_prefix_target(target);
@@ -514,8 +574,7 @@ impl CPU {
                 self.registers.f.subtract = false;
                 self.registers.f.half_carry = false;
                 self.registers.f.carry = carry == 1;
-                let cycles = Self::get_prefix_cycles(&target);
-                (self.registers.pc.wrapping_add(2), cycles)
+                self.registers.pc.wrapping_add(2)
             }
             Instruction::SWAP(target) => {
                 let value = self.read_prefix_target(target);
@@ -525,8 +584,7 @@ impl CPU {
                 self.registers.f.subtract = false;
                 self.registers.f.half_carry = false;
                 self.registers.f.carry = false;
-                let cycles = Self::get_prefix_cycles(&target);
-                (self.registers.pc.wrapping_add(2), cycles)
+                self.registers.pc.wrapping_add(2)
             }
             Instruction::SRL(target) => {
                 let value = self.read_prefix_target(target);
@@ -537,8 +595,7 @@ impl CPU {
                 self.registers.f.subtract = false;
                 self.registers.f.half_carry = false;
                 self.registers.f.carry = carry == 1;
-                let cycles = Self::get_prefix_cycles(&target);
-                (self.registers.pc.wrapping_add(2), cycles)
+                self.registers.pc.wrapping_add(2)
             }
             // BIT test: Check if a bit is set (zero flag = NOT bit)
             Instruction::BIT(bit, target) => {
@@ -547,35 +604,65 @@ impl CPU {
                 self.registers.f.zero = result == 0;
                 self.registers.f.subtract = false;
                 self.registers.f.half_carry = true;
-                let cycles = Self::get_prefix_cycles(&target);
-                (self.registers.pc.wrapping_add(2), cycles)
+                self.registers.pc.wrapping_add(2)
             }
             // RES: Clear (reset) a bit
             Instruction::RES(bit, target) => {
                 let value = self.read_prefix_target(target);
                 let result = value & !(1 << bit);
                 self.write_prefix_target(target, result);
-                let cycles = Self::get_prefix_cycles(&target);
-                (self.registers.pc.wrapping_add(2), cycles)
+                self.registers.pc.wrapping_add(2)
             }
             // SET: Set a bit to 1
             Instruction::SET(bit, target) => {
                 let value = self.read_prefix_target(target);
                 let result = value | (1 << bit);
                 self.write_prefix_target(target, result);
-                let cycles = Self::get_prefix_cycles(&target);
-                (self.registers.pc.wrapping_add(2), cycles)
+                self.registers.pc.wrapping_add(2)
             }
         }
     }
 
-    /// Calculate T-state cycles for CB-prefixed instructions.
-    /// Returns 8 cycles for register targets or 16 cycles for memory (HL) targets.
-    fn get_prefix_cycles(target: &crate::instructions::targets::PrefixTarget) -> u16 {
-        match target.to_register8() {
-            Some(_) => 8,
-            None => 16,
+    /// Execute STOP and return the next PC.
+    ///
+    /// Whether STOP consumes one or two bytes, and whether it enters STOP mode,
+    /// HALT mode or neither, depends on the joypad, pending interrupts and KEY1:
+    ///
+    /// | Button held | Speed switch armed | Result                                   |
+    /// |-------------|--------------------|------------------------------------------|
+    /// | yes         | -                  | HALT mode (only if no interrupt pending) |
+    /// | no          | yes                | DIV reset, speed switch (HALT meanwhile) |
+    /// | no          | no                 | DIV reset, STOP mode                     |
+    ///
+    /// In every case STOP is a 1-byte opcode when an interrupt is pending and
+    /// a 2-byte opcode (the following byte is skipped) otherwise.
+    ///
+    /// Reference: [Pan Docs — Using the STOP Instruction](https://gbdev.io/pandocs/Reducing_Power_Consumption.html#using-the-stop-instruction)
+    fn stop(&mut self) -> u16 {
+        let interrupt_pending = self.bus.any_interrupt_pending();
+        let length = if interrupt_pending { 1 } else { 2 };
+        let next_pc = self.registers.pc.wrapping_add(length);
+
+        if self.bus.joypad.any_selected_line_low() {
+            // STOP mode would be exited immediately; DIV is left untouched.
+            if !interrupt_pending {
+                self.is_halted = true;
+            }
+            return next_pc;
         }
+
+        self.bus.reset_div();
+
+        if self.bus.speed_switch_armed() {
+            self.bus.perform_speed_switch();
+            if !interrupt_pending {
+                self.speed_switch_delay = SPEED_SWITCH_M_CYCLES;
+            }
+        } else {
+            self.is_stopped = true;
+        }
+
+        next_pc
     }
 
     /// Read a byte from the specified source (register, memory location, or immediate value).
@@ -590,33 +677,33 @@ impl CPU {
             LoadByteSource::H => self.registers.h,
             LoadByteSource::L => self.registers.l,
             LoadByteSource::D8 => self.read_next_byte(),
-            LoadByteSource::HLI => self.bus.read_byte(self.registers.get_hl()),
-            LoadByteSource::BCI => self.bus.read_byte(self.registers.get_bc()),
-            LoadByteSource::DEI => self.bus.read_byte(self.registers.get_de()),
+            LoadByteSource::HLI => self.read_cycle(self.registers.get_hl()),
+            LoadByteSource::BCI => self.read_cycle(self.registers.get_bc()),
+            LoadByteSource::DEI => self.read_cycle(self.registers.get_de()),
             LoadByteSource::HLI_INC => {
-                let value = self.bus.read_byte(self.registers.get_hl());
+                let value = self.read_cycle(self.registers.get_hl());
                 self.registers
                     .set_hl(self.registers.get_hl().wrapping_add(1));
                 value
             }
             LoadByteSource::HLI_DEC => {
-                let value = self.bus.read_byte(self.registers.get_hl());
+                let value = self.read_cycle(self.registers.get_hl());
                 self.registers
                     .set_hl(self.registers.get_hl().wrapping_sub(1));
                 value
             }
             LoadByteSource::A16I => {
                 let address = self.read_next_word();
-                self.bus.read_byte(address)
+                self.read_cycle(address)
             }
             LoadByteSource::A8I => {
                 let offset = self.read_next_byte();
                 let address = 0xFF00 + offset as u16;
-                self.bus.read_byte(address)
+                self.read_cycle(address)
             }
             LoadByteSource::CI => {
                 let address = 0xFF00 + self.registers.c as u16;
-                self.bus.read_byte(address)
+                self.read_cycle(address)
             }
         }
     }
@@ -632,31 +719,31 @@ impl CPU {
             LoadByteTarget::E => self.registers.e = value,
             LoadByteTarget::H => self.registers.h = value,
             LoadByteTarget::L => self.registers.l = value,
-            LoadByteTarget::HLI => self.bus.write_byte(self.registers.get_hl(), value),
-            LoadByteTarget::DEI => self.bus.write_byte(self.registers.get_de(), value),
-            LoadByteTarget::BCI => self.bus.write_byte(self.registers.get_bc(), value),
+            LoadByteTarget::HLI => self.write_cycle(self.registers.get_hl(), value),
+            LoadByteTarget::DEI => self.write_cycle(self.registers.get_de(), value),
+            LoadByteTarget::BCI => self.write_cycle(self.registers.get_bc(), value),
             LoadByteTarget::A16I => {
                 let address = self.read_next_word();
-                self.bus.write_byte(address, value);
+                self.write_cycle(address, value);
             }
             LoadByteTarget::A8I => {
                 let offset = self.read_next_byte();
                 let address = 0xFF00 + offset as u16;
-                self.bus.write_byte(address, value);
+                self.write_cycle(address, value);
             }
             LoadByteTarget::HLI_INC => {
                 let address = self.registers.get_hl();
-                self.bus.write_byte(address, value);
+                self.write_cycle(address, value);
                 self.registers.set_hl(address.wrapping_add(1));
             }
             LoadByteTarget::HLI_DEC => {
                 let address = self.registers.get_hl();
-                self.bus.write_byte(address, value);
+                self.write_cycle(address, value);
                 self.registers.set_hl(address.wrapping_sub(1));
             }
             LoadByteTarget::CI => {
                 let address = 0xFF00 + self.registers.c as u16;
-                self.bus.write_byte(address, value);
+                self.write_cycle(address, value);
             }
         }
     }
@@ -674,47 +761,17 @@ impl CPU {
         }
     }
 
-    /// Compute T-cycle cost for LD byte operations based on both target and source.
-    fn get_load_byte_cycles(&self, target: LoadByteTarget, source: LoadByteSource) -> u16 {
-        let base = match source {
-            LoadByteSource::D8 => 8,
-            LoadByteSource::A16I => 16,
-            LoadByteSource::A8I => 12,
-            LoadByteSource::HLI => 8,
-            LoadByteSource::HLI_INC => 8,
-            LoadByteSource::HLI_DEC => 8,
-            LoadByteSource::BCI => 8,
-            LoadByteSource::DEI => 8,
-            LoadByteSource::CI => 8,
-            LoadByteSource::A => 4,
-            LoadByteSource::B => 4,
-            LoadByteSource::C => 4,
-            LoadByteSource::D => 4,
-            LoadByteSource::E => 4,
-            LoadByteSource::H => 4,
-            LoadByteSource::L => 4,
-        };
-
-        // Additional cost when the target is a memory location (different for
-        // no-immediate, 8-bit immediate, and 16-bit immediate target addressing)
-        let extra = match target {
-            LoadByteTarget::HLI
-            | LoadByteTarget::HLI_INC
-            | LoadByteTarget::HLI_DEC
-            | LoadByteTarget::BCI
-            | LoadByteTarget::DEI
-            | LoadByteTarget::CI => 4,
-            LoadByteTarget::A8I => 8,
-            LoadByteTarget::A16I => 12,
-            _ => 0,
-        };
-
-        base + extra
+    /// Calculate how much the PC should advance for an 8-bit arithmetic/logic operand.
+    fn get_arithmetic_pc_increment(target: ArithmeticTarget) -> u16 {
+        match target {
+            ArithmeticTarget::D8 => 2,
+            _ => 1,
+        }
     }
 
     /// Fetch the value from an 8-bit arithmetic target register.
     /// Used by arithmetic operations (ADD, SUB, AND, OR, XOR, CP) to get the operand.
-    fn get_arithmetic_target(&self, target: ArithmeticTarget) -> u8 {
+    fn get_arithmetic_target(&mut self, target: ArithmeticTarget) -> u8 {
         match target {
             ArithmeticTarget::A => self.registers.a,
             ArithmeticTarget::B => self.registers.b,
@@ -723,8 +780,8 @@ impl CPU {
             ArithmeticTarget::E => self.registers.e,
             ArithmeticTarget::H => self.registers.h,
             ArithmeticTarget::L => self.registers.l,
-            ArithmeticTarget::HLI => self.bus.read_byte(self.registers.get_hl()),
-            ArithmeticTarget::D8 => self.bus.read_byte(self.registers.pc.wrapping_add(1)),
+            ArithmeticTarget::HLI => self.read_cycle(self.registers.get_hl()),
+            ArithmeticTarget::D8 => self.read_next_byte(),
         }
     }
 
@@ -865,36 +922,69 @@ impl CPU {
     /// Execute a single CPU step and return the number of T-states consumed.
     ///
     /// Decodes the instruction at PC, executes it, and updates PC and cycle count.
-    /// Returns 0 cycles if the CPU is halted (HALT mode waiting for interrupt).
+    /// Returns 4 cycles if the CPU is halted (HALT mode waiting for interrupt).
+    ///
+    /// # Timing
+    /// Each memory access and internal delay is its own M-cycle, and the bus
+    /// (timer, PPU, OAM DMA) is ticked by 4 T-cycles before the access is
+    /// performed. Callers therefore must not tick the hardware themselves; the
+    /// returned T-cycle count is for bookkeeping only.
     ///
     /// # HALT Behavior
     /// When a HALT instruction is encountered, the CPU sets `is_halted = true`.
     /// The CPU remains halted until an interrupt becomes pending.
-    /// Call `wake_from_halt()` when implementing interrupt handling.
+    ///
+    /// # STOP Behavior
+    /// In STOP mode the system clock is halted: the timer and LCD are not ticked
+    /// while `is_stopped()` is true. Each call returns 4 cycles and STOP mode
+    /// ends once a selected joypad input line is held low.
     ///
     /// # Unknown Instructions
     /// Panics on unknown opcodes to make missing implementations obvious during development.
     pub(crate) fn step(&mut self) -> u16 {
+        self.step_cycles = 0;
+
+        if self.is_stopped {
+            if !self.bus.joypad.any_selected_line_low() {
+                return 4;
+            }
+            self.is_stopped = false;
+        }
+
+        // A CGB VRAM DMA block halts the CPU until it has been copied
+        if self.bus.is_hdma_active() {
+            self.internal_cycle();
+            return self.step_cycles;
+        }
+
+        if self.speed_switch_delay > 0 {
+            self.speed_switch_delay -= 1;
+            self.internal_cycle();
+            return self.step_cycles;
+        }
+
+        let was_halted = self.is_halted;
         if self.bus.any_interrupt_pending() {
             self.wake_from_halt();
         }
 
-        if let Some(cycles) = self.handle_interrupts() {
-            return cycles;
+        if self.handle_interrupts(was_halted) {
+            return self.step_cycles;
         }
 
         if self.is_halted {
-            // CPU is halted and no interrupt to service, consume 4 T-cycles
-            return 4;
+            // CPU is halted and no interrupt to service, idle for one M-cycle
+            self.internal_cycle();
+            return self.step_cycles;
         }
 
-        // Read first opcode byte and determine if it's a CB-prefix
-        let first_byte = self.bus.read_byte(self.registers.pc);
+        // Fetch first opcode byte and determine if it's a CB-prefix
+        let first_byte = self.fetch_cycle(self.registers.pc);
         let prefixed = first_byte == 0xCB;
 
         // For prefixed instructions, opcode byte is the second byte; otherwise use first.
         let opcode_byte = if prefixed {
-            self.bus.read_byte(self.registers.pc + 1)
+            self.fetch_cycle(self.registers.pc.wrapping_add(1))
         } else {
             first_byte
         };
@@ -912,13 +1002,20 @@ impl CPU {
 
             // Print a compact CPU state for debugging: PC, opcode, decoded instruction,
             // registers A,B,C,D,E,H,L, SP, HL and flags (raw F and booleans).
-            if self.registers.pc < 0x0206 || self.registers.pc > 0x020D {
+            if self.trace && (self.registers.pc < 0x0206 || self.registers.pc > 0x020D) {
+                // The enclosing symbol, when a symbol file is loaded
+                let symbol = self
+                    .bus
+                    .describe_address(self.registers.pc)
+                    .map(|name| format!(" <{name}>"))
+                    .unwrap_or_default();
                 println!(
-                    "PC={:#06X} OPCODE={} INST={:?} \
+                    "PC={:#06X}{} OPCODE={} INST={:?} \
     A={:#04X} F={:02X} Z={} N={} H={} C={} \
     B={:#04X} C={:#04X} D={:#04X} E={:#04X} H={:#04X} L={:#04X} \
     SP={:#06X} HL={:#06X}",
                     self.registers.pc,
+                    symbol,
                     opcode_str,
                     &instruction,
                     self.registers.a,
@@ -947,7 +1044,7 @@ impl CPU {
             }
 
             // Execute the decoded instruction and advance PC
-            let (next_pc, cycles) = self.execute(instruction);
+            let next_pc = self.execute(instruction);
             self.registers.pc = next_pc;
 
             // Handle EI delay: IME is enabled after the instruction following EI completes
@@ -956,7 +1053,7 @@ impl CPU {
                 self.interrupts_enabled = true;
             }
 
-            cycles
+            self.step_cycles
         } else {
             let instruction_str = if prefixed {
                 format!("0xCB{:02X}", opcode_byte)
@@ -975,43 +1072,98 @@ impl CPU {
         self.is_halted
     }
 
+    /// Check if the CPU is in STOP mode (system clock halted until a joypad press).
+    pub(crate) fn is_stopped(&self) -> bool {
+        self.is_stopped
+    }
+
     /// Wake the CPU from HALT state when an enabled interrupt becomes pending.
     fn wake_from_halt(&mut self) {
         self.is_halted = false;
     }
 
+    /// Advance the rest of the machine by one M-cycle (4 T-cycles).
+    fn tick(&mut self) {
+        self.bus.tick_m_cycle();
+        self.step_cycles += 4;
+    }
+
+    /// Read micro-op: one M-cycle on the bus, ending with the read.
+    fn read_cycle(&mut self, address: u16) -> u8 {
+        self.tick();
+        self.bus.read_byte(address)
+    }
+
+    /// Fetch micro-op: a read of an opcode or operand byte.
+    fn fetch_cycle(&mut self, address: u16) -> u8 {
+        self.tick();
+        self.bus.fetch_byte(address)
+    }
+
+    /// Write micro-op: one M-cycle on the bus, ending with the write.
+    fn write_cycle(&mut self, address: u16, value: u8) {
+        self.tick();
+        self.bus.write_byte(address, value);
+    }
+
+    /// Internal micro-op: one M-cycle with no bus access (ALU work, SP/PC adjust).
+    fn internal_cycle(&mut self) {
+        self.tick();
+    }
+
     /// Handle pending interrupts if IME is enabled.
     ///
-    /// If an interrupt is pending and IME is set:
-    /// 1. Disable IME
-    /// 2. Push current PC onto stack
-    /// 3. Clear the interrupt flag bit
-    /// 4. Jump to interrupt handler
+    /// If an interrupt is pending and IME is set, dispatch takes 5 M-cycles:
+    /// 1. Internal delay (IME is cleared)
+    /// 2. Internal delay (SP decremented)
+    /// 3. Push PC high byte
+    /// 4. Push PC low byte
+    /// 5. Jump to interrupt handler
+    ///
+    /// The interrupt to service is chosen only after the high byte is pushed.
+    /// If that write lands on IE (SP was 0x0000) and disables every pending
+    /// interrupt, the dispatch is cancelled: no IF bit is cleared and PC jumps
+    /// to 0x0000 instead.
     ///
-    /// Returns Some(cycles) if an interrupt was serviced, None otherwise.
-    fn handle_interrupts(&mut self) -> Option<u16> {
+    /// When the CPU is leaving HALT, one extra M-cycle elapses before dispatch.
+    ///
+    /// Reference: [Pan Docs — Interrupts](https://gbdev.io/pandocs/Interrupts.html)
+    ///
+    /// Returns true if a dispatch was performed.
+    fn handle_interrupts(&mut self, exiting_halt: bool) -> bool {
         // Only service interrupts if IME is enabled
-        if !self.interrupts_enabled {
-            return None;
+        if !self.interrupts_enabled || !self.bus.any_interrupt_pending() {
+            return false;
         }
 
-        // Get the highest priority pending interrupt
-        let interrupt = self.bus.interrupts.get_pending_interrupt()?;
+        if exiting_halt {
+            self.internal_cycle();
+        }
 
         // Disable IME
         self.interrupts_enabled = false;
+        self.internal_cycle();
+        self.internal_cycle();
 
-        // Push current PC onto stack
-        self.push(self.registers.pc);
+        let pc = self.registers.pc;
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.write_cycle(self.registers.sp, (pc >> 8) as u8);
 
-        // Service the interrupt (clears IF bit) and get handler address
-        let handler_address = self.bus.interrupts.service_interrupt(interrupt);
+        // Get the highest priority interrupt still pending after the high-byte push
+        let interrupt = self.bus.interrupts.get_pending_interrupt();
 
-        // Jump to handler
-        self.registers.pc = handler_address;
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.write_cycle(self.registers.sp, pc as u8);
 
-        // Return the number of cycles consumed
-        Some(INTERRUPT_CYCLES)
+        // Service the interrupt (clears IF bit) and jump to its handler
+        self.registers.pc = match interrupt {
+            Some(interrupt) => self.bus.interrupts.service_interrupt(interrupt),
+            None => CANCELLED_DISPATCH_ADDRESS,
+        };
+        self.enter_function(FrameKind::Interrupt, self.registers.pc, pc);
+        self.internal_cycle();
+
+        true
     }
 
     /// Evaluate a jump condition based on CPU flags.
@@ -1026,9 +1178,14 @@ impl CPU {
     }
     /// Execute an absolute jump to a 16-bit address (JP instruction).
     /// Returns the target address if should_jump is true, otherwise PC+3 (skip instruction).
+    ///
+    /// The address operand is always read; a taken jump costs one more
+    /// internal M-cycle to load PC.
     fn jump(&mut self, should_jump: bool) -> u16 {
+        let address = self.read_next_word();
         if should_jump {
-            self.read_next_word()
+            self.internal_cycle();
+            address
         } else {
             self.registers.pc.wrapping_add(3)
         }
@@ -1045,6 +1202,7 @@ impl CPU {
         // mistakes where callers might also advance PC incorrectly.
         let offset_byte = self.read_next_byte() as i8;
         if should_jump {
+            self.internal_cycle();
             (self.registers.pc.wrapping_add(2) as i16).wrapping_add(offset_byte as i16) as u16
         } else {
             self.registers.pc.wrapping_add(2)
@@ -1071,21 +1229,24 @@ impl CPU {
         }
     }
     /// Push a 16-bit value onto the stack (decrements SP twice, MSB written first).
+    ///
+    /// Takes 3 M-cycles: an internal SP decrement followed by the two writes.
     fn push(&mut self, value: u16) {
+        self.internal_cycle();
+
         self.registers.sp = self.registers.sp.wrapping_sub(1);
-        self.bus
-            .write_byte(self.registers.sp, ((value & 0xFF00) >> 8) as u8);
+        self.write_cycle(self.registers.sp, ((value & 0xFF00) >> 8) as u8);
 
         self.registers.sp = self.registers.sp.wrapping_sub(1);
-        self.bus.write_byte(self.registers.sp, (value & 0xFF) as u8);
+        self.write_cycle(self.registers.sp, (value & 0xFF) as u8);
     }
 
     /// Pop a 16-bit value from the stack (increments SP twice, LSB read first).
     fn pop(&mut self) -> u16 {
-        let lsb = self.bus.read_byte(self.registers.sp) as u16;
+        let lsb = self.read_cycle(self.registers.sp) as u16;
         self.registers.sp = self.registers.sp.wrapping_add(1);
 
-        let msb = self.bus.read_byte(self.registers.sp) as u16;
+        let msb = self.read_cycle(self.registers.sp) as u16;
         self.registers.sp = self.registers.sp.wrapping_add(1);
 
         (msb << 8) | lsb
@@ -1095,31 +1256,49 @@ impl CPU {
     /// Returns target address if should_jump is true, otherwise PC+3 (skip instruction).
     fn call(&mut self, should_jump: bool) -> u16 {
         let next_pc = self.registers.pc.wrapping_add(3);
+        let address = self.read_next_word();
         if should_jump {
             self.push(next_pc);
-            self.read_next_word()
+            self.enter_function(FrameKind::Call, address, next_pc);
+            address
         } else {
             next_pc
         }
     }
 
+    /// Record a call in the shadow call stack, if one is kept. The return
+    /// address has just been pushed.
+    fn enter_function(&mut self, kind: FrameKind, function: u16, return_address: u16) {
+        if let Some(call_stack) = &mut self.call_stack {
+            let bank = self.bus.bank_at(function);
+            call_stack.push(kind, bank, function, return_address, self.registers.sp);
+        }
+    }
+
     /// Read the next byte from memory at PC+1 (typically an immediate operand).
     fn read_next_byte(&mut self) -> u8 {
-        self.bus.read_byte(self.registers.pc + 1)
+        self.fetch_cycle(self.registers.pc.wrapping_add(1))
     }
 
     /// Read the next word (16-bit value) from memory at PC+1 (little-endian: LSB at PC+1, MSB at PC+2).
     fn read_next_word(&mut self) -> u16 {
-        let least_significant_byte = self.bus.read_byte(self.registers.pc + 1) as u16;
-        let most_significant_byte = self.bus.read_byte(self.registers.pc + 2) as u16;
+        let least_significant_byte = self.fetch_cycle(self.registers.pc.wrapping_add(1)) as u16;
+        let most_significant_byte = self.fetch_cycle(self.registers.pc.wrapping_add(2)) as u16;
         (most_significant_byte << 8) | least_significant_byte
     }
 
     /// Execute a RET instruction: conditionally pop return address from stack.
     /// Returns the popped address if should_jump is true, otherwise PC+1 (skip instruction).
+    ///
+    /// A taken return spends one more internal M-cycle loading PC.
     fn return_(&mut self, should_jump: bool) -> u16 {
         if should_jump {
-            self.pop()
+            if let Some(call_stack) = &mut self.call_stack {
+                call_stack.pop(self.registers.sp);
+            }
+            let address = self.pop();
+            self.internal_cycle();
+            address
         } else {
             self.registers.pc.wrapping_add(1)
         }
@@ -1130,7 +1309,7 @@ impl CPU {
         if let Some(reg) = target.to_register8() {
             self.registers.read_8bit(reg)
         } else {
-            self.bus.read_byte(self.registers.get_hl())
+            self.read_cycle(self.registers.get_hl())
         }
     }
 
@@ -1143,7 +1322,354 @@ impl CPU {
         if let Some(reg) = target.to_register8() {
             self.registers.write_8bit(reg, value);
         } else {
-            self.bus.write_byte(self.registers.get_hl(), value);
+            self.write_cycle(self.registers.get_hl(), value);
+        }
+    }
+}
+
+impl Snapshot for CPU {
+    fn save_to(&self, state: &mut StateWriter) {
+        self.registers.save_to(state);
+        state.bool(self.is_halted);
+        state.bool(self.is_stopped);
+        state.bool(self.interrupts_enabled);
+        state.bool(self.ei_pending);
+        state.bool(self.halt_bug);
+        state.u16(self.speed_switch_delay);
+        self.bus.save_to(state);
+    }
+
+    fn load_from(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.registers.load_from(state)?;
+        self.is_halted = state.bool()?;
+        self.is_stopped = state.bool()?;
+        self.interrupts_enabled = state.bool()?;
+        self.ei_pending = state.bool()?;
+        self.halt_bug = state.bool()?;
+        self.speed_switch_delay = state.u16()?;
+        self.step_cycles = 0;
+        self.bus.load_from(state)
+    }
+}
+
+/// Build a CPU for `model` whose cartridge contains `program` at the entry
+/// point (0x0100), with the instruction trace off.
+#[cfg(test)]
+pub(crate) fn cpu_with_program(model: Model, program: &[u8]) -> CPU {
+    let mut rom = vec![0u8; 0x8000];
+    rom[0x0100..0x0100 + program.len()].copy_from_slice(program);
+    let mut cpu = CPU::with_model(rom, model, None);
+    cpu.trace = false;
+    cpu
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interrupts::Interrupt;
+    use crate::joypad::Button;
+
+    fn run_timer(cpu: &mut CPU, t_cycles: usize) {
+        for _ in 0..t_cycles / 4 {
+            cpu.bus.tick_m_cycle();
+        }
+    }
+
+    /// M-cycles taken by the first instruction of `program`.
+    fn m_cycles(program: &[u8]) -> u16 {
+        let mut cpu = cpu_with_program(Model::Dmg, program);
+        cpu.step() / 4
+    }
+
+    #[test]
+    fn test_instruction_timing() {
+        // Post-boot flags have Z set and C clear
+        let cases: &[(&str, &[u8], u16)] = &[
+            ("NOP", &[0x00], 1),
+            ("LD BC,d16", &[0x01, 0x34, 0x12], 3),
+            ("LD (a16),SP", &[0x08, 0x00, 0xC0], 5),
+            ("INC BC", &[0x03], 2),
+            ("ADD HL,BC", &[0x09], 2),
+            ("LD A,(HL)", &[0x7E], 2),
+            ("LD (HL),d8", &[0x36, 0x00], 3),
+            ("INC (HL)", &[0x34], 3),
+            ("LDH A,(a8)", &[0xF0, 0x80], 3),
+            ("LD A,(a16)", &[0xFA, 0x00, 0xC0], 4),
+            ("JR taken", &[0x18, 0x00], 3),
+            ("JR NZ not taken", &[0x20, 0x00], 2),
+            ("JP taken", &[0xC3, 0x00, 0x02], 4),
+            ("JP NZ not taken", &[0xC2, 0x00, 0x02], 3),
+            ("JP HL", &[0xE9], 1),
+            ("CALL taken", &[0xCD, 0x00, 0x02], 6),
+            ("CALL NZ not taken", &[0xC4, 0x00, 0x02], 3),
+            ("RET", &[0xC9], 4),
+            ("RETI", &[0xD9], 4),
+            ("RET Z taken", &[0xC8], 5),
+            ("RET NZ not taken", &[0xC0], 2),
+            ("RST 38", &[0xFF], 4),
+            ("PUSH BC", &[0xC5], 4),
+            ("POP BC", &[0xC1], 3),
+            ("ADD SP,e", &[0xE8, 0x01], 4),
+            ("LD HL,SP+e", &[0xF8, 0x01], 3),
+            ("LD SP,HL", &[0xF9], 2),
+            ("RLC B", &[0xCB, 0x00], 2),
+            ("RLC (HL)", &[0xCB, 0x06], 4),
+            ("BIT 0,(HL)", &[0xCB, 0x46], 3),
+            ("SET 0,(HL)", &[0xCB, 0xC6], 4),
+        ];
+
+        for (name, program, expected) in cases {
+            assert_eq!(m_cycles(program), *expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn test_memory_read_happens_on_final_m_cycle() {
+        // LDH A,(DIV) reads on its third M-cycle. DIV ticks over after
+        // 256 T-cycles, so starting 61 M-cycles in, the read sees 1 only if
+        // all three cycles have elapsed before the access.
+        let mut cpu = cpu_with_program(Model::Dmg, &[0xF0, 0x04]);
+        cpu.bus.reset_div();
+        run_timer(&mut cpu, 61 * 4);
+
+        cpu.step();
+
+        assert_eq!(cpu.registers.a, 1);
+    }
+
+    #[test]
+    fn test_interrupt_dispatch_from_halt_takes_extra_m_cycle() {
+        let mut cpu = cpu_with_program(Model::Dmg, &[0x76, 0x00]);
+        cpu.interrupts_enabled = true;
+        cpu.bus.interrupts.write_ie(Interrupt::Timer.bit_mask());
+        cpu.step();
+        assert!(cpu.is_halted());
+
+        cpu.bus.request_interrupt(Interrupt::Timer);
+
+        assert_eq!(cpu.step(), 24);
+        assert_eq!(cpu.registers.pc, 0x0050);
+        assert_eq!(cpu.bus.read_byte(0xFFFC), 0x01);
+    }
+
+    #[test]
+    fn test_ie_push_cancels_dispatch() {
+        // SP = 0x0000: the high byte of PC (0x01) is written to IE, disabling
+        // the pending Timer interrupt before the vector is chosen.
+        let mut cpu = cpu_with_program(Model::Dmg, &[0x00]);
+        cpu.interrupts_enabled = true;
+        cpu.registers.sp = 0x0000;
+        cpu.bus.interrupts.write_if(0x00);
+        cpu.bus.interrupts.write_ie(Interrupt::Timer.bit_mask());
+        cpu.bus.request_interrupt(Interrupt::Timer);
+
+        assert_eq!(cpu.step(), 20);
+        assert_eq!(cpu.registers.pc, 0x0000);
+        assert_eq!(cpu.registers.sp, 0xFFFE);
+        assert_eq!(cpu.bus.interrupts.read_ie(), 0x01);
+        assert!(!cpu.interrupts_enabled);
+        // The cancelled interrupt stays requested
+        assert_ne!(
+            cpu.bus.interrupts.read_if() & Interrupt::Timer.bit_mask(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_ie_push_can_redirect_to_other_interrupt() {
+        // The high byte written to IE (0x01) still enables VBlank, which is
+        // serviced instead of the originally highest-priority Timer interrupt.
+        let mut cpu = cpu_with_program(Model::Dmg, &[0x00]);
+        cpu.interrupts_enabled = true;
+        cpu.registers.sp = 0x0000;
+        cpu.bus.interrupts.write_ie(Interrupt::Timer.bit_mask());
+        cpu.bus.request_interrupt(Interrupt::Timer);
+        cpu.bus.request_interrupt(Interrupt::VBlank);
+
+        cpu.step();
+
+        assert_eq!(cpu.registers.pc, 0x0040);
+        assert_eq!(
+            cpu.bus.interrupts.read_if() & Interrupt::VBlank.bit_mask(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_interrupt_dispatch_takes_five_m_cycles() {
+        let mut cpu = cpu_with_program(Model::Dmg, &[0x00]);
+        cpu.interrupts_enabled = true;
+        cpu.bus.interrupts.write_ie(Interrupt::Timer.bit_mask());
+        cpu.bus.request_interrupt(Interrupt::Timer);
+
+        assert_eq!(cpu.step(), 20);
+        assert_eq!(cpu.registers.pc, 0x0050);
+        assert_eq!(cpu.registers.sp, 0xFFFC);
+    }
+
+    #[test]
+    fn test_stop_enters_stop_mode_and_resets_div() {
+        let mut cpu = cpu_with_program(Model::Dmg, &[0x10, 0x00]);
+        run_timer(&mut cpu, 1024);
+        assert_ne!(cpu.bus.read_byte(0xFF04), 0);
+
+        cpu.step();
+
+        assert!(cpu.is_stopped());
+        assert!(!cpu.is_halted());
+        assert_eq!(cpu.registers.pc, 0x0102);
+        assert_eq!(cpu.bus.read_byte(0xFF04), 0);
+    }
+
+    #[test]
+    fn test_stop_is_one_byte_with_interrupt_pending() {
+        let mut cpu = cpu_with_program(Model::Dmg, &[0x10, 0x00]);
+        cpu.bus.interrupts.write_ie(Interrupt::Timer.bit_mask());
+        cpu.bus.request_interrupt(Interrupt::Timer);
+
+        cpu.step();
+
+        assert!(cpu.is_stopped());
+        assert_eq!(cpu.registers.pc, 0x0101);
+    }
+
+    #[test]
+    fn test_stop_with_button_held_enters_halt_without_div_reset() {
+        let mut cpu = cpu_with_program(Model::Dmg, &[0x10, 0x00]);
+        cpu.bus.write_byte(0xFF00, 0x10); // select action buttons
+        cpu.bus.set_button(Button::A, true);
+        cpu.bus.interrupts.write_if(0x00);
+        cpu.bus.reset_div();
+        run_timer(&mut cpu, 1024);
+
+        cpu.step();
+
+        assert!(!cpu.is_stopped());
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.registers.pc, 0x0102);
+        assert_eq!(cpu.bus.read_byte(0xFF04), 4);
+    }
+
+    #[test]
+    fn test_stop_mode_exits_on_selected_button_press() {
+        let mut cpu = cpu_with_program(Model::Dmg, &[0x10, 0x00, 0x00]);
+        cpu.bus.write_byte(0xFF00, 0x20); // select d-pad
+        cpu.step();
+        assert!(cpu.is_stopped());
+
+        // Unselected buttons don't wake the CPU
+        cpu.bus.set_button(Button::Start, true);
+        cpu.step();
+        assert!(cpu.is_stopped());
+        assert_eq!(cpu.registers.pc, 0x0102);
+
+        cpu.bus.set_button(Button::Left, true);
+        cpu.step();
+        assert!(!cpu.is_stopped());
+        assert_eq!(cpu.registers.pc, 0x0103);
+    }
+
+    #[test]
+    fn test_stop_performs_armed_speed_switch() {
+        let mut cpu = cpu_with_program(Model::Dmg, &[0x10, 0x00, 0x00]);
+        cpu.bus.cgb_mode = true;
+        cpu.bus.write_byte(0xFF4D, 0x01);
+        assert_eq!(cpu.bus.read_byte(0xFF4D), 0x7F);
+
+        cpu.step();
+
+        assert!(!cpu.is_stopped());
+        assert!(cpu.bus.is_double_speed());
+        assert_eq!(cpu.bus.read_byte(0xFF4D), 0xFE);
+        assert_eq!(cpu.registers.pc, 0x0102);
+
+        // The CPU idles until the switch completes, then resumes execution
+        for _ in 0..SPEED_SWITCH_M_CYCLES {
+            cpu.step();
+            assert_eq!(cpu.registers.pc, 0x0102);
+        }
+        cpu.step();
+        assert_eq!(cpu.registers.pc, 0x0103);
+    }
+
+    #[test]
+    fn test_general_purpose_hdma_halts_cpu() {
+        // LD A,0x00; LDH (0x55),A; NOP
+        let mut cpu = cpu_with_program(Model::Dmg, &[0x3E, 0x00, 0xE0, 0x55, 0x00]);
+        cpu.bus.cgb_mode = true;
+        cpu.step();
+        cpu.step();
+        assert!(cpu.bus.is_hdma_active());
+
+        // One 16-byte block takes 8 M-cycles at normal speed
+        for _ in 0..8 {
+            assert_eq!(cpu.step(), 4);
+            assert_eq!(cpu.registers.pc, 0x0104);
+        }
+        assert!(!cpu.bus.is_hdma_active());
+        cpu.step();
+        assert_eq!(cpu.registers.pc, 0x0105);
+    }
+
+    #[test]
+    fn test_key1_ignored_on_dmg() {
+        let mut cpu = cpu_with_program(Model::Dmg, &[0x10, 0x00]);
+        cpu.bus.write_byte(0xFF4D, 0x01);
+        assert_eq!(cpu.bus.read_byte(0xFF4D), 0xFF);
+
+        cpu.step();
+
+        assert!(cpu.is_stopped());
+        assert!(!cpu.bus.is_double_speed());
+    }
+
+    #[test]
+    fn test_boot_rom_runs_from_power_on_and_hands_over_at_0100() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0100] = 0x76; // HALT
+        let mut boot_rom = vec![0u8; 0x100]; // NOPs...
+        boot_rom[0xFC..].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]); // LD A,1; LDH (0x50),A
+        let mut cpu = CPU::with_model(rom, Model::Dmg, Some(boot_rom));
+        assert_eq!(cpu.registers.pc, 0x0000);
+        assert_eq!(cpu.bus.read_byte(0xFF04), 0x00);
+        assert_eq!(cpu.bus.read_byte(0xFF40), 0x00);
+
+        while cpu.registers.pc != 0x0100 {
+            cpu.step();
         }
+        assert!(!cpu.bus.boot_rom_mapped());
+        assert_eq!(cpu.bus.read_byte(0x00FC), 0x00);
+    }
+
+    #[test]
+    fn test_post_boot_registers_follow_model() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[HEADER_CHECKSUM_ADDR] = 0x00;
+        let cpu = CPU::with_model(rom.clone(), Model::Dmg, None);
+        assert_eq!(cpu.registers.get_af(), 0x0180);
+        assert_eq!(cpu.registers.pc, 0x0100);
+
+        rom[0x0143] = 0x80; // CGB flag
+        let cpu = CPU::with_model(rom, Model::Cgb, None);
+        assert_eq!(cpu.registers.get_af(), 0x1180);
+        assert_eq!(cpu.registers.get_de(), 0xFF56);
+        assert!(cpu.bus.cgb_mode);
+    }
+
+    #[test]
+    fn test_dmg_game_on_cgb_runs_in_compat_mode() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0134..0x013F].copy_from_slice(b"POKEMON RED");
+        rom[0x014B] = 0x01; // Nintendo
+        let mut cpu = CPU::with_model(rom, Model::Cgb, None);
+
+        assert!(cpu.bus.is_dmg_compat());
+        assert!(!cpu.bus.cgb_mode);
+        assert_eq!(cpu.registers.a, 0x11); // games can still detect the CGB
+        assert_eq!(cpu.bus.read_byte(0xFF4F), 0xFF);
+        // BG palette 0 colour 1 is the boot ROM's red for this title
+        assert_eq!(cpu.bus.gpu.read_register(0xFF68) & 0x3F, 0x00);
+        cpu.bus.gpu.write_register(0xFF68, 0x02);
+        assert_eq!(cpu.bus.gpu.read_register(0xFF69), 0x1F);
     }
 }
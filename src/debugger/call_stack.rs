@@ -0,0 +1,132 @@
+//! Shadow call stack, kept by the CPU alongside the real stack.
+//!
+//! CALL, RST and interrupt dispatches push a frame recording the function
+//! entered and where its return address was stored; RET and RETI pop it.
+//! Games do not always return the way they called: some discard return
+//! addresses with POP or reset SP, and `push hl; ret` is a common computed
+//! jump. A return therefore pops the frame whose return address it reads
+//! along with any deeper frames that were abandoned, and pops nothing when
+//! it reads above the innermost frame's return address. Likewise a call
+//! replaces any frames whose return addresses it overwrites.
+
+use crate::memory_bus::MemoryBus;
+
+/// Frames kept before the outermost ones are dropped, for code that calls
+/// without ever returning.
+const MAX_DEPTH: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    Call,
+    Rst,
+    Interrupt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    /// Bank mapped at the function's address when it was entered.
+    pub bank: u16,
+    pub function: u16,
+    pub return_address: u16,
+    /// Where the return address was pushed.
+    pub sp: u16,
+    /// Unique for every frame pushed, so two calls of the same function can
+    /// be told apart.
+    pub serial: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+    next_serial: u64,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record entering `function`, whose return address was just pushed to
+    /// `sp`.
+    pub fn push(
+        &mut self,
+        kind: FrameKind,
+        bank: u16,
+        function: u16,
+        return_address: u16,
+        sp: u16,
+    ) {
+        // Frames whose return address was stored at or below this one are
+        // stale: SP was moved past them without returning
+        self.pop(sp);
+        if self.frames.len() == MAX_DEPTH {
+            self.frames.remove(0);
+        }
+        self.frames.push(Frame {
+            kind,
+            bank,
+            function,
+            return_address,
+            sp,
+            serial: self.next_serial,
+        });
+        self.next_serial += 1;
+    }
+
+    /// Record a return that read its address from `sp`.
+    pub fn pop(&mut self, sp: u16) {
+        while self.frames.last().is_some_and(|frame| frame.sp <= sp) {
+            self.frames.pop();
+        }
+    }
+
+    /// The frames, outermost first.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+}
+
+/// A function entry as its symbol, or `BANK:ADDRESS` without one.
+pub fn function_name(bus: &MemoryBus, bank: u16, address: u16) -> String {
+    bus.symbols
+        .as_ref()
+        .and_then(|symbols| symbols.describe(bank, address))
+        .unwrap_or_else(|| format!("{bank:02X}:{address:04X}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn functions(stack: &CallStack) -> Vec<u16> {
+        stack.frames().iter().map(|frame| frame.function).collect()
+    }
+
+    #[test]
+    fn test_returns_pop_matching_and_abandoned_frames() {
+        let mut stack = CallStack::new();
+        stack.push(FrameKind::Call, 0, 0x0200, 0x0153, 0xFFFC);
+        stack.push(FrameKind::Rst, 0, 0x0038, 0x0203, 0xFFFA);
+        stack.push(FrameKind::Interrupt, 0, 0x0040, 0x0038, 0xFFF8);
+        assert_eq!(functions(&stack), [0x0200, 0x0038, 0x0040]);
+
+        // `push hl; ret` inside the interrupt handler returns to no frame
+        stack.pop(0xFFF6);
+        assert_eq!(stack.frames().len(), 3);
+        stack.pop(0xFFF8);
+        assert_eq!(functions(&stack), [0x0200, 0x0038]);
+
+        // The RST handler discarded its return address and returned for the
+        // function that called it
+        stack.pop(0xFFFC);
+        assert!(stack.frames().is_empty());
+
+        // A call after SP was reset replaces the frames it overwrote
+        stack.push(FrameKind::Call, 0, 0x0200, 0x0153, 0xFFFC);
+        stack.push(FrameKind::Call, 0, 0x0300, 0x0153, 0xFFFA);
+        stack.push(FrameKind::Call, 0, 0x0200, 0x0153, 0xFFFC);
+        assert_eq!(functions(&stack), [0x0200]);
+        assert_eq!(stack.frames()[0].serial, 5);
+    }
+}
@@ -0,0 +1,181 @@
+//! ROM coverage: which bytes of each bank were executed, read as data or
+//! never touched.
+//!
+//! The bus flags every cartridge ROM byte the CPU fetches as part of an
+//! instruction (opcode or operand) as executed, and every other CPU or DMA
+//! read of it as data. Bytes are identified by their offset in the ROM
+//! file, found from the bank mapped when they were accessed, so that the
+//! same address in different banks is told apart.
+//!
+//! Coverage can be written as a text report or as a CDL (code/data log)
+//! file: one flag byte per ROM byte, bit 0 for code and bit 1 for data, the
+//! layout FCEUX-style CDL tools read.
+
+use crate::debugger::symbols::Symbols;
+use std::cell::Cell;
+use std::fmt::Write;
+
+pub const EXECUTED: u8 = 0x01;
+pub const READ: u8 = 0x02;
+
+const BANK_SIZE: usize = 0x4000;
+
+pub struct Coverage {
+    /// Flags of every ROM byte, indexed by ROM offset.
+    flags: Vec<Cell<u8>>,
+}
+
+/// Byte counts of one bank.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct BankTotals {
+    executed: usize,
+    data: usize,
+    untouched: usize,
+}
+
+impl Coverage {
+    /// Coverage of a ROM of `rom_size` bytes, rounded up to whole banks.
+    pub fn new(rom_size: usize) -> Self {
+        let banks = rom_size.div_ceil(BANK_SIZE).max(2);
+        Coverage {
+            flags: vec![Cell::new(0); banks * BANK_SIZE],
+        }
+    }
+
+    /// Flag the byte at `offset` in the ROM with `access`.
+    pub fn record(&self, offset: usize, access: u8) {
+        if let Some(flags) = self.flags.get(offset) {
+            flags.set(flags.get() | access);
+        }
+    }
+
+    /// The flags of every ROM byte, as a CDL file.
+    pub fn to_cdl(&self) -> Vec<u8> {
+        self.flags.iter().map(Cell::get).collect()
+    }
+
+    fn bank_totals(&self, bank: usize) -> BankTotals {
+        let mut totals = BankTotals::default();
+        for flags in &self.flags[bank * BANK_SIZE..(bank + 1) * BANK_SIZE] {
+            // Bytes both executed and read count as executed
+            match flags.get() {
+                0 => totals.untouched += 1,
+                flags if flags & EXECUTED != 0 => totals.executed += 1,
+                _ => totals.data += 1,
+            }
+        }
+        totals
+    }
+
+    /// A summary per bank followed by the ranges of bytes never touched,
+    /// named after the closest symbol when symbols are loaded.
+    pub fn report(&self, symbols: Option<&Symbols>) -> String {
+        let banks = self.flags.len() / BANK_SIZE;
+        let percent = |count: usize| count as f64 * 100.0 / BANK_SIZE as f64;
+        let mut report = String::from("bank  executed       %      data       %  untouched\n");
+        let mut total = BankTotals::default();
+        for bank in 0..banks {
+            let totals = self.bank_totals(bank);
+            let _ = writeln!(
+                report,
+                "  {bank:02X}  {:>8} {:>6.2}%  {:>8} {:>6.2}%  {:>9}",
+                totals.executed,
+                percent(totals.executed),
+                totals.data,
+                percent(totals.data),
+                totals.untouched
+            );
+            total.executed += totals.executed;
+            total.data += totals.data;
+            total.untouched += totals.untouched;
+        }
+        let _ = writeln!(
+            report,
+            "total {:>8} {:>6.2}%  {:>8} {:>6.2}%  {:>9}",
+            total.executed,
+            percent(total.executed) / banks as f64,
+            total.data,
+            percent(total.data) / banks as f64,
+            total.untouched
+        );
+
+        report.push_str("\nUntouched ranges:\n");
+        let mut offset = 0;
+        while offset < self.flags.len() {
+            if self.flags[offset].get() != 0 {
+                offset += 1;
+                continue;
+            }
+            // Ranges stop at bank boundaries
+            let bank_end = (offset / BANK_SIZE + 1) * BANK_SIZE;
+            let end = (offset..bank_end)
+                .find(|&end| self.flags[end].get() != 0)
+                .unwrap_or(bank_end);
+            let (bank, start) = bank_address(offset);
+            let last = bank_address(end - 1).1;
+            let _ = write!(
+                report,
+                "  {bank:02X}:{start:04X}-{last:04X} {:>6} bytes",
+                end - offset
+            );
+            if let Some(name) = symbols.and_then(|symbols| symbols.describe(bank, start)) {
+                let _ = write!(report, "  {name}");
+            }
+            report.push('\n');
+            offset = end;
+        }
+        report
+    }
+}
+
+/// The bank and CPU address of a ROM offset.
+fn bank_address(offset: usize) -> (u16, u16) {
+    let bank = offset / BANK_SIZE;
+    let address = match bank {
+        0 => offset,
+        _ => BANK_SIZE + offset % BANK_SIZE,
+    };
+    (bank as u16, address as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_and_cdl() {
+        let coverage = Coverage::new(0x8000);
+        for offset in 0..0x4000 {
+            coverage.record(offset, READ);
+        }
+        for offset in 0x150..0x160 {
+            coverage.record(offset, EXECUTED);
+        }
+        coverage.record(0x4000, EXECUTED);
+        coverage.record(0x7FFF, READ);
+        coverage.record(0x8000, READ); // past the end of the ROM
+
+        let cdl = coverage.to_cdl();
+        assert_eq!(cdl.len(), 0x8000);
+        assert_eq!(cdl[0x150], EXECUTED | READ);
+        assert_eq!(cdl[0x4001], 0);
+
+        let symbols = Symbols::parse("01:4000 Main\n").unwrap();
+        let report = coverage.report(Some(&symbols));
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(
+            lines[1],
+            "  00        16   0.10%     16368  99.90%          0"
+        );
+        assert_eq!(
+            lines[2],
+            "  01         1   0.01%         1   0.01%      16382"
+        );
+        assert_eq!(
+            lines[3],
+            "total       17   0.05%     16369  49.95%      16382"
+        );
+        assert_eq!(lines[6], "  01:4001-7FFE  16382 bytes  Main+1");
+        assert_eq!(lines.len(), 7);
+    }
+}
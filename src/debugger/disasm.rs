@@ -0,0 +1,394 @@
+//! Disassembler built on the instruction decoder.
+//!
+//! Instructions are printed in RGBDS syntax (`ld a, [hl+]`, `ldh [$FF44], a`)
+//! with their operands read from memory and relative jumps resolved to their
+//! target address. Jump, call and memory operands that have a symbol (see
+//! [`MemoryBus::symbol_at`]) are printed by name. Opcodes the decoder
+//! rejects are printed as `db`.
+
+use crate::instructions::{
+    ArithmeticTarget, IncDecTarget, Instruction, JumpTest, LoadByteSource, LoadByteTarget,
+    LoadType, LoadWordSource, LoadWordTarget, PrefixTarget, StackTarget,
+};
+use crate::memory_bus::MemoryBus;
+use crate::register::{Register16, Register8};
+
+/// One decoded instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disassembly {
+    pub address: u16,
+    pub length: u16,
+    pub text: String,
+}
+
+/// Decode the instruction at `address` without side effects.
+pub fn decode_at(bus: &MemoryBus, address: u16) -> Option<Instruction> {
+    match bus.peek(address) {
+        0xCB => Instruction::from_byte(bus.peek(address.wrapping_add(1)), true),
+        opcode => Instruction::from_byte(opcode, false),
+    }
+}
+
+/// Disassemble the instruction at `address`.
+pub fn disassemble(bus: &MemoryBus, address: u16) -> Disassembly {
+    let byte = |offset: u16| bus.peek(address.wrapping_add(offset));
+    let word = || u16::from_le_bytes([byte(1), byte(2)]);
+
+    let Some(instruction) = decode_at(bus, address) else {
+        return Disassembly {
+            address,
+            length: 1,
+            text: format!("db ${:02X}", byte(0)),
+        };
+    };
+    let length = length(&instruction);
+    let text = match instruction {
+        Instruction::ADD(target) => alu("add", target, byte(1)),
+        Instruction::ADC(target) => alu("adc", target, byte(1)),
+        Instruction::SUB(target) => alu("sub", target, byte(1)),
+        Instruction::SBC(target) => alu("sbc", target, byte(1)),
+        Instruction::AND(target) => alu("and", target, byte(1)),
+        Instruction::OR(target) => alu("or", target, byte(1)),
+        Instruction::XOR(target) => alu("xor", target, byte(1)),
+        Instruction::CP(target) => alu("cp", target, byte(1)),
+
+        Instruction::JP(test) => format!("jp {}{}", condition(test), operand(bus, word())),
+        Instruction::JR(test) => {
+            let target = address.wrapping_add(2).wrapping_add(byte(1) as i8 as u16);
+            format!("jr {}{}", condition(test), operand(bus, target))
+        }
+        Instruction::CALL(test) => format!("call {}{}", condition(test), operand(bus, word())),
+        Instruction::RET(JumpTest::Always) => "ret".to_string(),
+        Instruction::RET(test) => format!("ret {}", condition(test).trim_end_matches(", ")),
+        Instruction::RETI => "reti".to_string(),
+        Instruction::RST(vector) => format!("rst ${vector:02X}"),
+
+        Instruction::LD(LoadType::Byte(target, source)) => {
+            let high_ram = matches!(target, LoadByteTarget::A8I | LoadByteTarget::CI)
+                || matches!(source, LoadByteSource::A8I | LoadByteSource::CI);
+            let mnemonic = if high_ram { "ldh" } else { "ld" };
+            format!(
+                "{mnemonic} {}, {}",
+                byte_target(bus, target, byte(1), word()),
+                byte_source(bus, source, byte(1), word())
+            )
+        }
+        Instruction::LD(LoadType::Word(target, source)) => {
+            let target = match target {
+                LoadWordTarget::HL => "hl".to_string(),
+                LoadWordTarget::BC => "bc".to_string(),
+                LoadWordTarget::DE => "de".to_string(),
+                LoadWordTarget::SP => "sp".to_string(),
+                LoadWordTarget::A16I => format!("[{}]", operand(bus, word())),
+            };
+            let source = match source {
+                LoadWordSource::D16 => format!("${:04X}", word()),
+                LoadWordSource::SP => "sp".to_string(),
+                LoadWordSource::HL => "hl".to_string(),
+            };
+            format!("ld {target}, {source}")
+        }
+
+        Instruction::POP(target) => format!("pop {}", stack_target(target)),
+        Instruction::PUSH(target) => format!("push {}", stack_target(target)),
+        Instruction::INC(target) => format!("inc {}", inc_dec_target(target)),
+        Instruction::DEC(target) => format!("dec {}", inc_dec_target(target)),
+
+        Instruction::RLC(target) => format!("rlc {}", prefix_target(target)),
+        Instruction::RRC(target) => format!("rrc {}", prefix_target(target)),
+        Instruction::RL(target) => format!("rl {}", prefix_target(target)),
+        Instruction::RR(target) => format!("rr {}", prefix_target(target)),
+        Instruction::SLA(target) => format!("sla {}", prefix_target(target)),
+        Instruction::SRA(target) => format!("sra {}", prefix_target(target)),
+        Instruction::SWAP(target) => format!("swap {}", prefix_target(target)),
+        Instruction::SRL(target) => format!("srl {}", prefix_target(target)),
+        Instruction::BIT(bit, target) => format!("bit {bit}, {}", prefix_target(target)),
+        Instruction::RES(bit, target) => format!("res {bit}, {}", prefix_target(target)),
+        Instruction::SET(bit, target) => format!("set {bit}, {}", prefix_target(target)),
+
+        Instruction::NOP => "nop".to_string(),
+        Instruction::STOP => "stop".to_string(),
+        Instruction::HALT => "halt".to_string(),
+        Instruction::DI => "di".to_string(),
+        Instruction::EI => "ei".to_string(),
+        Instruction::RLCA => "rlca".to_string(),
+        Instruction::RRCA => "rrca".to_string(),
+        Instruction::RLA => "rla".to_string(),
+        Instruction::RRA => "rra".to_string(),
+        Instruction::DAA => "daa".to_string(),
+        Instruction::CPL => "cpl".to_string(),
+        Instruction::SCF => "scf".to_string(),
+        Instruction::CCF => "ccf".to_string(),
+
+        Instruction::ADDHL(register) => format!("add hl, {}", register16(register)),
+        Instruction::ADDSP => format!("add sp, {}", byte(1) as i8),
+        Instruction::LDHLSP => format!("ld hl, sp{:+}", byte(1) as i8),
+        Instruction::JP_HL => "jp hl".to_string(),
+    };
+    Disassembly {
+        address,
+        length,
+        text,
+    }
+}
+
+/// Instruction length in bytes, including the opcode (and CB prefix).
+pub fn length(instruction: &Instruction) -> u16 {
+    match instruction {
+        Instruction::ADD(target)
+        | Instruction::ADC(target)
+        | Instruction::SUB(target)
+        | Instruction::SBC(target)
+        | Instruction::AND(target)
+        | Instruction::OR(target)
+        | Instruction::XOR(target)
+        | Instruction::CP(target) => match target {
+            ArithmeticTarget::D8 => 2,
+            _ => 1,
+        },
+        Instruction::JP(_) | Instruction::CALL(_) => 3,
+        Instruction::JR(_) | Instruction::ADDSP | Instruction::LDHLSP | Instruction::STOP => 2,
+        Instruction::LD(LoadType::Byte(target, source)) => match (target, source) {
+            (LoadByteTarget::A16I, _) | (_, LoadByteSource::A16I) => 3,
+            (LoadByteTarget::A8I, _) | (_, LoadByteSource::A8I) | (_, LoadByteSource::D8) => 2,
+            _ => 1,
+        },
+        Instruction::LD(LoadType::Word(target, source)) => match (target, source) {
+            (LoadWordTarget::A16I, _) | (_, LoadWordSource::D16) => 3,
+            _ => 1,
+        },
+        Instruction::RLC(_)
+        | Instruction::RRC(_)
+        | Instruction::RL(_)
+        | Instruction::RR(_)
+        | Instruction::SLA(_)
+        | Instruction::SRA(_)
+        | Instruction::SWAP(_)
+        | Instruction::SRL(_)
+        | Instruction::BIT(..)
+        | Instruction::RES(..)
+        | Instruction::SET(..) => 2,
+        _ => 1,
+    }
+}
+
+/// Disassemble up to `count` instructions ending just before `address`.
+///
+/// Code cannot be decoded backwards reliably, so this tries start addresses
+/// further back first and keeps the first one whose instructions line up
+/// with `address`.
+pub fn instructions_before(bus: &MemoryBus, address: u16, count: usize) -> Vec<Disassembly> {
+    // Walk in u32 so that an instruction running past $FFFF ends the walk
+    // instead of wrapping around.
+    let address = address as u32;
+    let max_back = (count as u32).saturating_mul(3).min(address);
+    for back in (1..=max_back).rev() {
+        let mut listing = Vec::new();
+        let mut pc = address - back;
+        while pc < address {
+            let instruction = disassemble(bus, pc as u16);
+            pc += instruction.length as u32;
+            listing.push(instruction);
+        }
+        if pc == address {
+            let skip = listing.len().saturating_sub(count);
+            return listing.split_off(skip);
+        }
+    }
+    Vec::new()
+}
+
+fn alu(mnemonic: &str, target: ArithmeticTarget, immediate: u8) -> String {
+    let operand = match target {
+        ArithmeticTarget::A => "a".to_string(),
+        ArithmeticTarget::B => "b".to_string(),
+        ArithmeticTarget::C => "c".to_string(),
+        ArithmeticTarget::D => "d".to_string(),
+        ArithmeticTarget::E => "e".to_string(),
+        ArithmeticTarget::H => "h".to_string(),
+        ArithmeticTarget::L => "l".to_string(),
+        ArithmeticTarget::HLI => "[hl]".to_string(),
+        ArithmeticTarget::D8 => format!("${immediate:02X}"),
+    };
+    format!("{mnemonic} a, {operand}")
+}
+
+fn condition(test: JumpTest) -> &'static str {
+    match test {
+        JumpTest::NotZero => "nz, ",
+        JumpTest::Zero => "z, ",
+        JumpTest::NotCarry => "nc, ",
+        JumpTest::Carry => "c, ",
+        JumpTest::Always => "",
+    }
+}
+
+/// An address operand: its symbol if it has one, else `$ADDRESS`.
+fn operand(bus: &MemoryBus, address: u16) -> String {
+    match bus.symbol_at(address) {
+        Some(name) => name.to_string(),
+        None => format!("${address:04X}"),
+    }
+}
+
+fn byte_target(bus: &MemoryBus, target: LoadByteTarget, immediate: u8, address: u16) -> String {
+    match target {
+        LoadByteTarget::A => "a".to_string(),
+        LoadByteTarget::B => "b".to_string(),
+        LoadByteTarget::C => "c".to_string(),
+        LoadByteTarget::D => "d".to_string(),
+        LoadByteTarget::E => "e".to_string(),
+        LoadByteTarget::H => "h".to_string(),
+        LoadByteTarget::L => "l".to_string(),
+        LoadByteTarget::HLI => "[hl]".to_string(),
+        LoadByteTarget::DEI => "[de]".to_string(),
+        LoadByteTarget::BCI => "[bc]".to_string(),
+        LoadByteTarget::HLI_INC => "[hl+]".to_string(),
+        LoadByteTarget::HLI_DEC => "[hl-]".to_string(),
+        LoadByteTarget::A16I => format!("[{}]", operand(bus, address)),
+        LoadByteTarget::A8I => format!("[{}]", operand(bus, 0xFF00 | immediate as u16)),
+        LoadByteTarget::CI => "[c]".to_string(),
+    }
+}
+
+fn byte_source(bus: &MemoryBus, source: LoadByteSource, immediate: u8, address: u16) -> String {
+    match source {
+        LoadByteSource::A => "a".to_string(),
+        LoadByteSource::B => "b".to_string(),
+        LoadByteSource::C => "c".to_string(),
+        LoadByteSource::D => "d".to_string(),
+        LoadByteSource::E => "e".to_string(),
+        LoadByteSource::H => "h".to_string(),
+        LoadByteSource::L => "l".to_string(),
+        LoadByteSource::D8 => format!("${immediate:02X}"),
+        LoadByteSource::HLI => "[hl]".to_string(),
+        LoadByteSource::HLI_INC => "[hl+]".to_string(),
+        LoadByteSource::HLI_DEC => "[hl-]".to_string(),
+        LoadByteSource::BCI => "[bc]".to_string(),
+        LoadByteSource::DEI => "[de]".to_string(),
+        LoadByteSource::A16I => format!("[{}]", operand(bus, address)),
+        LoadByteSource::A8I => format!("[{}]", operand(bus, 0xFF00 | immediate as u16)),
+        LoadByteSource::CI => "[c]".to_string(),
+    }
+}
+
+fn stack_target(target: StackTarget) -> &'static str {
+    match target {
+        StackTarget::BC => "bc",
+        StackTarget::DE => "de",
+        StackTarget::HL => "hl",
+        StackTarget::AF => "af",
+    }
+}
+
+fn inc_dec_target(target: IncDecTarget) -> &'static str {
+    match target {
+        IncDecTarget::Reg8(register) => register8(register),
+        IncDecTarget::Reg16(register) => register16(register),
+        IncDecTarget::HLI => "[hl]",
+    }
+}
+
+fn prefix_target(target: PrefixTarget) -> &'static str {
+    match target.to_register8() {
+        Some(register) => register8(register),
+        None => "[hl]",
+    }
+}
+
+fn register8(register: Register8) -> &'static str {
+    match register {
+        Register8::A => "a",
+        Register8::B => "b",
+        Register8::C => "c",
+        Register8::D => "d",
+        Register8::E => "e",
+        Register8::H => "h",
+        Register8::L => "l",
+    }
+}
+
+fn register16(register: Register16) -> &'static str {
+    match register {
+        Register16::BC => "bc",
+        Register16::DE => "de",
+        Register16::HL => "hl",
+        Register16::SP => "sp",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bus_with_code(code: &[u8]) -> MemoryBus {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x150..0x150 + code.len()].copy_from_slice(code);
+        MemoryBus::new(rom)
+    }
+
+    fn listing(code: &[u8]) -> Vec<String> {
+        let bus = bus_with_code(code);
+        let mut address = 0x150;
+        let mut lines = Vec::new();
+        while address < 0x150 + code.len() as u16 {
+            let instruction = disassemble(&bus, address);
+            address += instruction.length;
+            lines.push(instruction.text);
+        }
+        lines
+    }
+
+    #[test]
+    fn test_disassemble_operands() {
+        let code = [
+            0x3E, 0x12, // ld a, $12
+            0xE0, 0x44, // ldh [$FF44], a
+            0x2A, // ld a, [hl+]
+            0x08, 0x00, 0xC0, // ld [$C000], sp
+            0xCD, 0x34, 0x12, // call $1234
+            0xC0, // ret nz
+            0x18, 0xFE, // jr $015C (to itself)
+            0xCB, 0x7E, // bit 7, [hl]
+            0xF8, 0xFE, // ld hl, sp-2
+            0xD3, // invalid
+        ];
+        assert_eq!(
+            listing(&code),
+            [
+                "ld a, $12",
+                "ldh [$FF44], a",
+                "ld a, [hl+]",
+                "ld [$C000], sp",
+                "call $1234",
+                "ret nz",
+                "jr $015C",
+                "bit 7, [hl]",
+                "ld hl, sp-2",
+                "db $D3",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_instructions_before_lines_up_with_address() {
+        // ld hl, $C000; ld a, $01; xor a
+        let bus = bus_with_code(&[0x21, 0x00, 0xC0, 0x3E, 0x01, 0xAF]);
+        let before = instructions_before(&bus, 0x155, 2);
+        let addresses: Vec<_> = before.iter().map(|line| line.address).collect();
+        assert_eq!(addresses, [0x150, 0x153]);
+    }
+
+    #[test]
+    fn test_instructions_before_top_of_memory() {
+        // From $FFFC the walk meets jp $xxxx at $FFFE, which runs past the
+        // end of memory; from $FFFD it finds ld a, $C3 ending at $FFFF.
+        let mut bus = bus_with_code(&[]);
+        for (address, byte) in [(0xFFFC, 0x3E), (0xFFFD, 0x3E), (0xFFFE, 0xC3)] {
+            bus.write_byte(address, byte);
+        }
+        let before = instructions_before(&bus, 0xFFFF, 1);
+        let addresses: Vec<_> = before.iter().map(|line| line.address).collect();
+        assert_eq!(addresses, [0xFFFD]);
+    }
+}
@@ -0,0 +1,334 @@
+//! Expressions for conditional breakpoints, such as `a == $10 && [hl] != 0`.
+//!
+//! Operands are registers (`a`-`l`, `af`, `bc`, `de`, `hl`, `sp`, `pc`),
+//! flags (`zf`, `nf`, `hf`, `cf`, `ime`), numbers and memory bytes
+//! (`[address]`). Numbers are decimal, or hexadecimal with a `$` or `0x`
+//! prefix: bare hex would be ambiguous with the register names.
+//!
+//! Operators, loosest first: `||`, `&&`, comparisons (`==`, `!=`, `<`, `<=`,
+//! `>`, `>=`), `|`, `^`, `&`, `+` and `-`, then unary `!` and `-`.
+//! Comparisons and logic operators yield 1 or 0.
+
+use crate::cpu::CPU;
+use crate::register::FlagsRegister;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Number(i64),
+    Register(Register),
+    Memory(Box<Expr>),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+    PC,
+    ZeroFlag,
+    SubtractFlag,
+    HalfCarryFlag,
+    CarryFlag,
+    Ime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Add,
+    Sub,
+}
+
+/// Operators of each precedence level, loosest first.
+const LEVELS: [&[(&str, Op)]; 7] = [
+    &[("||", Op::Or)],
+    &[("&&", Op::And)],
+    &[
+        ("==", Op::Eq),
+        ("!=", Op::Ne),
+        ("<=", Op::Le),
+        (">=", Op::Ge),
+        ("<", Op::Lt),
+        (">", Op::Gt),
+    ],
+    &[("|", Op::BitOr)],
+    &[("^", Op::BitXor)],
+    &[("&", Op::BitAnd)],
+    &[("+", Op::Add), ("-", Op::Sub)],
+];
+
+impl Register {
+    pub fn from_name(name: &str) -> Option<Register> {
+        let register = match name.to_ascii_lowercase().as_str() {
+            "a" => Register::A,
+            "f" => Register::F,
+            "b" => Register::B,
+            "c" => Register::C,
+            "d" => Register::D,
+            "e" => Register::E,
+            "h" => Register::H,
+            "l" => Register::L,
+            "af" => Register::AF,
+            "bc" => Register::BC,
+            "de" => Register::DE,
+            "hl" => Register::HL,
+            "sp" => Register::SP,
+            "pc" => Register::PC,
+            "zf" => Register::ZeroFlag,
+            "nf" => Register::SubtractFlag,
+            "hf" => Register::HalfCarryFlag,
+            "cf" => Register::CarryFlag,
+            "ime" => Register::Ime,
+            _ => return None,
+        };
+        Some(register)
+    }
+
+    pub fn read(self, cpu: &CPU) -> u16 {
+        let registers = &cpu.registers;
+        match self {
+            Register::A => registers.a as u16,
+            Register::F => registers.f.to_byte() as u16,
+            Register::B => registers.b as u16,
+            Register::C => registers.c as u16,
+            Register::D => registers.d as u16,
+            Register::E => registers.e as u16,
+            Register::H => registers.h as u16,
+            Register::L => registers.l as u16,
+            Register::AF => registers.get_af(),
+            Register::BC => registers.get_bc(),
+            Register::DE => registers.get_de(),
+            Register::HL => registers.get_hl(),
+            Register::SP => registers.sp,
+            Register::PC => registers.pc,
+            Register::ZeroFlag => registers.f.zero as u16,
+            Register::SubtractFlag => registers.f.subtract as u16,
+            Register::HalfCarryFlag => registers.f.half_carry as u16,
+            Register::CarryFlag => registers.f.carry as u16,
+            Register::Ime => cpu.interrupts_enabled as u16,
+        }
+    }
+
+    /// Set the register; 8-bit registers and flags keep the low bits only.
+    pub fn write(self, cpu: &mut CPU, value: u16) {
+        let registers = &mut cpu.registers;
+        match self {
+            Register::A => registers.a = value as u8,
+            Register::F => registers.f = FlagsRegister::from_byte(value as u8),
+            Register::B => registers.b = value as u8,
+            Register::C => registers.c = value as u8,
+            Register::D => registers.d = value as u8,
+            Register::E => registers.e = value as u8,
+            Register::H => registers.h = value as u8,
+            Register::L => registers.l = value as u8,
+            Register::AF => registers.set_af(value),
+            Register::BC => registers.set_bc(value),
+            Register::DE => registers.set_de(value),
+            Register::HL => registers.set_hl(value),
+            Register::SP => registers.sp = value,
+            Register::PC => registers.pc = value,
+            Register::ZeroFlag => registers.f.zero = value & 1 != 0,
+            Register::SubtractFlag => registers.f.subtract = value & 1 != 0,
+            Register::HalfCarryFlag => registers.f.half_carry = value & 1 != 0,
+            Register::CarryFlag => registers.f.carry = value & 1 != 0,
+            Register::Ime => cpu.interrupts_enabled = value & 1 != 0,
+        }
+    }
+}
+
+impl Expr {
+    pub fn parse(text: &str) -> Result<Expr, String> {
+        let mut parser = Parser { rest: text };
+        let expr = parser.level(0)?;
+        parser.skip_space();
+        if !parser.rest.is_empty() {
+            return Err(format!("Unexpected `{}` in expression", parser.rest));
+        }
+        Ok(expr)
+    }
+
+    pub fn eval(&self, cpu: &CPU) -> i64 {
+        match self {
+            Expr::Number(value) => *value,
+            Expr::Register(register) => register.read(cpu) as i64,
+            Expr::Memory(address) => cpu.bus.peek(address.eval(cpu) as u16) as i64,
+            Expr::Not(operand) => (operand.eval(cpu) == 0) as i64,
+            Expr::Negate(operand) => operand.eval(cpu).wrapping_neg(),
+            Expr::Binary(op, left, right) => {
+                let left = left.eval(cpu);
+                // Logic operators short-circuit
+                match op {
+                    Op::Or if left != 0 => return 1,
+                    Op::And if left == 0 => return 0,
+                    _ => {}
+                }
+                let right = right.eval(cpu);
+                match op {
+                    Op::Or | Op::And => (right != 0) as i64,
+                    Op::Eq => (left == right) as i64,
+                    Op::Ne => (left != right) as i64,
+                    Op::Lt => (left < right) as i64,
+                    Op::Le => (left <= right) as i64,
+                    Op::Gt => (left > right) as i64,
+                    Op::Ge => (left >= right) as i64,
+                    Op::BitOr => left | right,
+                    Op::BitXor => left ^ right,
+                    Op::BitAnd => left & right,
+                    Op::Add => left.wrapping_add(right),
+                    Op::Sub => left.wrapping_sub(right),
+                }
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl Parser<'_> {
+    fn skip_space(&mut self) {
+        self.rest = self.rest.trim_start();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_space();
+        match self.rest.strip_prefix(token) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Binary operators of precedence `level` and tighter.
+    fn level(&mut self, level: usize) -> Result<Expr, String> {
+        let Some(operators) = LEVELS.get(level) else {
+            return self.unary();
+        };
+        let mut left = self.level(level + 1)?;
+        'operands: loop {
+            for &(token, op) in *operators {
+                // `|` and `&` must not swallow the first half of `||` and `&&`
+                let doubled = token.len() == 1
+                    && self
+                        .rest
+                        .trim_start()
+                        .get(1..)
+                        .is_some_and(|next| next.starts_with(token));
+                if !doubled && self.eat(token) {
+                    let right = self.level(level + 1)?;
+                    left = Expr::Binary(op, Box::new(left), Box::new(right));
+                    continue 'operands;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("-") {
+            return Ok(Expr::Negate(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let expr = self.level(0)?;
+            return self.close(")", expr);
+        }
+        if self.eat("[") {
+            let address = self.level(0)?;
+            return self.close("]", Expr::Memory(Box::new(address)));
+        }
+        self.operand()
+    }
+
+    fn close(&mut self, token: &str, expr: Expr) -> Result<Expr, String> {
+        if self.eat(token) {
+            Ok(expr)
+        } else {
+            Err(format!("Missing `{token}` in expression"))
+        }
+    }
+
+    fn operand(&mut self) -> Result<Expr, String> {
+        self.skip_space();
+        let len = self
+            .rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '$' || c == '_'))
+            .unwrap_or(self.rest.len());
+        let (token, rest) = self.rest.split_at(len);
+        if token.is_empty() {
+            return Err("Missing operand in expression".to_string());
+        }
+        self.rest = rest;
+        if let Some(register) = Register::from_name(token) {
+            return Ok(Expr::Register(register));
+        }
+        let hex = token.strip_prefix('$').or_else(|| token.strip_prefix("0x"));
+        let value = match hex {
+            Some(digits) => i64::from_str_radix(digits, 16),
+            None => token.parse(),
+        };
+        value
+            .map(Expr::Number)
+            .map_err(|_| format!("Unknown operand `{token}`"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(text: &str, cpu: &CPU) -> i64 {
+        Expr::parse(text).unwrap().eval(cpu)
+    }
+
+    #[test]
+    fn test_precedence_and_operands() {
+        let mut cpu = CPU::new(vec![0; 0x8000]);
+        cpu.registers.a = 0x10;
+        cpu.registers.set_hl(0xC000);
+        cpu.bus.write_byte(0xC000, 7);
+
+        assert_eq!(eval("a == $10 && [hl] == 7", &cpu), 1);
+        assert_eq!(eval("1 + 2 & 3", &cpu), 3);
+        assert_eq!(eval("(1 + 2) | 4 == 7", &cpu), 1);
+        assert_eq!(eval("hl + 1 - 0xC001 || 0", &cpu), 0);
+        assert_eq!(eval("![hl+1] && -a < 0", &cpu), 1);
+        assert_eq!(eval("cf", &cpu), cpu.registers.f.carry as i64);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Expr::parse("a ==").is_err());
+        assert!(Expr::parse("[hl").is_err());
+        assert!(Expr::parse("a == ix").is_err());
+        assert!(Expr::parse("a b").is_err());
+    }
+}
@@ -0,0 +1,566 @@
+//! GDB remote serial protocol stub, so that GDB and other RSP clients can
+//! drive the emulator over TCP.
+//!
+//! The stub serves one client and handles the packets needed for
+//! source-level debugging: registers (`g`, `G`, `p`, `P`), memory (`m`,
+//! `M`), continue and single step (`c`, `s`), breakpoints (`Z0`/`Z1`) and
+//! write, read and access watchpoints (`Z2`-`Z4`), plus Ctrl-C while the
+//! game runs. Anything else gets the empty "unsupported" reply.
+//!
+//! Registers are the 16-bit pairs af, bc, de, hl, sp and pc, sent little
+//! endian and described to the client by `target.xml`. Breakpoints are
+//! compared against PC after every [`CPU::step`] instead of being patched
+//! into memory, so they work in ROM too. Serial output produced while
+//! running is forwarded as console output (`O` packets).
+
+use crate::cpu::CPU;
+use crate::debugger::expr::Register;
+use crate::debugger::watch::{WatchHit, WatchKind, Watchpoint, Watchpoints};
+use std::collections::{BTreeSet, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+/// Largest packet the client may send, advertised in `qSupported`.
+const PACKET_SIZE: usize = 0x1000;
+/// Instructions run between checks for a Ctrl-C from the client.
+const INTERRUPT_POLL_STEPS: u32 = 4096;
+const INTERRUPT_BYTE: u8 = 0x03;
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+/// Register numbers of `p` and `P`, in `g` packet order.
+const REGISTERS: [Register; 6] = [
+    Register::AF,
+    Register::BC,
+    Register::DE,
+    Register::HL,
+    Register::SP,
+    Register::PC,
+];
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.rusty_gameboy.sm83">
+    <reg name="af" bitsize="16" type="int" regnum="0"/>
+    <reg name="bc" bitsize="16" type="int"/>
+    <reg name="de" bitsize="16" type="int"/>
+    <reg name="hl" bitsize="16" type="int"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+/// What the session does after a packet.
+#[derive(Debug, PartialEq, Eq)]
+enum Response {
+    Reply(String),
+    Resume { step: bool },
+    Detach,
+    Kill,
+}
+
+pub struct GdbStub {
+    breakpoints: BTreeSet<u16>,
+    next_watch_id: u32,
+    /// Whether the client understands `swbreak` stop reasons.
+    swbreak: bool,
+}
+
+impl GdbStub {
+    pub fn new() -> Self {
+        GdbStub {
+            breakpoints: BTreeSet::new(),
+            next_watch_id: 1,
+            swbreak: false,
+        }
+    }
+
+    /// Serve the client on `stream` until it detaches, kills the target or
+    /// disconnects.
+    pub fn serve(&mut self, cpu: &mut CPU, stream: TcpStream) -> io::Result<()> {
+        let mut connection = Connection::new(stream)?;
+        cpu.trace = false;
+        while let Some(packet) = connection.read_packet()? {
+            match self.handle(cpu, &packet) {
+                Response::Reply(reply) => {
+                    connection.send_packet(&reply)?;
+                    if packet == "QStartNoAckMode" {
+                        connection.no_ack = true;
+                    }
+                }
+                Response::Resume { step } => {
+                    let reply = self.resume(cpu, step, &mut connection)?;
+                    connection.send_packet(&reply)?;
+                }
+                Response::Detach => {
+                    connection.send_packet("OK")?;
+                    break;
+                }
+                Response::Kill => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, cpu: &mut CPU, packet: &str) -> Response {
+        let Some(command) = packet.chars().next() else {
+            return Response::Reply(String::new());
+        };
+        let args = &packet[command.len_utf8()..];
+        match command {
+            'c' | 's' | 'C' | 'S' => {
+                // `c ADDR` resumes at ADDR; the signal of `C SIG;ADDR` is ignored
+                let address = match command {
+                    'c' | 's' => args,
+                    _ => args.split_once(';').map_or("", |(_, address)| address),
+                };
+                if !address.is_empty() {
+                    match parse_u16(address) {
+                        Some(pc) => cpu.registers.pc = pc,
+                        None => return Response::Reply("E01".to_string()),
+                    }
+                }
+                Response::Resume {
+                    step: command.eq_ignore_ascii_case(&'s'),
+                }
+            }
+            'D' => Response::Detach,
+            'k' => Response::Kill,
+            _ => Response::Reply(
+                self.reply(cpu, command, args)
+                    .unwrap_or_else(|| "E01".to_string()),
+            ),
+        }
+    }
+
+    /// The reply to a packet that does not resume execution, or None if the
+    /// packet is malformed.
+    fn reply(&mut self, cpu: &mut CPU, command: char, args: &str) -> Option<String> {
+        let reply = match command {
+            '?' => format!("S{SIGTRAP:02x}"),
+            'g' => REGISTERS
+                .iter()
+                .map(|register| encode_hex(&register.read(cpu).to_le_bytes()))
+                .collect(),
+            'G' => {
+                let bytes = decode_hex(args)?;
+                if bytes.len() != REGISTERS.len() * 2 {
+                    return None;
+                }
+                for (register, value) in REGISTERS.iter().zip(bytes.chunks(2)) {
+                    register.write(cpu, u16::from_le_bytes([value[0], value[1]]));
+                }
+                "OK".to_string()
+            }
+            'p' => {
+                let register = REGISTERS.get(usize::from_str_radix(args, 16).ok()?)?;
+                encode_hex(&register.read(cpu).to_le_bytes())
+            }
+            'P' => {
+                let (number, value) = args.split_once('=')?;
+                let register = REGISTERS.get(usize::from_str_radix(number, 16).ok()?)?;
+                let value: [u8; 2] = decode_hex(value)?.try_into().ok()?;
+                register.write(cpu, u16::from_le_bytes(value));
+                "OK".to_string()
+            }
+            'm' => {
+                let (address, length) = args.split_once(',')?;
+                let address = parse_u16(address)?;
+                let length = usize::from_str_radix(length, 16).ok()?.min(PACKET_SIZE / 2);
+                let bytes: Vec<u8> = (0..length)
+                    .map(|offset| cpu.bus.peek(address.wrapping_add(offset as u16)))
+                    .collect();
+                encode_hex(&bytes)
+            }
+            'M' => {
+                let (range, data) = args.split_once(':')?;
+                let (address, length) = range.split_once(',')?;
+                let address = parse_u16(address)?;
+                let bytes = decode_hex(data)?;
+                if bytes.len() != usize::from_str_radix(length, 16).ok()? {
+                    return None;
+                }
+                for (offset, &byte) in bytes.iter().enumerate() {
+                    cpu.bus
+                        .write_byte(address.wrapping_add(offset as u16), byte);
+                }
+                "OK".to_string()
+            }
+            'Z' | 'z' => self.breakpoint(cpu, command == 'Z', args)?,
+            // There is a single thread, so thread selection always succeeds
+            'H' | 'T' => "OK".to_string(),
+            'q' => self.query(args)?,
+            'Q' if args == "StartNoAckMode" => "OK".to_string(),
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    fn query(&mut self, args: &str) -> Option<String> {
+        if let Some(features) = args.strip_prefix("Supported") {
+            self.swbreak = features.contains("swbreak+");
+            return Some(format!(
+                "PacketSize={PACKET_SIZE:x};qXfer:features:read+;swbreak+;hwbreak+;QStartNoAckMode+"
+            ));
+        }
+        if let Some(range) = args.strip_prefix("Xfer:features:read:target.xml:") {
+            let (offset, length) = range.split_once(',')?;
+            let offset = usize::from_str_radix(offset, 16).ok()?;
+            let end = offset.saturating_add(usize::from_str_radix(length, 16).ok()?);
+            let chunk = TARGET_XML.get(offset..end.min(TARGET_XML.len()))?;
+            let marker = if end >= TARGET_XML.len() { 'l' } else { 'm' };
+            return Some(format!("{marker}{chunk}"));
+        }
+        let reply = match args {
+            "Attached" => "1",
+            "C" => "QC1",
+            "fThreadInfo" => "m1",
+            "sThreadInfo" => "l",
+            _ => "",
+        };
+        Some(reply.to_string())
+    }
+
+    /// Insert or remove a breakpoint or watchpoint: `TYPE,ADDR,KIND`, where
+    /// KIND is the watched length for watchpoints.
+    fn breakpoint(&mut self, cpu: &mut CPU, insert: bool, args: &str) -> Option<String> {
+        let mut fields = args.split([',', ';']);
+        let (kind, address, length) = (fields.next()?, fields.next()?, fields.next()?);
+        let address = parse_u16(address)?;
+        let kind = match kind {
+            "0" | "1" => {
+                if insert {
+                    self.breakpoints.insert(address);
+                } else {
+                    self.breakpoints.remove(&address);
+                }
+                return Some("OK".to_string());
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return Some(String::new()),
+        };
+        let length = parse_u16(length).filter(|&length| length > 0)?;
+        let end = address.checked_add(length - 1)?;
+        let watchpoints = cpu.bus.watchpoints.get_or_insert_with(Watchpoints::new);
+        if insert {
+            watchpoints.add(Watchpoint {
+                id: self.next_watch_id,
+                start: address,
+                end,
+                kind,
+                value: None,
+            });
+            self.next_watch_id += 1;
+        } else {
+            let id = watchpoints
+                .iter()
+                .find(|w| w.start == address && w.end == end && w.kind == kind)
+                .map(|w| w.id);
+            if let Some(id) = id {
+                watchpoints.remove(id);
+            }
+            if watchpoints.is_empty() {
+                cpu.bus.watchpoints = None;
+            }
+        }
+        Some("OK".to_string())
+    }
+
+    /// Run one instruction, or until a breakpoint, watchpoint or interrupt,
+    /// and return the stop reply.
+    fn resume(
+        &mut self,
+        cpu: &mut CPU,
+        step: bool,
+        connection: &mut Connection,
+    ) -> io::Result<String> {
+        // Forget accesses made by `M` packets
+        if let Some(watchpoints) = &cpu.bus.watchpoints {
+            watchpoints.take_hit();
+        }
+        let mut steps: u32 = 0;
+        loop {
+            cpu.step();
+            if cpu.bus.has_serial_output() {
+                let output = cpu.bus.get_serial_output();
+                cpu.bus.clear_serial_output();
+                connection.send_packet(&format!("O{}", encode_hex(output.as_bytes())))?;
+            }
+            let hit = cpu.bus.watchpoints.as_ref().and_then(Watchpoints::take_hit);
+            if let Some(hit) = hit {
+                return Ok(watch_reply(cpu, hit));
+            }
+            if step || cpu.is_stopped() {
+                return Ok(format!("S{SIGTRAP:02x}"));
+            }
+            if !cpu.is_halted() && self.breakpoints.contains(&cpu.registers.pc) {
+                return Ok(if self.swbreak {
+                    format!("T{SIGTRAP:02x}swbreak:;")
+                } else {
+                    format!("S{SIGTRAP:02x}")
+                });
+            }
+            steps = steps.wrapping_add(1);
+            if steps.is_multiple_of(INTERRUPT_POLL_STEPS) && connection.interrupted()? {
+                return Ok(format!("S{SIGINT:02x}"));
+            }
+        }
+    }
+}
+
+/// The stop reply for a watchpoint hit, naming the watched address.
+fn watch_reply(cpu: &CPU, hit: WatchHit) -> String {
+    let (id, address) = match hit {
+        WatchHit::Read { id, address, .. } | WatchHit::Write { id, address, .. } => (id, address),
+    };
+    let kind = cpu
+        .bus
+        .watchpoints
+        .iter()
+        .flat_map(Watchpoints::iter)
+        .find(|watchpoint| watchpoint.id == id)
+        .map(|watchpoint| watchpoint.kind);
+    let reason = match kind {
+        Some(WatchKind::Read) => "rwatch",
+        Some(WatchKind::Access) => "awatch",
+        _ => "watch",
+    };
+    format!("T{SIGTRAP:02x}{reason}:{address:x};")
+}
+
+/// Packet framing and acknowledgements over the client's socket.
+struct Connection {
+    stream: TcpStream,
+    /// Bytes received but not consumed yet.
+    pending: VecDeque<u8>,
+    /// Set by `QStartNoAckMode`: packets are no longer acknowledged.
+    no_ack: bool,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(Connection {
+            stream,
+            pending: VecDeque::new(),
+            no_ack: false,
+        })
+    }
+
+    /// The next byte from the client, or None once it disconnects.
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if self.pending.is_empty() {
+            let mut buffer = [0; 1024];
+            let len = self.stream.read(&mut buffer)?;
+            self.pending.extend(&buffer[..len]);
+        }
+        Ok(self.pending.pop_front())
+    }
+
+    /// The contents of the next valid packet, or None once the client
+    /// disconnects. Packets with a bad checksum are rejected with `-`.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // Skip acknowledgements and interrupts that arrive while stopped
+            match self.read_byte()? {
+                Some(b'$') => {}
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                    None => return Ok(None),
+                }
+            }
+            let mut digits = [0; 2];
+            for digit in &mut digits {
+                match self.read_byte()? {
+                    Some(byte) => *digit = byte,
+                    None => return Ok(None),
+                }
+            }
+            let valid = std::str::from_utf8(&digits)
+                .ok()
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                == Some(checksum(&data));
+            if !self.no_ack {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+        }
+    }
+
+    /// Send a packet, resending it until the client acknowledges it.
+    fn send_packet(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${data}#{:02x}", checksum(data.as_bytes()));
+        loop {
+            self.stream.write_all(packet.as_bytes())?;
+            if self.no_ack {
+                return Ok(());
+            }
+            loop {
+                match self.read_byte()? {
+                    Some(b'+') => return Ok(()),
+                    Some(b'-') => break,
+                    Some(_) => {}
+                    None => return Err(io::ErrorKind::UnexpectedEof.into()),
+                }
+            }
+        }
+    }
+
+    /// Whether the client sent a Ctrl-C, without blocking.
+    fn interrupted(&mut self) -> io::Result<bool> {
+        let mut buffer = [0; 64];
+        self.stream.set_nonblocking(true)?;
+        let result = self.stream.read(&mut buffer);
+        self.stream.set_nonblocking(false)?;
+        match result {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(len) => self.pending.extend(&buffer[..len]),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+        let interrupted = self.pending.contains(&INTERRUPT_BYTE);
+        self.pending.retain(|&byte| byte != INTERRUPT_BYTE);
+        Ok(interrupted)
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn parse_u16(text: &str) -> Option<u16> {
+    u16::from_str_radix(text, 16).ok()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::tests::program_cpu;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn reply(stub: &mut GdbStub, cpu: &mut CPU, packet: &str) -> String {
+        match stub.handle(cpu, packet) {
+            Response::Reply(reply) => reply,
+            other => panic!("{packet}: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_register_memory_and_breakpoint_packets() {
+        let mut cpu = program_cpu();
+        let mut stub = GdbStub::new();
+        cpu.registers.set_hl(0xC000);
+        let registers = reply(&mut stub, &mut cpu, "g");
+        assert_eq!(&registers[12..], "00c0feff0001");
+
+        assert_eq!(reply(&mut stub, &mut cpu, "P1=3412"), "OK");
+        assert_eq!(cpu.registers.get_bc(), 0x1234);
+        assert_eq!(reply(&mut stub, &mut cpu, "p5"), "0001");
+        assert_eq!(reply(&mut stub, &mut cpu, "p6"), "E01");
+
+        assert_eq!(reply(&mut stub, &mut cpu, "m150,3"), "cd5801");
+        assert_eq!(reply(&mut stub, &mut cpu, "MC000,2:abcd"), "OK");
+        assert_eq!(cpu.bus.peek(0xC001), 0xCD);
+        assert_eq!(reply(&mut stub, &mut cpu, "MC000,3:abcd"), "E01");
+
+        assert_eq!(reply(&mut stub, &mut cpu, "Z0,158,1"), "OK");
+        assert_eq!(reply(&mut stub, &mut cpu, "Z2,fffc,2"), "OK");
+        assert_eq!(cpu.bus.watchpoints.as_ref().unwrap().iter().count(), 1);
+        assert_eq!(reply(&mut stub, &mut cpu, "z2,fffc,2"), "OK");
+        assert!(cpu.bus.watchpoints.is_none());
+
+        let xml = reply(&mut stub, &mut cpu, "qXfer:features:read:target.xml:0,10");
+        assert_eq!(xml, "m<?xml version=\"1");
+        let xml = reply(
+            &mut stub,
+            &mut cpu,
+            "qXfer:features:read:target.xml:10,1000",
+        );
+        assert!(xml.starts_with('l') && xml.ends_with("</target>\n"));
+        assert_eq!(reply(&mut stub, &mut cpu, "vMustReplyEmpty"), "");
+    }
+
+    fn send(stream: &mut TcpStream, data: &str) {
+        let packet = format!("${data}#{:02x}", checksum(data.as_bytes()));
+        stream.write_all(packet.as_bytes()).unwrap();
+    }
+
+    /// Read the acknowledgement of the last packet sent, then the reply.
+    fn receive(stream: &mut TcpStream) -> String {
+        let mut bytes = Vec::new();
+        let mut byte = [0];
+        while bytes.len() < 3 || bytes[bytes.len() - 3] != b'#' {
+            stream.read_exact(&mut byte).unwrap();
+            if bytes.is_empty() && byte[0] == b'+' {
+                continue;
+            }
+            bytes.push(byte[0]);
+        }
+        stream.write_all(b"+").unwrap();
+        let text = String::from_utf8(bytes).unwrap();
+        text[1..text.len() - 3].to_string()
+    }
+
+    #[test]
+    fn test_session_breakpoint_watchpoint_and_interrupt() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            send(&mut stream, "Z0,158,1");
+            assert_eq!(receive(&mut stream), "OK");
+            send(&mut stream, "c");
+            assert_eq!(receive(&mut stream), "S05");
+            send(&mut stream, "p5");
+            assert_eq!(receive(&mut stream), "5801");
+
+            send(&mut stream, "z0,158,1");
+            assert_eq!(receive(&mut stream), "OK");
+            send(&mut stream, "Z2,fffd,1");
+            assert_eq!(receive(&mut stream), "OK");
+            send(&mut stream, "c");
+            // The return address pushed by the next call
+            assert_eq!(receive(&mut stream), "T05watch:fffd;");
+
+            send(&mut stream, "z2,fffd,1");
+            assert_eq!(receive(&mut stream), "OK");
+            send(&mut stream, "c");
+            stream.write_all(&[INTERRUPT_BYTE]).unwrap();
+            assert_eq!(receive(&mut stream), "S02");
+            send(&mut stream, "D");
+            assert_eq!(receive(&mut stream), "OK");
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        let mut cpu = program_cpu();
+        GdbStub::new().serve(&mut cpu, stream).unwrap();
+        client.join().unwrap();
+    }
+}
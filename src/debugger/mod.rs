@@ -0,0 +1,847 @@
+//! Interactive command-line debugger.
+//!
+//! The debugger drives the CPU one `step` at a time from a prompt. It can
+//! step into or over calls, run to the end of the current function or to a
+//! breakpoint, inspect and modify registers and memory, and disassemble the
+//! code around PC. Watchpoints stop execution on memory accesses (see
+//! [`watch`]). The per-instruction trace of [`CPU::step`] is switched off
+//! while debugging (see `trace`).
+//!
+//! Addresses and byte values given to commands are hexadecimal, with an
+//! optional `$` or `0x` prefix; an address may be qualified with a bank as
+//! `BANK:ADDRESS`, or named by a symbol when a symbol file is loaded (see
+//! [`symbols`]). Counts are decimal. Breakpoint conditions are [`expr`]
+//! expressions. An empty line repeats the previous command.
+
+pub mod call_stack;
+pub mod coverage;
+pub mod disasm;
+pub mod expr;
+pub mod gdb;
+pub mod profiler;
+pub mod symbols;
+pub mod watch;
+
+use crate::cpu::CPU;
+use crate::instructions::Instruction;
+use call_stack::{CallStack, FrameKind};
+use disasm::Disassembly;
+use expr::{Expr, Register};
+use std::io::{self, BufRead, Write};
+use watch::{WatchHit, WatchKind, Watchpoint, Watchpoints};
+
+const PROMPT: &str = "(gbdb) ";
+const DEFAULT_DUMP_BYTES: usize = 64;
+const DEFAULT_LISTING: usize = 10;
+/// Most bytes `x` dumps or instructions `disasm` lists: the address space.
+const MAX_LISTING: usize = 0x10000;
+/// Instructions listed before PC by `disasm` without an address.
+const LISTING_CONTEXT: usize = 3;
+
+const HELP: &str = "\
+step [N]             (s)   execute N instructions, entering calls
+next [N]             (n)   execute N instructions, running over CALL and RST
+finish               (fin) run until the current function returns
+continue [FRAMES]    (c)   run until a breakpoint, or for at most FRAMES frames
+break ADDR [if EXPR] (b)   stop at ADDR, optionally only while EXPR is nonzero
+watch [KIND] ADDR[-END] [== BYTE]
+                     (wa)  stop on a read, write, access or change (KIND, default write)
+                           of ADDR to END, optionally only for the byte value BYTE
+delete [N]           (d)   delete breakpoint or watchpoint N, or all of them
+breakpoints          (bl)  list breakpoints and watchpoints
+registers            (r)   show the registers
+backtrace            (bt)  show the calls leading to PC
+set REG VALUE              set a register (a-l, af-hl, sp, pc) or flag (zf, nf, hf, cf, ime)
+x ADDR [COUNT]             dump COUNT bytes of memory
+write ADDR BYTE...   (w)   write bytes to memory, as the CPU would
+disasm [ADDR] [COUNT](dis) disassemble, by default around PC
+trace on|off               print every executed instruction
+quit                 (q)   leave the debugger
+
+ADDR is hexadecimal, BANK:ADDRESS or a symbol name; BYTE values are hexadecimal.";
+
+/// What the prompt loop does after a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Prompt,
+    Quit,
+}
+
+/// Why a run command returned to the prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stop {
+    Done,
+    Breakpoint(u32),
+    /// A watchpoint hit, with the PC of the instruction responsible.
+    Watchpoint(WatchHit, u16),
+    CpuStopped,
+}
+
+struct Breakpoint {
+    id: u32,
+    bank: Option<u16>,
+    address: u16,
+    condition: Option<(String, Expr)>,
+}
+
+impl Breakpoint {
+    /// The address as given, with its bank if it had one.
+    fn location(&self) -> String {
+        match self.bank {
+            Some(bank) => format!("{bank:02X}:{:04X}", self.address),
+            None => format!("{:04X}", self.address),
+        }
+    }
+}
+
+enum CommandError {
+    Invalid(String),
+    Io(io::Error),
+}
+
+impl From<String> for CommandError {
+    fn from(message: String) -> Self {
+        CommandError::Invalid(message)
+    }
+}
+
+impl From<io::Error> for CommandError {
+    fn from(e: io::Error) -> Self {
+        CommandError::Io(e)
+    }
+}
+
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    next_id: u32,
+    last_command: String,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: Vec::new(),
+            next_id: 1,
+            last_command: String::new(),
+        }
+    }
+
+    /// Read and execute commands until `quit` or the end of the input.
+    pub fn repl(
+        &mut self,
+        cpu: &mut CPU,
+        input: &mut impl BufRead,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        cpu.trace = false;
+        cpu.call_stack.get_or_insert_with(CallStack::new);
+        self.show_location(cpu, out)?;
+        let mut line = String::new();
+        loop {
+            write!(out, "{PROMPT}")?;
+            out.flush()?;
+            line.clear();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            if self.execute(cpu, &line, out)? == Flow::Quit {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Execute one command line, reporting mistakes to `out`.
+    pub fn execute(&mut self, cpu: &mut CPU, line: &str, out: &mut dyn Write) -> io::Result<Flow> {
+        let line = match line.trim() {
+            "" => self.last_command.clone(),
+            line => line.to_string(),
+        };
+        if line.is_empty() {
+            return Ok(Flow::Prompt);
+        }
+        self.last_command.clone_from(&line);
+        match self.command(cpu, &line, out) {
+            Ok(flow) => Ok(flow),
+            Err(CommandError::Invalid(message)) => {
+                writeln!(out, "{message}")?;
+                Ok(Flow::Prompt)
+            }
+            Err(CommandError::Io(e)) => Err(e),
+        }
+    }
+
+    fn command(
+        &mut self,
+        cpu: &mut CPU,
+        line: &str,
+        out: &mut dyn Write,
+    ) -> Result<Flow, CommandError> {
+        let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+        let args = args.trim();
+        let words: Vec<&str> = args.split_whitespace().collect();
+        match name {
+            "step" | "s" => {
+                let count = parse_count(words.first(), 1)?;
+                let mut steps = 0;
+                let stop = self.run(cpu, out, |_, _| {
+                    steps += 1;
+                    steps >= count
+                })?;
+                self.report(cpu, stop, out)?;
+            }
+            "next" | "n" => {
+                let mut stop = Stop::Done;
+                for _ in 0..parse_count(words.first(), 1)? {
+                    stop = self.step_over(cpu, out)?;
+                    if stop != Stop::Done {
+                        break;
+                    }
+                }
+                self.report(cpu, stop, out)?;
+            }
+            "finish" | "fin" => {
+                let sp = cpu.registers.sp;
+                let stop = self.run(cpu, out, |cpu, returning| {
+                    returning && cpu.registers.sp > sp
+                })?;
+                self.report(cpu, stop, out)?;
+            }
+            "continue" | "c" => {
+                let end = match words.first() {
+                    Some(frames) => Some(cpu.bus.gpu.frame_count() + parse_count(Some(frames), 0)?),
+                    None => None,
+                };
+                let stop = self.run(cpu, out, |cpu, _| {
+                    end.is_some_and(|end| cpu.bus.gpu.frame_count() >= end)
+                })?;
+                self.report(cpu, stop, out)?;
+            }
+            "break" | "b" => self.add_breakpoint(cpu, args, out)?,
+            "watch" | "wa" => self.add_watchpoint(cpu, &words, out)?,
+            "delete" | "d" => match words.first() {
+                Some(id) => {
+                    let id: u32 = id
+                        .parse()
+                        .map_err(|_| format!("Invalid breakpoint number: {id}"))?;
+                    let before = self.breakpoints.len();
+                    self.breakpoints.retain(|breakpoint| breakpoint.id != id);
+                    let removed = self.breakpoints.len() != before
+                        || cpu
+                            .bus
+                            .watchpoints
+                            .as_mut()
+                            .is_some_and(|watchpoints| watchpoints.remove(id));
+                    if !removed {
+                        return Err(format!("No breakpoint {id}").into());
+                    }
+                    if cpu
+                        .bus
+                        .watchpoints
+                        .as_ref()
+                        .is_some_and(Watchpoints::is_empty)
+                    {
+                        cpu.bus.watchpoints = None;
+                    }
+                }
+                None => {
+                    self.breakpoints.clear();
+                    cpu.bus.watchpoints = None;
+                }
+            },
+            "breakpoints" | "bl" => {
+                let watchpoints = cpu.bus.watchpoints.iter().flat_map(Watchpoints::iter);
+                if self.breakpoints.is_empty() && cpu.bus.watchpoints.is_none() {
+                    writeln!(out, "No breakpoints")?;
+                }
+                for breakpoint in &self.breakpoints {
+                    write!(out, "{}: {}", breakpoint.id, breakpoint.location())?;
+                    if let Some((text, _)) = &breakpoint.condition {
+                        write!(out, " if {text}")?;
+                    }
+                    writeln!(out)?;
+                }
+                for watchpoint in watchpoints {
+                    write!(
+                        out,
+                        "{}: watch {} {:04X}",
+                        watchpoint.id,
+                        watchpoint.kind.name(),
+                        watchpoint.start
+                    )?;
+                    if watchpoint.end != watchpoint.start {
+                        write!(out, "-{:04X}", watchpoint.end)?;
+                    }
+                    if let Some(value) = watchpoint.value {
+                        write!(out, " == {value:02X}")?;
+                    }
+                    writeln!(out)?;
+                }
+            }
+            "registers" | "r" => show_registers(cpu, out)?,
+            "backtrace" | "bt" => backtrace(cpu, out)?,
+            "set" => {
+                let [name, value] = words[..] else {
+                    return Err("Usage: set REG VALUE".to_string().into());
+                };
+                let register =
+                    Register::from_name(name).ok_or(format!("Unknown register: {name}"))?;
+                register.write(cpu, parse_hex(value)?);
+                show_registers(cpu, out)?;
+            }
+            "x" => {
+                let address = parse_address(
+                    cpu,
+                    words.first().ok_or("Usage: x ADDR [COUNT]".to_string())?,
+                )?
+                .1;
+                let count = parse_listing_count(words.get(1), DEFAULT_DUMP_BYTES)?;
+                dump(cpu, address, count, out)?;
+            }
+            "write" | "w" => {
+                let (address, bytes) = words
+                    .split_first()
+                    .ok_or("Usage: write ADDR BYTE...".to_string())?;
+                let address = parse_address(cpu, address)?.1;
+                let bytes = bytes
+                    .iter()
+                    .map(|byte| parse_byte(byte))
+                    .collect::<Result<Vec<_>, _>>()?;
+                for (offset, &byte) in bytes.iter().enumerate() {
+                    cpu.bus
+                        .write_byte(address.wrapping_add(offset as u16), byte);
+                }
+            }
+            "disasm" | "dis" => {
+                let listing = match words.first() {
+                    Some(address) => {
+                        let count = parse_listing_count(words.get(1), DEFAULT_LISTING)?;
+                        listing_from(cpu, parse_address(cpu, address)?.1, count)
+                    }
+                    None => {
+                        let pc = cpu.registers.pc;
+                        let mut listing =
+                            disasm::instructions_before(&cpu.bus, pc, LISTING_CONTEXT);
+                        listing.extend(listing_from(cpu, pc, DEFAULT_LISTING - listing.len()));
+                        listing
+                    }
+                };
+                for instruction in &listing {
+                    self.show_line(cpu, instruction, out)?;
+                }
+            }
+            "trace" => match args {
+                "on" => cpu.trace = true,
+                "off" => cpu.trace = false,
+                _ => return Err("Usage: trace on|off".to_string().into()),
+            },
+            "help" | "h" => writeln!(out, "{HELP}")?,
+            "quit" | "q" => return Ok(Flow::Quit),
+            _ => return Err(format!("Unknown command: {name} (try `help`)").into()),
+        }
+        Ok(Flow::Prompt)
+    }
+
+    fn add_breakpoint(
+        &mut self,
+        cpu: &CPU,
+        args: &str,
+        out: &mut dyn Write,
+    ) -> Result<(), CommandError> {
+        let (location, condition) = match args.split_once(" if ") {
+            Some((location, condition)) => (location.trim(), Some(condition.trim())),
+            None => (args, None),
+        };
+        if location.is_empty() {
+            return Err("Usage: break ADDR [if EXPR]".to_string().into());
+        }
+        let (bank, address) = parse_address(cpu, location)?;
+        let condition = match condition {
+            Some(text) => Some((text.to_string(), Expr::parse(text)?)),
+            None => None,
+        };
+        let breakpoint = Breakpoint {
+            id: self.next_id,
+            bank,
+            address,
+            condition,
+        };
+        self.next_id += 1;
+        writeln!(
+            out,
+            "Breakpoint {} at {}: {}",
+            breakpoint.id,
+            breakpoint.location(),
+            disasm::disassemble(&cpu.bus, address).text
+        )?;
+        self.breakpoints.push(breakpoint);
+        Ok(())
+    }
+
+    fn add_watchpoint(
+        &mut self,
+        cpu: &mut CPU,
+        words: &[&str],
+        out: &mut dyn Write,
+    ) -> Result<(), CommandError> {
+        const USAGE: &str = "Usage: watch [read|write|access|change] ADDR[-END] [== BYTE]";
+        let (kind, words) = match words.split_first() {
+            Some((name, rest)) if WatchKind::from_name(name).is_some() => {
+                (WatchKind::from_name(name).unwrap(), rest)
+            }
+            _ => (WatchKind::Write, words),
+        };
+        let (range, value) = match words {
+            [range] => (range, None),
+            [range, "==", value] => (range, Some(parse_byte(value)?)),
+            _ => return Err(USAGE.to_string().into()),
+        };
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (parse_address(cpu, start)?.1, parse_address(cpu, end)?.1),
+            None => (parse_address(cpu, range)?.1, parse_address(cpu, range)?.1),
+        };
+        if end < start {
+            return Err(format!("Empty address range: {range}").into());
+        }
+        let watchpoint = Watchpoint {
+            id: self.next_id,
+            start,
+            end,
+            kind,
+            value,
+        };
+        self.next_id += 1;
+        writeln!(
+            out,
+            "Watchpoint {} ({}) at {range}",
+            watchpoint.id,
+            kind.name()
+        )?;
+        cpu.bus
+            .watchpoints
+            .get_or_insert_with(Watchpoints::new)
+            .add(watchpoint);
+        Ok(())
+    }
+
+    /// Run one instruction, or a whole CALL or RST until it returns.
+    fn step_over(&mut self, cpu: &mut CPU, out: &mut dyn Write) -> io::Result<Stop> {
+        let pc = cpu.registers.pc;
+        let call = disasm::decode_at(&cpu.bus, pc).filter(|instruction| {
+            matches!(instruction, Instruction::CALL(_) | Instruction::RST(_))
+        });
+        match call {
+            Some(instruction) => {
+                let return_address = pc.wrapping_add(disasm::length(&instruction));
+                let sp = cpu.registers.sp;
+                self.run(cpu, out, |cpu, _| {
+                    cpu.registers.pc == return_address && cpu.registers.sp >= sp
+                })
+            }
+            None => self.run(cpu, out, |_, _| true),
+        }
+    }
+
+    /// Step the CPU until `done` returns true, a breakpoint or watchpoint is
+    /// hit or the CPU enters STOP mode. `done` is called after every step, with whether the
+    /// step started on a RET or RETI.
+    ///
+    /// The first step never stops at a breakpoint, so that running from a
+    /// breakpoint moves past it.
+    fn run(
+        &mut self,
+        cpu: &mut CPU,
+        out: &mut dyn Write,
+        mut done: impl FnMut(&CPU, bool) -> bool,
+    ) -> io::Result<Stop> {
+        // Forget accesses made by commands such as `write`
+        if let Some(watchpoints) = &cpu.bus.watchpoints {
+            watchpoints.take_hit();
+        }
+        loop {
+            let pc = cpu.registers.pc;
+            let returning = !cpu.is_halted()
+                && matches!(
+                    disasm::decode_at(&cpu.bus, cpu.registers.pc),
+                    Some(Instruction::RET(_) | Instruction::RETI)
+                );
+            cpu.step();
+            if cpu.bus.has_serial_output() {
+                write!(out, "{}", cpu.bus.get_serial_output())?;
+                cpu.bus.clear_serial_output();
+            }
+            if cpu.is_stopped() {
+                return Ok(Stop::CpuStopped);
+            }
+            let hit = cpu.bus.watchpoints.as_ref().and_then(Watchpoints::take_hit);
+            if let Some(hit) = hit {
+                return Ok(Stop::Watchpoint(hit, pc));
+            }
+            if done(cpu, returning) {
+                return Ok(Stop::Done);
+            }
+            if let Some(id) = self.breakpoint_hit(cpu) {
+                return Ok(Stop::Breakpoint(id));
+            }
+        }
+    }
+
+    fn breakpoint_hit(&self, cpu: &CPU) -> Option<u32> {
+        if cpu.is_halted() {
+            return None;
+        }
+        let pc = cpu.registers.pc;
+        self.breakpoints
+            .iter()
+            .find(|breakpoint| {
+                breakpoint.address == pc
+                    && breakpoint
+                        .bank
+                        .is_none_or(|bank| bank == cpu.bus.bank_at(pc))
+                    && breakpoint
+                        .condition
+                        .as_ref()
+                        .is_none_or(|(_, condition)| condition.eval(cpu) != 0)
+            })
+            .map(|breakpoint| breakpoint.id)
+    }
+
+    fn report(&self, cpu: &CPU, stop: Stop, out: &mut dyn Write) -> io::Result<()> {
+        match stop {
+            Stop::Done => {}
+            Stop::Breakpoint(id) => writeln!(out, "Breakpoint {id}")?,
+            Stop::Watchpoint(hit, pc) => {
+                match hit {
+                    WatchHit::Read { id, address, value } => {
+                        write!(out, "Watchpoint {id}: read {value:02X} from {address:04X}")?
+                    }
+                    WatchHit::Write {
+                        id,
+                        address,
+                        old,
+                        value,
+                    } => write!(
+                        out,
+                        "Watchpoint {id}: write {value:02X} to {address:04X} (was {old:02X})"
+                    )?,
+                }
+                writeln!(out, " by:")?;
+                self.show_line(cpu, &disasm::disassemble(&cpu.bus, pc), out)?;
+            }
+            Stop::CpuStopped => writeln!(out, "CPU entered STOP mode")?,
+        }
+        self.show_location(cpu, out)
+    }
+
+    fn show_location(&self, cpu: &CPU, out: &mut dyn Write) -> io::Result<()> {
+        let instruction = disasm::disassemble(&cpu.bus, cpu.registers.pc);
+        self.show_line(cpu, &instruction, out)?;
+        if cpu.is_halted() {
+            writeln!(out, "(halted)")?;
+        }
+        Ok(())
+    }
+
+    /// One listing line: a marker for PC and breakpoints, the bank-qualified
+    /// address, the instruction bytes and the disassembly.
+    fn show_line(
+        &self,
+        cpu: &CPU,
+        instruction: &Disassembly,
+        out: &mut dyn Write,
+    ) -> io::Result<()> {
+        let address = instruction.address;
+        let marker = if address == cpu.registers.pc {
+            "=>"
+        } else if self.breakpoints.iter().any(|b| b.address == address) {
+            " *"
+        } else {
+            "  "
+        };
+        if let Some(name) = cpu.bus.symbol_at(address) {
+            writeln!(out, "{name}:")?;
+        }
+        let bytes: Vec<String> = (0..instruction.length)
+            .map(|offset| format!("{:02X}", cpu.bus.peek(address.wrapping_add(offset))))
+            .collect();
+        writeln!(
+            out,
+            "{marker} {:02X}:{address:04X}  {:<9} {}",
+            cpu.bus.bank_at(address),
+            bytes.join(" "),
+            instruction.text
+        )
+    }
+}
+
+fn listing_from(cpu: &CPU, mut address: u16, count: usize) -> Vec<Disassembly> {
+    (0..count)
+        .map(|_| {
+            let instruction = disasm::disassemble(&cpu.bus, address);
+            address = address.wrapping_add(instruction.length);
+            instruction
+        })
+        .collect()
+}
+
+/// The current location, then where each function on the call stack was
+/// called or interrupted from, innermost first.
+fn backtrace(cpu: &CPU, out: &mut dyn Write) -> io::Result<()> {
+    let frames = cpu.call_stack.as_ref().map_or(&[][..], CallStack::frames);
+    let locations = std::iter::once((cpu.registers.pc, None)).chain(
+        frames
+            .iter()
+            .rev()
+            .map(|frame| (frame.return_address, Some(frame.kind))),
+    );
+    for (depth, (address, kind)) in locations.enumerate() {
+        write!(
+            out,
+            "#{depth:<3} {:02X}:{address:04X}",
+            cpu.bus.bank_at(address)
+        )?;
+        if let Some(name) = cpu.bus.describe_address(address) {
+            write!(out, "  {name}")?;
+        }
+        if kind == Some(FrameKind::Interrupt) {
+            write!(out, "  (interrupted)")?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn show_registers(cpu: &CPU, out: &mut dyn Write) -> io::Result<()> {
+    let registers = &cpu.registers;
+    let flags = &registers.f;
+    let flag = |set: bool, name: char| if set { name } else { '-' };
+    writeln!(
+        out,
+        "AF={:04X} BC={:04X} DE={:04X} HL={:04X} SP={:04X} PC={:04X} F={}{}{}{} IME={}{}",
+        registers.get_af(),
+        registers.get_bc(),
+        registers.get_de(),
+        registers.get_hl(),
+        registers.sp,
+        registers.pc,
+        flag(flags.zero, 'Z'),
+        flag(flags.subtract, 'N'),
+        flag(flags.half_carry, 'H'),
+        flag(flags.carry, 'C'),
+        cpu.interrupts_enabled as u8,
+        if cpu.is_halted() { " (halted)" } else { "" },
+    )
+}
+
+fn dump(cpu: &CPU, start: u16, count: usize, out: &mut dyn Write) -> io::Result<()> {
+    let bytes: Vec<u8> = (0..count)
+        .map(|offset| cpu.bus.peek(start.wrapping_add(offset as u16)))
+        .collect();
+    for (row, chunk) in bytes.chunks(16).enumerate() {
+        let address = start.wrapping_add(row as u16 * 16);
+        let hex: Vec<String> = chunk.iter().map(|byte| format!("{byte:02X}")).collect();
+        writeln!(out, "{address:04X}: {}", hex.join(" "))?;
+    }
+    Ok(())
+}
+
+/// A hexadecimal number with an optional `$` or `0x` prefix.
+fn parse_hex(text: &str) -> Result<u16, String> {
+    let digits = text
+        .strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid hexadecimal number: {text}"))
+}
+
+fn parse_byte(text: &str) -> Result<u8, String> {
+    u8::try_from(parse_hex(text)?).map_err(|_| format!("Not a byte: {text}"))
+}
+
+/// A symbol, `ADDRESS` or `BANK:ADDRESS`. Symbols come first, since names
+/// such as `Add` are valid hexadecimal too.
+fn parse_address(cpu: &CPU, text: &str) -> Result<(Option<u16>, u16), String> {
+    let symbol = cpu.bus.symbols.as_ref().and_then(|s| s.address_of(text));
+    if let Some((bank, address)) = symbol {
+        return Ok((Some(bank), address));
+    }
+    match text.split_once(':') {
+        Some((bank, address)) => Ok((Some(parse_hex(bank)?), parse_hex(address)?)),
+        None => Ok((None, parse_hex(text)?)),
+    }
+}
+
+fn parse_count(text: Option<&&str>, default: u64) -> Result<u64, String> {
+    match text {
+        Some(text) => text.parse().map_err(|_| format!("Invalid count: {text}")),
+        None => Ok(default),
+    }
+}
+
+/// A count of bytes or instructions, at most [`MAX_LISTING`].
+fn parse_listing_count(text: Option<&&str>, default: usize) -> Result<usize, String> {
+    let count = parse_count(text, default as u64)?;
+    if count > MAX_LISTING as u64 {
+        return Err(format!("Count too large: {count} (at most {MAX_LISTING})"));
+    }
+    Ok(count as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::cpu_with_program;
+    use crate::model::Model;
+    use symbols::Symbols;
+
+    // 0150: call $0158; inc b; jr $0150
+    // 0158: inc c; ret
+    pub(super) fn program_cpu() -> CPU {
+        let mut program = [0u8; 0x5A];
+        program[..0x04].copy_from_slice(&[0xC3, 0x50, 0x01, 0x00]); // jp $0150
+        program[0x50..0x56].copy_from_slice(&[0xCD, 0x58, 0x01, 0x04, 0x18, 0xFA]);
+        program[0x58..].copy_from_slice(&[0x0C, 0xC9]);
+        cpu_with_program(Model::Dmg, &program)
+    }
+
+    fn execute(debugger: &mut Debugger, cpu: &mut CPU, line: &str) -> String {
+        let mut out = Vec::new();
+        debugger.execute(cpu, line, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_step_next_and_finish() {
+        let mut cpu = program_cpu();
+        let mut debugger = Debugger::new();
+        execute(&mut debugger, &mut cpu, "step 2");
+        assert_eq!(cpu.registers.pc, 0x0158);
+
+        let output = execute(&mut debugger, &mut cpu, "finish");
+        assert_eq!(cpu.registers.pc, 0x0153);
+        assert!(output.contains("=> 00:0153  04        inc b"), "{output}");
+
+        execute(&mut debugger, &mut cpu, "n 2");
+        execute(&mut debugger, &mut cpu, "next");
+        assert_eq!(cpu.registers.pc, 0x0153);
+        assert_eq!(cpu.registers.c, 0x13 + 2);
+        // An empty line repeats the last command
+        execute(&mut debugger, &mut cpu, "");
+        assert_eq!(cpu.registers.pc, 0x0154);
+    }
+
+    #[test]
+    fn test_conditional_and_bank_qualified_breakpoints() {
+        let mut cpu = program_cpu();
+        let mut debugger = Debugger::new();
+        // Without an MBC, 0x0000-0x3FFF is always bank 0
+        execute(&mut debugger, &mut cpu, "break 01:0158");
+        let output = execute(&mut debugger, &mut cpu, "continue 1");
+        assert!(!output.contains("Breakpoint"), "{output}");
+        assert_eq!(cpu.bus.gpu.frame_count(), 1);
+
+        execute(&mut debugger, &mut cpu, "break $0158 if c == $15");
+        let output = execute(&mut debugger, &mut cpu, "c");
+        assert!(output.starts_with("Breakpoint 2"), "{output}");
+        assert_eq!(cpu.registers.pc, 0x0158);
+        assert_eq!(cpu.registers.c, 0x15);
+
+        let output = execute(&mut debugger, &mut cpu, "bl");
+        assert_eq!(output, "1: 01:0158\n2: 0158 if c == $15\n");
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut cpu = program_cpu();
+        let mut debugger = Debugger::new();
+        execute(&mut debugger, &mut cpu, "step");
+        execute(&mut debugger, &mut cpu, "watch change FFFC-FFFD");
+        // The return address pushed by the call
+        let output = execute(&mut debugger, &mut cpu, "continue 1");
+        assert!(
+            output.starts_with(
+                "Watchpoint 1: write 01 to FFFD (was 00) by:\n   00:0150  CD 58 01  call $0158\n"
+            ),
+            "{output}"
+        );
+        // The same return address is pushed again, which changes nothing
+        let output = execute(&mut debugger, &mut cpu, "continue 1");
+        assert!(!output.contains("Watchpoint"), "{output}");
+
+        execute(&mut debugger, &mut cpu, "wa read 0159 == C9");
+        let output = execute(&mut debugger, &mut cpu, "c");
+        assert!(
+            output.starts_with("Watchpoint 2: read C9 from 0159"),
+            "{output}"
+        );
+        // Execution stops after the instruction that made the access
+        assert!(output.contains("00:0159  C9        ret\n"), "{output}");
+        assert_eq!(cpu.registers.pc, 0x0153);
+
+        assert_eq!(
+            execute(&mut debugger, &mut cpu, "bl"),
+            "1: watch change FFFC-FFFD\n2: watch read 0159 == C9\n"
+        );
+        execute(&mut debugger, &mut cpu, "d 1");
+        execute(&mut debugger, &mut cpu, "d 2");
+        assert!(cpu.bus.watchpoints.is_none());
+    }
+
+    #[test]
+    fn test_symbols() {
+        let mut cpu = program_cpu();
+        cpu.bus.symbols = Some(Symbols::parse("00:0150 Main\n00:0158 Delay\n").unwrap());
+        cpu.call_stack = Some(CallStack::new());
+        let mut debugger = Debugger::new();
+        assert_eq!(
+            execute(&mut debugger, &mut cpu, "break Delay"),
+            "Breakpoint 1 at 00:0158: inc c\n"
+        );
+        let output = execute(&mut debugger, &mut cpu, "continue");
+        assert!(output.contains("Delay:\n=> 00:0158"), "{output}");
+        assert_eq!(
+            execute(&mut debugger, &mut cpu, "bt"),
+            "#0   00:0158  Delay\n#1   00:0153  Main+3\n"
+        );
+
+        let output = execute(&mut debugger, &mut cpu, "disasm Main 3");
+        assert_eq!(
+            output,
+            "Main:\n   00:0150  CD 58 01  call Delay\n   00:0153  04        inc b\n   00:0154  18 FA     jr Main\n"
+        );
+    }
+
+    #[test]
+    fn test_registers_memory_and_errors() {
+        let mut cpu = program_cpu();
+        let mut debugger = Debugger::new();
+        execute(&mut debugger, &mut cpu, "set hl C000");
+        execute(&mut debugger, &mut cpu, "write $C000 12 34");
+        assert_eq!(
+            execute(&mut debugger, &mut cpu, "x C000 3"),
+            "C000: 12 34 00\n"
+        );
+        assert!(execute(&mut debugger, &mut cpu, "r").contains("HL=C000"));
+        assert_eq!(
+            execute(&mut debugger, &mut cpu, "set ix 1"),
+            "Unknown register: ix\n"
+        );
+        assert_eq!(
+            execute(&mut debugger, &mut cpu, "break 150 if a =="),
+            "Missing operand in expression\n"
+        );
+        assert_eq!(
+            execute(&mut debugger, &mut cpu, "x 0 65537"),
+            "Count too large: 65537 (at most 65536)\n"
+        );
+        assert_eq!(
+            execute(&mut debugger, &mut cpu, "disasm 0 99999999999"),
+            "Count too large: 99999999999 (at most 65536)\n"
+        );
+        assert_eq!(
+            execute(&mut debugger, &mut cpu, "x 0 65536")
+                .lines()
+                .count(),
+            4096
+        );
+    }
+}
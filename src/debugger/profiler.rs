@@ -0,0 +1,256 @@
+//! Exact cycle profiler attributing T-cycles to functions.
+//!
+//! After every [`CPU::step`] the profiler charges the step's T-cycles to
+//! the innermost frame of the shadow call stack (see [`call_stack`]), so an
+//! instruction counts towards the function running after it: a CALL is
+//! charged to the callee and a RET to the caller. Cycles are kept per call
+//! path in a tree, from which it builds:
+//!
+//! - a flat report of self and inclusive cycles and call counts per
+//!   function, where recursive calls count once towards inclusive cycles;
+//! - folded stacks (`root;Main;DrawSprites 1234` per line), the input of
+//!   `flamegraph.pl` and compatible viewers.
+//!
+//! Cycles spent outside any tracked call are charged to `(root)`.
+//!
+//! [`CPU::step`]: crate::cpu::CPU::step
+//! [`call_stack`]: super::call_stack
+
+use crate::debugger::call_stack::{self, CallStack};
+use crate::memory_bus::MemoryBus;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+const ROOT: usize = 0;
+const ROOT_NAME: &str = "(root)";
+
+/// A call path: the function entered from its parent's path.
+struct Node {
+    /// Bank and address of the function; unused for the root.
+    function: (u16, u16),
+    parent: usize,
+    children: HashMap<(u16, u16), usize>,
+    calls: u64,
+    cycles: u64,
+}
+
+/// Totals of one function over all its call paths.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct FunctionTotals {
+    self_cycles: u64,
+    inclusive_cycles: u64,
+    calls: u64,
+}
+
+pub struct Profiler {
+    nodes: Vec<Node>,
+    /// The frame serials of the last stack seen, with their nodes.
+    path: Vec<(u64, usize)>,
+    total_cycles: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            nodes: vec![Node {
+                function: (0, 0),
+                parent: ROOT,
+                children: HashMap::new(),
+                calls: 0,
+                cycles: 0,
+            }],
+            path: Vec::new(),
+            total_cycles: 0,
+        }
+    }
+
+    /// Charge `cycles` to the innermost function on `call_stack`.
+    pub fn record(&mut self, call_stack: &CallStack, cycles: u64) {
+        let frames = call_stack.frames();
+        let unchanged = self
+            .path
+            .iter()
+            .zip(frames)
+            .take_while(|((serial, _), frame)| *serial == frame.serial)
+            .count();
+        self.path.truncate(unchanged);
+        for frame in &frames[unchanged..] {
+            let parent = self.path.last().map_or(ROOT, |&(_, node)| node);
+            let node = self.child(parent, (frame.bank, frame.function));
+            self.nodes[node].calls += 1;
+            self.path.push((frame.serial, node));
+        }
+        let current = self.path.last().map_or(ROOT, |&(_, node)| node);
+        self.nodes[current].cycles += cycles;
+        self.total_cycles += cycles;
+    }
+
+    fn child(&mut self, parent: usize, function: (u16, u16)) -> usize {
+        if let Some(&node) = self.nodes[parent].children.get(&function) {
+            return node;
+        }
+        let node = self.nodes.len();
+        self.nodes.push(Node {
+            function,
+            parent,
+            children: HashMap::new(),
+            calls: 0,
+            cycles: 0,
+        });
+        self.nodes[parent].children.insert(function, node);
+        node
+    }
+
+    /// The functions on the path to `node`, innermost first, excluding the
+    /// root.
+    fn ancestry(&self, mut node: usize) -> Vec<(u16, u16)> {
+        let mut functions = Vec::new();
+        while node != ROOT {
+            functions.push(self.nodes[node].function);
+            node = self.nodes[node].parent;
+        }
+        functions
+    }
+
+    /// Per-function totals; `None` is the root.
+    fn totals(&self) -> HashMap<Option<(u16, u16)>, FunctionTotals> {
+        let mut totals: HashMap<Option<(u16, u16)>, FunctionTotals> = HashMap::new();
+        let root = totals.entry(None).or_default();
+        root.self_cycles = self.nodes[ROOT].cycles;
+        root.inclusive_cycles = self.total_cycles;
+        for (index, node) in self.nodes.iter().enumerate().skip(1) {
+            let own = totals.entry(Some(node.function)).or_default();
+            own.self_cycles += node.cycles;
+            own.calls += node.calls;
+            let mut seen = Vec::new();
+            for function in self.ancestry(index) {
+                if !seen.contains(&function) {
+                    seen.push(function);
+                    totals.entry(Some(function)).or_default().inclusive_cycles += node.cycles;
+                }
+            }
+        }
+        totals
+    }
+
+    /// A table of every function's self and inclusive cycles, busiest first.
+    pub fn flat_report(&self, bus: &MemoryBus) -> String {
+        let mut rows: Vec<_> = self
+            .totals()
+            .into_iter()
+            .map(|(function, totals)| (name(bus, function), totals))
+            .collect();
+        rows.sort_by(|(a_name, a), (b_name, b)| {
+            (b.self_cycles, b.inclusive_cycles)
+                .cmp(&(a.self_cycles, a.inclusive_cycles))
+                .then_with(|| a_name.cmp(b_name))
+        });
+        let percent = |cycles: u64| cycles as f64 * 100.0 / self.total_cycles.max(1) as f64;
+        let mut report = format!(
+            "{:>12} {:>7} {:>12} {:>7} {:>8}  function\n",
+            "self", "%", "inclusive", "%", "calls"
+        );
+        for (name, totals) in rows {
+            let _ = writeln!(
+                report,
+                "{:>12} {:>6.2}% {:>12} {:>6.2}% {:>8}  {name}",
+                totals.self_cycles,
+                percent(totals.self_cycles),
+                totals.inclusive_cycles,
+                percent(totals.inclusive_cycles),
+                totals.calls,
+            );
+        }
+        let _ = writeln!(report, "{:>12} T-cycles in total", self.total_cycles);
+        report
+    }
+
+    /// One line per call path with cycles of its own: the functions from
+    /// the root separated by `;`, a space and the cycles.
+    pub fn folded_stacks(&self, bus: &MemoryBus) -> String {
+        let mut lines: Vec<String> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.cycles > 0)
+            .map(|(index, node)| {
+                let mut names = vec![ROOT_NAME.to_string()];
+                names.extend(
+                    self.ancestry(index)
+                        .into_iter()
+                        .rev()
+                        .map(|function| name(bus, Some(function))),
+                );
+                format!("{} {}", names.join(";"), node.cycles)
+            })
+            .collect();
+        lines.sort();
+        lines.iter().map(|line| format!("{line}\n")).collect()
+    }
+}
+
+fn name(bus: &MemoryBus, function: Option<(u16, u16)>) -> String {
+    match function {
+        Some((bank, address)) => call_stack::function_name(bus, bank, address),
+        None => ROOT_NAME.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::call_stack::FrameKind;
+    use crate::debugger::symbols::Symbols;
+
+    #[test]
+    fn test_self_inclusive_and_folded() {
+        let mut bus = MemoryBus::new(vec![0; 0x8000]);
+        bus.symbols = Some(Symbols::parse("00:0200 Main\n00:0300 Draw\n").unwrap());
+        let mut stack = CallStack::new();
+        let mut profiler = Profiler::new();
+
+        profiler.record(&stack, 4);
+        stack.push(FrameKind::Call, 0, 0x0200, 0x0150, 0xFFFC);
+        profiler.record(&stack, 24);
+        // Draw calls itself once
+        stack.push(FrameKind::Call, 0, 0x0300, 0x0203, 0xFFFA);
+        profiler.record(&stack, 24);
+        stack.push(FrameKind::Call, 0, 0x0300, 0x0310, 0xFFF8);
+        profiler.record(&stack, 24);
+        stack.pop(0xFFF8);
+        stack.pop(0xFFFA);
+        profiler.record(&stack, 16);
+        // A second call of Draw from Main
+        stack.push(FrameKind::Call, 0, 0x0300, 0x0206, 0xFFFA);
+        profiler.record(&stack, 24);
+        stack.push(FrameKind::Interrupt, 0, 0x0040, 0x0300, 0xFFF8);
+        profiler.record(&stack, 20);
+
+        assert_eq!(
+            profiler.folded_stacks(&bus),
+            "(root) 4\n\
+             (root);Main 40\n\
+             (root);Main;Draw 48\n\
+             (root);Main;Draw;00:0040 20\n\
+             (root);Main;Draw;Draw 24\n"
+        );
+
+        let totals = profiler.totals();
+        let draw = totals[&Some((0, 0x0300))];
+        assert_eq!(
+            draw,
+            FunctionTotals {
+                self_cycles: 72,
+                inclusive_cycles: 92,
+                calls: 3
+            }
+        );
+        assert_eq!(totals[&None].inclusive_cycles, 136);
+
+        let report = profiler.flat_report(&bus);
+        let lines: Vec<_> = report.lines().collect();
+        assert!(lines[1].ends_with("Draw"), "{report}");
+        assert!(lines[1].contains(" 52.94% "), "{report}");
+        assert_eq!(lines.last().unwrap().trim(), "136 T-cycles in total");
+    }
+}
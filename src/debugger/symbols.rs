@@ -0,0 +1,176 @@
+//! Symbol files naming ROM and RAM addresses, as written by `rgblink -n`
+//! and read by no$gmb and BGB.
+//!
+//! Each line is `BANK:ADDRESS NAME` in hexadecimal, such as
+//! `01:4000 Main.loop`; `;` starts a comment and `[section]` headers are
+//! skipped. Symbols are keyed by bank and address, so the same address in
+//! two ROM banks can carry different names; lookups through the bus use the
+//! bank currently mapped there (see [`MemoryBus::bank_at`]).
+//!
+//! [`MemoryBus::bank_at`]: crate::memory_bus::MemoryBus::bank_at
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+
+#[derive(Debug)]
+pub enum SymbolError {
+    Io(io::Error),
+    /// A line that is not `BANK:ADDRESS NAME`, numbered from 1.
+    Syntax {
+        line: usize,
+        text: String,
+    },
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SymbolError::Io(e) => write!(f, "{e}"),
+            SymbolError::Syntax { line, text } => {
+                write!(
+                    f,
+                    "line {line}: expected `BANK:ADDRESS NAME`, found `{text}`"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for SymbolError {}
+
+impl From<io::Error> for SymbolError {
+    fn from(e: io::Error) -> Self {
+        SymbolError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Symbols {
+    /// The first name given to each bank and address.
+    names: BTreeMap<(u16, u16), String>,
+    addresses: HashMap<String, (u16, u16)>,
+}
+
+impl Symbols {
+    pub fn load(path: &str) -> Result<Symbols, SymbolError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Symbols, SymbolError> {
+        let mut symbols = Symbols::default();
+        for (index, line) in text.lines().enumerate() {
+            let content = line.split(';').next().unwrap_or("").trim();
+            if content.is_empty() || content.starts_with('[') {
+                continue;
+            }
+            let syntax_error = || SymbolError::Syntax {
+                line: index + 1,
+                text: line.to_string(),
+            };
+            let mut fields = content.split_whitespace();
+            let (Some(location), Some(name), None) = (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(syntax_error());
+            };
+            let (bank, address) = location.split_once(':').ok_or_else(syntax_error)?;
+            let bank = u16::from_str_radix(bank, 16).map_err(|_| syntax_error())?;
+            let address = u16::from_str_radix(address, 16).map_err(|_| syntax_error())?;
+            symbols
+                .names
+                .entry((bank, address))
+                .or_insert_with(|| name.to_string());
+            symbols.addresses.insert(name.to_string(), (bank, address));
+        }
+        Ok(symbols)
+    }
+
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    /// The bank and address of the symbol called `name`.
+    pub fn address_of(&self, name: &str) -> Option<(u16, u16)> {
+        self.addresses.get(name).copied()
+    }
+
+    /// The name of exactly this bank and address.
+    pub fn name_at(&self, bank: u16, address: u16) -> Option<&str> {
+        self.names.get(&(bank, address)).map(String::as_str)
+    }
+
+    /// The closest symbol at or before `address` in the same bank and memory
+    /// region, as `NAME` or `NAME+OFFSET` with a hexadecimal offset.
+    pub fn describe(&self, bank: u16, address: u16) -> Option<String> {
+        let (&(_, start), name) = self
+            .names
+            .range((bank, 0)..=(bank, address))
+            .next_back()
+            .filter(|((_, start), _)| region(*start) == region(address))?;
+        Some(match address - start {
+            0 => name.clone(),
+            offset => format!("{name}+{offset:X}"),
+        })
+    }
+}
+
+/// Regions of the address map a label can extend over: ROM0, ROMX, VRAM,
+/// cartridge RAM, WRAM0, WRAMX, echo RAM to I/O, and HRAM.
+fn region(address: u16) -> u8 {
+    match address {
+        0x0000..=0x3FFF => 0,
+        0x4000..=0x7FFF => 1,
+        0x8000..=0x9FFF => 2,
+        0xA000..=0xBFFF => 3,
+        0xC000..=0xCFFF => 4,
+        0xD000..=0xDFFF => 5,
+        0xE000..=0xFF7F => 6,
+        0xFF80..=0xFFFF => 7,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYM: &str = "\
+; File generated by rgblink
+00:0150 Start
+00:0150 Start.init
+00:0158 Delay ; busy loop
+01:4000 Main
+02:4000 Music
+[labels]
+00:c000 wCounter
+";
+
+    #[test]
+    fn test_parse_and_lookup() {
+        let symbols = Symbols::parse(SYM).unwrap();
+        assert_eq!(symbols.len(), 6);
+        assert_eq!(symbols.address_of("Start.init"), Some((0, 0x0150)));
+        assert_eq!(symbols.address_of("Music"), Some((2, 0x4000)));
+        assert_eq!(symbols.name_at(0, 0x0150), Some("Start"));
+        assert_eq!(symbols.name_at(1, 0x4000), Some("Main"));
+
+        assert_eq!(symbols.describe(0, 0x0157).as_deref(), Some("Start+7"));
+        assert_eq!(symbols.describe(2, 0x4010).as_deref(), Some("Music+10"));
+        assert_eq!(symbols.describe(0, 0xC000).as_deref(), Some("wCounter"));
+        // Labels do not extend past their bank or region
+        assert_eq!(symbols.describe(3, 0x4010), None);
+        assert_eq!(symbols.describe(0, 0x8000), None);
+        assert_eq!(symbols.describe(0, 0x0100), None);
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        let error = Symbols::parse("00:0150 Start\n0150 Main\n").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 2: expected `BANK:ADDRESS NAME`, found `0150 Main`"
+        );
+        assert!(Symbols::parse("00:0150 Start extra").is_err());
+        assert!(Symbols::parse("zz:0150 Start").is_err());
+    }
+}
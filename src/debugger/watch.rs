@@ -0,0 +1,195 @@
+//! Memory watchpoints, checked by the bus on every CPU read and write.
+//!
+//! A watchpoint covers an address range and triggers on reads, writes, either,
+//! or writes that change the stored value, optionally only when the byte read
+//! or written equals a given value. The bus records the first hit of each CPU
+//! step and the debugger picks it up after the step, so the instruction
+//! responsible is the one the step started on.
+//!
+//! The bus only holds a [`Watchpoints`] while at least one is set; otherwise
+//! each access costs a single `None` check.
+
+use core::cell::Cell;
+
+/// Which accesses trigger a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+    Change,
+}
+
+impl WatchKind {
+    pub fn from_name(name: &str) -> Option<WatchKind> {
+        match name {
+            "read" => Some(WatchKind::Read),
+            "write" => Some(WatchKind::Write),
+            "access" => Some(WatchKind::Access),
+            "change" => Some(WatchKind::Change),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::Access => "access",
+            WatchKind::Change => "change",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub id: u32,
+    pub start: u16,
+    pub end: u16, // inclusive
+    pub kind: WatchKind,
+    pub value: Option<u8>,
+}
+
+/// An access that triggered a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchHit {
+    Read {
+        id: u32,
+        address: u16,
+        value: u8,
+    },
+    Write {
+        id: u32,
+        address: u16,
+        old: u8,
+        value: u8,
+    },
+}
+
+pub struct Watchpoints {
+    list: Vec<Watchpoint>,
+    hit: Cell<Option<WatchHit>>,
+}
+
+impl Watchpoints {
+    pub fn new() -> Self {
+        Watchpoints {
+            list: Vec::new(),
+            hit: Cell::new(None),
+        }
+    }
+
+    pub fn add(&mut self, watchpoint: Watchpoint) {
+        self.list.push(watchpoint);
+    }
+
+    /// Remove watchpoint `id`, returning whether it existed.
+    pub fn remove(&mut self, id: u32) -> bool {
+        let before = self.list.len();
+        self.list.retain(|watchpoint| watchpoint.id != id);
+        self.list.len() != before
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Watchpoint> {
+        self.list.iter()
+    }
+
+    pub fn check_read(&self, address: u16, value: u8) {
+        let triggered = self.list.iter().find(|watchpoint| {
+            matches!(watchpoint.kind, WatchKind::Read | WatchKind::Access)
+                && watchpoint.matches(address, value)
+        });
+        if let Some(watchpoint) = triggered {
+            self.record(WatchHit::Read {
+                id: watchpoint.id,
+                address,
+                value,
+            });
+        }
+    }
+
+    pub fn check_write(&self, address: u16, old: u8, value: u8) {
+        let triggered = self.list.iter().find(|watchpoint| {
+            let kind_matches = match watchpoint.kind {
+                WatchKind::Read => false,
+                WatchKind::Write | WatchKind::Access => true,
+                WatchKind::Change => old != value,
+            };
+            kind_matches && watchpoint.matches(address, value)
+        });
+        if let Some(watchpoint) = triggered {
+            self.record(WatchHit::Write {
+                id: watchpoint.id,
+                address,
+                old,
+                value,
+            });
+        }
+    }
+
+    /// The first hit since the last call, if any.
+    pub fn take_hit(&self) -> Option<WatchHit> {
+        self.hit.take()
+    }
+
+    fn record(&self, hit: WatchHit) {
+        if self.hit.get().is_none() {
+            self.hit.set(Some(hit));
+        }
+    }
+}
+
+impl Watchpoint {
+    fn matches(&self, address: u16, value: u8) -> bool {
+        (self.start..=self.end).contains(&address) && self.value.is_none_or(|v| v == value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kinds_ranges_and_values() {
+        let mut watchpoints = Watchpoints::new();
+        watchpoints.add(Watchpoint {
+            id: 1,
+            start: 0xC000,
+            end: 0xC00F,
+            kind: WatchKind::Change,
+            value: None,
+        });
+        watchpoints.add(Watchpoint {
+            id: 2,
+            start: 0xFF44,
+            end: 0xFF44,
+            kind: WatchKind::Read,
+            value: Some(0x90),
+        });
+
+        watchpoints.check_write(0xC010, 0, 1);
+        watchpoints.check_write(0xC00F, 1, 1);
+        watchpoints.check_read(0xFF44, 0x8F);
+        assert_eq!(watchpoints.take_hit(), None);
+
+        watchpoints.check_read(0xFF44, 0x90);
+        watchpoints.check_write(0xC000, 0, 1);
+        // Only the first hit is kept until it is taken
+        assert_eq!(
+            watchpoints.take_hit(),
+            Some(WatchHit::Read {
+                id: 2,
+                address: 0xFF44,
+                value: 0x90
+            })
+        );
+        assert_eq!(watchpoints.take_hit(), None);
+
+        assert!(watchpoints.remove(2));
+        assert!(!watchpoints.remove(2));
+    }
+}
@@ -0,0 +1,240 @@
+//! CGB VRAM DMA (HDMA1-HDMA5, 0xFF51-0xFF55).
+//!
+//! Copies 16-byte blocks from ROM or RAM into the selected VRAM bank, in one
+//! of two modes chosen by bit 7 of the HDMA5 write:
+//!
+//! - General-purpose DMA copies every block at once, halting the CPU until
+//!   the transfer is done.
+//! - HBlank DMA copies one block at the start of each HBlank on lines 0-143,
+//!   halting the CPU only while that block is copied. Writing HDMA5 with bit 7
+//!   clear during the transfer cancels it.
+//!
+//! A block takes 8 M-cycles at normal speed and 16 in double-speed mode. The
+//! bus performs the copies; this module tracks the registers and progress.
+//!
+//! Reference: [Pan Docs — CGB Registers](https://gbdev.io/pandocs/CGB_Registers.html#lcd-vram-dma-transfers)
+
+use crate::save_state::{Snapshot, StateError, StateReader, StateWriter};
+
+pub const HDMA1_REGISTER: u16 = 0xFF51;
+pub const HDMA2_REGISTER: u16 = 0xFF52;
+pub const HDMA3_REGISTER: u16 = 0xFF53;
+pub const HDMA4_REGISTER: u16 = 0xFF54;
+pub const HDMA5_REGISTER: u16 = 0xFF55;
+
+const BLOCK_SIZE: u8 = 0x10;
+const HBLANK_MODE_BIT: u8 = 0b1000_0000;
+const LENGTH_MASK: u8 = 0x7F;
+const ADDRESS_LOW_MASK: u8 = 0xF0;
+const DESTINATION_HIGH_MASK: u8 = 0x1F;
+/// HDMA5 once a transfer has completed.
+const HDMA5_IDLE: u8 = 0xFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Idle,
+    GeneralPurpose,
+    HBlank,
+}
+
+pub struct Hdma {
+    source: u16,      // HDMA1/HDMA2, low 4 bits ignored
+    destination: u16, // HDMA3/HDMA4 as an offset into VRAM (0x0000-0x1FF0)
+    mode: Mode,
+    blocks_left: u8, // blocks not yet started, including the one being copied
+    block_bytes: u8, // bytes left in the block being copied (0 = not copying)
+    idle_status: u8, // HDMA5 read value while no transfer is running
+}
+
+impl Hdma {
+    pub fn new() -> Self {
+        Hdma {
+            source: 0,
+            destination: 0,
+            mode: Mode::Idle,
+            blocks_left: 0,
+            block_bytes: 0,
+            idle_status: HDMA5_IDLE,
+        }
+    }
+
+    /// HDMA1-HDMA4 are write-only and read 0xFF; HDMA5 reads the number of
+    /// blocks left minus one, with bit 7 set when no transfer is active.
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            HDMA5_REGISTER if self.mode != Mode::Idle => self.blocks_left.wrapping_sub(1),
+            HDMA5_REGISTER => self.idle_status,
+            _ => 0xFF,
+        }
+    }
+
+    /// Write a register. `lcd_enabled` is needed because an HBlank transfer
+    /// started with the LCD off copies its first block immediately.
+    pub fn write(&mut self, address: u16, value: u8, lcd_enabled: bool) {
+        match address {
+            HDMA1_REGISTER => self.source = (self.source & 0x00FF) | (value as u16) << 8,
+            HDMA2_REGISTER => {
+                self.source = (self.source & 0xFF00) | (value & ADDRESS_LOW_MASK) as u16
+            }
+            HDMA3_REGISTER => {
+                let high = (value & DESTINATION_HIGH_MASK) as u16;
+                self.destination = (self.destination & 0x00FF) | high << 8;
+            }
+            HDMA4_REGISTER => {
+                let low = (value & ADDRESS_LOW_MASK) as u16;
+                self.destination = (self.destination & 0xFF00) | low;
+            }
+            HDMA5_REGISTER => self.write_control(value, lcd_enabled),
+            _ => {}
+        }
+    }
+
+    fn write_control(&mut self, value: u8, lcd_enabled: bool) {
+        if self.mode == Mode::HBlank && value & HBLANK_MODE_BIT == 0 {
+            // Cancel: HDMA5 keeps the remaining length with bit 7 set
+            self.mode = Mode::Idle;
+            self.idle_status = HBLANK_MODE_BIT | self.blocks_left.wrapping_sub(1);
+            return;
+        }
+
+        self.blocks_left = (value & LENGTH_MASK) + 1;
+        if value & HBLANK_MODE_BIT != 0 {
+            self.mode = Mode::HBlank;
+            if !lcd_enabled {
+                self.start_block();
+            }
+        } else {
+            self.mode = Mode::GeneralPurpose;
+            self.start_block();
+        }
+    }
+
+    /// Called when the PPU enters HBlank on a visible line.
+    pub fn on_hblank(&mut self) {
+        if self.mode == Mode::HBlank && self.block_bytes == 0 {
+            self.start_block();
+        }
+    }
+
+    /// Whether a block is being copied (the CPU is halted meanwhile).
+    pub fn is_copying(&self) -> bool {
+        self.block_bytes > 0
+    }
+
+    /// Advance the copy by one byte, returning the source address and the
+    /// VRAM offset to copy it to.
+    pub fn next_transfer(&mut self) -> Option<(u16, u16)> {
+        if self.block_bytes == 0 {
+            return None;
+        }
+        let transfer = (self.source, self.destination);
+        self.source = self.source.wrapping_add(1);
+        self.destination = (self.destination + 1) & 0x1FFF;
+        self.block_bytes -= 1;
+        if self.block_bytes == 0 {
+            self.finish_block();
+        }
+        Some(transfer)
+    }
+
+    fn start_block(&mut self) {
+        self.block_bytes = BLOCK_SIZE;
+    }
+
+    fn finish_block(&mut self) {
+        self.blocks_left = self.blocks_left.saturating_sub(1);
+        if self.blocks_left == 0 {
+            self.mode = Mode::Idle;
+            self.idle_status = HDMA5_IDLE;
+        } else if self.mode == Mode::GeneralPurpose {
+            self.start_block();
+        }
+    }
+}
+
+impl Snapshot for Hdma {
+    fn save_to(&self, state: &mut StateWriter) {
+        state.u16(self.source);
+        state.u16(self.destination);
+        state.u8(self.mode as u8);
+        state.u8(self.blocks_left);
+        state.u8(self.block_bytes);
+        state.u8(self.idle_status);
+    }
+
+    fn load_from(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.source = state.u16()?;
+        self.destination = state.u16()?;
+        self.mode = match state.u8()? {
+            0 => Mode::Idle,
+            1 => Mode::GeneralPurpose,
+            2 => Mode::HBlank,
+            _ => return Err(StateError::Corrupt("invalid VRAM DMA mode")),
+        };
+        self.blocks_left = state.u8()?;
+        self.block_bytes = state.u8()?;
+        self.idle_status = state.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set_addresses(hdma: &mut Hdma, source: u16, destination: u16) {
+        hdma.write(HDMA1_REGISTER, (source >> 8) as u8, true);
+        hdma.write(HDMA2_REGISTER, source as u8, true);
+        hdma.write(HDMA3_REGISTER, (destination >> 8) as u8, true);
+        hdma.write(HDMA4_REGISTER, destination as u8, true);
+    }
+
+    fn copy_block(hdma: &mut Hdma) -> Vec<(u16, u16)> {
+        std::iter::from_fn(|| hdma.next_transfer())
+            .take(16)
+            .collect()
+    }
+
+    #[test]
+    fn test_general_purpose_copies_all_blocks() {
+        let mut hdma = Hdma::new();
+        set_addresses(&mut hdma, 0xC12F, 0x8A0F); // low nibbles are ignored
+        hdma.write(HDMA5_REGISTER, 0x01, true);
+        assert_eq!(hdma.read(HDMA5_REGISTER), 0x01);
+
+        let transfers: Vec<_> = std::iter::from_fn(|| hdma.next_transfer()).collect();
+        assert_eq!(transfers.len(), 32);
+        assert_eq!(transfers[0], (0xC120, 0x0A00));
+        assert_eq!(transfers[31], (0xC13F, 0x0A1F));
+        assert_eq!(hdma.read(HDMA5_REGISTER), 0xFF);
+    }
+
+    #[test]
+    fn test_hblank_mode_copies_one_block_per_hblank() {
+        let mut hdma = Hdma::new();
+        set_addresses(&mut hdma, 0x4000, 0x9000);
+        hdma.write(HDMA5_REGISTER, 0x82, true);
+        assert!(!hdma.is_copying());
+        assert_eq!(hdma.read(HDMA5_REGISTER), 0x02);
+
+        hdma.on_hblank();
+        assert_eq!(copy_block(&mut hdma)[0], (0x4000, 0x1000));
+        assert!(!hdma.is_copying());
+        assert_eq!(hdma.read(HDMA5_REGISTER), 0x01);
+
+        // Cancelled: bit 7 set, remaining length kept, no more copies
+        hdma.write(HDMA5_REGISTER, 0x00, true);
+        assert_eq!(hdma.read(HDMA5_REGISTER), 0x81);
+        hdma.on_hblank();
+        assert!(!hdma.is_copying());
+    }
+
+    #[test]
+    fn test_hblank_mode_with_lcd_off_copies_first_block() {
+        let mut hdma = Hdma::new();
+        hdma.write(HDMA5_REGISTER, 0x80, false);
+        assert!(hdma.is_copying());
+        assert_eq!(copy_block(&mut hdma).len(), 16);
+        assert_eq!(hdma.read(HDMA5_REGISTER), 0xFF);
+    }
+}
@@ -21,6 +21,8 @@
 //!
 //! Reference: [Pan Docs — Interrupts](https://gbdev.io/pandocs/Interrupts.html)
 
+use crate::save_state::{Snapshot, StateError, StateReader, StateWriter};
+
 /// The 5 Game Boy interrupts in priority order.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)] //Represent as u8 for easy bit manipulation
@@ -185,12 +187,18 @@ impl InterruptController {
     }
 }
 
-/// Number of T-cycles consumed when servicing an interrupt.
-/// The interrupt dispatch takes 5 M-cycles = 20 T-cycles:
-/// - 2 M-cycles: Internal delay (and IME = 0)
-/// - 2 M-cycles: Push PC onto stack
-/// - 1 M-cycle: Set PC to handler address
-pub const INTERRUPT_CYCLES: u16 = 20;
+impl Snapshot for InterruptController {
+    fn save_to(&self, state: &mut StateWriter) {
+        state.u8(self.interrupt_flag);
+        state.u8(self.interrupt_enable);
+    }
+
+    fn load_from(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.interrupt_flag = state.u8()?;
+        self.interrupt_enable = state.u8()?;
+        Ok(())
+    }
+}
 
 #[cfg(test)]
 mod tests {
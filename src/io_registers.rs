@@ -0,0 +1,154 @@
+//! I/O register table (0xFF00-0xFF7F).
+//!
+//! Describes every address in the I/O range: its name, the value the DMG boot
+//! ROM leaves in it, and which bits always read back as 1. Unused bits,
+//! write-only registers and unmapped addresses all read as 1 on hardware;
+//! the bus ORs the read mask into every I/O read so no module can leak a 0
+//! from a bit it doesn't implement. CGB-only registers are unmapped on DMG.
+//!
+//! Reference: [Pan Docs — Hardware Registers](https://gbdev.io/pandocs/Hardware_Reg_List.html),
+//! [Pan Docs — Power Up Sequence](https://gbdev.io/pandocs/Power_Up_Sequence.html)
+
+pub const IO_START: u16 = 0xFF00;
+pub const IO_END: u16 = 0xFF7F;
+
+const IO_COUNT: usize = (IO_END - IO_START + 1) as usize;
+
+/// Description of one I/O address.
+#[derive(Debug, Clone, Copy)]
+pub struct IoRegister {
+    #[allow(dead_code)]
+    pub name: &'static str,
+    /// Value after the DMG boot ROM, or `None` when it depends on timing or
+    /// is random (DIV, LY, STAT mode, wave RAM, ...).
+    #[allow(dead_code)]
+    pub post_boot: Option<u8>,
+    /// Bits that always read back as 1.
+    pub read_mask: u8,
+    /// Only present on CGB hardware; reads 0xFF on DMG.
+    pub cgb_only: bool,
+}
+
+const UNMAPPED: IoRegister = IoRegister {
+    name: "(unmapped)",
+    post_boot: Some(0xFF),
+    read_mask: 0xFF,
+    cgb_only: false,
+};
+
+const fn dmg(name: &'static str, post_boot: Option<u8>, read_mask: u8) -> IoRegister {
+    IoRegister {
+        name,
+        post_boot,
+        read_mask,
+        cgb_only: false,
+    }
+}
+
+const fn cgb(name: &'static str, read_mask: u8) -> IoRegister {
+    IoRegister {
+        name,
+        post_boot: Some(0xFF),
+        read_mask,
+        cgb_only: true,
+    }
+}
+
+/// Every address in 0xFF00-0xFF7F, indexed from 0xFF00.
+pub const IO_REGISTERS: [IoRegister; IO_COUNT] = build_table();
+
+const fn build_table() -> [IoRegister; IO_COUNT] {
+    let mut table = [UNMAPPED; IO_COUNT];
+
+    // Joypad, serial, timer and interrupts
+    table[0x00] = dmg("P1", Some(0xCF), 0xC0);
+    table[0x01] = dmg("SB", Some(0x00), 0x00);
+    table[0x02] = dmg("SC", Some(0x7E), 0x7E);
+    table[0x04] = dmg("DIV", None, 0x00);
+    table[0x05] = dmg("TIMA", Some(0x00), 0x00);
+    table[0x06] = dmg("TMA", Some(0x00), 0x00);
+    table[0x07] = dmg("TAC", Some(0xF8), 0xF8);
+    table[0x0F] = dmg("IF", Some(0xE1), 0xE0);
+
+    // Audio
+    table[0x10] = dmg("NR10", Some(0x80), 0x80);
+    table[0x11] = dmg("NR11", Some(0xBF), 0x3F);
+    table[0x12] = dmg("NR12", Some(0xF3), 0x00);
+    table[0x13] = dmg("NR13", Some(0xFF), 0xFF);
+    table[0x14] = dmg("NR14", Some(0xBF), 0xBF);
+    table[0x16] = dmg("NR21", Some(0x3F), 0x3F);
+    table[0x17] = dmg("NR22", Some(0x00), 0x00);
+    table[0x18] = dmg("NR23", Some(0xFF), 0xFF);
+    table[0x19] = dmg("NR24", Some(0xBF), 0xBF);
+    table[0x1A] = dmg("NR30", Some(0x7F), 0x7F);
+    table[0x1B] = dmg("NR31", Some(0xFF), 0xFF);
+    table[0x1C] = dmg("NR32", Some(0x9F), 0x9F);
+    table[0x1D] = dmg("NR33", Some(0xFF), 0xFF);
+    table[0x1E] = dmg("NR34", Some(0xBF), 0xBF);
+    table[0x20] = dmg("NR41", Some(0xFF), 0xFF);
+    table[0x21] = dmg("NR42", Some(0x00), 0x00);
+    table[0x22] = dmg("NR43", Some(0x00), 0x00);
+    table[0x23] = dmg("NR44", Some(0xBF), 0xBF);
+    table[0x24] = dmg("NR50", Some(0x77), 0x00);
+    table[0x25] = dmg("NR51", Some(0xF3), 0x00);
+    table[0x26] = dmg("NR52", Some(0xF1), 0x70);
+    let mut wave = 0x30;
+    while wave <= 0x3F {
+        table[wave] = dmg("WAVE", None, 0x00);
+        wave += 1;
+    }
+
+    // LCD
+    table[0x40] = dmg("LCDC", Some(0x91), 0x00);
+    table[0x41] = dmg("STAT", None, 0x80);
+    table[0x42] = dmg("SCY", Some(0x00), 0x00);
+    table[0x43] = dmg("SCX", Some(0x00), 0x00);
+    table[0x44] = dmg("LY", None, 0x00);
+    table[0x45] = dmg("LYC", Some(0x00), 0x00);
+    table[0x46] = dmg("DMA", Some(0xFF), 0x00);
+    table[0x47] = dmg("BGP", Some(0xFC), 0x00);
+    table[0x48] = dmg("OBP0", None, 0x00);
+    table[0x49] = dmg("OBP1", None, 0x00);
+    table[0x4A] = dmg("WY", Some(0x00), 0x00);
+    table[0x4B] = dmg("WX", Some(0x00), 0x00);
+    table[0x50] = dmg("BANK", Some(0xFF), 0xFF);
+
+    // CGB-only
+    table[0x4D] = cgb("KEY1", 0x7E);
+    table[0x4F] = cgb("VBK", 0xFE);
+    table[0x51] = cgb("HDMA1", 0xFF);
+    table[0x52] = cgb("HDMA2", 0xFF);
+    table[0x53] = cgb("HDMA3", 0xFF);
+    table[0x54] = cgb("HDMA4", 0xFF);
+    table[0x55] = cgb("HDMA5", 0x00);
+    table[0x56] = cgb("RP", 0x3C);
+    table[0x68] = cgb("BCPS", 0x40);
+    table[0x69] = cgb("BCPD", 0x00);
+    table[0x6A] = cgb("OCPS", 0x40);
+    table[0x6B] = cgb("OCPD", 0x00);
+    table[0x6C] = cgb("OPRI", 0xFE);
+    table[0x70] = cgb("SVBK", 0xF8);
+    table[0x72] = cgb("FF72", 0x00);
+    table[0x73] = cgb("FF73", 0x00);
+    table[0x74] = cgb("FF74", 0x00);
+    table[0x75] = cgb("FF75", 0x8F);
+    table[0x76] = cgb("PCM12", 0x00);
+    table[0x77] = cgb("PCM34", 0x00);
+
+    table
+}
+
+/// Table entry for an I/O address (0xFF00-0xFF7F).
+pub fn io_register(address: u16) -> &'static IoRegister {
+    &IO_REGISTERS[(address - IO_START) as usize]
+}
+
+/// Bits of an I/O register that read as 1 on the given hardware.
+pub fn read_mask(address: u16, cgb_mode: bool) -> u8 {
+    let register = io_register(address);
+    if register.cgb_only && !cgb_mode {
+        UNMAPPED.read_mask
+    } else {
+        register.read_mask
+    }
+}
@@ -0,0 +1,212 @@
+//! Joypad input module (P1/JOYP register).
+//!
+//! The eight buttons are wired as a 2x4 matrix. Bits 5 and 4 of P1 select
+//! which half of the matrix (action buttons / d-pad) drives the input lines
+//! in bits 3-0. Both selects and inputs are active-low: a pressed button on a
+//! selected row reads back as 0.
+//!
+//! A Joypad interrupt is requested whenever one of the input lines P10-P13
+//! goes from high to low. The same transition is what wakes the CPU from STOP.
+//!
+//! Reference: [Pan Docs — Joypad Input](https://gbdev.io/pandocs/Joypad_Input.html)
+
+use crate::save_state::{Snapshot, StateError, StateReader, StateWriter};
+
+pub const JOYP_REGISTER: u16 = 0xFF00;
+
+const SELECT_BUTTONS_BIT: u8 = 0b0010_0000; // P15
+const SELECT_DPAD_BIT: u8 = 0b0001_0000; // P14
+const SELECT_MASK: u8 = SELECT_BUTTONS_BIT | SELECT_DPAD_BIT;
+const INPUT_LINES_MASK: u8 = 0b0000_1111;
+const UNUSED_BITS: u8 = 0b1100_0000;
+
+/// The eight Game Boy buttons.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::Right,
+        Button::Left,
+        Button::Up,
+        Button::Down,
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+    ];
+
+    /// Parse a button name such as `a` or `start` (case-insensitive).
+    pub fn from_name(name: &str) -> Option<Button> {
+        match name.to_ascii_lowercase().as_str() {
+            "right" => Some(Button::Right),
+            "left" => Some(Button::Left),
+            "up" => Some(Button::Up),
+            "down" => Some(Button::Down),
+            "a" => Some(Button::A),
+            "b" => Some(Button::B),
+            "select" => Some(Button::Select),
+            "start" => Some(Button::Start),
+            _ => None,
+        }
+    }
+
+    /// Bit mask for this button in the internal pressed-state byte.
+    ///
+    /// The low nibble holds the d-pad and the high nibble the action buttons,
+    /// each in the same order as the P10-P13 input lines.
+    pub const fn mask(self) -> u8 {
+        match self {
+            Button::Right => 0x01,
+            Button::Left => 0x02,
+            Button::Up => 0x04,
+            Button::Down => 0x08,
+            Button::A => 0x10,
+            Button::B => 0x20,
+            Button::Select => 0x40,
+            Button::Start => 0x80,
+        }
+    }
+}
+
+pub struct Joypad {
+    select: u8,  // P14/P15 as last written (active-low)
+    pressed: u8, // one bit per button, 1 = held (see `Button::mask`)
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad {
+            select: SELECT_MASK,
+            pressed: 0,
+        }
+    }
+
+    /// Read P1: unused bits read as 1, inputs are 0 while pressed and selected.
+    pub fn read(&self) -> u8 {
+        UNUSED_BITS | self.select | self.input_lines()
+    }
+
+    /// Write P1 (only the select bits are writable).
+    ///
+    /// Returns true if selecting a row pulled an input line low, in which
+    /// case a Joypad interrupt should be requested.
+    pub fn write(&mut self, value: u8) -> bool {
+        let before = self.input_lines();
+        self.select = value & SELECT_MASK;
+        Self::any_falling_edge(before, self.input_lines())
+    }
+
+    /// Update the held state of a button.
+    ///
+    /// Returns true if the change pulled a selected input line low, in which
+    /// case a Joypad interrupt should be requested.
+    pub fn set_button(&mut self, button: Button, pressed: bool) -> bool {
+        let before = self.input_lines();
+        if pressed {
+            self.pressed |= button.mask();
+        } else {
+            self.pressed &= !button.mask();
+        }
+        Self::any_falling_edge(before, self.input_lines())
+    }
+
+    /// Buttons currently held, as a mask of [`Button::mask`] bits.
+    pub fn pressed(&self) -> u8 {
+        self.pressed
+    }
+
+    /// True if any currently selected input line is held low.
+    ///
+    /// This is the condition the STOP instruction checks on entry and the one
+    /// that ends STOP mode.
+    pub fn any_selected_line_low(&self) -> bool {
+        self.input_lines() != INPUT_LINES_MASK
+    }
+
+    // Current state of P10-P13 (active-low) given the selected rows.
+    fn input_lines(&self) -> u8 {
+        let mut low = 0;
+        if self.select & SELECT_DPAD_BIT == 0 {
+            low |= self.pressed & 0x0F;
+        }
+        if self.select & SELECT_BUTTONS_BIT == 0 {
+            low |= self.pressed >> 4;
+        }
+        !low & INPUT_LINES_MASK
+    }
+
+    fn any_falling_edge(before: u8, after: u8) -> bool {
+        before & !after & INPUT_LINES_MASK != 0
+    }
+}
+
+impl Snapshot for Joypad {
+    fn save_to(&self, state: &mut StateWriter) {
+        state.u8(self.select);
+        state.u8(self.pressed);
+    }
+
+    fn load_from(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.select = state.u8()?;
+        self.pressed = state.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nothing_selected_reads_high() {
+        let mut joypad = Joypad::new();
+        joypad.set_button(Button::A, true);
+        assert_eq!(joypad.read(), 0xFF);
+        assert!(!joypad.any_selected_line_low());
+    }
+
+    #[test]
+    fn test_pressed_button_reads_low_when_selected() {
+        let mut joypad = Joypad::new();
+        joypad.write(0x10); // select action buttons (P15 low)
+        joypad.set_button(Button::Start, true);
+        assert_eq!(joypad.read(), 0xD7);
+        assert!(joypad.any_selected_line_low());
+
+        joypad.write(0x20); // select d-pad instead
+        assert_eq!(joypad.read(), 0xEF);
+        assert!(!joypad.any_selected_line_low());
+    }
+
+    #[test]
+    fn test_press_on_selected_row_requests_interrupt() {
+        let mut joypad = Joypad::new();
+        joypad.write(0x20); // select d-pad
+        assert!(joypad.set_button(Button::Down, true));
+        // Already low: pressing a second line still counts as a new edge
+        assert!(joypad.set_button(Button::Up, true));
+        // Releasing never requests an interrupt
+        assert!(!joypad.set_button(Button::Up, false));
+        // Unselected row produces no edge
+        assert!(!joypad.set_button(Button::A, true));
+    }
+
+    #[test]
+    fn test_selecting_row_with_held_button_requests_interrupt() {
+        let mut joypad = Joypad::new();
+        joypad.set_button(Button::B, true);
+        assert!(joypad.write(0x10));
+        assert!(!joypad.write(0x10));
+    }
+}
@@ -0,0 +1,467 @@
+//! Link cable emulation between two emulator instances.
+//!
+//! Both ends run independently and synchronise every
+//! [`SYNC_QUANTUM_T_CYCLES`]. At each sync point a side reports whether it is
+//! waiting for a transfer on the external clock (and the byte it offers), and
+//! any byte it sent as clock master during the quantum. A byte sent as master
+//! is paired at the next sync point with the byte the partner is offering
+//! there: the partner receives it and the master gets the offer back. A
+//! transfer lasts a whole quantum, so the answer normally arrives before the
+//! master needs it; otherwise the master's last bit is held until it does.
+//! Because every decision is based only on state exchanged at sync points, a
+//! transfer completes on the same cycle on every run regardless of how fast
+//! either side executes.
+//!
+//! Two transports are provided:
+//! - [`LinkedPair`]: two cores in one process, stepped a quantum at a time.
+//! - [`SocketLink`]: one core per process, exchanging sync messages over a
+//!   local TCP or Unix domain socket.
+//!
+//! Reference: [Pan Docs — Serial Data Transfer](https://gbdev.io/pandocs/Serial_Data_Transfer_(Link_Cable).html)
+
+use crate::cpu::CPU;
+use crate::memory_bus::MemoryBus;
+use crate::serial::SerialPeer;
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::rc::Rc;
+
+/// T-cycles each side runs between sync points (one 8192 Hz byte transfer).
+pub const SYNC_QUANTUM_T_CYCLES: u32 = 4096;
+
+/// Value received by the master when the partner was not waiting on the
+/// external clock at the sync point after the transfer started.
+const IDLE_LINE_BYTE: u8 = 0xFF;
+
+const SYNC_OFFERED_FLAG: u8 = 0b01;
+const SYNC_SENT_FLAG: u8 = 0b10;
+
+/// State reported by one side of the cable at a sync point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SyncMessage {
+    /// SB of a side waiting on the external clock.
+    pub offered: Option<u8>,
+    /// Byte clocked out to the partner as master during the quantum.
+    pub sent: Option<u8>,
+}
+
+impl SyncMessage {
+    pub const SIZE: usize = 3;
+
+    pub fn to_bytes(self) -> [u8; Self::SIZE] {
+        let mut flags = 0;
+        if self.offered.is_some() {
+            flags |= SYNC_OFFERED_FLAG;
+        }
+        if self.sent.is_some() {
+            flags |= SYNC_SENT_FLAG;
+        }
+        [flags, self.offered.unwrap_or(0), self.sent.unwrap_or(0)]
+    }
+
+    pub fn from_bytes(bytes: [u8; Self::SIZE]) -> Self {
+        SyncMessage {
+            offered: (bytes[0] & SYNC_OFFERED_FLAG != 0).then_some(bytes[1]),
+            sent: (bytes[0] & SYNC_SENT_FLAG != 0).then_some(bytes[2]),
+        }
+    }
+}
+
+#[derive(Default)]
+struct LinkState {
+    offered: Option<u8>,  // our SB while waiting on the external clock
+    sent: Option<u8>,     // our byte clocked out as master this quantum
+    answer: Option<u8>,   // partner's byte for our transfer as master
+    incoming: Option<u8>, // partner's byte for our pending external transfer
+}
+
+/// The serial peer plugged into a `MemoryBus`; shares state with its `LinkPort`.
+struct LinkPeer {
+    state: Rc<RefCell<LinkState>>,
+}
+
+impl SerialPeer for LinkPeer {
+    fn exchange(&mut self, outgoing: u8) -> Option<u8> {
+        let mut state = self.state.borrow_mut();
+        // Answered at the next sync point
+        state.sent = Some(outgoing);
+        state.answer = None;
+        None
+    }
+
+    fn poll_exchange(&mut self) -> Option<u8> {
+        self.state.borrow_mut().answer.take()
+    }
+
+    fn poll_external(&mut self, outgoing: u8) -> Option<u8> {
+        let mut state = self.state.borrow_mut();
+        let incoming = state.incoming.take();
+        // Once the transfer completes our byte is no longer on offer
+        state.offered = match incoming {
+            Some(_) => None,
+            None => Some(outgoing),
+        };
+        incoming
+    }
+}
+
+/// One end of the cable, tracking elapsed cycles until the next sync point.
+pub struct LinkPort {
+    state: Rc<RefCell<LinkState>>,
+    elapsed: u32,
+    reported: SyncMessage, // what we reported at the current sync point
+}
+
+impl LinkPort {
+    /// Plug a new cable end into the bus's serial port.
+    pub fn attach(bus: &mut MemoryBus) -> LinkPort {
+        let state = Rc::new(RefCell::new(LinkState::default()));
+        bus.serial.connect(Box::new(LinkPeer {
+            state: Rc::clone(&state),
+        }));
+        LinkPort {
+            state,
+            elapsed: 0,
+            reported: SyncMessage::default(),
+        }
+    }
+
+    /// Count T-cycles run by the attached core. Returns true once a sync point
+    /// is reached, after which `outgoing_sync` and `apply_sync` must be performed
+    /// before continuing.
+    pub fn advance(&mut self, t_cycles: u16) -> bool {
+        self.elapsed += t_cycles as u32;
+        if self.elapsed < SYNC_QUANTUM_T_CYCLES {
+            return false;
+        }
+        self.elapsed -= SYNC_QUANTUM_T_CYCLES;
+        true
+    }
+
+    /// Collect this side's state for the current sync point.
+    pub fn outgoing_sync(&mut self) -> SyncMessage {
+        let mut state = self.state.borrow_mut();
+        self.reported = SyncMessage {
+            offered: state.offered.take(),
+            sent: state.sent.take(),
+        };
+        self.reported
+    }
+
+    /// Apply the partner's state from the current sync point.
+    pub fn apply_sync(&mut self, message: SyncMessage) {
+        let mut state = self.state.borrow_mut();
+        // A byte sent by either side pairs with the other side's offer
+        if self.reported.sent.is_some() {
+            state.answer = Some(message.offered.unwrap_or(IDLE_LINE_BYTE));
+        }
+        if self.reported.offered.is_some() && message.sent.is_some() {
+            state.incoming = message.sent;
+        }
+    }
+}
+
+/// Run `cpu` until `port` reaches its next sync point.
+fn run_quantum(cpu: &mut CPU, port: &mut LinkPort) -> u64 {
+    let mut t_cycles = 0;
+    loop {
+        let step = cpu.step();
+        t_cycles += step as u64;
+        if port.advance(step) {
+            return t_cycles;
+        }
+    }
+}
+
+/// Two cores in one process connected by a link cable.
+pub struct LinkedPair {
+    pub first: CPU,
+    pub second: CPU,
+    ports: [LinkPort; 2],
+}
+
+impl LinkedPair {
+    pub fn new(mut first: CPU, mut second: CPU) -> LinkedPair {
+        let ports = [
+            LinkPort::attach(&mut first.bus),
+            LinkPort::attach(&mut second.bus),
+        ];
+        LinkedPair {
+            first,
+            second,
+            ports,
+        }
+    }
+
+    /// Run both cores for one sync quantum and exchange their link state.
+    ///
+    /// Returns the T-cycles run by the first core.
+    pub fn run_quantum(&mut self) -> u64 {
+        let [first_port, second_port] = &mut self.ports;
+        let t_cycles = run_quantum(&mut self.first, first_port);
+        run_quantum(&mut self.second, second_port);
+
+        let first_message = first_port.outgoing_sync();
+        let second_message = second_port.outgoing_sync();
+        first_port.apply_sync(second_message);
+        second_port.apply_sync(first_message);
+        t_cycles
+    }
+}
+
+/// A byte stream carrying sync messages to another process.
+trait LinkStream: Read + Write {}
+
+impl<T: Read + Write> LinkStream for T {}
+
+/// One core linked to a partner process over a socket.
+pub struct SocketLink {
+    stream: Box<dyn LinkStream>,
+    port: LinkPort,
+}
+
+impl SocketLink {
+    /// Wait for a partner to connect on a TCP address (e.g. `127.0.0.1:5000`).
+    pub fn listen_tcp(address: &str, bus: &mut MemoryBus) -> io::Result<SocketLink> {
+        let (stream, _) = TcpListener::bind(address)?.accept()?;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(Box::new(stream), bus))
+    }
+
+    /// Connect to a partner listening on a TCP address.
+    pub fn connect_tcp(address: &str, bus: &mut MemoryBus) -> io::Result<SocketLink> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(Box::new(stream), bus))
+    }
+
+    /// Wait for a partner to connect on a Unix domain socket path.
+    #[cfg(unix)]
+    pub fn listen_unix(path: &str, bus: &mut MemoryBus) -> io::Result<SocketLink> {
+        use std::os::unix::net::UnixListener;
+        let _ = std::fs::remove_file(path);
+        let (stream, _) = UnixListener::bind(path)?.accept()?;
+        Ok(Self::new(Box::new(stream), bus))
+    }
+
+    /// Connect to a partner listening on a Unix domain socket path.
+    #[cfg(unix)]
+    pub fn connect_unix(path: &str, bus: &mut MemoryBus) -> io::Result<SocketLink> {
+        use std::os::unix::net::UnixStream;
+        let stream = UnixStream::connect(path)?;
+        Ok(Self::new(Box::new(stream), bus))
+    }
+
+    fn new(stream: Box<dyn LinkStream>, bus: &mut MemoryBus) -> SocketLink {
+        SocketLink {
+            stream,
+            port: LinkPort::attach(bus),
+        }
+    }
+
+    /// Account for a CPU step; at each sync point, exchange link state with
+    /// the partner, blocking until its message arrives.
+    pub fn after_step(&mut self, t_cycles: u16) -> io::Result<()> {
+        if !self.port.advance(t_cycles) {
+            return Ok(());
+        }
+
+        let message = self.port.outgoing_sync();
+        self.stream.write_all(&message.to_bytes())?;
+        self.stream.flush()?;
+
+        let mut bytes = [0; SyncMessage::SIZE];
+        self.stream.read_exact(&mut bytes)?;
+        self.port.apply_sync(SyncMessage::from_bytes(bytes));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::cpu_with_program;
+    use crate::model::Model;
+    use std::thread;
+
+    /// Program at 0x0100: wait `delay` loop iterations (0 = 256), load SB,
+    /// start a transfer with the given SC value, then spin forever.
+    fn transfer_program(delay: u8, sb: u8, sc: u8) -> CPU {
+        let program = [
+            0x06, delay, // LD B,delay
+            0x05,  // DEC B
+            0x20, 0xFD, // JR NZ,-3
+            0x3E, sb, // LD A,sb
+            0xE0, 0x01, // LDH (SB),A
+            0x3E, sc, // LD A,sc
+            0xE0, 0x02, // LDH (SC),A
+            0x18, 0xFE, // JR -2
+        ];
+        cpu_with_program(Model::Dmg, &program)
+    }
+
+    fn transfer_done(cpu: &CPU) -> bool {
+        cpu.bus.read_byte(0xFF02) & 0x80 == 0
+    }
+
+    #[test]
+    fn test_sync_message_round_trip() {
+        let message = SyncMessage {
+            offered: Some(0x00),
+            sent: Some(0xA5),
+        };
+        assert_eq!(SyncMessage::from_bytes(message.to_bytes()), message);
+        let empty = SyncMessage::default();
+        assert_eq!(SyncMessage::from_bytes(empty.to_bytes()), empty);
+    }
+
+    #[test]
+    fn test_linked_pair_exchanges_bytes() {
+        let master = transfer_program(0x00, 0x42, 0x81);
+        let slave = transfer_program(0x01, 0x99, 0x80);
+        let mut pair = LinkedPair::new(master, slave);
+
+        for _ in 0..8 {
+            pair.run_quantum();
+        }
+
+        assert!(transfer_done(&pair.first));
+        assert!(transfer_done(&pair.second));
+        assert_eq!(pair.first.bus.read_byte(0xFF01), 0x99);
+        assert_eq!(pair.second.bus.read_byte(0xFF01), 0x42);
+    }
+
+    #[test]
+    fn test_linked_pair_is_deterministic() {
+        let run = || {
+            let mut pair = LinkedPair::new(
+                transfer_program(0x00, 0x42, 0x81),
+                transfer_program(0x01, 0x99, 0x80),
+            );
+            let mut t_cycles = 0;
+            while !transfer_done(&pair.second) {
+                t_cycles += pair.run_quantum();
+            }
+            t_cycles
+        };
+        assert_eq!(run(), run());
+    }
+
+    /// Program at 0x0100: transfer four bytes with the given SC value, each
+    /// after two `delay` loops, storing every byte received from 0xC000.
+    fn multi_transfer_program(delay: u8, sc: u8, bytes: [u8; 4]) -> CPU {
+        let program = [
+            0x21, 0x00, 0xC0, // LD HL,0xC000
+            0x11, 0x28, 0x01, // LD DE,0x0128
+            0x06, delay, // loop: LD B,delay
+            0x05,  // DEC B
+            0x20, 0xFD, // JR NZ,-3
+            0x06, delay, // LD B,delay
+            0x05,  // DEC B
+            0x20, 0xFD, // JR NZ,-3
+            0x1A, // LD A,(DE)
+            0x13, // INC DE
+            0xE0, 0x01, // LDH (SB),A
+            0x3E, sc, // LD A,sc
+            0xE0, 0x02, // LDH (SC),A
+            0xF0, 0x02, // wait: LDH A,(SC)
+            0xCB, 0x7F, // BIT 7,A
+            0x20, 0xFA, // JR NZ,wait
+            0xF0, 0x01, // LDH A,(SB)
+            0x22, // LD (HL+),A
+            0x7D, // LD A,L
+            0xFE, 0x04, // CP 4
+            0x20, 0xE0, // JR NZ,loop
+            0x18, 0xFE, // JR -2
+            bytes[0], bytes[1], bytes[2], bytes[3], // 0x0128: bytes to send
+        ];
+        cpu_with_program(Model::Dmg, &program)
+    }
+
+    fn received(cpu: &CPU) -> Vec<u8> {
+        (0xC000..0xC004)
+            .map(|addr| cpu.bus.read_byte(addr))
+            .collect()
+    }
+
+    #[test]
+    fn test_linked_pair_exchanges_bytes_back_to_back() {
+        let run = |master_delay: u8, slave_delay: u8| {
+            let master = multi_transfer_program(master_delay, 0x81, [0x11, 0x22, 0x33, 0x44]);
+            let slave = multi_transfer_program(slave_delay, 0x80, [0xA1, 0xB2, 0xC3, 0xD4]);
+            let mut pair = LinkedPair::new(master, slave);
+            for _ in 0..40 {
+                pair.run_quantum();
+            }
+            (received(&pair.first), received(&pair.second))
+        };
+
+        // The slave re-arms at once, so each byte pairs with its offer
+        let (master, slave) = run(0x00, 0x01);
+        assert_eq!(master, [0xA1, 0xB2, 0xC3, 0xD4]);
+        assert_eq!(slave, [0x11, 0x22, 0x33, 0x44]);
+
+        // The master clocks before the slave's offer reaches a sync point;
+        // the offer is picked up at the next one, before the transfer ends
+        let (master, slave) = run(0x80, 0x80);
+        assert_eq!(master, [0xA1, 0xB2, 0xC3, 0xD4]);
+        assert_eq!(slave, [0x11, 0x22, 0x33, 0x44]);
+    }
+
+    #[test]
+    fn test_master_without_listening_partner_reads_ff() {
+        let master = transfer_program(0x00, 0x42, 0x81);
+        let idle = transfer_program(0x01, 0x99, 0x00);
+        let mut pair = LinkedPair::new(master, idle);
+
+        for _ in 0..8 {
+            pair.run_quantum();
+        }
+
+        assert_eq!(pair.first.bus.read_byte(0xFF01), 0xFF);
+        assert_eq!(pair.second.bus.read_byte(0xFF01), 0x99);
+    }
+
+    #[test]
+    fn test_tcp_link_exchanges_bytes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let run_side = |listen: bool, delay: u8, sb: u8, sc: u8, address: String| {
+            move || {
+                let mut cpu = transfer_program(delay, sb, sc);
+                let mut link = if listen {
+                    SocketLink::listen_tcp(&address, &mut cpu.bus).unwrap()
+                } else {
+                    // Give the listener a moment to bind
+                    let mut attempts = 0;
+                    loop {
+                        match SocketLink::connect_tcp(&address, &mut cpu.bus) {
+                            Ok(link) => break link,
+                            Err(_) if attempts < 100 => {
+                                attempts += 1;
+                                thread::sleep(std::time::Duration::from_millis(10));
+                            }
+                            Err(e) => panic!("{e}"),
+                        }
+                    }
+                };
+                let mut t_cycles: u64 = 0;
+                while t_cycles < 8 * SYNC_QUANTUM_T_CYCLES as u64 {
+                    let step = cpu.step();
+                    t_cycles += step as u64;
+                    link.after_step(step).unwrap();
+                }
+                cpu.bus.read_byte(0xFF01)
+            }
+        };
+
+        let master = thread::spawn(run_side(true, 0x00, 0x42, 0x81, address.clone()));
+        let slave = thread::spawn(run_side(false, 0x01, 0x99, 0x80, address));
+
+        assert_eq!(master.join().unwrap(), 0x99);
+        assert_eq!(slave.join().unwrap(), 0x42);
+    }
+}
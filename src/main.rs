@@ -1,98 +1,1050 @@
 //! Game Boy emulator - main entry point.
 //!
-//! This module orchestrates the emulation loop, loading ROMs and running CPU cycles
-//! with per-cycle hardware ticking (timer, GPU, etc.).
+//! This module orchestrates the emulation loop, loading ROMs and running CPU cycles.
+//! The CPU ticks the rest of the hardware (timer, PPU, DMA) on every M-cycle.
+//!
+//! Usage: `rusty_gameboy_emulator [ROM ...] [HARDWARE OPTIONS] [LINK OPTION] [AUDIO OPTIONS]`
+//!
+//! Hardware options:
+//! - `--model NAME`: emulated model, one of `dmg0`, `dmg`, `mgb`, `sgb`, `cgb`
+//!   (default: `cgb` for games with the CGB flag set in their header, `sgb`
+//!   for games with the SGB flag, else `dmg`)
+//! - `--boot-rom PATH`: run this boot ROM before the cartridge instead of
+//!   starting in the model's post-boot state
+//! - `--compat-palette COMBO`: run DMG games on a CGB with the colour palettes
+//!   of a boot logo button combination such as `left+a` (implies `--model cgb`)
+//!
+//! Serial port options (require exactly one ROM):
+//! - `--link-listen ADDR`: wait for a partner process to connect
+//! - `--link-connect ADDR`: connect to a listening partner process
+//! - `--link-pair ROM2`: run ROM2 in-process as the link partner
+//! - `--printer DIR`: attach a Game Boy Printer saving printouts as PNGs in DIR
+//!
+//! `ADDR` is `host:port` for TCP or `unix:/path` for a Unix domain socket.
+//!
+//! Audio options:
+//! - `--wav PATH`: record the audio output to a WAV file (requires exactly one ROM)
+//! - `--sample-rate HZ`: host sample rate (default 48000, at most 1048576)
+//! - `--channel-wavs DIR`: record each channel to its own WAV file in DIR
+//!   (requires exactly one ROM)
+//! - `--mute N[,N...]`: mute channels 1-4 in the mix
+//! - `--solo N`: play only channel N
+//!
+//! Video options:
+//! - `--screenshot PATH`: save the last frame as a PNG when the run ends
+//!   (requires exactly one ROM)
+//! - `--color-correction MODE`: `lcd` (default) to approximate the CGB LCD's
+//!   colours in screenshots, or `none` for the raw RGB555 values
+//!
+//! Save state options (require exactly one ROM):
+//! - `--load-state PATH`: start from a save state made with the same ROM and model
+//! - `--save-state PATH`: save the machine state when the run ends
+//! - `--rewind FRAMES`: keep a rewind buffer and step back this many frames
+//!   when the run ends, before the screenshot and save state are written
+//!   (not with `--link-pair`)
+//! - `--rewind-interval N`: keep a rewind buffer with a snapshot of every Nth
+//!   frame (default 1); on its own it rewinds only on request
+//!
+//! Debugger options (require exactly one ROM, no link cable, movie or rewind):
+//! - `--debug`: run the ROM under the interactive debugger, reading commands
+//!   from standard input (`help` lists them)
+//! - `--gdb PORT`: wait for a GDB remote protocol client on 127.0.0.1:PORT
+//!   and let it control the ROM
+//!
+//! Analysis options (require exactly one ROM, not with `--link-pair` or a
+//! debugger):
+//! - `--profile PATH`: track calls and write a report of the T-cycles spent
+//!   in each function, on its own and including its callees, when the run ends
+//! - `--profile-folded PATH`: write the cycles of every call stack as folded
+//!   stacks for flame graph tools
+//! - `--coverage PATH`: write a report of the ROM bytes of each bank that
+//!   were executed, read as data or never touched
+//! - `--cdl PATH`: write the same coverage as a CDL file with one flag byte
+//!   per ROM byte (bit 0 code, bit 1 data)
+//!
+//! Symbol option (requires exactly one ROM):
+//! - `--symbols PATH`: name addresses in the debugger and the instruction
+//!   trace after the labels of an RGBDS/no$gmb `.sym` file. Without it, a
+//!   `.sym` file next to the ROM with the same name is loaded if present.
+//!
+//! Movie options (require exactly one ROM, not with `--link-listen`,
+//! `--link-connect` or `--link-pair`):
+//! - `--record PATH`: record the joypad input of every frame, with the
+//!   starting state and periodic state hashes, to a movie file
+//! - `--play PATH`: replay a movie from its starting state, stopping at its
+//!   end or as soon as the machine state no longer matches the recording
+//!   (not with `--load-state`)
+//!
+//! Except under `--link-pair` or a debugger, commands typed on standard input
+//! control the run (see [`controls`]): `press a+start` and `release a` hold and let go of
+//! buttons, and are what `--record` records; `rewind [N]` steps back N
+//! snapshots of the rewind buffer, except while a movie is recorded or
+//! played.
 
+mod apu;
+mod audio;
 mod cartridge_header;
+mod controls;
 mod cpu;
+mod debugger;
 mod flag_helpers;
+mod hdma;
 mod instructions;
 mod interrupts;
+mod io_registers;
+mod joypad;
+mod link;
 mod memory_bus;
+mod model;
+mod movie;
 mod ppu;
+mod printer;
 mod register;
+mod rewind;
+mod save_state;
+mod serial;
+mod sgb;
 mod timer;
 
+use crate::apu::Channel;
+use crate::audio::{AudioOutput, DEFAULT_SAMPLE_RATE, INPUT_SAMPLE_RATE};
 use crate::cartridge_header::CartridgeHeader;
+use crate::controls::Control;
 use crate::cpu::CPU;
+use crate::debugger::call_stack::CallStack;
+use crate::debugger::coverage::Coverage;
+use crate::debugger::gdb::GdbStub;
+use crate::debugger::profiler::Profiler;
+use crate::debugger::symbols::Symbols;
+use crate::debugger::Debugger;
+use crate::link::{LinkedPair, SocketLink};
+use crate::model::Model;
+use crate::movie::{Movie, MoviePlayer, MovieRecorder};
+use crate::ppu::compat::CompatPalettes;
+use crate::ppu::palette::ColorCorrection;
+use crate::printer::Printer;
+use crate::rewind::RewindBuffer;
 use std::fs;
 use std::io::{self, Write};
+use std::net::TcpListener;
+use std::path::Path;
+
+const MAX_CYCLES: u64 = 10_000_000; // 10 million T-states should be enough
+
+/// What is plugged into the serial port of the emulated Game Boy.
+enum LinkOption {
+    Listen(String),
+    Connect(String),
+    Pair(String),
+    Printer(String),
+}
+
+/// Audio output settings, applied to every ROM that is run.
+struct AudioOptions {
+    sample_rate: u32,
+    wav: Option<String>,
+    channel_wavs: Option<String>,
+    muted: Vec<Channel>,
+    solo: Option<Channel>,
+}
+
+/// Screenshot settings.
+struct VideoOptions {
+    screenshot: Option<String>,
+    color_correction: ColorCorrection,
+}
+
+/// An input movie to write or to replay.
+enum MovieOption {
+    Record(String),
+    Play(String),
+}
+
+/// Where to write profiles and ROM coverage when the run ends.
+struct AnalysisOptions {
+    profile: Option<String>,
+    profile_folded: Option<String>,
+    coverage: Option<String>,
+    cdl: Option<String>,
+}
+
+impl AnalysisOptions {
+    fn profiling(&self) -> bool {
+        self.profile.is_some() || self.profile_folded.is_some()
+    }
+
+    fn covering(&self) -> bool {
+        self.coverage.is_some() || self.cdl.is_some()
+    }
+}
+
+/// Which debugger controls the ROM.
+enum DebugOption {
+    Console,
+    Gdb(u16),
+}
+
+/// Save state files to start from and to write when the run ends, how many
+/// frames to rewind before writing, and the movie to record or play.
+struct StateOptions {
+    load: Option<String>,
+    save: Option<String>,
+    rewind: Option<u64>,
+    rewind_interval: Option<u64>,
+    movie: Option<MovieOption>,
+}
+
+impl StateOptions {
+    fn rewinding(&self) -> bool {
+        self.rewind.is_some() || self.rewind_interval.is_some()
+    }
+}
+
+struct Options {
+    roms: Vec<String>,
+    model: Option<Model>,
+    boot_rom: Option<String>,
+    compat_palette: Option<CompatPalettes>,
+    link: Option<LinkOption>,
+    audio: AudioOptions,
+    video: VideoOptions,
+    state: StateOptions,
+    analysis: AnalysisOptions,
+    debug: Option<DebugOption>,
+    symbols: Option<String>,
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut roms = Vec::new();
+    let mut model = None;
+    let mut boot_rom = None;
+    let mut compat_palette = None;
+    let mut link = None;
+    let mut audio = AudioOptions {
+        sample_rate: DEFAULT_SAMPLE_RATE,
+        wav: None,
+        channel_wavs: None,
+        muted: Vec::new(),
+        solo: None,
+    };
+    let mut video = VideoOptions {
+        screenshot: None,
+        color_correction: ColorCorrection::Lcd,
+    };
+    let mut state = StateOptions {
+        load: None,
+        save: None,
+        rewind: None,
+        rewind_interval: None,
+        movie: None,
+    };
+    let mut analysis = AnalysisOptions {
+        profile: None,
+        profile_folded: None,
+        coverage: None,
+        cdl: None,
+    };
+    let mut debug = None;
+    let mut symbols = None;
+    let mut args = args;
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            roms.push(arg);
+            continue;
+        }
+        if arg == "--debug" {
+            if debug.replace(DebugOption::Console).is_some() {
+                return Err("Only one debugger option may be given".to_string());
+            }
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {arg}"))?;
+
+        let link_option: fn(String) -> LinkOption = match arg.as_str() {
+            "--link-listen" => LinkOption::Listen,
+            "--link-connect" => LinkOption::Connect,
+            "--link-pair" => LinkOption::Pair,
+            "--printer" => LinkOption::Printer,
+            "--model" => {
+                model = Some(Model::from_name(&value).ok_or(format!("Unknown model: {value}"))?);
+                continue;
+            }
+            "--boot-rom" => {
+                boot_rom = Some(value);
+                continue;
+            }
+            "--compat-palette" => {
+                let palettes = CompatPalettes::from_button_combo(&value).ok_or_else(|| {
+                    let names: Vec<_> = CompatPalettes::button_combo_names().collect();
+                    format!(
+                        "Unknown palette: {value} (expected one of {})",
+                        names.join(", ")
+                    )
+                })?;
+                compat_palette = Some(palettes);
+                continue;
+            }
+            "--wav" => {
+                audio.wav = Some(value);
+                continue;
+            }
+            "--channel-wavs" => {
+                audio.channel_wavs = Some(value);
+                continue;
+            }
+            "--sample-rate" => {
+                audio.sample_rate = match value.parse() {
+                    Ok(rate) if (1..=INPUT_SAMPLE_RATE).contains(&rate) => rate,
+                    _ => return Err(format!("Invalid sample rate: {value}")),
+                };
+                continue;
+            }
+            "--mute" => {
+                for number in value.split(',') {
+                    audio.muted.push(parse_channel(number)?);
+                }
+                continue;
+            }
+            "--solo" => {
+                audio.solo = Some(parse_channel(&value)?);
+                continue;
+            }
+            "--screenshot" => {
+                video.screenshot = Some(value);
+                continue;
+            }
+            "--color-correction" => {
+                video.color_correction = match value.as_str() {
+                    "lcd" => ColorCorrection::Lcd,
+                    "none" => ColorCorrection::None,
+                    _ => return Err(format!("Unknown colour correction: {value}")),
+                };
+                continue;
+            }
+            "--load-state" => {
+                state.load = Some(value);
+                continue;
+            }
+            "--save-state" => {
+                state.save = Some(value);
+                continue;
+            }
+            "--rewind" => {
+                state.rewind = match value.parse() {
+                    Ok(frames) if frames > 0 => Some(frames),
+                    _ => return Err(format!("Invalid rewind frame count: {value}")),
+                };
+                continue;
+            }
+            "--rewind-interval" => {
+                state.rewind_interval = match value.parse() {
+                    Ok(frames) if frames > 0 => Some(frames),
+                    _ => return Err(format!("Invalid rewind interval: {value}")),
+                };
+                continue;
+            }
+            "--record" | "--play" => {
+                let movie = match arg.as_str() {
+                    "--record" => MovieOption::Record(value),
+                    _ => MovieOption::Play(value),
+                };
+                if state.movie.replace(movie).is_some() {
+                    return Err("Only one movie option may be given".to_string());
+                }
+                continue;
+            }
+            "--profile" => {
+                analysis.profile = Some(value);
+                continue;
+            }
+            "--profile-folded" => {
+                analysis.profile_folded = Some(value);
+                continue;
+            }
+            "--coverage" => {
+                analysis.coverage = Some(value);
+                continue;
+            }
+            "--cdl" => {
+                analysis.cdl = Some(value);
+                continue;
+            }
+            "--symbols" => {
+                symbols = Some(value);
+                continue;
+            }
+            "--gdb" => {
+                let port = value
+                    .parse()
+                    .map_err(|_| format!("Invalid GDB port: {value}"))?;
+                if debug.replace(DebugOption::Gdb(port)).is_some() {
+                    return Err("Only one debugger option may be given".to_string());
+                }
+                continue;
+            }
+            _ => return Err(format!("Unknown option: {arg}")),
+        };
+        if link.replace(link_option(value)).is_some() {
+            return Err("Only one serial port option may be given".to_string());
+        }
+    }
+
+    if compat_palette.is_some() {
+        if model.is_some_and(|model| !model.is_cgb()) {
+            return Err("--compat-palette requires the CGB model".to_string());
+        }
+        if boot_rom.is_some() {
+            return Err("--compat-palette cannot be combined with --boot-rom".to_string());
+        }
+        model = Some(Model::Cgb);
+    }
+    if roms.is_empty() {
+        roms.push("blargg/cpu_instrs/individual/01-special.gb".to_string());
+    }
+    if link.is_some() && roms.len() != 1 {
+        return Err("Serial port options require exactly one ROM".to_string());
+    }
+    if (audio.wav.is_some() || audio.channel_wavs.is_some()) && roms.len() != 1 {
+        return Err("WAV recording requires exactly one ROM".to_string());
+    }
+    if video.screenshot.is_some() && roms.len() != 1 {
+        return Err("Screenshots require exactly one ROM".to_string());
+    }
+    if (state.load.is_some() || state.save.is_some()) && roms.len() != 1 {
+        return Err("Save states require exactly one ROM".to_string());
+    }
+    if state.rewinding() && matches!(link, Some(LinkOption::Pair(_))) {
+        return Err("Rewind options cannot be combined with --link-pair".to_string());
+    }
+    if let Some(movie) = &state.movie {
+        if roms.len() != 1 {
+            return Err("Movies require exactly one ROM".to_string());
+        }
+        if !matches!(link, None | Some(LinkOption::Printer(_))) {
+            return Err("Movies cannot be combined with a link cable".to_string());
+        }
+        if matches!(movie, MovieOption::Play(_)) && state.load.is_some() {
+            return Err("--play cannot be combined with --load-state".to_string());
+        }
+    }
+    if analysis.profiling() || analysis.covering() {
+        if roms.len() != 1 {
+            return Err("Profiling and coverage require exactly one ROM".to_string());
+        }
+        if matches!(link, Some(LinkOption::Pair(_))) || debug.is_some() {
+            return Err(
+                "Profiling and coverage cannot be combined with --link-pair or a debugger"
+                    .to_string(),
+            );
+        }
+    }
+    if symbols.is_some() && roms.len() != 1 {
+        return Err("--symbols requires exactly one ROM".to_string());
+    }
+    if debug.is_some() {
+        if roms.len() != 1 {
+            return Err("Debugger options require exactly one ROM".to_string());
+        }
+        if link.is_some() || state.movie.is_some() || state.rewinding() {
+            return Err(
+                "Debugger options cannot be combined with serial port, movie or rewind options"
+                    .to_string(),
+            );
+        }
+    }
+
+    Ok(Options {
+        roms,
+        model,
+        boot_rom,
+        compat_palette,
+        link,
+        audio,
+        video,
+        state,
+        analysis,
+        debug,
+        symbols,
+    })
+}
+
+fn parse_channel(number: &str) -> Result<Channel, String> {
+    number
+        .trim()
+        .parse()
+        .ok()
+        .and_then(Channel::from_number)
+        .ok_or_else(|| format!("Invalid audio channel (expected 1-4): {number}"))
+}
 
 fn main() {
-    let test_roms = vec!["blargg/cpu_instrs/individual/01-special.gb"];
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    };
 
-    for rom_path in test_roms {
+    let boot_rom = match options.boot_rom.as_deref().map(fs::read) {
+        Some(Ok(data)) => Some(data),
+        Some(Err(e)) => {
+            eprintln!("Failed to read boot ROM: {e}");
+            std::process::exit(2);
+        }
+        None => None,
+    };
+
+    for rom_path in &options.roms {
         println!("==========================================");
         println!("Running test: {}", rom_path);
         println!("==========================================\n");
 
-        let rom_data = match fs::read(rom_path) {
-            Ok(data) => data,
-            Err(e) => {
-                println!("Failed to read ROM file: {}", e);
-                println!("Make sure the ROM exists at: {}\n", rom_path);
+        let Some(mut cpu) = load_rom(rom_path, options.model, boot_rom.as_deref()) else {
+            continue;
+        };
+        if let Some(palettes) = &options.compat_palette {
+            apply_compat_palette(&mut cpu, palettes);
+        }
+
+        if let Err(e) = load_symbols(&mut cpu, rom_path, options.symbols.as_deref()) {
+            println!("Failed to load symbols: {e}");
+            continue;
+        }
+        if let Err(e) = configure_audio(&mut cpu, &options.audio) {
+            println!("Failed to start audio recording: {e}");
+            continue;
+        }
+        if let Some(path) = &options.state.load {
+            if let Err(e) = load_state_file(&mut cpu, path) {
+                println!("Failed to load save state {path}: {e}");
                 continue;
             }
-        };
+        }
 
-        match CartridgeHeader::parse(&rom_data) {
-            Ok(header) => {
-                println!("{}", header.summary_line());
+        let (video, state, analysis) = (&options.video, &options.state, &options.analysis);
+        match &options.link {
+            None => match options.debug {
+                Some(DebugOption::Console) => run_debugger(cpu, video, state),
+                Some(DebugOption::Gdb(port)) => run_gdb(cpu, video, state, port),
+                None => run(cpu, video, state, analysis, |_| Ok(())),
+            },
+            Some(LinkOption::Pair(partner_path)) => {
+                if let Some(mut partner) =
+                    load_rom(partner_path, options.model, boot_rom.as_deref())
+                {
+                    if let Some(palettes) = &options.compat_palette {
+                        apply_compat_palette(&mut partner, palettes);
+                    }
+                    run_pair(LinkedPair::new(cpu, partner), video, state);
+                }
             }
-            Err(e) => {
-                println!("Could not parse cartridge header: {e}");
+            Some(LinkOption::Printer(dir)) => run_with_printer(cpu, video, state, analysis, dir),
+            Some(LinkOption::Listen(address)) => {
+                run_socket(cpu, video, state, analysis, address, true)
+            }
+            Some(LinkOption::Connect(address)) => {
+                run_socket(cpu, video, state, analysis, address, false)
             }
         }
 
-        let mut cpu = CPU::new(rom_data);
-        let mut cycle_count: u64 = 0;
-        const MAX_CYCLES: u64 = 10_000_000; // 10 million T-states should be enough
+        println!("\n==========================================\n");
+    }
+}
 
-        // Run the emulation until max cycles or until CPU halts
-        while cycle_count < MAX_CYCLES {
-            if cpu.is_halted() {
-                println!("\n CPU halted after {} cycles", cycle_count);
-                break;
+/// Load a ROM into a new CPU. Without an explicit model, CGB games run on a
+/// CGB, SGB games on an SGB and everything else on a DMG.
+fn load_rom(rom_path: &str, model: Option<Model>, boot_rom: Option<&[u8]>) -> Option<CPU> {
+    let rom_data = match fs::read(rom_path) {
+        Ok(data) => data,
+        Err(e) => {
+            println!("Failed to read ROM file: {}", e);
+            println!("Make sure the ROM exists at: {}\n", rom_path);
+            return None;
+        }
+    };
+
+    let default_model = match CartridgeHeader::parse(&rom_data) {
+        Ok(header) => {
+            println!("{}", header.summary_line());
+            if header.supports_cgb() {
+                Model::Cgb
+            } else if header.supports_sgb() {
+                Model::Sgb
+            } else {
+                Model::Dmg
             }
+        }
+        Err(e) => {
+            println!("Could not parse cartridge header: {e}");
+            Model::Dmg
+        }
+    };
+
+    let model = model.unwrap_or(default_model);
+    if let Some(boot_rom) = boot_rom {
+        if boot_rom.len() != model.boot_rom_size() {
+            println!(
+                "Boot ROM is {} bytes, expected {} for {model:?}",
+                boot_rom.len(),
+                model.boot_rom_size()
+            );
+            return None;
+        }
+    }
 
-            let t_cycles = cpu.step() as usize;
+    Some(CPU::with_model(
+        rom_data,
+        model,
+        boot_rom.map(<[u8]>::to_vec),
+    ))
+}
+
+/// Override the boot ROM's palette choice for a DMG game on a CGB.
+fn apply_compat_palette(cpu: &mut CPU, palettes: &CompatPalettes) {
+    if cpu.bus.is_dmg_compat() {
+        cpu.bus.gpu.load_compat_palettes(palettes);
+    } else {
+        println!("Not a DMG game, ignoring --compat-palette");
+    }
+}
 
-            // Advance per-T-cycle hardware (Timer, GPU/PPU, DMA, etc.)
-            for _ in 0..t_cycles {
-                // Tick timer once per T-cycle. Timer interrupt is automatically
-                // requested via the interrupt controller when TIMA overflows.
-                cpu.bus.tick_timer();
+fn configure_audio(cpu: &mut CPU, options: &AudioOptions) -> io::Result<()> {
+    for &channel in &options.muted {
+        cpu.bus.apu.set_muted(channel, true);
+    }
+    cpu.bus.apu.set_solo(options.solo);
+
+    cpu.bus.audio = AudioOutput::new(options.sample_rate);
+    if let Some(path) = &options.wav {
+        cpu.bus.audio.start_wav_recording(path.as_ref())?;
+    }
+    if let Some(dir) = &options.channel_wavs {
+        fs::create_dir_all(dir)?;
+        cpu.bus.audio.start_channel_wav_recording(dir.as_ref())?;
+    }
+    Ok(())
+}
+
+fn run_with_printer(
+    mut cpu: CPU,
+    video: &VideoOptions,
+    state: &StateOptions,
+    analysis: &AnalysisOptions,
+    dir: &str,
+) {
+    if let Err(e) = fs::create_dir_all(dir) {
+        println!("Failed to create printer output directory {dir}: {e}");
+        return;
+    }
+    cpu.bus
+        .serial
+        .connect(Box::new(Printer::new(Some(dir.into()))));
+    run(cpu, video, state, analysis, |_| Ok(()));
+}
+
+fn run_socket(
+    mut cpu: CPU,
+    video: &VideoOptions,
+    state: &StateOptions,
+    analysis: &AnalysisOptions,
+    address: &str,
+    listen: bool,
+) {
+    let link = match (address.strip_prefix("unix:"), listen) {
+        #[cfg(unix)]
+        (Some(path), true) => SocketLink::listen_unix(path, &mut cpu.bus),
+        #[cfg(unix)]
+        (Some(path), false) => SocketLink::connect_unix(path, &mut cpu.bus),
+        #[cfg(not(unix))]
+        (Some(_), _) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Unix domain sockets are not supported on this platform",
+        )),
+        (None, true) => SocketLink::listen_tcp(address, &mut cpu.bus),
+        (None, false) => SocketLink::connect_tcp(address, &mut cpu.bus),
+    };
+
+    match link {
+        Ok(mut link) => run(cpu, video, state, analysis, move |t_cycles| {
+            link.after_step(t_cycles)
+        }),
+        Err(e) => println!("Failed to establish link on {address}: {e}"),
+    }
+}
 
-                // TODO: Tick other per-T-cycle systems here (GPU/PPU, DMA timing, etc.)
+/// Run a single core until max cycles or until the CPU stops, calling
+/// `after_step` with the T-cycles of every step.
+fn run(
+    mut cpu: CPU,
+    video: &VideoOptions,
+    state: &StateOptions,
+    analysis: &AnalysisOptions,
+    mut after_step: impl FnMut(u16) -> io::Result<()>,
+) {
+    let mut cycle_count: u64 = 0;
+    let mut rewind = state.rewinding().then(|| {
+        let interval = state.rewind_interval.unwrap_or(1);
+        RewindBuffer::new(interval, rewind::DEFAULT_MAX_BYTES)
+    });
+    let mut recorder = None;
+    let mut player = None;
+    match &state.movie {
+        Some(MovieOption::Record(path)) => {
+            recorder = Some((path, MovieRecorder::new(&cpu, movie::DEFAULT_HASH_INTERVAL)));
+        }
+        Some(MovieOption::Play(path)) => match start_movie(&mut cpu, path) {
+            Ok(started) => player = Some(started),
+            Err(e) => {
+                println!("Failed to play movie {path}: {e}");
+                return;
             }
+        },
+        None => {}
+    }
+    let mut profiler = None;
+    if analysis.profiling() {
+        cpu.call_stack = Some(CallStack::new());
+        profiler = Some(Profiler::new());
+    }
+    if analysis.covering() {
+        cpu.bus.coverage = Some(Coverage::new(cpu.bus.rom_size()));
+    }
 
-            cycle_count = cycle_count.wrapping_add(t_cycles as u64);
+    let mut last_control_frame = cpu.bus.gpu.frame_count();
 
-            // Check for serial output and print it immediately
-            if cpu.bus.has_serial_output() {
-                let output = cpu.bus.get_serial_output();
-                print!("{}", output);
-                io::stdout().flush().unwrap();
-                cpu.bus.clear_serial_output();
+    // Run the emulation until max cycles or until CPU stops
+    while cycle_count < MAX_CYCLES {
+        let t_cycles = cpu.step();
+        if let (Some(profiler), Some(call_stack)) = (&mut profiler, &cpu.call_stack) {
+            profiler.record(call_stack, t_cycles as u64);
+        }
+        let frame = cpu.bus.gpu.frame_count();
+        if frame != last_control_frame {
+            last_control_frame = frame;
+            let recorder = recorder.as_mut().map(|(_, recorder)| recorder);
+            apply_controls(&mut cpu, rewind.as_mut(), recorder, player.is_some());
+        }
+        if let Some(rewind) = &mut rewind {
+            rewind.capture_if_due(&cpu);
+        }
+        if let Some((_, recorder)) = &mut recorder {
+            recorder.after_step(&mut cpu);
+        }
+        if let Some(player) = &mut player {
+            match player.after_step(&mut cpu) {
+                Ok(true) => {}
+                Ok(false) => {
+                    println!("\n Movie finished at frame {}", cpu.bus.gpu.frame_count());
+                    break;
+                }
+                Err(e) => {
+                    println!("\n Movie playback stopped: {e}");
+                    break;
+                }
             }
+        }
+
+        // STOP halts the system clock until a button is pressed. Input only
+        // arrives at frame boundaries, which never come while the clock is
+        // stopped, so the run can never resume.
+        if cpu.is_stopped() {
+            println!("\n CPU entered STOP mode after {} cycles", cycle_count);
+            break;
+        }
+
+        if let Err(e) = after_step(t_cycles) {
+            println!("\n Link cable disconnected: {e}");
+            break;
+        }
+
+        let previous_count = cycle_count;
+        cycle_count = cycle_count.wrapping_add(t_cycles as u64);
+
+        // Check for serial output and print it immediately
+        flush_serial_output(&mut cpu, "");
+        report_progress(previous_count, cycle_count);
+    }
+
+    if cycle_count >= MAX_CYCLES {
+        println!("\n Reached maximum cycle count ({})", MAX_CYCLES);
+    }
+
+    // Print any remaining serial output
+    flush_serial_output(&mut cpu, "");
+    finish_audio(&mut cpu);
+    if let Some((path, recorder)) = recorder {
+        save_movie_file(path, recorder.finish());
+    }
+    if let Some(profiler) = &profiler {
+        save_profiles(&cpu, profiler, analysis);
+    }
+    if let Some(coverage) = &cpu.bus.coverage {
+        save_coverage(&cpu, coverage, analysis);
+    }
+    if let (Some(buffer), Some(frames)) = (&mut rewind, state.rewind) {
+        rewind_frames(&mut cpu, buffer, frames);
+    }
+    save_screenshot(&cpu, video);
+    save_state_file(&cpu, state);
+}
+
+/// Hand the core to the interactive debugger until the user quits.
+fn run_debugger(mut cpu: CPU, video: &VideoOptions, state: &StateOptions) {
+    if let Err(e) = Debugger::new().repl(&mut cpu, &mut io::stdin().lock(), &mut io::stdout()) {
+        println!("Debugger I/O error: {e}");
+    }
+    finish_audio(&mut cpu);
+    save_screenshot(&cpu, video);
+    save_state_file(&cpu, state);
+}
+
+/// Hand the core to a GDB remote protocol client until it detaches.
+fn run_gdb(mut cpu: CPU, video: &VideoOptions, state: &StateOptions, port: u16) {
+    let listener = match TcpListener::bind(("127.0.0.1", port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("Failed to listen for GDB on port {port}: {e}");
+            return;
+        }
+    };
+    println!("Waiting for GDB on 127.0.0.1:{port} (target remote :{port})");
+    let result = listener
+        .accept()
+        .and_then(|(stream, _)| GdbStub::new().serve(&mut cpu, stream));
+    if let Err(e) = result {
+        println!("GDB connection error: {e}");
+    }
+    finish_audio(&mut cpu);
+    save_screenshot(&cpu, video);
+    save_state_file(&cpu, state);
+}
+
+/// Run two linked cores in lockstep, printing the serial output of each.
+fn run_pair(mut pair: LinkedPair, video: &VideoOptions, state: &StateOptions) {
+    let mut cycle_count: u64 = 0;
+
+    while cycle_count < MAX_CYCLES {
+        let previous_count = cycle_count;
+        cycle_count += pair.run_quantum();
+
+        flush_serial_output(&mut pair.first, "");
+        flush_serial_output(&mut pair.second, "[partner] ");
+        report_progress(previous_count, cycle_count);
+
+        if pair.first.is_stopped() || pair.second.is_stopped() {
+            println!("\n CPU entered STOP mode after {} cycles", cycle_count);
+            break;
+        }
+    }
+
+    if cycle_count >= MAX_CYCLES {
+        println!("\n Reached maximum cycle count ({})", MAX_CYCLES);
+    }
+
+    finish_audio(&mut pair.first);
+    save_screenshot(&pair.first, video);
+    save_state_file(&pair.first, state);
+}
+
+fn flush_serial_output(cpu: &mut CPU, prefix: &str) {
+    if cpu.bus.has_serial_output() {
+        let output = cpu.bus.get_serial_output();
+        print!("{prefix}{output}");
+        io::stdout().flush().unwrap();
+        cpu.bus.clear_serial_output();
+    }
+}
+
+fn finish_audio(cpu: &mut CPU) {
+    if let Err(e) = cpu.bus.audio.finish_wav_recording() {
+        println!("Failed to finish WAV file: {e}");
+    }
+}
 
-            // Print progress every million cycles
-            if cycle_count % 1_000_000 == 0 {
-                eprint!("\r Cycles: {}M...", cycle_count / 1_000_000);
-                io::stderr().flush().unwrap();
+/// Save the last frame; on an SGB, the composed 256x224 output including the
+/// border, without LCD colour correction since the SNES drives a TV.
+fn save_screenshot(cpu: &CPU, video: &VideoOptions) {
+    let Some(path) = &video.screenshot else {
+        return;
+    };
+    let result = match &cpu.bus.sgb {
+        Some(sgb) => ppu::write_png(
+            path.as_ref(),
+            &sgb.compose(),
+            sgb::BORDER_WIDTH,
+            ColorCorrection::None,
+        ),
+        None => cpu
+            .bus
+            .gpu
+            .write_screenshot(path.as_ref(), video.color_correction),
+    };
+    if let Err(e) = result {
+        println!("Failed to save screenshot: {e}");
+    }
+}
+
+/// Apply the controls typed on standard input since the last frame. Button
+/// presses go through the movie recorder when there is one, so that they
+/// are recorded; a movie being played supplies all input itself.
+fn apply_controls(
+    cpu: &mut CPU,
+    mut rewind: Option<&mut RewindBuffer>,
+    mut recorder: Option<&mut MovieRecorder>,
+    playing: bool,
+) {
+    for line in controls::poll_stdin() {
+        let (mask, pressed) = match controls::parse(&line) {
+            Ok(Some(Control::Press(mask))) => (mask, true),
+            Ok(Some(Control::Release(mask))) => (mask, false),
+            Ok(Some(Control::Rewind(_))) if recorder.is_some() || playing => {
+                println!("\n Cannot rewind while a movie is recorded or played");
+                continue;
             }
+            Ok(Some(Control::Rewind(steps))) => {
+                let Some(buffer) = rewind.as_deref_mut() else {
+                    println!("\n No rewind buffer: start with --rewind or --rewind-interval");
+                    continue;
+                };
+                let from_frame = cpu.bus.gpu.frame_count();
+                for _ in 0..steps {
+                    if !rewind_once(cpu, buffer) {
+                        break;
+                    }
+                }
+                println!(
+                    "\n Rewound from frame {from_frame} to frame {}",
+                    cpu.bus.gpu.frame_count()
+                );
+                continue;
+            }
+            Ok(None) => continue,
+            Err(e) => {
+                println!("\n {e}");
+                continue;
+            }
+        };
+        if playing {
+            println!("\n Input is ignored while a movie is played");
+        } else if let Some(recorder) = recorder.as_deref_mut() {
+            recorder.set_buttons(mask, pressed);
+        } else {
+            let held = cpu.bus.buttons();
+            cpu.bus
+                .set_buttons(if pressed { held | mask } else { held & !mask });
+        }
+    }
+}
+
+/// Step back at least `frames` frames, or as far as the buffer goes.
+fn rewind_frames(cpu: &mut CPU, buffer: &mut RewindBuffer, frames: u64) {
+    let end_frame = cpu.bus.gpu.frame_count();
+    println!(
+        "\n Rewind buffer: {} snapshots in {} KiB",
+        buffer.len(),
+        buffer.memory_usage() / 1024
+    );
+    while cpu.bus.gpu.frame_count() + frames > end_frame {
+        if !rewind_once(cpu, buffer) {
+            break;
         }
+    }
+    println!(
+        " Rewound from frame {end_frame} to frame {}",
+        cpu.bus.gpu.frame_count()
+    );
+}
 
-        if cycle_count >= MAX_CYCLES {
-            println!("\n Reached maximum cycle count ({})", MAX_CYCLES);
+/// Step back one snapshot. Returns false when the buffer is used up.
+fn rewind_once(cpu: &mut CPU, buffer: &mut RewindBuffer) -> bool {
+    match buffer.rewind(cpu) {
+        Ok(rewound) => rewound,
+        Err(e) => {
+            println!("\n Failed to rewind: {e}");
+            false
         }
+    }
+}
+
+/// Load the symbol file at `path`, or else the ROM's `.sym` file if there
+/// is one.
+fn load_symbols(
+    cpu: &mut CPU,
+    rom_path: &str,
+    path: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let default_path = Path::new(rom_path).with_extension("sym");
+    let path = match path {
+        Some(path) => path,
+        None if default_path.is_file() => default_path.to_str().ok_or("invalid path")?,
+        None => return Ok(()),
+    };
+    let symbols = Symbols::load(path).map_err(|e| format!("{path}: {e}"))?;
+    println!("Loaded {} symbols from {path}", symbols.len());
+    cpu.bus.symbols = Some(symbols);
+    Ok(())
+}
+
+fn load_state_file(cpu: &mut CPU, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let data = fs::read(path)?;
+    cpu.load_state(&data)?;
+    Ok(())
+}
+
+fn start_movie(cpu: &mut CPU, path: &str) -> Result<MoviePlayer, Box<dyn std::error::Error>> {
+    let movie = Movie::from_bytes(&fs::read(path)?)?;
+    println!("Playing {} frames from {path}", movie.frames());
+    Ok(MoviePlayer::start(movie, cpu)?)
+}
+
+fn save_movie_file(path: &str, movie: Movie) {
+    match fs::write(path, movie.to_bytes()) {
+        Ok(()) => println!(" Recorded {} frames to {path}", movie.frames()),
+        Err(e) => println!("Failed to save movie: {e}"),
+    }
+}
+
+fn save_profiles(cpu: &CPU, profiler: &Profiler, analysis: &AnalysisOptions) {
+    if let Some(path) = &analysis.profile {
+        save_analysis(path, profiler.flat_report(&cpu.bus));
+    }
+    if let Some(path) = &analysis.profile_folded {
+        save_analysis(path, profiler.folded_stacks(&cpu.bus));
+    }
+}
 
-        // Print any remaining serial output
-        if cpu.bus.has_serial_output() {
-            let output = cpu.bus.get_serial_output();
-            print!("{}", output);
+fn save_coverage(cpu: &CPU, coverage: &Coverage, analysis: &AnalysisOptions) {
+    if let Some(path) = &analysis.coverage {
+        save_analysis(path, coverage.report(cpu.bus.symbols.as_ref()));
+    }
+    if let Some(path) = &analysis.cdl {
+        save_analysis(path, coverage.to_cdl());
+    }
+}
+
+fn save_analysis(path: &str, contents: impl AsRef<[u8]>) {
+    match fs::write(path, contents) {
+        Ok(()) => println!(" Saved {path}"),
+        Err(e) => println!("Failed to save {path}: {e}"),
+    }
+}
+
+fn save_state_file(cpu: &CPU, state: &StateOptions) {
+    if let Some(path) = &state.save {
+        if let Err(e) = fs::write(path, cpu.save_state()) {
+            println!("Failed to save state: {e}");
         }
+    }
+}
 
-        println!("\n==========================================\n");
+// Print progress every million cycles
+fn report_progress(previous_count: u64, cycle_count: u64) {
+    if previous_count / 1_000_000 != cycle_count / 1_000_000 {
+        eprint!("\r Cycles: {}M...", cycle_count / 1_000_000);
+        io::stderr().flush().unwrap();
     }
 }
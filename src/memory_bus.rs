@@ -5,18 +5,30 @@
 //! This module implements the emulator's memory map. See the linked pandocs page
 //! for the canonical description of each memory region.
 
+use crate::apu::{self, APU};
+use crate::audio::{AudioOutput, DEFAULT_SAMPLE_RATE};
+use crate::debugger::coverage::{self, Coverage};
+use crate::debugger::symbols::Symbols;
+use crate::debugger::watch::Watchpoints;
+use crate::hdma::{Hdma, HDMA1_REGISTER, HDMA5_REGISTER};
 use crate::interrupts::{Interrupt, InterruptController};
+use crate::io_registers;
+use crate::joypad::{Button, Joypad, JOYP_REGISTER};
+use crate::model::Model;
 use crate::ppu;
-use crate::timer::Timer;
+use crate::save_state::{Snapshot, StateError, StateReader, StateWriter};
+use crate::serial::{Serial, SB_REGISTER, SC_REGISTER};
+use crate::sgb::Sgb;
+use crate::timer::{Timer, DIV_REGISTER};
 
 /// Memory Bus implementing the Game Boy memory map:
 ///
 /// 0x0000-0x3FFF : ROM Bank 0 (16KB) - Fixed bank
 /// 0x4000-0x7FFF : ROM Bank 1-N (16KB) - Switchable via MBC
-/// 0x8000-0x9FFF : VRAM (8KB) - Video RAM
+/// 0x8000-0x9FFF : VRAM (8KB) - Video RAM (CGB: bank 0/1 via VBK)
 /// 0xA000-0xBFFF : External RAM (8KB) - Cartridge RAM (if present)
 /// 0xC000-0xCFFF : Work RAM Bank 0 (4KB)
-/// 0xD000-0xDFFF : Work RAM Bank 1 (4KB)
+/// 0xD000-0xDFFF : Work RAM Bank 1 (4KB) (CGB: bank 1-7 via SVBK)
 /// 0xE000-0xFDFF : Echo RAM (mirror of 0xC000-0xDDFF)
 /// 0xFE00-0xFE9F : OAM (Object Attribute Memory) - Sprite data
 /// 0xFEA0-0xFEFF : Unusable memory
@@ -28,6 +40,7 @@ pub const MEM_SIZE: usize = 0x10000; // 64KB = 65,536 bytes (0x0000 - 0xFFFF + 1
 //Memory region boundaries
 const ROM_START: usize = 0x0000;
 const ROM_END: usize = 0x7FFF;
+const ROM_BANK_SIZE: usize = 0x4000;
 
 const VRAM_START: usize = 0x8000;
 const VRAM_END: usize = 0x9FFF;
@@ -46,30 +59,102 @@ const ECHO_RAM_END: usize = 0xFDFF;
 const OAM_START: usize = 0xFE00;
 const OAM_END: usize = 0xFE9F;
 
-const IO_REGISTERS_START: usize = 0xFF00;
-const IO_REGISTERS_END: usize = 0xFF7F;
+const IO_REGISTERS_START: usize = io_registers::IO_START as usize;
+const IO_REGISTERS_END: usize = io_registers::IO_END as usize;
 
 const HRAM_START: usize = 0xFF80;
 const HRAM_END: usize = 0xFFFE;
 
 // Specific I/O register addresses
-const SERIAL_TRANSFER_DATA: usize = 0xFF01; // SB register
-const SERIAL_TRANSFER_CONTROL: usize = 0xFF02; // SC register
+const JOYPAD: usize = JOYP_REGISTER as usize; // P1/JOYP register
+const SERIAL_TRANSFER_DATA: usize = SB_REGISTER as usize; // SB register
+const SERIAL_TRANSFER_CONTROL: usize = SC_REGISTER as usize; // SC register
+const OAM_DMA: usize = 0xFF46; // DMA register (OAM DMA source and start)
+const CGB_MODE_SELECT: usize = 0xFF4C; // KEY0 register (CGB boot ROM only)
+const SPEED_SWITCH: usize = 0xFF4D; // KEY1 register (CGB only)
+const BOOT_ROM_DISABLE: usize = 0xFF50; // BANK register (boot ROM unmap)
+const VRAM_BANK_SELECT: usize = 0xFF4F; // VBK register (CGB only)
+const HDMA_START: usize = HDMA1_REGISTER as usize; // HDMA1-HDMA5 (CGB only)
+const HDMA_END: usize = HDMA5_REGISTER as usize;
+const PALETTES_START: usize = 0xFF68; // BCPS/BCPD/OCPS/OCPD/OPRI (CGB only)
+const PALETTES_END: usize = 0xFF6C;
+const WRAM_BANK_SELECT: usize = 0xFF70; // SVBK register (CGB only)
+const APU_START: usize = apu::APU_REGISTERS_START as usize; // NR10
+const APU_END: usize = apu::WAVE_RAM_END as usize; // end of wave RAM
+const PCM12: usize = apu::PCM12_REGISTER as usize; // channel outputs (CGB only)
+const PCM34: usize = apu::PCM34_REGISTER as usize;
+
+// Work RAM banking: bank 0 is fixed, 0xD000-0xDFFF maps bank 1 (DMG) or 1-7 (CGB)
+const WRAM_BANK_SIZE: usize = 0x1000;
+const WRAM_BANKS: usize = 8;
+const SVBK_BANK_MASK: u8 = 0x07;
 
 // Memory offsets
 const VRAM_OFFSET: usize = VRAM_START;
 const ECHO_RAM_MIRROR_OFFSET: usize = 0x2000;
+const OAM_OFFSET: usize = OAM_START;
+
+// Boot ROM overlay: 0x0000-0x00FF, plus 0x0200-0x08FF for the CGB boot ROM
+const BOOT_ROM_END: usize = 0x00FF;
+const CGB_BOOT_ROM_START: usize = 0x0200;
 
 // Default values
-const SERIAL_CONTROL_IDLE: u8 = 0x7E; // Bit 7 = 0 (no transfer in progress)
 const UNMAPPED_MEMORY_VALUE: u8 = 0xFF;
+const KEY1_UNUSED_BITS: u8 = 0x7E;
+// KEY0 bits 2-3: 0b01 selects DMG compatibility mode
+const KEY0_MODE_MASK: u8 = 0x0C;
+const KEY0_DMG_MODE: u8 = 0x04;
+
+/// An OAM DMA transfer in progress: one byte is copied per M-cycle.
+#[derive(Clone, Copy)]
+struct OamDma {
+    source: u16,
+    offset: u16,
+}
 
 pub struct MemoryBus {
     pub memory: [u8; MEM_SIZE],
+    /// Work RAM, 8 banks of 4 KiB (only banks 0 and 1 are reachable on DMG).
+    wram: Vec<u8>,
+    /// SVBK (0xFF70) bits 0-2: Work RAM bank at 0xD000 (0 selects bank 1).
+    wram_bank: u8,
     pub gpu: ppu::GPU,
+    pub apu: APU,
+    /// Resampled APU output for the host.
+    pub audio: AudioOutput,
     pub timer: Timer,
     pub interrupts: InterruptController,
-    pub serial_output: Vec<u8>,
+    pub joypad: Joypad,
+    pub serial: Serial,
+    /// Super Game Boy command handling, when an SGB game runs on an SGB.
+    pub sgb: Option<Sgb>,
+    /// True when running CGB hardware in CGB mode; gates CGB-only registers
+    /// such as KEY1. False for DMG games on a CGB (DMG compatibility mode).
+    pub cgb_mode: bool,
+    /// KEY0 as written by the CGB boot ROM, applied when the boot ROM unmaps.
+    key0: u8,
+    /// KEY1 bit 0: a speed switch will happen on the next STOP.
+    speed_switch_armed: bool,
+    /// KEY1 bit 7: the CPU is running in double-speed mode.
+    double_speed: bool,
+    /// Toggled every M-cycle, to sample audio at the normal rate in double speed.
+    odd_m_cycle: bool,
+    /// OAM DMA source written to 0xFF46, starting after a one M-cycle delay.
+    pending_dma: Option<u16>,
+    /// The running OAM DMA transfer, if any.
+    dma: Option<OamDma>,
+    /// CGB VRAM DMA (HDMA1-HDMA5).
+    hdma: Hdma,
+    /// Boot ROM mapped over the start of the cartridge ROM until 0xFF50 is written.
+    boot_rom: Option<Vec<u8>>,
+    /// Debugger watchpoints, only present while at least one is set.
+    pub watchpoints: Option<Watchpoints>,
+    /// Names of the game's addresses, loaded from a symbol file.
+    pub symbols: Option<Symbols>,
+    /// ROM coverage, recorded only while present.
+    pub coverage: Option<Coverage>,
+    /// Size of the cartridge ROM file.
+    rom_size: usize,
 }
 
 impl MemoryBus {
@@ -78,38 +163,220 @@ impl MemoryBus {
         let copy_len = std::cmp::min(rom_data.len(), MEM_SIZE);
         memory[..copy_len].copy_from_slice(&rom_data[..copy_len]);
 
-        // Initialize serial registers to sensible defaults so reads behave predictably.
-        memory[SERIAL_TRANSFER_DATA] = 0x00;
-        memory[SERIAL_TRANSFER_CONTROL] = SERIAL_CONTROL_IDLE;
-
         MemoryBus {
             memory,
+            wram: vec![0; WRAM_BANK_SIZE * WRAM_BANKS],
+            wram_bank: 0,
             gpu: ppu::GPU::new(),
+            apu: APU::new(),
+            audio: AudioOutput::new(DEFAULT_SAMPLE_RATE),
             timer: Timer::new(),
             interrupts: InterruptController::new(),
-            serial_output: Vec::new(),
+            joypad: Joypad::new(),
+            serial: Serial::new(),
+            sgb: None,
+            cgb_mode: false,
+            key0: 0,
+            speed_switch_armed: false,
+            double_speed: false,
+            odd_m_cycle: false,
+            pending_dma: None,
+            dma: None,
+            hdma: Hdma::new(),
+            boot_rom: None,
+            watchpoints: None,
+            symbols: None,
+            coverage: None,
+            rom_size: rom_data.len(),
+        }
+    }
+
+    /// Set up the I/O state `model`'s boot ROM leaves behind when the
+    /// cartridge starts at 0x0100.
+    pub fn apply_post_boot_state(&mut self, model: Model) {
+        self.cgb_mode = model.is_cgb();
+        self.gpu.set_cgb_mode(self.cgb_mode);
+        self.apu.set_cgb_hardware(model.is_cgb());
+        self.timer.set_div_counter(model.post_boot_div());
+        self.write_joypad(0x00); // P1 = 0xCF: both button rows selected
+        self.interrupts.write_if(Interrupt::VBlank.bit_mask());
+        if model == Model::Sgb {
+            self.apu.skip_boot_chime();
+        }
+        if model.is_cgb() {
+            // SC reads 0x7F: the CGB boot ROM leaves the internal clock selected
+            self.serial.write(SC_REGISTER, 0x01);
+            self.gpu.write_register(ppu::DMA_ADDR, 0x00);
+        }
+    }
+
+    /// Map a boot ROM over the cartridge and put the hardware in its
+    /// power-on state: LCD and APU off, DIV counter at zero.
+    pub fn map_boot_rom(&mut self, model: Model, boot_rom: Vec<u8>) {
+        self.cgb_mode = model.is_cgb();
+        self.gpu.set_cgb_mode(self.cgb_mode);
+        self.apu.set_cgb_hardware(model.is_cgb());
+        self.boot_rom = Some(boot_rom);
+        self.timer.set_div_counter(0);
+        self.apu.write(apu::NR52_REGISTER, 0x00);
+        for (register, value) in [(ppu::LCDC_ADDR, 0x00), (ppu::BGP_ADDR, 0x00)] {
+            self.gpu.write_register(register, value);
+        }
+    }
+
+    /// Switch a CGB into DMG compatibility mode: CGB-only registers are
+    /// unmapped and the DMG palettes are coloured through palette RAM.
+    pub fn enter_dmg_compat(&mut self) {
+        self.cgb_mode = false;
+        self.gpu.set_cgb_mode(false);
+        self.gpu.set_dmg_compat(true);
+    }
+
+    /// Whether a DMG game is running in compatibility mode on a CGB.
+    pub fn is_dmg_compat(&self) -> bool {
+        self.gpu.is_dmg_compat()
+    }
+
+    // The CGB boot ROM selects the mode through KEY0 before unmapping itself.
+    fn unmap_boot_rom(&mut self) {
+        if self.boot_rom.take().is_some()
+            && self.cgb_mode
+            && self.key0 & KEY0_MODE_MASK == KEY0_DMG_MODE
+        {
+            self.enter_dmg_compat();
+        }
+    }
+
+    /// Whether the boot ROM is still mapped over the cartridge.
+    #[allow(dead_code)]
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_rom.is_some()
+    }
+
+    /// The bank mapped at `address`, for bank-qualified addresses such as
+    /// `01:4000`. Unbanked regions are bank 0. Without a memory bank
+    /// controller, 0x4000-0x7FFF always holds ROM bank 1.
+    pub fn bank_at(&self, address: u16) -> u16 {
+        match address as usize {
+            ROM_START..=0x3FFF => 0,
+            0x4000..=ROM_END => 1,
+            VRAM_START..=VRAM_END => self.gpu.vram_bank() as u16,
+            WORK_RAM_BANK1_START..=WORK_RAM_BANK1_END => (self.wram_bank as u16).max(1),
+            // Echo of the switchable Work RAM bank
+            0xF000..=ECHO_RAM_END => (self.wram_bank as u16).max(1),
+            _ => 0,
         }
     }
 
+    /// The symbol naming exactly `address` in the banks mapped now.
+    pub fn symbol_at(&self, address: u16) -> Option<&str> {
+        self.symbols
+            .as_ref()?
+            .name_at(self.bank_at(address), address)
+    }
+
+    /// `address` as the closest symbol before it plus an offset, such as
+    /// `Main.loop+3`, in the banks mapped now.
+    pub fn describe_address(&self, address: u16) -> Option<String> {
+        self.symbols
+            .as_ref()?
+            .describe(self.bank_at(address), address)
+    }
+
+    pub fn rom_size(&self) -> usize {
+        self.rom_size
+    }
+
+    /// Read a byte as the CPU, checking watchpoints.
     pub fn read_byte(&self, address: u16) -> u8 {
+        self.cpu_read(address, coverage::READ)
+    }
+
+    /// Read an opcode or operand byte as the CPU. Unlike `read_byte`, ROM
+    /// coverage counts it as executed.
+    pub fn fetch_byte(&self, address: u16) -> u8 {
+        self.cpu_read(address, coverage::EXECUTED)
+    }
+
+    fn cpu_read(&self, address: u16, access: u8) -> u8 {
+        let value = self.peek(address);
+        if let Some(watchpoints) = &self.watchpoints {
+            watchpoints.check_read(address, value);
+        }
+        self.record_coverage(address, access);
+        value
+    }
+
+    fn record_coverage(&self, address: u16, access: u8) {
+        let Some(coverage) = &self.coverage else {
+            return;
+        };
+        let address = address as usize;
+        if address <= ROM_END && !self.boot_rom_overlays(address) {
+            let bank = self.bank_at(address as u16) as usize;
+            coverage.record(bank * ROM_BANK_SIZE + address % ROM_BANK_SIZE, access);
+        }
+    }
+
+    /// Read a byte without triggering watchpoints, for debuggers and DMA.
+    /// Bits of I/O registers that are unused or write-only always read as 1,
+    /// following the I/O register table.
+    pub fn peek(&self, address: u16) -> u8 {
+        let value = self.read_mapped(address);
+        if (io_registers::IO_START..=io_registers::IO_END).contains(&address) {
+            value | io_registers::read_mask(address, self.cgb_mode)
+        } else {
+            value
+        }
+    }
+
+    // Individual I/O registers are matched ahead of the generic I/O range.
+    #[allow(clippy::match_overlapping_arm)]
+    fn read_mapped(&self, address: u16) -> u8 {
         let address = address as usize;
         match address {
-            ROM_START..=ROM_END => self.memory[address],
-            VRAM_START..=VRAM_END => self.gpu.read_vram(address - VRAM_OFFSET),
+            ROM_START..=ROM_END => self.read_rom(address),
+            VRAM_START..=VRAM_END => {
+                if self.gpu.vram_accessible() {
+                    self.gpu.read_vram(address - VRAM_OFFSET)
+                } else {
+                    UNMAPPED_MEMORY_VALUE
+                }
+            }
             EXTERNAL_RAM_START..=EXTERNAL_RAM_END => self.memory[address],
-            WORK_RAM_START..=WORK_RAM_BANK0_END => self.memory[address],
-            WORK_RAM_BANK1_START..=WORK_RAM_BANK1_END => self.memory[address],
+            WORK_RAM_START..=WORK_RAM_BANK1_END => self.wram[self.wram_index(address)],
             ECHO_RAM_START..=ECHO_RAM_END => {
                 // Echo RAM - mirrors Work RAM
                 let mirror_address = address - ECHO_RAM_MIRROR_OFFSET;
-                self.memory[mirror_address]
+                self.wram[self.wram_index(mirror_address)]
             }
-            OAM_START..=OAM_END => self.memory[address],
-            SERIAL_TRANSFER_DATA | SERIAL_TRANSFER_CONTROL => self.memory[address],
+            OAM_START..=OAM_END => {
+                if self.oam_accessible() {
+                    self.gpu.read_oam(address - OAM_OFFSET)
+                } else {
+                    UNMAPPED_MEMORY_VALUE
+                }
+            }
+            JOYPAD => match &self.sgb {
+                Some(sgb) => sgb.read_joypad(self.joypad.read()),
+                None => self.joypad.read(),
+            },
+            // Serial registers (0xFF01-0xFF02) are handled by the serial module
+            SERIAL_TRANSFER_DATA | SERIAL_TRANSFER_CONTROL => self.serial.read(address as u16),
             // Timer registers (0xFF04-0xFF07) are handled by the timer module
             0xFF04 | 0xFF05 | 0xFF06 | 0xFF07 => self.timer.read(address as u16),
+            // Audio registers and wave RAM (0xFF10-0xFF3F) are handled by the APU
+            APU_START..=APU_END => self.apu.read(address as u16),
             // LCD registers (0xFF40-0xFF4B) are handled by the PPU
             0xFF40..=0xFF4B => self.gpu.read_register(address as u16),
+            SPEED_SWITCH => self.read_key1(),
+            // CGB bank selects; the I/O table masks them to 0xFF on DMG
+            VRAM_BANK_SELECT => self.gpu.vram_bank() as u8,
+            HDMA_START..=HDMA_END => self.hdma.read(address as u16),
+            PALETTES_START..=PALETTES_END => self.gpu.read_register(address as u16),
+            WRAM_BANK_SELECT => self.wram_bank,
+            PCM12 | PCM34 => self.apu.read_pcm(address as u16),
+            BOOT_ROM_DISABLE => UNMAPPED_MEMORY_VALUE,
             // Interrupt Flag register (0xFF0F)
             0xFF0F => self.interrupts.read_if(),
             IO_REGISTERS_START..=IO_REGISTERS_END => self.memory[address],
@@ -120,41 +387,67 @@ impl MemoryBus {
         }
     }
 
+    /// Write a byte as the CPU, checking watchpoints.
     pub fn write_byte(&mut self, address: u16, value: u8) {
+        if let Some(watchpoints) = &self.watchpoints {
+            watchpoints.check_write(address, self.peek(address), value);
+        }
+        self.write_mapped(address, value);
+    }
+
+    #[allow(clippy::match_overlapping_arm)]
+    fn write_mapped(&mut self, address: u16, value: u8) {
         let address = address as usize;
         match address {
             ROM_START..=ROM_END => {} // ROM - ignore writes
-            VRAM_START..=VRAM_END => self.gpu.write_vram(address - VRAM_OFFSET, value),
+            VRAM_START..=VRAM_END => self.write_vram(address, value),
             EXTERNAL_RAM_START..=EXTERNAL_RAM_END => self.memory[address] = value,
-            WORK_RAM_START..=WORK_RAM_BANK1_END => self.memory[address] = value,
+            WORK_RAM_START..=WORK_RAM_BANK1_END => {
+                let index = self.wram_index(address);
+                self.wram[index] = value;
+            }
             ECHO_RAM_START..=ECHO_RAM_END => {
-                self.memory[address - ECHO_RAM_MIRROR_OFFSET] = value;
-            }
-            OAM_START..=OAM_END => self.memory[address] = value,
-            SERIAL_TRANSFER_DATA => {
-                // Store the value in the SB hardware register so reads return it
-                self.memory[address] = value;
-            }
-            SERIAL_TRANSFER_CONTROL => {
-                // If bit 7 is set, start a transfer. Use `read_byte` so any special
-                // behavior for reading SB (0xFF01) is preserved.
-                if value & 0x80 != 0 {
-                    let character = self.read_byte(0xFF01);
-                    self.serial_output.push(character);
-
-                    // Reset bit 7 to signal transfer complete while preserving other bits
-                    self.memory[SERIAL_TRANSFER_CONTROL] = value & 0x7F;
-                } else {
-                    // For other writes, store the value in the SC register
-                    self.memory[address] = value;
-                }
+                let index = self.wram_index(address - ECHO_RAM_MIRROR_OFFSET);
+                self.wram[index] = value;
+            }
+            OAM_START..=OAM_END => self.write_oam(address, value),
+            JOYPAD => self.write_joypad(value),
+            // Serial registers (0xFF01-0xFF02) are handled by the serial module
+            SERIAL_TRANSFER_DATA | SERIAL_TRANSFER_CONTROL => {
+                self.serial.write(address as u16, value);
             }
             // Timer registers (0xFF04-0xFF07) are handled by the timer module
             0xFF04 | 0xFF05 | 0xFF06 | 0xFF07 => {
                 self.timer.write(address as u16, value);
             }
+            // Audio registers and wave RAM (0xFF10-0xFF3F) are handled by the APU
+            APU_START..=APU_END => self.apu.write(address as u16, value),
+            OAM_DMA => self.write_dma(value),
             // LCD registers (0xFF40-0xFF4B) are handled by the PPU
             0xFF40..=0xFF4B => self.gpu.write_register(address as u16, value),
+            SPEED_SWITCH => self.write_key1(value),
+            VRAM_BANK_SELECT if self.cgb_mode => self.gpu.set_vram_bank(value as usize),
+            WRAM_BANK_SELECT if self.cgb_mode => self.wram_bank = value & SVBK_BANK_MASK,
+            HDMA_START..=HDMA_END if self.cgb_mode => {
+                let lcd_enabled = self.gpu.lcd_enabled();
+                self.hdma.write(address as u16, value, lcd_enabled);
+            }
+            PALETTES_START..=PALETTES_END if self.cgb_mode => {
+                self.gpu.write_register(address as u16, value);
+            }
+            CGB_MODE_SELECT if self.cgb_mode && self.boot_rom.is_some() => self.key0 = value,
+            // Writing any non-zero value unmaps the boot ROM until reset
+            BOOT_ROM_DISABLE if value != 0 => self.unmap_boot_rom(),
+            // Ignored: CGB-only registers on DMG, KEY0 after boot, zero
+            // writes to BANK and the read-only PCM registers
+            CGB_MODE_SELECT
+            | VRAM_BANK_SELECT
+            | WRAM_BANK_SELECT
+            | HDMA_START..=HDMA_END
+            | PALETTES_START..=PALETTES_END
+            | BOOT_ROM_DISABLE
+            | PCM12
+            | PCM34 => {}
             // Interrupt Flag register (0xFF0F)
             0xFF0F => self.interrupts.write_if(value),
             IO_REGISTERS_START..=IO_REGISTERS_END => self.memory[address] = value,
@@ -168,27 +461,68 @@ impl MemoryBus {
     /// Check if there's serial output available and return it as a string
     /// This is used by Blargg test ROMs to output test results
     pub fn get_serial_output(&self) -> String {
-        String::from_utf8_lossy(&self.serial_output).to_string()
+        String::from_utf8_lossy(self.serial.output()).to_string()
     }
 
     /// Clear the serial output buffer
     pub fn clear_serial_output(&mut self) {
-        self.serial_output.clear();
+        self.serial.clear_output();
     }
 
     /// Check if serial output has content
     pub fn has_serial_output(&self) -> bool {
-        !self.serial_output.is_empty()
+        !self.serial.output().is_empty()
     }
 
-    /// Tick the timer by one T-cycle and request timer interrupt if needed.
+    /// Advance all bus hardware by one M-cycle (4 T-cycles).
+    ///
+    /// The CPU calls this before each of its memory accesses and internal
+    /// cycles, so peripherals are always up to date when the access happens.
+    /// The timer, serial port, APU and PPU run per T-cycle; OAM DMA copies one byte
+    /// per M-cycle, and the APU output is sampled once per M-cycle.
     ///
-    /// This must be called once per T-cycle in the emulation loop.
-    /// If the timer overflows, the timer interrupt is automatically requested.
-    pub fn tick_timer(&mut self) {
-        if self.timer.tick() {
-            self.interrupts.request_interrupt(Interrupt::Timer);
+    /// In CGB double-speed mode the timer, serial port and OAM DMA follow the
+    /// doubled CPU clock, while the PPU and APU stay on the normal clock: they
+    /// advance 2 T-cycles per M-cycle and audio is sampled every other M-cycle.
+    pub fn tick_m_cycle(&mut self) {
+        let frame = self.gpu.frame_count();
+        for t_cycle in 0..4 {
+            if self.timer.tick() {
+                self.interrupts.request_interrupt(Interrupt::Timer);
+            }
+            if self.serial.tick() {
+                self.interrupts.request_interrupt(Interrupt::Serial);
+            }
+            if !self.double_speed || t_cycle & 1 == 0 {
+                self.apu.tick(self.timer.div_counter());
+                if self.gpu.tick(&mut self.interrupts) {
+                    self.hdma.on_hblank();
+                }
+            }
         }
+        if self.gpu.frame_count() != frame {
+            if let Some(sgb) = &mut self.sgb {
+                sgb.on_frame(self.gpu.framebuffer());
+            }
+        }
+        self.odd_m_cycle = !self.odd_m_cycle;
+        if !self.double_speed || self.odd_m_cycle {
+            self.audio
+                .push(self.apu.output(), self.apu.channel_outputs());
+        }
+        self.tick_dma();
+        self.tick_hdma();
+    }
+
+    /// Whether a CGB VRAM DMA block is being copied; the CPU is halted
+    /// meanwhile.
+    pub fn is_hdma_active(&self) -> bool {
+        self.hdma.is_copying()
+    }
+
+    /// Whether an OAM DMA transfer is currently copying bytes.
+    pub fn is_dma_active(&self) -> bool {
+        self.dma.is_some_and(|dma| dma.offset > 0)
     }
 
     /// Request an interrupt.
@@ -201,4 +535,603 @@ impl MemoryBus {
     pub fn any_interrupt_pending(&self) -> bool {
         self.interrupts.any_interrupt_pending()
     }
+
+    /// Press or release a button, requesting the Joypad interrupt on a
+    /// high-to-low transition of a selected input line.
+    #[allow(dead_code)]
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        if self.joypad.set_button(button, pressed) {
+            self.interrupts.request_interrupt(Interrupt::Joypad);
+        }
+    }
+
+    /// Set every button at once from a mask of [`Button::mask`] bits.
+    pub fn set_buttons(&mut self, pressed: u8) {
+        for button in Button::ALL {
+            self.set_button(button, pressed & button.mask() != 0);
+        }
+    }
+
+    /// Buttons currently held, as a mask of [`Button::mask`] bits.
+    pub fn buttons(&self) -> u8 {
+        self.joypad.pressed()
+    }
+
+    /// Reset the internal divider, exactly as a write to DIV would.
+    pub fn reset_div(&mut self) {
+        self.timer.write(DIV_REGISTER, 0);
+    }
+
+    /// Whether KEY1 has been armed for a speed switch on the next STOP.
+    pub fn speed_switch_armed(&self) -> bool {
+        self.speed_switch_armed
+    }
+
+    /// Toggle between normal and double speed and disarm KEY1.
+    ///
+    /// Called by the CPU when STOP executes with a speed switch armed.
+    pub fn perform_speed_switch(&mut self) {
+        self.double_speed = !self.double_speed;
+        self.speed_switch_armed = false;
+        self.apu.set_double_speed(self.double_speed);
+    }
+
+    /// Whether the CPU is currently running in CGB double-speed mode.
+    #[allow(dead_code)]
+    pub fn is_double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    // VRAM writes are dropped while the PPU is drawing.
+    fn write_vram(&mut self, address: usize, value: u8) {
+        if self.gpu.vram_accessible() {
+            self.gpu.write_vram(address - VRAM_OFFSET, value);
+        }
+    }
+
+    fn write_oam(&mut self, address: usize, value: u8) {
+        if self.oam_accessible() {
+            self.gpu.write_oam(address - OAM_OFFSET, value);
+        }
+    }
+
+    // OAM is locked while the PPU is scanning or drawing and while DMA is copying.
+    fn oam_accessible(&self) -> bool {
+        self.gpu.oam_accessible() && !self.is_dma_active()
+    }
+
+    // DMA (0xFF46): latch the source page; the transfer starts one M-cycle later.
+    fn write_dma(&mut self, value: u8) {
+        self.gpu.write_register(OAM_DMA as u16, value);
+        self.pending_dma = Some((value as u16) << 8);
+    }
+
+    fn tick_dma(&mut self) {
+        if let Some(mut dma) = self.dma {
+            let value = self.read_dma_source(dma.source + dma.offset);
+            self.gpu.write_oam(dma.offset as usize, value);
+            dma.offset += 1;
+            self.dma = if (dma.offset as usize) < ppu::OAM_SIZE {
+                Some(dma)
+            } else {
+                None
+            };
+        }
+
+        if let Some(source) = self.pending_dma.take() {
+            self.dma = Some(OamDma { source, offset: 0 });
+        }
+    }
+
+    // VRAM DMA copies 16 bytes per 8 M-cycles at normal speed. In double-speed
+    // mode the M-cycles are twice as short, so each copies one byte.
+    fn tick_hdma(&mut self) {
+        let bytes = if self.double_speed { 1 } else { 2 };
+        for _ in 0..bytes {
+            if let Some((source, destination)) = self.hdma.next_transfer() {
+                let value = self.read_dma_source(source);
+                self.gpu.write_vram(destination as usize, value);
+            }
+        }
+    }
+
+    // DMA reads bypass the CPU's VRAM lock; sources at 0xE000 and above read
+    // from the Work RAM mirror.
+    fn read_dma_source(&self, address: u16) -> u8 {
+        let address = address as usize;
+        match address {
+            VRAM_START..=VRAM_END => self.gpu.read_vram(address - VRAM_OFFSET),
+            ECHO_RAM_START.. => self.wram[self.wram_index(address - ECHO_RAM_MIRROR_OFFSET)],
+            _ => {
+                self.record_coverage(address as u16, coverage::READ);
+                self.peek(address as u16)
+            }
+        }
+    }
+
+    fn write_joypad(&mut self, value: u8) {
+        if self.joypad.write(value) {
+            self.interrupts.request_interrupt(Interrupt::Joypad);
+        }
+        if let Some(sgb) = &mut self.sgb {
+            sgb.write_joypad(value);
+        }
+    }
+
+    // Index into `wram` for an address in 0xC000-0xDFFF.
+    fn wram_index(&self, address: usize) -> usize {
+        if address <= WORK_RAM_BANK0_END {
+            return address - WORK_RAM_START;
+        }
+        let bank = (self.wram_bank as usize).max(1);
+        bank * WRAM_BANK_SIZE + (address - WORK_RAM_BANK1_START)
+    }
+
+    // Cartridge ROM, with the boot ROM overlaid while it is mapped.
+    fn read_rom(&self, address: usize) -> u8 {
+        match &self.boot_rom {
+            Some(boot_rom) if self.boot_rom_overlays(address) => boot_rom[address],
+            _ => self.memory[address],
+        }
+    }
+
+    fn boot_rom_overlays(&self, address: usize) -> bool {
+        self.boot_rom.as_ref().is_some_and(|boot_rom| {
+            address <= BOOT_ROM_END || (CGB_BOOT_ROM_START..boot_rom.len()).contains(&address)
+        })
+    }
+
+    // KEY1 (0xFF4D): only bit 0 (arm speed switch) is writable, and only on CGB.
+    fn write_key1(&mut self, value: u8) {
+        if self.cgb_mode {
+            self.speed_switch_armed = value & 0x01 != 0;
+        }
+    }
+
+    // KEY1 (0xFF4D): bit 7 = current speed, bit 0 = switch armed. Reads 0xFF on DMG.
+    fn read_key1(&self) -> u8 {
+        if !self.cgb_mode {
+            return UNMAPPED_MEMORY_VALUE;
+        }
+        KEY1_UNUSED_BITS
+            | if self.double_speed { 0x80 } else { 0 }
+            | if self.speed_switch_armed { 0x01 } else { 0 }
+    }
+}
+
+/// Cartridge ROM is not saved; it is checked through the state header instead.
+impl Snapshot for MemoryBus {
+    fn save_to(&self, state: &mut StateWriter) {
+        state.bytes(&self.memory[EXTERNAL_RAM_START..]);
+        state.bytes(&self.wram);
+        state.u8(self.wram_bank);
+        state.bool(self.cgb_mode);
+        state.u8(self.key0);
+        state.bool(self.speed_switch_armed);
+        state.bool(self.double_speed);
+        state.bool(self.odd_m_cycle);
+        state.option(self.pending_dma, StateWriter::u16);
+        state.option(self.dma, |state, dma| {
+            state.u16(dma.source);
+            state.u16(dma.offset);
+        });
+        self.hdma.save_to(state);
+        state.option(self.boot_rom.as_deref(), |state, boot_rom| {
+            state.u16(boot_rom.len() as u16);
+            state.bytes(boot_rom);
+        });
+        self.gpu.save_to(state);
+        self.apu.save_to(state);
+        self.timer.save_to(state);
+        self.interrupts.save_to(state);
+        self.joypad.save_to(state);
+        self.serial.save_to(state);
+        state.option(self.sgb.as_ref(), |state, sgb| sgb.save_to(state));
+    }
+
+    fn load_from(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.bytes(&mut self.memory[EXTERNAL_RAM_START..])?;
+        state.bytes(&mut self.wram)?;
+        self.wram_bank = state.u8()? & SVBK_BANK_MASK;
+        self.cgb_mode = state.bool()?;
+        self.key0 = state.u8()?;
+        self.speed_switch_armed = state.bool()?;
+        self.double_speed = state.bool()?;
+        self.odd_m_cycle = state.bool()?;
+        self.pending_dma = state.option(StateReader::u16)?;
+        self.dma = state.option(|state| {
+            Ok(OamDma {
+                source: state.u16()?,
+                offset: state.u16()?.min(ppu::OAM_SIZE as u16 - 1),
+            })
+        })?;
+        self.hdma.load_from(state)?;
+        self.boot_rom = state.option(|state| {
+            let len = state.u16()? as usize;
+            if Some(len) != state.model().map(Model::boot_rom_size) {
+                return Err(StateError::Corrupt(
+                    "boot ROM size does not match the model",
+                ));
+            }
+            let mut boot_rom = vec![0; len];
+            state.bytes(&mut boot_rom)?;
+            Ok(boot_rom)
+        })?;
+        self.gpu.load_from(state)?;
+        self.apu.load_from(state)?;
+        self.timer.load_from(state)?;
+        self.interrupts.load_from(state)?;
+        self.joypad.load_from(state)?;
+        self.serial.load_from(state)?;
+        let has_sgb = state.bool()?;
+        match &mut self.sgb {
+            Some(sgb) if has_sgb => sgb.load_from(state),
+            None if !has_sgb => Ok(()),
+            _ => Err(StateError::Corrupt(
+                "SGB support does not match the machine",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bus_with_lcd_off() -> MemoryBus {
+        let mut bus = MemoryBus::new(vec![0; 0x8000]);
+        bus.write_byte(0xFF40, 0x00);
+        bus
+    }
+
+    #[test]
+    fn test_oam_dma_copies_one_byte_per_m_cycle_after_delay() {
+        let mut bus = bus_with_lcd_off();
+        for i in 0..0xA0u16 {
+            bus.write_byte(0xC000 + i, i as u8);
+        }
+
+        bus.write_byte(0xFF46, 0xC0);
+        bus.tick_m_cycle(); // start-up delay
+        assert!(!bus.is_dma_active());
+        assert_eq!(bus.read_byte(0xFE00), 0x00);
+
+        bus.tick_m_cycle();
+        assert!(bus.is_dma_active());
+        assert_eq!(bus.read_byte(0xFE00), 0xFF); // OAM locked during transfer
+        assert_eq!(bus.gpu.read_oam(0), 0x00);
+
+        for _ in 1..0xA0 {
+            bus.tick_m_cycle();
+        }
+        assert!(!bus.is_dma_active());
+        assert_eq!(bus.read_byte(0xFE00), 0x00);
+        assert_eq!(bus.read_byte(0xFE9F), 0x9F);
+    }
+
+    #[test]
+    fn test_oam_dma_from_echo_page_reads_work_ram() {
+        let mut bus = bus_with_lcd_off();
+        bus.write_byte(0xDE05, 0x77);
+
+        bus.write_byte(0xFF46, 0xFE);
+        for _ in 0..=0xA0 {
+            bus.tick_m_cycle();
+        }
+
+        assert_eq!(bus.read_byte(0xFE05), 0x77);
+    }
+
+    #[test]
+    fn test_vram_locked_while_drawing() {
+        let mut bus = MemoryBus::new(vec![0; 0x8000]);
+        bus.write_byte(0x8000, 0x12); // mode 2: VRAM still accessible
+        for _ in 0..20 {
+            bus.tick_m_cycle(); // into mode 3
+        }
+        assert_eq!(bus.read_byte(0x8000), 0xFF);
+        bus.write_byte(0x8000, 0x34);
+        assert_eq!(bus.read_byte(0xFE00), 0xFF);
+
+        for _ in 0..50 {
+            bus.tick_m_cycle(); // into HBlank
+        }
+        assert_eq!(bus.read_byte(0x8000), 0x12);
+    }
+
+    #[test]
+    fn test_boot_rom_overlay_until_ff50_write() {
+        let mut rom = vec![0x11; 0x8000];
+        rom[0x0100] = 0x22;
+        let mut bus = MemoryBus::new(rom);
+        bus.map_boot_rom(Model::Dmg, vec![0xAA; 0x100]);
+        assert_eq!(bus.read_byte(0x0000), 0xAA);
+        assert_eq!(bus.read_byte(0x00FF), 0xAA);
+        assert_eq!(bus.read_byte(0x0100), 0x22);
+        assert_eq!(bus.read_byte(0x0200), 0x11);
+
+        bus.write_byte(0xFF50, 0x00);
+        assert!(bus.boot_rom_mapped());
+        bus.write_byte(0xFF50, 0x01);
+        assert!(!bus.boot_rom_mapped());
+        assert_eq!(bus.read_byte(0x0000), 0x11);
+        assert_eq!(bus.read_byte(0xFF50), 0xFF);
+    }
+
+    #[test]
+    fn test_cgb_boot_rom_leaves_header_visible() {
+        let mut bus = MemoryBus::new(vec![0x11; 0x8000]);
+        bus.map_boot_rom(Model::Cgb, vec![0xAA; 0x900]);
+        assert_eq!(bus.read_byte(0x00FF), 0xAA);
+        assert_eq!(bus.read_byte(0x0150), 0x11);
+        assert_eq!(bus.read_byte(0x0200), 0xAA);
+        assert_eq!(bus.read_byte(0x08FF), 0xAA);
+        assert_eq!(bus.read_byte(0x0900), 0x11);
+    }
+
+    #[test]
+    fn test_post_boot_state_per_model() {
+        let mut bus = MemoryBus::new(vec![0; 0x8000]);
+        bus.apply_post_boot_state(Model::Dmg);
+        assert_eq!(bus.read_byte(0xFF04), 0xAB);
+        assert_eq!(bus.read_byte(0xFF26), 0xF1);
+        assert_eq!(bus.read_byte(0xFF02), 0x7E);
+
+        let mut bus = MemoryBus::new(vec![0; 0x8000]);
+        bus.apply_post_boot_state(Model::Sgb);
+        assert_eq!(bus.read_byte(0xFF26), 0xF0);
+
+        let mut bus = MemoryBus::new(vec![0; 0x8000]);
+        bus.apply_post_boot_state(Model::Cgb);
+        assert!(bus.cgb_mode);
+        assert_eq!(bus.read_byte(0xFF02), 0x7F);
+    }
+
+    #[test]
+    fn test_io_registers_match_post_boot_table() {
+        let mut bus = MemoryBus::new(vec![0; 0x8000]);
+        bus.apply_post_boot_state(Model::Dmg);
+        for address in io_registers::IO_START..=io_registers::IO_END {
+            let register = io_registers::io_register(address);
+            if let Some(expected) = register.post_boot {
+                assert_eq!(
+                    bus.read_byte(address),
+                    expected,
+                    "{} (0x{address:04X})",
+                    register.name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_unused_io_bits_read_as_one() {
+        let mut bus = MemoryBus::new(vec![0; 0x8000]);
+        for address in io_registers::IO_START..=io_registers::IO_END {
+            let register = io_registers::io_register(address);
+            if register.read_mask == 0xFF || register.cgb_only {
+                bus.write_byte(address, 0x00);
+                assert_eq!(bus.read_byte(address), 0xFF, "0x{address:04X}");
+            }
+        }
+        for (address, mask) in [
+            (0xFF02, 0x7E),
+            (0xFF07, 0xF8),
+            (0xFF0F, 0xE0),
+            (0xFF10, 0x80),
+        ] {
+            bus.write_byte(address, 0x00);
+            assert_eq!(bus.read_byte(address), mask, "0x{address:04X}");
+        }
+    }
+
+    #[test]
+    fn test_cgb_registers_mapped_only_in_cgb_mode() {
+        let mut bus = MemoryBus::new(vec![0; 0x8000]);
+        bus.cgb_mode = true;
+        bus.write_byte(0xFF70, 0x00);
+        assert_eq!(bus.read_byte(0xFF70), 0xF8);
+        bus.write_byte(0xFF4C, 0x00);
+        assert_eq!(bus.read_byte(0xFF4C), 0xFF);
+    }
+
+    fn cgb_bus() -> MemoryBus {
+        let mut bus = MemoryBus::new(vec![0; 0x8000]);
+        bus.apply_post_boot_state(Model::Cgb);
+        bus
+    }
+
+    #[test]
+    fn test_key0_selects_dmg_compat_when_boot_rom_unmaps() {
+        let mut bus = MemoryBus::new(vec![0; 0x8000]);
+        bus.map_boot_rom(Model::Cgb, vec![0; Model::Cgb.boot_rom_size()]);
+        bus.write_byte(0xFF4C, 0x04);
+        assert!(bus.cgb_mode);
+        bus.write_byte(0xFF50, 0x01);
+        assert!(!bus.cgb_mode);
+        assert!(bus.is_dmg_compat());
+
+        // KEY0 is locked once the boot ROM is gone
+        let mut bus = cgb_bus();
+        bus.write_byte(0xFF4C, 0x04);
+        bus.write_byte(0xFF50, 0x01);
+        assert!(bus.cgb_mode);
+    }
+
+    #[test]
+    fn test_vbk_selects_vram_bank_on_cgb() {
+        let mut bus = cgb_bus();
+        bus.write_byte(0xFF40, 0x00);
+        bus.write_byte(0x8000, 0x11);
+        bus.write_byte(0xFF4F, 0x01);
+        assert_eq!(bus.read_byte(0xFF4F), 0xFF);
+        assert_eq!(bus.read_byte(0x8000), 0x00);
+        bus.write_byte(0x8000, 0x22);
+        bus.write_byte(0xFF4F, 0x00);
+        assert_eq!(bus.read_byte(0xFF4F), 0xFE);
+        assert_eq!(bus.read_byte(0x8000), 0x11);
+
+        let mut bus = bus_with_lcd_off();
+        bus.write_byte(0xFF4F, 0x01);
+        assert_eq!(bus.gpu.vram_bank(), 0);
+    }
+
+    #[test]
+    fn test_general_purpose_hdma_copies_into_selected_bank() {
+        let mut bus = cgb_bus();
+        bus.write_byte(0xFF40, 0x00);
+        for i in 0..0x20u16 {
+            bus.write_byte(0xC000 + i, i as u8 + 1);
+        }
+        bus.write_byte(0xFF4F, 0x01);
+        for (register, value) in [
+            (0xFF51, 0xC0),
+            (0xFF52, 0x00),
+            (0xFF53, 0x81),
+            (0xFF54, 0x00),
+        ] {
+            bus.write_byte(register, value);
+        }
+        bus.write_byte(0xFF55, 0x01);
+        assert!(bus.is_hdma_active());
+
+        // 32 bytes take 16 M-cycles at normal speed
+        for _ in 0..15 {
+            bus.tick_m_cycle();
+        }
+        assert!(bus.is_hdma_active());
+        bus.tick_m_cycle();
+        assert!(!bus.is_hdma_active());
+        assert_eq!(bus.read_byte(0xFF55), 0xFF);
+        assert_eq!(bus.read_byte(0x8100), 0x01);
+        assert_eq!(bus.read_byte(0x811F), 0x20);
+        bus.write_byte(0xFF4F, 0x00);
+        assert_eq!(bus.read_byte(0x8100), 0x00);
+    }
+
+    #[test]
+    fn test_hblank_dma_copies_a_block_per_line() {
+        let mut bus = cgb_bus();
+        bus.write_byte(0xFF40, 0x00);
+        bus.write_byte(0xFF40, 0x91);
+        bus.write_byte(0xFF51, 0xC0);
+        bus.write_byte(0xFF55, 0x81);
+        assert!(!bus.is_hdma_active());
+
+        // Mode 3 ends 252 dots into line 0
+        for _ in 0..63 {
+            bus.tick_m_cycle();
+        }
+        assert!(bus.is_hdma_active());
+        for _ in 0..8 {
+            bus.tick_m_cycle();
+        }
+        assert!(!bus.is_hdma_active());
+        assert_eq!(bus.read_byte(0xFF55), 0x00);
+
+        // The second block waits for the next line's HBlank
+        for _ in 0..(456 - 4 * 8) / 4 {
+            bus.tick_m_cycle();
+        }
+        assert!(bus.is_hdma_active());
+        for _ in 0..8 {
+            bus.tick_m_cycle();
+        }
+        assert_eq!(bus.read_byte(0xFF55), 0xFF);
+    }
+
+    #[test]
+    fn test_palette_ram_through_bus() {
+        let mut bus = cgb_bus();
+        bus.write_byte(0xFF40, 0x00);
+        bus.write_byte(0xFF68, 0x80);
+        for value in [0x1F, 0x00, 0xE0, 0x03] {
+            bus.write_byte(0xFF69, value);
+        }
+        assert_eq!(bus.read_byte(0xFF68), 0xC4);
+        bus.write_byte(0xFF68, 0x02);
+        assert_eq!(bus.read_byte(0xFF69), 0xE0);
+        bus.write_byte(0xFF6C, 0x01);
+        assert_eq!(bus.read_byte(0xFF6C), 0xFF);
+
+        // Unmapped on DMG
+        let mut bus = bus_with_lcd_off();
+        bus.write_byte(0xFF68, 0x80);
+        assert_eq!(bus.read_byte(0xFF68), 0xFF);
+        assert_eq!(bus.gpu.read_register(0xFF68), 0x40);
+    }
+
+    #[test]
+    fn test_svbk_selects_wram_bank_on_cgb() {
+        let mut bus = cgb_bus();
+        bus.write_byte(0xD000, 0x01); // bank 0 selected means bank 1
+        for bank in 2..8 {
+            bus.write_byte(0xFF70, bank);
+            bus.write_byte(0xD000, bank);
+        }
+        bus.write_byte(0xFF70, 0x03);
+        assert_eq!(bus.read_byte(0xFF70), 0xFB);
+        assert_eq!(bus.read_byte(0xD000), 0x03);
+        assert_eq!(bus.read_byte(0xF000), 0x03); // echo follows the bank
+        bus.write_byte(0xFF70, 0x00);
+        assert_eq!(bus.read_byte(0xD000), 0x01);
+        bus.write_byte(0xC000, 0xAA);
+        bus.write_byte(0xFF70, 0x07);
+        assert_eq!(bus.read_byte(0xC000), 0xAA); // bank 0 is fixed
+
+        let mut bus = MemoryBus::new(vec![0; 0x8000]);
+        bus.write_byte(0xD000, 0x01);
+        bus.write_byte(0xFF70, 0x02);
+        assert_eq!(bus.read_byte(0xFF70), 0xFF);
+        assert_eq!(bus.read_byte(0xD000), 0x01);
+    }
+
+    #[test]
+    fn test_pcm_registers_read_channel_outputs() {
+        let mut bus = cgb_bus();
+        bus.write_byte(0xFF26, 0x00); // APU off: every DAC is off
+        bus.write_byte(0xFF76, 0x5A);
+        bus.write_byte(0xFF77, 0x5A);
+        assert_eq!(bus.read_byte(0xFF76), 0x00);
+        assert_eq!(bus.read_byte(0xFF77), 0x00);
+
+        // Channel 2 at full volume alternates between 0 and 15
+        bus.write_byte(0xFF26, 0x80);
+        bus.write_byte(0xFF16, 0x80); // 50% duty
+        bus.write_byte(0xFF17, 0xF0);
+        bus.write_byte(0xFF19, 0x87);
+        let mut seen = Vec::new();
+        for _ in 0..256 {
+            bus.tick_m_cycle();
+            let value = bus.read_byte(0xFF76);
+            if !seen.contains(&value) {
+                seen.push(value);
+            }
+        }
+        seen.sort();
+        assert_eq!(seen, [0x00, 0xF0]);
+
+        let bus = MemoryBus::new(vec![0; 0x8000]);
+        assert_eq!(bus.read_byte(0xFF76), 0xFF);
+    }
+
+    #[test]
+    fn test_double_speed_keeps_ppu_on_normal_clock() {
+        let mut bus = cgb_bus();
+        bus.write_byte(0xFF40, 0x00);
+        bus.write_byte(0xFF40, 0x80);
+        bus.reset_div();
+        bus.perform_speed_switch();
+        assert!(bus.is_double_speed());
+
+        // One scanline is 456 dots: 228 M-cycles at double speed
+        for _ in 0..227 {
+            bus.tick_m_cycle();
+        }
+        assert_eq!(bus.read_byte(0xFF44), 0);
+        bus.tick_m_cycle();
+        assert_eq!(bus.read_byte(0xFF44), 1);
+        // The timer follows the CPU clock
+        assert_eq!(bus.timer.div_counter(), 228 * 4);
+    }
 }
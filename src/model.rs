@@ -0,0 +1,106 @@
+//! Game Boy hardware models and their post-boot state.
+//!
+//! Without a boot ROM the emulator starts at 0x0100 with the CPU registers
+//! and DIV counter that each model's boot ROM leaves behind. With a boot ROM
+//! it starts from the power-on state at 0x0000 and the boot ROM sets
+//! everything up itself.
+//!
+//! Reference: [Pan Docs — Power Up Sequence](https://gbdev.io/pandocs/Power_Up_Sequence.html)
+
+/// Supported hardware models.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    /// Early DMG boot ROM revision (DMG0)
+    Dmg0,
+    /// Original Game Boy (DMG)
+    Dmg,
+    /// Game Boy Pocket (MGB)
+    Mgb,
+    /// Super Game Boy (SGB)
+    Sgb,
+    /// Game Boy Color (CGB) running a CGB game
+    Cgb,
+}
+
+/// CPU registers left by the boot ROM, in the order A, F, B, C, D, E, H, L.
+type RegisterValues = [u8; 8];
+
+impl Model {
+    /// Parse a model name as given on the command line (case-insensitive).
+    pub fn from_name(name: &str) -> Option<Model> {
+        match name.to_ascii_lowercase().as_str() {
+            "dmg0" => Some(Model::Dmg0),
+            "dmg" => Some(Model::Dmg),
+            "mgb" => Some(Model::Mgb),
+            "sgb" => Some(Model::Sgb),
+            "cgb" => Some(Model::Cgb),
+            _ => None,
+        }
+    }
+
+    /// Whether this model has the CGB-only hardware (KEY1, banking, palettes).
+    pub const fn is_cgb(self) -> bool {
+        matches!(self, Model::Cgb)
+    }
+
+    /// Size in bytes of this model's boot ROM.
+    ///
+    /// The CGB boot ROM is mapped at 0x0000-0x00FF and 0x0200-0x08FF, leaving
+    /// the cartridge header at 0x0100-0x01FF visible.
+    pub const fn boot_rom_size(self) -> usize {
+        match self {
+            Model::Cgb => 0x900,
+            _ => 0x100,
+        }
+    }
+
+    /// CPU registers after the boot ROM hands over to the cartridge.
+    ///
+    /// On DMG and MGB the H and C flags depend on the header checksum at
+    /// 0x014D: both are clear when it is zero and set otherwise.
+    pub fn post_boot_registers(self, header_checksum: u8) -> RegisterValues {
+        let checksum_flags = if header_checksum == 0 { 0x80 } else { 0xB0 };
+        match self {
+            Model::Dmg0 => [0x01, 0x00, 0xFF, 0x13, 0x00, 0xC1, 0x84, 0x03],
+            Model::Dmg => [0x01, checksum_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D],
+            Model::Mgb => [0xFF, checksum_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D],
+            Model::Sgb => [0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60],
+            Model::Cgb => [0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D],
+        }
+    }
+
+    /// Internal 16-bit DIV counter when the cartridge code starts (DIV is
+    /// the upper byte).
+    ///
+    /// Pan Docs only documents DIV for the DMG models; the SGB and CGB values
+    /// are the ones measured by other emulators at the boot ROM's last
+    /// instruction.
+    pub const fn post_boot_div(self) -> u16 {
+        match self {
+            Model::Dmg0 => 0x182C,
+            Model::Dmg | Model::Mgb => 0xABCC,
+            Model::Sgb => 0xD85C,
+            Model::Cgb => 0x267C,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dmg_flags_depend_on_header_checksum() {
+        assert_eq!(Model::Dmg.post_boot_registers(0x00)[1], 0x80);
+        assert_eq!(Model::Dmg.post_boot_registers(0x3C)[1], 0xB0);
+        assert_eq!(Model::Mgb.post_boot_registers(0x3C)[..2], [0xFF, 0xB0]);
+        assert_eq!(Model::Cgb.post_boot_registers(0x00)[..2], [0x11, 0x80]);
+    }
+
+    #[test]
+    fn test_model_names() {
+        assert_eq!(Model::from_name("CGB"), Some(Model::Cgb));
+        assert_eq!(Model::from_name("dmg0"), Some(Model::Dmg0));
+        assert_eq!(Model::from_name("agb"), None);
+    }
+}
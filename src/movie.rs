@@ -0,0 +1,394 @@
+//! Input movies: joypad input recorded frame by frame, for bit-exact replay.
+//!
+//! A movie starts from a save state and holds the buttons held during each
+//! frame after it. The core has no source of nondeterminism (no real-time
+//! clock, no host time, no randomness), so replaying the same input from the
+//! same state reproduces the run exactly. Input is only changed at frame
+//! boundaries, when the PPU enters VBlank; while the LCD is off no frames
+//! complete and the input stays as it was.
+//!
+//! To catch desyncs, the recorder stores a hash of the whole machine state
+//! every `hash_interval` frames, and the player compares against it.
+//!
+//! | Size | Contents                                          |
+//! |------|---------------------------------------------------|
+//! | 4    | Magic `GBMV`                                      |
+//! | 2    | Format version                                    |
+//! | 1    | Hardware model (as in save states)                |
+//! | 4    | CRC-32 of the cartridge ROM                       |
+//! | 4    | Hash interval in frames                           |
+//! | 4+n  | Length, then the starting save state              |
+//! | 4+n  | Frame count, then one button mask per frame       |
+//! | 4+8n | Hash count, then the FNV-1a hashes of the states  |
+//!
+//! All integers are little-endian. Button masks use [`Button::mask`] bits.
+//!
+//! [`Button::mask`]: crate::joypad::Button::mask
+
+use crate::cpu::CPU;
+use crate::model::Model;
+use crate::save_state::{self, StateError, StateReader, StateWriter};
+use core::fmt;
+
+const MAGIC: [u8; 4] = *b"GBMV";
+/// Bumped whenever the file layout changes; older movies are rejected.
+pub const FORMAT_VERSION: u16 = 1;
+/// Frames between state hashes: one per second of emulated time.
+pub const DEFAULT_HASH_INTERVAL: u32 = 60;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieError {
+    /// The data does not start with the movie magic.
+    NotAMovie,
+    /// The movie was written by another version of the format.
+    UnsupportedVersion(u16),
+    /// The movie was recorded on another hardware model.
+    ModelMismatch { movie: Model, machine: Model },
+    /// The movie was recorded while running another ROM.
+    RomMismatch,
+    /// The file is truncated or holds invalid values.
+    Corrupt(&'static str),
+    /// The starting save state could not be loaded.
+    State(StateError),
+    /// The machine state no longer matches the recording.
+    Desync { frame: u64 },
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::NotAMovie => write!(f, "not a movie"),
+            MovieError::UnsupportedVersion(version) => write!(
+                f,
+                "movie format version {version} is not supported (expected {FORMAT_VERSION})"
+            ),
+            MovieError::ModelMismatch { movie, machine } => write!(
+                f,
+                "movie was recorded on {movie:?}, but the emulator is running {machine:?}"
+            ),
+            MovieError::RomMismatch => write!(f, "movie was recorded with a different ROM"),
+            MovieError::Corrupt(reason) => write!(f, "movie is corrupt: {reason}"),
+            MovieError::State(e) => write!(f, "movie start state: {e}"),
+            MovieError::Desync { frame } => {
+                write!(f, "playback desynced from the recording at frame {frame}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl From<StateError> for MovieError {
+    fn from(e: StateError) -> Self {
+        match e {
+            StateError::Corrupt(reason) => MovieError::Corrupt(reason),
+            e => MovieError::State(e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub model: Model,
+    pub rom_checksum: u32,
+    pub hash_interval: u32,
+    pub start_state: Vec<u8>,
+    pub inputs: Vec<u8>,  // buttons held during each frame, from the start state
+    pub hashes: Vec<u64>, // state at the start of frames hash_interval, 2 * hash_interval, ...
+}
+
+impl Movie {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = StateWriter::new();
+        data.bytes(&MAGIC);
+        data.u16(FORMAT_VERSION);
+        data.u8(save_state::model_id(self.model));
+        data.u32(self.rom_checksum);
+        data.u32(self.hash_interval);
+        data.u32(self.start_state.len() as u32);
+        data.bytes(&self.start_state);
+        data.u32(self.inputs.len() as u32);
+        data.bytes(&self.inputs);
+        data.u32(self.hashes.len() as u32);
+        for &hash in &self.hashes {
+            data.u64(hash);
+        }
+        data.into_bytes()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Movie, MovieError> {
+        let mut data = StateReader::new(data);
+        if data.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err(MovieError::NotAMovie);
+        }
+        let version = data.u16()?;
+        if version != FORMAT_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        let model =
+            save_state::model_from_id(data.u8()?).ok_or(MovieError::Corrupt("unknown model"))?;
+        let rom_checksum = data.u32()?;
+        let hash_interval = data.u32()?;
+        if hash_interval == 0 {
+            return Err(MovieError::Corrupt("zero hash interval"));
+        }
+        let len = data.u32()? as usize;
+        let start_state = data.take(len)?.to_vec();
+        let len = data.u32()? as usize;
+        let inputs = data.take(len)?.to_vec();
+        let len = data.u32()? as usize;
+        let hashes = (0..len).map(|_| data.u64()).collect::<Result<_, _>>()?;
+        data.finish()?;
+        Ok(Movie {
+            model,
+            rom_checksum,
+            hash_interval,
+            start_state,
+            inputs,
+            hashes,
+        })
+    }
+
+    /// Frames of input in the movie.
+    pub fn frames(&self) -> u64 {
+        self.inputs.len() as u64
+    }
+}
+
+/// Records the input given to a machine, starting from its current state.
+pub struct MovieRecorder {
+    movie: Movie,
+    start_frame: u64,
+    buttons: u8, // held now, applied to the machine at the next frame
+}
+
+impl MovieRecorder {
+    pub fn new(cpu: &CPU, hash_interval: u32) -> Self {
+        let buttons = cpu.bus.buttons();
+        MovieRecorder {
+            movie: Movie {
+                model: cpu.model(),
+                rom_checksum: cpu.rom_checksum(),
+                hash_interval: hash_interval.max(1),
+                start_state: cpu.save_state(),
+                inputs: vec![buttons],
+                hashes: Vec::new(),
+            },
+            start_frame: cpu.bus.gpu.frame_count(),
+            buttons,
+        }
+    }
+
+    /// Press or release the buttons in `mask` from the next frame on.
+    pub fn set_buttons(&mut self, mask: u8, pressed: bool) {
+        if pressed {
+            self.buttons |= mask;
+        } else {
+            self.buttons &= !mask;
+        }
+    }
+
+    /// Call after every CPU step: at the start of each frame this applies
+    /// and records the held buttons.
+    pub fn after_step(&mut self, cpu: &mut CPU) {
+        let frame = cpu.bus.gpu.frame_count() - self.start_frame;
+        if frame < self.movie.frames() {
+            return;
+        }
+        cpu.bus.set_buttons(self.buttons);
+        self.movie.inputs.push(self.buttons);
+        if frame.is_multiple_of(self.movie.hash_interval as u64) {
+            self.movie.hashes.push(state_hash(cpu));
+        }
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+/// Feeds a movie's input to a machine and checks it stays in sync.
+pub struct MoviePlayer {
+    movie: Movie,
+    start_frame: u64,
+    next_frame: u64, // first frame whose input has not been applied yet
+}
+
+impl MoviePlayer {
+    /// Check that the movie belongs to the machine and load its start state.
+    pub fn start(movie: Movie, cpu: &mut CPU) -> Result<Self, MovieError> {
+        if movie.model != cpu.model() {
+            return Err(MovieError::ModelMismatch {
+                movie: movie.model,
+                machine: cpu.model(),
+            });
+        }
+        if movie.rom_checksum != cpu.rom_checksum() {
+            return Err(MovieError::RomMismatch);
+        }
+        cpu.load_state(&movie.start_state)?;
+        Ok(MoviePlayer {
+            start_frame: cpu.bus.gpu.frame_count(),
+            next_frame: 1,
+            movie,
+        })
+    }
+
+    /// Call after every CPU step: at the start of each frame this applies the
+    /// recorded buttons and checks the state hash when one is due.
+    ///
+    /// Returns false once the input of the last frame has been applied.
+    pub fn after_step(&mut self, cpu: &mut CPU) -> Result<bool, MovieError> {
+        let frame = cpu.bus.gpu.frame_count() - self.start_frame;
+        if frame < self.next_frame {
+            return Ok(true);
+        }
+        self.next_frame = frame + 1;
+        let next_frame_is_recorded = self.next_frame < self.movie.frames();
+        let Some(&buttons) = self.movie.inputs.get(frame as usize) else {
+            return Ok(false);
+        };
+        cpu.bus.set_buttons(buttons);
+
+        let interval = self.movie.hash_interval as u64;
+        if frame.is_multiple_of(interval) {
+            let expected = self.movie.hashes.get((frame / interval - 1) as usize);
+            if expected.is_some_and(|&hash| hash != state_hash(cpu)) {
+                return Err(MovieError::Desync { frame });
+            }
+        }
+        Ok(next_frame_is_recorded)
+    }
+}
+
+/// FNV-1a hash of the machine's save state.
+fn state_hash(cpu: &CPU) -> u64 {
+    cpu.save_state()
+        .iter()
+        .fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01B3)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controls::{self, Control};
+    use crate::cpu::cpu_with_program;
+    use crate::joypad::Button;
+
+    // LD A,0x20; LDH (0x00),A; LDH A,(0x00); LD (HL),A; INC L; JR -6
+    // (selects the d-pad and logs the joypad register to WRAM forever),
+    // then `rom_tag` to tell ROMs apart
+    fn joypad_logger(rom_tag: u8) -> CPU {
+        let program = [
+            0x3E, 0x20, 0xE0, 0x00, 0xF0, 0x00, 0x77, 0x2C, 0x18, 0xFA, rom_tag,
+        ];
+        let mut cpu = cpu_with_program(Model::Dmg, &program);
+        cpu.registers.set_hl(0xC000);
+        cpu
+    }
+
+    fn record(cpu: &mut CPU, frames: u64) -> Movie {
+        let mut recorder = MovieRecorder::new(cpu, 4);
+        let end = cpu.bus.gpu.frame_count() + frames;
+        while cpu.bus.gpu.frame_count() < end {
+            let frame = cpu.bus.gpu.frame_count();
+            recorder.set_buttons(Button::Right.mask(), frame.is_multiple_of(3));
+            recorder.set_buttons(Button::Down.mask(), frame % 5 == 1);
+            cpu.step();
+            recorder.after_step(cpu);
+        }
+        recorder.finish()
+    }
+
+    fn play(cpu: &mut CPU, movie: Movie) -> Result<(), MovieError> {
+        let mut player = MoviePlayer::start(movie, cpu)?;
+        while player.after_step(cpu)? {
+            cpu.step();
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_playback_reproduces_recording() {
+        let mut cpu = joypad_logger(0);
+        for _ in 0..1000 {
+            cpu.step();
+        }
+        let movie = record(&mut cpu, 20);
+        assert_eq!(movie.frames(), 21);
+        assert_eq!(movie.hashes.len(), 5);
+        assert!(movie.inputs.iter().any(|&buttons| buttons != 0));
+        let expected = cpu.save_state();
+
+        let mut fresh = joypad_logger(0);
+        play(&mut fresh, Movie::from_bytes(&movie.to_bytes()).unwrap()).unwrap();
+        assert_eq!(fresh.save_state(), expected);
+    }
+
+    #[test]
+    fn test_typed_controls_are_recorded_and_replayed() {
+        let script = [(2, "press right+down"), (5, "release down"), (8, "u right")];
+        let mut cpu = joypad_logger(0);
+        let mut recorder = MovieRecorder::new(&cpu, 4);
+        let start = cpu.bus.gpu.frame_count();
+        let mut applied = start;
+        while cpu.bus.gpu.frame_count() < start + 10 {
+            // As in the runner: step, apply what was typed, then record
+            cpu.step();
+            let frame = cpu.bus.gpu.frame_count();
+            if frame != applied {
+                applied = frame;
+                for &(_, line) in script.iter().filter(|(at, _)| start + at == frame) {
+                    match controls::parse(line) {
+                        Ok(Some(Control::Press(mask))) => recorder.set_buttons(mask, true),
+                        Ok(Some(Control::Release(mask))) => recorder.set_buttons(mask, false),
+                        other => panic!("unexpected control {other:?}"),
+                    }
+                }
+            }
+            recorder.after_step(&mut cpu);
+        }
+        let movie = recorder.finish();
+        let (right, down) = (Button::Right.mask(), Button::Down.mask());
+        assert_eq!(movie.inputs[2..5], [right | down; 3]);
+        assert_eq!(movie.inputs[5..8], [right; 3]);
+        assert_eq!(movie.inputs[8..], [0; 3]);
+        let expected = cpu.save_state();
+
+        let mut fresh = joypad_logger(0);
+        play(&mut fresh, Movie::from_bytes(&movie.to_bytes()).unwrap()).unwrap();
+        assert_eq!(fresh.save_state(), expected);
+    }
+
+    #[test]
+    fn test_changed_input_is_reported_as_desync() {
+        let mut cpu = joypad_logger(0);
+        let mut movie = record(&mut cpu, 12);
+        // The logger keeps overwriting WRAM, so only the frame right before
+        // a hash leaves a trace
+        movie.inputs[7] ^= Button::Up.mask();
+        assert_eq!(
+            play(&mut joypad_logger(0), movie),
+            Err(MovieError::Desync { frame: 8 })
+        );
+    }
+
+    #[test]
+    fn test_movie_for_other_rom_is_rejected() {
+        let movie = record(&mut joypad_logger(0), 2);
+        assert_eq!(
+            MoviePlayer::start(movie.clone(), &mut joypad_logger(1)).err(),
+            Some(MovieError::RomMismatch)
+        );
+
+        let mut data = movie.to_bytes();
+        assert_eq!(Movie::from_bytes(b"GBST"), Err(MovieError::NotAMovie));
+        data.pop();
+        assert!(matches!(
+            Movie::from_bytes(&data),
+            Err(MovieError::Corrupt(_))
+        ));
+    }
+}
@@ -3,31 +3,96 @@
 //! This module handles VRAM management, tile rendering, and video output
 //! for the Game Boy display. It includes support for LCD I/O registers
 //! that control the PPU's operation.
+//!
+//! Timing follows the dot clock (one dot per T-cycle): each scanline is 456
+//! dots split into OAM scan (mode 2), drawing (mode 3) and HBlank (mode 0),
+//! and lines 144-153 are VBlank (mode 1). VRAM is inaccessible to the CPU in
+//! mode 3 and OAM in modes 2 and 3.
+//!
+//! CGB hardware has two VRAM banks, selected for CPU access by VBK (0xFF4F),
+//! and colour palette RAM (see [`palette`]). DMG games on a CGB keep the DMG
+//! palette registers but are coloured through palette RAM (see [`compat`]).
+//! The PPU always runs on the normal-speed clock, also in double-speed mode.
+//!
+//! Lines are drawn into an RGB555 framebuffer as they finish mode 3 (see
+//! [`render`]).
+//!
+//! Reference: [Pan Docs — Rendering](https://gbdev.io/pandocs/Rendering.html)
+
+pub mod compat;
+pub mod palette;
+mod render;
+
+use crate::interrupts::{Interrupt, InterruptController};
+use crate::save_state::{Snapshot, StateError, StateReader, StateWriter};
+use compat::CompatPalettes;
+use palette::{ColorCorrection, PaletteRam, Rgb555};
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
 
 const VRAM_BEGIN: usize = 0x8000;
 const VRAM_END: usize = 0x9FFF;
 const VRAM_SIZE: usize = VRAM_END - VRAM_BEGIN + 1;
+const VRAM_BANKS: usize = 2;
+const TILE_DATA_SIZE: usize = 0x1800; // 384 tiles of 16 bytes at 0x8000-0x97FF
+pub const OAM_SIZE: usize = 0xA0;
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
 
 // LCD I/O Register Addresses
-const LCDC_ADDR: u16 = 0xFF40;
+pub const LCDC_ADDR: u16 = 0xFF40;
 const STAT_ADDR: u16 = 0xFF41;
 const SCY_ADDR: u16 = 0xFF42;
 const SCX_ADDR: u16 = 0xFF43;
 const LY_ADDR: u16 = 0xFF44;
 const LYC_ADDR: u16 = 0xFF45;
-const DMA_ADDR: u16 = 0xFF46;
-const BGP_ADDR: u16 = 0xFF47;
+pub const DMA_ADDR: u16 = 0xFF46;
+pub const BGP_ADDR: u16 = 0xFF47;
 const OBP0_ADDR: u16 = 0xFF48;
 const OBP1_ADDR: u16 = 0xFF49;
 const WY_ADDR: u16 = 0xFF4A;
 const WX_ADDR: u16 = 0xFF4B;
+// CGB palette and object priority registers
+const BCPS_ADDR: u16 = 0xFF68;
+const BCPD_ADDR: u16 = 0xFF69;
+const OCPS_ADDR: u16 = 0xFF6A;
+const OCPD_ADDR: u16 = 0xFF6B;
+const OPRI_ADDR: u16 = 0xFF6C;
+
+// Scanline timing, in dots (T-cycles)
+const DOTS_PER_LINE: u16 = 456;
+const OAM_SCAN_DOTS: u16 = 80;
+const DRAWING_DOTS: u16 = 172;
+const VISIBLE_LINES: u8 = 144;
+const TOTAL_LINES: u8 = 154;
+
+const LCDC_ENABLE_BIT: u8 = 0b1000_0000;
+
+// STAT bits
+const STAT_UNUSED_BIT: u8 = 0b1000_0000;
+const STAT_LYC_INTERRUPT: u8 = 0b0100_0000;
+const STAT_OAM_INTERRUPT: u8 = 0b0010_0000;
+const STAT_VBLANK_INTERRUPT: u8 = 0b0001_0000;
+const STAT_HBLANK_INTERRUPT: u8 = 0b0000_1000;
+const STAT_WRITABLE_MASK: u8 = 0b0111_1000;
+const STAT_COINCIDENCE_BIT: u8 = 0b0000_0100;
+
+/// PPU mode as reported in STAT bits 0-1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
 
 #[derive(Copy, Clone)]
 enum TilePixelValue {
-    Zero,
-    One,
-    Two,
-    Three,
+    Zero = 0,
+    One = 1,
+    Two = 2,
+    Three = 3,
 }
 
 type Tile = [[TilePixelValue; 8]; 8];
@@ -36,11 +101,17 @@ fn empty_tile() -> Tile {
 }
 
 pub(crate) struct GPU {
-    vram: [u8; VRAM_SIZE],
-    tile_set: [Tile; 384],
+    vram: [[u8; VRAM_SIZE]; VRAM_BANKS],
+    tile_set: [[Tile; 384]; VRAM_BANKS],
+    vram_bank: usize, // VBK (0xFF4F) - bank used for CPU and OAM DMA access
+    oam: [u8; OAM_SIZE],
+    // Timing state
+    dot: u16,        // position within the current scanline (0-455)
+    mode: Mode,      // current PPU mode
+    stat_line: bool, // OR of all enabled STAT interrupt sources, for edge detection
     // LCD I/O Registers
     lcdc: u8, // 0xFF40 - LCD Control
-    stat: u8, // 0xFF41 - LCD Status
+    stat: u8, // 0xFF41 - LCD Status (interrupt select bits 3-6 only)
     scy: u8,  // 0xFF42 - Scroll Y
     scx: u8,  // 0xFF43 - Scroll X
     ly: u8,   // 0xFF44 - LCD Y-Coordinate
@@ -51,37 +122,223 @@ pub(crate) struct GPU {
     obp1: u8, // 0xFF49 - OBJ Palette 1 Data
     wy: u8,   // 0xFF4A - Window Y Position
     wx: u8,   // 0xFF4B - Window X Position
+    // CGB
+    cgb_mode: bool,
+    dmg_compat: bool,         // DMG game on CGB: DMG shades index the CGB palettes
+    bg_palettes: PaletteRam,  // 0xFF68/0xFF69 - BCPS/BCPD
+    obj_palettes: PaletteRam, // 0xFF6A/0xFF6B - OCPS/OCPD
+    opri: u8,                 // 0xFF6C - Object priority mode (bit 0: by X coordinate)
+    // Output
+    framebuffer: Vec<Rgb555>, // SCREEN_WIDTH x SCREEN_HEIGHT pixels, row-major
+    window_line: u8,          // window row drawn on the next line that shows it
+    frame_count: u64,         // completed frames, incremented at VBlank
 }
 
 impl GPU {
     pub(crate) fn new() -> GPU {
         GPU {
-            vram: [0; VRAM_SIZE],
-            tile_set: [empty_tile(); 384],
+            vram: [[0; VRAM_SIZE]; VRAM_BANKS],
+            tile_set: [[empty_tile(); 384]; VRAM_BANKS],
+            vram_bank: 0,
+            oam: [0; OAM_SIZE],
+            dot: 0,
+            mode: Mode::OamScan,
+            stat_line: false,
             lcdc: 0x91, // Default value: display on, BG on
             stat: 0,
             scy: 0,
             scx: 0,
             ly: 0,
             lyc: 0,
-            dma: 0,
+            dma: 0xFF, // Last OAM DMA source written by the boot ROM
             bgp: 0xFC, // Default palette
             obp0: 0xFF,
             obp1: 0xFF,
             wy: 0,
             wx: 0,
+            cgb_mode: false,
+            dmg_compat: false,
+            bg_palettes: PaletteRam::new(),
+            obj_palettes: PaletteRam::new(),
+            opri: 0,
+            framebuffer: vec![palette::DMG_SHADES[0]; SCREEN_WIDTH * SCREEN_HEIGHT],
+            window_line: 0,
+            frame_count: 0,
+        }
+    }
+
+    /// Switch CGB features (colour palettes, BG attributes, VRAM bank 1) on or off.
+    pub fn set_cgb_mode(&mut self, cgb_mode: bool) {
+        self.cgb_mode = cgb_mode;
+    }
+
+    /// Colour DMG shades through CGB palette RAM (DMG compatibility mode).
+    pub fn set_dmg_compat(&mut self, dmg_compat: bool) {
+        self.dmg_compat = dmg_compat;
+    }
+
+    pub fn is_dmg_compat(&self) -> bool {
+        self.dmg_compat
+    }
+
+    /// Load compatibility palettes into BG palette 0 and OBJ palettes 0 and 1,
+    /// as the CGB boot ROM does for DMG games.
+    pub fn load_compat_palettes(&mut self, palettes: &CompatPalettes) {
+        for color in 0..4 {
+            let index = color as usize;
+            self.bg_palettes.set_color(0, color, palettes.bg[index]);
+            self.obj_palettes.set_color(0, color, palettes.obj0[index]);
+            self.obj_palettes.set_color(1, color, palettes.obj1[index]);
+        }
+    }
+
+    /// The last rendered frame as RGB555 pixels, row by row.
+    pub fn framebuffer(&self) -> &[Rgb555] {
+        &self.framebuffer
+    }
+
+    /// Save the framebuffer as an 8-bit RGB PNG.
+    pub fn write_screenshot(&self, path: &Path, correction: ColorCorrection) -> io::Result<()> {
+        write_png(path, &self.framebuffer, SCREEN_WIDTH, correction)
+    }
+
+    /// Number of frames completed so far (incremented on entering VBlank).
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Advance the PPU by one dot, requesting VBlank and STAT interrupts as needed.
+    ///
+    /// Returns true when a visible line enters HBlank (the HBlank DMA trigger).
+    /// Does nothing while the LCD is off.
+    pub fn tick(&mut self, interrupts: &mut InterruptController) -> bool {
+        if !self.lcd_enabled() {
+            return false;
+        }
+
+        self.dot += 1;
+        if self.dot == DOTS_PER_LINE {
+            self.dot = 0;
+            self.ly = (self.ly + 1) % TOTAL_LINES;
+        }
+
+        let previous_mode = self.mode;
+        self.mode = if self.ly >= VISIBLE_LINES {
+            Mode::VBlank
+        } else if self.dot < OAM_SCAN_DOTS {
+            Mode::OamScan
+        } else if self.dot < OAM_SCAN_DOTS + DRAWING_DOTS {
+            Mode::Drawing
+        } else {
+            Mode::HBlank
+        };
+
+        let entered_hblank = previous_mode == Mode::Drawing && self.mode == Mode::HBlank;
+        if entered_hblank {
+            self.render_scanline();
+        }
+
+        if self.ly == VISIBLE_LINES && self.dot == 0 {
+            interrupts.request_interrupt(Interrupt::VBlank);
+            self.frame_count += 1;
+            self.window_line = 0;
+        }
+
+        // STAT interrupt fires on the rising edge of the combined source line
+        let stat_line = self.stat_sources_active();
+        if stat_line && !self.stat_line {
+            interrupts.request_interrupt(Interrupt::LcdStat);
+        }
+        self.stat_line = stat_line;
+
+        entered_hblank
+    }
+
+    /// Current PPU mode (HBlank while the LCD is off).
+    pub fn mode(&self) -> Mode {
+        if self.lcd_enabled() {
+            self.mode
+        } else {
+            Mode::HBlank
+        }
+    }
+
+    /// Whether the CPU can currently access VRAM.
+    pub fn vram_accessible(&self) -> bool {
+        self.mode() != Mode::Drawing
+    }
+
+    /// Whether the CPU can currently access OAM.
+    pub fn oam_accessible(&self) -> bool {
+        !matches!(self.mode(), Mode::OamScan | Mode::Drawing)
+    }
+
+    pub fn read_oam(&self, index: usize) -> u8 {
+        self.oam[index]
+    }
+
+    pub fn write_oam(&mut self, index: usize, value: u8) {
+        self.oam[index] = value;
+    }
+
+    pub fn lcd_enabled(&self) -> bool {
+        self.lcdc & LCDC_ENABLE_BIT != 0
+    }
+
+    fn read_stat(&self) -> u8 {
+        let coincidence = if self.ly == self.lyc {
+            STAT_COINCIDENCE_BIT
+        } else {
+            0
+        };
+        STAT_UNUSED_BIT | self.stat | coincidence | self.mode() as u8
+    }
+
+    fn write_lcdc(&mut self, value: u8) {
+        let was_enabled = self.lcd_enabled();
+        self.lcdc = value;
+        if was_enabled != self.lcd_enabled() {
+            // Switching the LCD on or off restarts the frame at line 0
+            self.ly = 0;
+            self.dot = 0;
+            self.mode = Mode::OamScan;
+            self.stat_line = false;
+            self.window_line = 0;
         }
     }
 
+    fn stat_sources_active(&self) -> bool {
+        (self.stat & STAT_LYC_INTERRUPT != 0 && self.ly == self.lyc)
+            || (self.stat & STAT_HBLANK_INTERRUPT != 0 && self.mode == Mode::HBlank)
+            || (self.stat & STAT_VBLANK_INTERRUPT != 0 && self.mode == Mode::VBlank)
+            || (self.stat & STAT_OAM_INTERRUPT != 0 && self.mode == Mode::OamScan)
+    }
+
+    /// VRAM bank selected by VBK (0 or 1).
+    pub fn vram_bank(&self) -> usize {
+        self.vram_bank
+    }
+
+    /// Select the VRAM bank for CPU access (VBK bit 0; CGB only).
+    pub fn set_vram_bank(&mut self, bank: usize) {
+        self.vram_bank = bank & 1;
+    }
+
     pub fn read_vram(&self, address: usize) -> u8 {
-        self.vram[address]
+        self.vram[self.vram_bank][address]
     }
 
     pub fn write_vram(&mut self, index: usize, value: u8) {
-        self.vram[index] = value;
+        let bank = self.vram_bank;
+        self.vram[bank][index] = value;
+        self.decode_tile_row(bank, index);
+    }
+
+    // Update the decoded tile set after VRAM byte `index` of `bank` changed.
+    fn decode_tile_row(&mut self, bank: usize, index: usize) {
         // If our index is greater than 0x1800, we're not writing to the tile set storage
         // so we can just return.
-        if index >= 0x1800 {
+        if index >= TILE_DATA_SIZE {
             return;
         }
 
@@ -92,8 +349,8 @@ impl GPU {
         let normalized_index = index & 0xFFFE;
 
         // First we need to get the two bytes that encode the tile row.
-        let byte1 = self.vram[normalized_index];
-        let byte2 = self.vram[normalized_index + 1];
+        let byte1 = self.vram[bank][normalized_index];
+        let byte2 = self.vram[bank][normalized_index + 1];
 
         // A tiles is 8 rows tall. Since each row is encoded with two bytes a tile
         // is therefore 16 bytes in total.
@@ -136,7 +393,7 @@ impl GPU {
                 (false, false) => TilePixelValue::Zero,
             };
 
-            self.tile_set[tile_index][row_index][pixel_index] = value;
+            self.tile_set[bank][tile_index][row_index][pixel_index] = value;
         }
     }
 
@@ -150,7 +407,7 @@ impl GPU {
     pub fn read_register(&self, addr: u16) -> u8 {
         match addr {
             LCDC_ADDR => self.lcdc,
-            STAT_ADDR => self.stat,
+            STAT_ADDR => self.read_stat(),
             SCY_ADDR => self.scy,
             SCX_ADDR => self.scx,
             LY_ADDR => self.ly,
@@ -161,6 +418,13 @@ impl GPU {
             OBP1_ADDR => self.obp1,
             WY_ADDR => self.wy,
             WX_ADDR => self.wx,
+            BCPS_ADDR => self.bg_palettes.read_spec(),
+            OCPS_ADDR => self.obj_palettes.read_spec(),
+            // Palette RAM is inaccessible while the PPU is drawing
+            BCPD_ADDR | OCPD_ADDR if self.mode() == Mode::Drawing => 0xFF,
+            BCPD_ADDR => self.bg_palettes.read_data(),
+            OCPD_ADDR => self.obj_palettes.read_data(),
+            OPRI_ADDR => self.opri,
             _ => 0,
         }
     }
@@ -172,14 +436,11 @@ impl GPU {
     /// * `value` - The value to write
     pub fn write_register(&mut self, addr: u16, value: u8) {
         match addr {
-            LCDC_ADDR => self.lcdc = value,
-            STAT_ADDR => self.stat = value,
+            LCDC_ADDR => self.write_lcdc(value),
+            STAT_ADDR => self.stat = value & STAT_WRITABLE_MASK,
             SCY_ADDR => self.scy = value,
             SCX_ADDR => self.scx = value,
-            LY_ADDR => {
-                // LY is read-only in hardware, but we allow writing for emulation purposes
-                self.ly = value;
-            }
+            LY_ADDR => {} // LY is read-only
             LYC_ADDR => self.lyc = value,
             DMA_ADDR => self.dma = value,
             BGP_ADDR => self.bgp = value,
@@ -187,7 +448,236 @@ impl GPU {
             OBP1_ADDR => self.obp1 = value,
             WY_ADDR => self.wy = value,
             WX_ADDR => self.wx = value,
+            BCPS_ADDR => self.bg_palettes.write_spec(value),
+            OCPS_ADDR => self.obj_palettes.write_spec(value),
+            BCPD_ADDR if self.mode() == Mode::Drawing => self.bg_palettes.write_data_locked(),
+            OCPD_ADDR if self.mode() == Mode::Drawing => self.obj_palettes.write_data_locked(),
+            BCPD_ADDR => self.bg_palettes.write_data(value),
+            OCPD_ADDR => self.obj_palettes.write_data(value),
+            OPRI_ADDR => self.opri = value & 0x01,
             _ => {}
         }
     }
 }
+
+/// The decoded tile set is rebuilt from VRAM instead of being saved.
+impl Snapshot for GPU {
+    fn save_to(&self, state: &mut StateWriter) {
+        for bank in &self.vram {
+            state.bytes(bank);
+        }
+        state.u8(self.vram_bank as u8);
+        state.bytes(&self.oam);
+        state.u16(self.dot);
+        state.u8(self.mode as u8);
+        state.bool(self.stat_line);
+        for register in [
+            self.lcdc, self.stat, self.scy, self.scx, self.ly, self.lyc, self.dma, self.bgp,
+            self.obp0, self.obp1, self.wy, self.wx,
+        ] {
+            state.u8(register);
+        }
+        state.bool(self.cgb_mode);
+        state.bool(self.dmg_compat);
+        self.bg_palettes.save_to(state);
+        self.obj_palettes.save_to(state);
+        state.u8(self.opri);
+        for &pixel in &self.framebuffer {
+            state.u16(pixel);
+        }
+        state.u8(self.window_line);
+        state.u64(self.frame_count);
+    }
+
+    fn load_from(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        for bank in &mut self.vram {
+            state.bytes(bank)?;
+        }
+        self.vram_bank = (state.u8()? & 1) as usize;
+        state.bytes(&mut self.oam)?;
+        self.dot = state.u16()?;
+        if self.dot >= DOTS_PER_LINE {
+            return Err(StateError::Corrupt("invalid PPU dot"));
+        }
+        self.mode = match state.u8()? {
+            0 => Mode::HBlank,
+            1 => Mode::VBlank,
+            2 => Mode::OamScan,
+            3 => Mode::Drawing,
+            _ => return Err(StateError::Corrupt("invalid PPU mode")),
+        };
+        self.stat_line = state.bool()?;
+        for register in [
+            &mut self.lcdc,
+            &mut self.stat,
+            &mut self.scy,
+            &mut self.scx,
+            &mut self.ly,
+            &mut self.lyc,
+            &mut self.dma,
+            &mut self.bgp,
+            &mut self.obp0,
+            &mut self.obp1,
+            &mut self.wy,
+            &mut self.wx,
+        ] {
+            *register = state.u8()?;
+        }
+        if self.ly >= TOTAL_LINES {
+            return Err(StateError::Corrupt("invalid LY"));
+        }
+        self.cgb_mode = state.bool()?;
+        self.dmg_compat = state.bool()?;
+        self.bg_palettes.load_from(state)?;
+        self.obj_palettes.load_from(state)?;
+        self.opri = state.u8()?;
+        for pixel in &mut self.framebuffer {
+            *pixel = state.u16()?;
+        }
+        self.window_line = state.u8()?;
+        if self.window_line > VISIBLE_LINES {
+            return Err(StateError::Corrupt("invalid window line"));
+        }
+        self.frame_count = state.u64()?;
+
+        for bank in 0..VRAM_BANKS {
+            for index in (0..TILE_DATA_SIZE).step_by(2) {
+                self.decode_tile_row(bank, index);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Save `width`-pixel wide RGB555 `pixels` as an 8-bit RGB PNG.
+pub fn write_png(
+    path: &Path,
+    pixels: &[Rgb555],
+    width: usize,
+    correction: ColorCorrection,
+) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let height = pixels.len() / width;
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let data: Vec<u8> = pixels
+        .iter()
+        .flat_map(|&color| palette::to_rgb888(color, correction))
+        .collect();
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&data))
+        .map_err(io::Error::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn requested(interrupts: &InterruptController, interrupt: Interrupt) -> bool {
+        interrupts.read_if() & interrupt.bit_mask() != 0
+    }
+
+    fn run_dots(gpu: &mut GPU, interrupts: &mut InterruptController, dots: u32) {
+        for _ in 0..dots {
+            gpu.tick(interrupts);
+        }
+    }
+
+    #[test]
+    fn test_out_of_range_position_is_rejected_on_load() {
+        let mut state = StateWriter::new();
+        GPU::new().save_to(&mut state);
+        let saved = state.into_bytes();
+        let dot = VRAM_BANKS * VRAM_SIZE + 1 + OAM_SIZE;
+        let ly = dot + 2 + 1 + 1 + 4;
+
+        let mut bad_dot = saved.clone();
+        bad_dot[dot..dot + 2].copy_from_slice(&DOTS_PER_LINE.to_le_bytes());
+        let mut bad_ly = saved.clone();
+        bad_ly[ly] = TOTAL_LINES;
+
+        let mut gpu = GPU::new();
+        assert!(gpu.load_from(&mut StateReader::new(&saved)).is_ok());
+        for bad in [bad_dot, bad_ly] {
+            assert!(matches!(
+                gpu.load_from(&mut StateReader::new(&bad)),
+                Err(StateError::Corrupt(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_mode_sequence_within_scanline() {
+        let mut gpu = GPU::new();
+        let mut interrupts = InterruptController::new();
+
+        assert_eq!(gpu.mode(), Mode::OamScan);
+        run_dots(&mut gpu, &mut interrupts, 80);
+        assert_eq!(gpu.mode(), Mode::Drawing);
+        assert!(!gpu.vram_accessible());
+        run_dots(&mut gpu, &mut interrupts, 172);
+        assert_eq!(gpu.mode(), Mode::HBlank);
+        assert!(gpu.vram_accessible() && gpu.oam_accessible());
+        run_dots(&mut gpu, &mut interrupts, 204);
+        assert_eq!(gpu.read_register(LY_ADDR), 1);
+        assert_eq!(gpu.mode(), Mode::OamScan);
+    }
+
+    #[test]
+    fn test_vblank_interrupt_at_line_144() {
+        let mut gpu = GPU::new();
+        let mut interrupts = InterruptController::new();
+
+        run_dots(&mut gpu, &mut interrupts, 144 * 456 - 1);
+        assert!(!requested(&interrupts, Interrupt::VBlank));
+        gpu.tick(&mut interrupts);
+        assert!(requested(&interrupts, Interrupt::VBlank));
+        assert_eq!(gpu.mode(), Mode::VBlank);
+        assert_eq!(gpu.read_register(STAT_ADDR) & 0x03, 1);
+
+        // The frame wraps back to line 0 after 154 lines
+        run_dots(&mut gpu, &mut interrupts, 10 * 456);
+        assert_eq!(gpu.read_register(LY_ADDR), 0);
+    }
+
+    #[test]
+    fn test_lyc_stat_interrupt_on_rising_edge() {
+        let mut gpu = GPU::new();
+        let mut interrupts = InterruptController::new();
+        gpu.write_register(LYC_ADDR, 2);
+        gpu.write_register(STAT_ADDR, STAT_LYC_INTERRUPT);
+
+        run_dots(&mut gpu, &mut interrupts, 2 * 456 - 1);
+        assert!(!requested(&interrupts, Interrupt::LcdStat));
+        gpu.tick(&mut interrupts);
+        assert!(requested(&interrupts, Interrupt::LcdStat));
+        assert_eq!(
+            gpu.read_register(STAT_ADDR),
+            STAT_UNUSED_BIT | STAT_LYC_INTERRUPT | STAT_COINCIDENCE_BIT | Mode::OamScan as u8
+        );
+    }
+
+    #[test]
+    fn test_lcd_off_resets_ly_and_unlocks_memory() {
+        let mut gpu = GPU::new();
+        let mut interrupts = InterruptController::new();
+        run_dots(&mut gpu, &mut interrupts, 3 * 456 + 100);
+
+        gpu.write_register(LCDC_ADDR, 0x11);
+        run_dots(&mut gpu, &mut interrupts, 1000);
+
+        assert_eq!(gpu.read_register(LY_ADDR), 0);
+        assert_eq!(gpu.mode(), Mode::HBlank);
+        assert!(gpu.vram_accessible() && gpu.oam_accessible());
+    }
+
+    #[test]
+    fn test_ly_is_read_only() {
+        let mut gpu = GPU::new();
+        gpu.write_register(LY_ADDR, 0x42);
+        assert_eq!(gpu.read_register(LY_ADDR), 0);
+    }
+}
@@ -0,0 +1,332 @@
+//! Colour palettes for DMG games running on a CGB.
+//!
+//! A game without the CGB flag runs in DMG compatibility mode: BGP, OBP0 and
+//! OBP1 still pick shades 0-3, but each shade is looked up in CGB background
+//! palette 0 or object palette 0/1. The CGB boot ROM fills those palettes
+//! from a table keyed by the sum of the title bytes, for Nintendo-licensed
+//! games only; a few checksums are shared by several titles and are told
+//! apart by the fourth title letter. Every other game gets the default
+//! palettes. Holding a direction (and optionally A or B) during the boot logo
+//! picks one of twelve palette sets instead.
+//!
+//! The tables below are the boot ROM's: 79 title checksums, the last 14 of
+//! which are shared and followed by up to three rows of fourth letters; a
+//! palette combination for each of the 94 resulting entries; and the colours
+//! the 51 combinations draw from.
+//!
+//! Reference: [Pan Docs — Power Up Sequence](https://gbdev.io/pandocs/Power_Up_Sequence.html#compatibility-palettes)
+
+use super::palette::Rgb555;
+use crate::cartridge_header::CartridgeHeader;
+
+/// Background palette 0 and object palettes 0 and 1, shades 0-3 each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompatPalettes {
+    pub bg: [Rgb555; 4],
+    pub obj0: [Rgb555; 4],
+    pub obj1: [Rgb555; 4],
+}
+
+/// The boot ROM's colour palettes.
+const PALETTES: [[Rgb555; 4]; 30] = [
+    [0x7FFF, 0x32BF, 0x00D0, 0x0000],
+    [0x639F, 0x4279, 0x15B0, 0x04CB],
+    [0x7FFF, 0x6E31, 0x454A, 0x0000],
+    [0x7FFF, 0x1BEF, 0x0200, 0x0000],
+    [0x7FFF, 0x421F, 0x1CF2, 0x0000],
+    [0x7FFF, 0x5294, 0x294A, 0x0000],
+    [0x7FFF, 0x03FF, 0x012F, 0x0000],
+    [0x7FFF, 0x03EF, 0x01D6, 0x0000],
+    [0x7FFF, 0x42B5, 0x3DC8, 0x0000],
+    [0x7E74, 0x03FF, 0x0180, 0x0000],
+    [0x67FF, 0x77AC, 0x1A13, 0x2D6B],
+    [0x7ED6, 0x4BFF, 0x2175, 0x0000],
+    [0x53FF, 0x4A5F, 0x7E52, 0x0000],
+    [0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0],
+    [0x03ED, 0x7FFF, 0x255F, 0x0000],
+    [0x036A, 0x021F, 0x03FF, 0x7FFF],
+    [0x7FFF, 0x01DF, 0x0112, 0x0000],
+    [0x231F, 0x035F, 0x00F2, 0x0009],
+    [0x7FFF, 0x03EA, 0x011F, 0x0000],
+    [0x299F, 0x001A, 0x000C, 0x0000],
+    [0x7FFF, 0x027F, 0x001F, 0x0000],
+    [0x7FFF, 0x03E0, 0x0206, 0x0120],
+    [0x7FFF, 0x7EEB, 0x001F, 0x7C00],
+    [0x7FFF, 0x3FFF, 0x7E00, 0x001F],
+    [0x7FFF, 0x03FF, 0x001F, 0x0000],
+    [0x03FF, 0x001F, 0x000C, 0x0000],
+    [0x7FFF, 0x033F, 0x0193, 0x0000],
+    [0x0000, 0x4200, 0x037F, 0x7FFF],
+    [0x7FFF, 0x7E8C, 0x7C00, 0x0000],
+    [0x7FFF, 0x1BEF, 0x6180, 0x0000],
+];
+
+/// Where object palettes 0 and 1 and the background palette start, counted
+/// in colours from the start of [`PALETTES`]. A few combinations start at
+/// the last colour of a palette and run into the next one.
+#[derive(Debug, Clone, Copy)]
+struct Combination {
+    obj0: usize,
+    obj1: usize,
+    bg: usize,
+}
+
+/// A combination of three whole palettes.
+const fn whole(obj0: usize, obj1: usize, bg: usize) -> Combination {
+    Combination {
+        obj0: obj0 * 4,
+        obj1: obj1 * 4,
+        bg: bg * 4,
+    }
+}
+
+const COMBINATIONS: [Combination; 51] = [
+    whole(4, 4, 29),
+    whole(18, 18, 18),
+    whole(20, 20, 20),
+    whole(24, 24, 24),
+    whole(9, 9, 9),
+    whole(0, 0, 0),
+    whole(27, 27, 27),
+    whole(5, 5, 5),
+    whole(12, 12, 12),
+    whole(26, 26, 26),
+    whole(16, 8, 8),
+    whole(4, 28, 28),
+    whole(4, 2, 2),
+    whole(3, 4, 4),
+    whole(4, 29, 29),
+    whole(28, 4, 28),
+    whole(2, 17, 2),
+    whole(16, 16, 8),
+    whole(4, 4, 7),
+    whole(4, 4, 18),
+    whole(4, 4, 20),
+    whole(19, 19, 9),
+    Combination {
+        obj0: 4 * 4 - 1,
+        obj1: 4 * 4 - 1,
+        bg: 11 * 4,
+    },
+    whole(17, 17, 2),
+    whole(4, 4, 2),
+    whole(4, 4, 3),
+    whole(28, 28, 0),
+    whole(3, 3, 0),
+    whole(0, 0, 1),
+    whole(18, 22, 18),
+    whole(20, 22, 20),
+    whole(24, 22, 24),
+    whole(16, 22, 8),
+    whole(17, 4, 13),
+    Combination {
+        obj0: 28 * 4 - 1,
+        obj1: 0,
+        bg: 14 * 4,
+    },
+    Combination {
+        obj0: 28 * 4 - 1,
+        obj1: 4 * 4,
+        bg: 15 * 4,
+    },
+    whole(19, 22, 9),
+    whole(16, 28, 10),
+    whole(4, 23, 28),
+    whole(17, 22, 2),
+    whole(4, 0, 2),
+    whole(4, 28, 3),
+    whole(28, 3, 0),
+    whole(3, 28, 4),
+    whole(21, 28, 4),
+    whole(3, 28, 0),
+    whole(25, 3, 28),
+    whole(0, 28, 8),
+    whole(4, 3, 28),
+    whole(28, 3, 6),
+    whole(4, 28, 29),
+];
+
+/// Combination for games without a title table entry (the same as "right").
+const DEFAULT_COMBINATION: usize = 0;
+
+/// Combinations selectable with the button combinations on the boot logo.
+const BUTTON_COMBOS: [(&str, usize); 12] = [
+    ("up", 5),
+    ("up+a", 43),
+    ("up+b", 28),
+    ("left", 48),
+    ("left+a", 40),
+    ("left+b", 7),
+    ("down", 8),
+    ("down+a", 3),
+    ("down+b", 49),
+    ("right", DEFAULT_COMBINATION),
+    ("right+a", 1),
+    ("right+b", 6),
+];
+
+/// Sums of the title bytes of the games with their own palettes.
+const TITLE_CHECKSUMS: [u8; 79] = [
+    0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C, 0x58, 0xC9, 0x3E, 0x70,
+    0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA, 0x75, 0x95, 0x99, 0x34, 0x6F, 0x15, 0xFF, 0x97,
+    0x4B, 0x90, 0x17, 0x10, 0x39, 0xF7, 0xF6, 0xA2, 0x49, 0x4E, 0x43, 0x68, 0xE0, 0x8B, 0xF0, 0xCE,
+    0x0C, 0x29, 0xE8, 0xB7, 0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD, 0x5D, 0x6D, 0x67, 0x3F,
+    0x6B, // the rest are shared by several games
+    0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4,
+];
+
+/// Index of the first shared checksum in [`TITLE_CHECKSUMS`].
+const FIRST_SHARED_CHECKSUM: usize = 65;
+const SHARED_CHECKSUMS: usize = TITLE_CHECKSUMS.len() - FIRST_SHARED_CHECKSUM;
+
+/// Fourth title letters telling the games with a shared checksum apart, in
+/// rows of one letter per shared checksum.
+const FOURTH_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+/// The combination of each title table entry: one per unshared checksum,
+/// then one per fourth letter.
+const TITLE_COMBINATIONS: [u8; FIRST_SHARED_CHECKSUM + FOURTH_LETTERS.len()] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44, 21, 32, 31, 20, 5, 33, 13, 14, 5, 29,
+    5, 18, 9, 3, 2, 26, 25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34, 5, 42, 6,
+    5, 33, 25, 42, 42, 40, 2, 16, 25, 42, 42, 5, 0, 39, // shared checksums:
+    36, 22, 25, 6, 32, 12, 36, 11, 39, 18, 39, 24, 31, 50, 17, 46, 6, 27, 0, 47, 41, 41, 0, 0, 19,
+    34, 23, 18, 29,
+];
+
+/// The combination the boot ROM picks for a title checksum and fourth
+/// title letter.
+fn title_combination(checksum: u8, fourth_letter: u8) -> usize {
+    let Some(index) = TITLE_CHECKSUMS.iter().position(|&sum| sum == checksum) else {
+        return DEFAULT_COMBINATION;
+    };
+    if index < FIRST_SHARED_CHECKSUM {
+        return TITLE_COMBINATIONS[index] as usize;
+    }
+    (index - FIRST_SHARED_CHECKSUM..FOURTH_LETTERS.len())
+        .step_by(SHARED_CHECKSUMS)
+        .find(|&letter| FOURTH_LETTERS[letter] == fourth_letter)
+        .map_or(DEFAULT_COMBINATION, |letter| {
+            TITLE_COMBINATIONS[FIRST_SHARED_CHECKSUM + letter] as usize
+        })
+}
+
+/// Four colours from [`PALETTES`] starting at colour `start`.
+fn colors(start: usize) -> [Rgb555; 4] {
+    std::array::from_fn(|shade| {
+        let color = start + shade;
+        PALETTES[color / 4][color % 4]
+    })
+}
+
+fn combination(index: usize) -> CompatPalettes {
+    let combination = COMBINATIONS[index];
+    CompatPalettes {
+        bg: colors(combination.bg),
+        obj0: colors(combination.obj0),
+        obj1: colors(combination.obj1),
+    }
+}
+
+impl CompatPalettes {
+    /// The palettes the CGB boot ROM picks for `header`.
+    pub fn for_header(header: &CartridgeHeader) -> CompatPalettes {
+        if !header.is_nintendo_licensed() {
+            return combination(DEFAULT_COMBINATION);
+        }
+        combination(title_combination(header.title_checksum(), header.title[3]))
+    }
+
+    /// The palettes for a boot logo button combination such as `left+a`
+    /// (case-insensitive).
+    pub fn from_button_combo(name: &str) -> Option<CompatPalettes> {
+        let name = name.to_ascii_lowercase();
+        BUTTON_COMBOS
+            .iter()
+            .find(|(combo, _)| *combo == name)
+            .map(|&(_, index)| combination(index))
+    }
+
+    /// Names accepted by [`CompatPalettes::from_button_combo`].
+    pub fn button_combo_names() -> impl Iterator<Item = &'static str> {
+        BUTTON_COMBOS.iter().map(|&(name, _)| name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: [Rgb555; 4] = PALETTES[4];
+    const GREEN: [Rgb555; 4] = PALETTES[3];
+    const BLUE: [Rgb555; 4] = PALETTES[28];
+
+    fn header(title: &str, old_licensee: u8) -> CartridgeHeader {
+        let mut rom = vec![0u8; 0x150];
+        rom[0x134..0x134 + title.len()].copy_from_slice(title.as_bytes());
+        rom[0x14B] = old_licensee;
+        CartridgeHeader::parse(&rom).unwrap()
+    }
+
+    fn palettes(title: &str) -> CompatPalettes {
+        CompatPalettes::for_header(&header(title, 0x01))
+    }
+
+    #[test]
+    fn test_palettes_by_title_checksum() {
+        let red = palettes("POKEMON RED");
+        assert_eq!(red.bg, RED);
+        assert_eq!(red.obj0, GREEN);
+        assert_eq!(red.obj1, RED);
+        assert_eq!(
+            Some(palettes("TETRIS")),
+            CompatPalettes::from_button_combo("down+a")
+        );
+        assert_eq!(palettes("ALLEY WAY").bg, [0x7E74, 0x03FF, 0x0180, 0x0000]);
+
+        // Same title from another licensee: default palettes
+        assert_eq!(
+            CompatPalettes::for_header(&header("POKEMON RED", 0x08)),
+            combination(DEFAULT_COMBINATION)
+        );
+    }
+
+    #[test]
+    fn test_fourth_letter_disambiguates_shared_checksums() {
+        // First row of fourth letters
+        let blue = palettes("POKEMON BLUE");
+        assert_eq!((blue.bg, blue.obj0, blue.obj1), (BLUE, RED, BLUE));
+        // Second row: the same checksum as POKEMON BLUE
+        assert_eq!(header("VEGAS STAKES", 0x01).title_checksum(), 0x61);
+        let vegas = palettes("VEGAS STAKES");
+        assert_eq!((vegas.bg, vegas.obj0, vegas.obj1), (GREEN, RED, BLUE));
+        // Third row
+        let attack = palettes("TETRIS ATTACK");
+        assert_eq!(attack.obj1, PALETTES[22]);
+
+        // Objects starting at the last colour of a palette
+        let mario = palettes("SUPER MARIOLAND");
+        assert_eq!(mario.obj0, [0x0000, 0x7FFF, 0x421F, 0x1CF2]);
+        assert_eq!(mario.bg, PALETTES[11]);
+
+        // Same checksum (letters swapped), a fourth letter in no row
+        let other = header("POKMEON BLUE", 0x01);
+        assert_eq!(other.title_checksum(), 0x61);
+        assert_eq!(
+            CompatPalettes::for_header(&other),
+            combination(DEFAULT_COMBINATION)
+        );
+    }
+
+    #[test]
+    fn test_button_combo_names() {
+        assert_eq!(CompatPalettes::button_combo_names().count(), 12);
+        assert_eq!(
+            CompatPalettes::from_button_combo("RIGHT"),
+            Some(combination(DEFAULT_COMBINATION))
+        );
+        let up_b = CompatPalettes::from_button_combo("up+b").unwrap();
+        assert_eq!(up_b.bg, [0x639F, 0x4279, 0x15B0, 0x04CB]);
+        assert_eq!(up_b.obj0, PALETTES[0]);
+        assert_eq!(CompatPalettes::from_button_combo("up+start"), None);
+    }
+}
@@ -0,0 +1,168 @@
+//! CGB colour palette RAM and RGB555 colour conversion.
+//!
+//! Background and object palettes each have 64 bytes of palette RAM: 8
+//! palettes of 4 colours, stored as little-endian RGB555. The RAM is accessed
+//! through an index register (BCPS/OCPS) and a data register (BCPD/OCPD);
+//! with bit 7 of the index set, each data write advances the index.
+//!
+//! Reference: [Pan Docs — Palettes](https://gbdev.io/pandocs/Palettes.html)
+
+use crate::save_state::{Snapshot, StateError, StateReader, StateWriter};
+
+const PALETTE_RAM_SIZE: usize = 64;
+const INDEX_MASK: u8 = 0x3F;
+const AUTO_INCREMENT_BIT: u8 = 0b1000_0000;
+const SPEC_UNUSED_BIT: u8 = 0b0100_0000;
+
+/// A 15-bit colour: bits 0-4 red, 5-9 green, 10-14 blue.
+pub type Rgb555 = u16;
+
+/// DMG shades 0-3 (white to black) as RGB555 greys.
+pub const DMG_SHADES: [Rgb555; 4] = [0x7FFF, grey(21), grey(10), 0x0000];
+
+const fn grey(level: u16) -> Rgb555 {
+    level | (level << 5) | (level << 10)
+}
+
+/// How RGB555 colours are converted to 8-bit RGB for display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorCorrection {
+    /// Scale each channel linearly to 0-255.
+    None,
+    /// Approximate the washed-out, cross-bleeding colours of the CGB LCD.
+    Lcd,
+}
+
+/// Convert an RGB555 colour to 8-bit RGB.
+pub fn to_rgb888(color: Rgb555, correction: ColorCorrection) -> [u8; 3] {
+    let r = (color & 0x1F) as u32;
+    let g = ((color >> 5) & 0x1F) as u32;
+    let b = ((color >> 10) & 0x1F) as u32;
+    match correction {
+        ColorCorrection::None => [r, g, b].map(|c| (c * 255 / 31) as u8),
+        ColorCorrection::Lcd => {
+            // Each output channel mixes in some of the others, with the
+            // maximum brightness limited to 240.
+            let mix = |c: u32| (c.min(960) >> 2) as u8;
+            [
+                mix(r * 26 + g * 4 + b * 2),
+                mix(g * 24 + b * 8),
+                mix(r * 6 + g * 4 + b * 22),
+            ]
+        }
+    }
+}
+
+/// One palette RAM (background or objects) with its index register.
+pub struct PaletteRam {
+    data: [u8; PALETTE_RAM_SIZE],
+    index: u8,
+    auto_increment: bool,
+}
+
+impl PaletteRam {
+    pub fn new() -> Self {
+        PaletteRam {
+            // The boot ROM initialises palette RAM to white
+            data: [0xFF; PALETTE_RAM_SIZE],
+            index: 0,
+            auto_increment: false,
+        }
+    }
+
+    /// BCPS/OCPS: bit 7 = auto-increment, bits 0-5 = byte index.
+    pub fn read_spec(&self) -> u8 {
+        let auto_increment = if self.auto_increment {
+            AUTO_INCREMENT_BIT
+        } else {
+            0
+        };
+        auto_increment | SPEC_UNUSED_BIT | self.index
+    }
+
+    pub fn write_spec(&mut self, value: u8) {
+        self.index = value & INDEX_MASK;
+        self.auto_increment = value & AUTO_INCREMENT_BIT != 0;
+    }
+
+    /// BCPD/OCPD: the palette RAM byte at the current index.
+    pub fn read_data(&self) -> u8 {
+        self.data[self.index as usize]
+    }
+
+    pub fn write_data(&mut self, value: u8) {
+        self.data[self.index as usize] = value;
+        self.advance();
+    }
+
+    /// A data write while the PPU is drawing: the RAM is locked but the
+    /// index still advances.
+    pub fn write_data_locked(&mut self) {
+        self.advance();
+    }
+
+    /// Colour `color` (0-3) of palette `palette` (0-7).
+    pub fn color(&self, palette: u8, color: u8) -> Rgb555 {
+        let offset = (palette as usize & 0x07) * 8 + color as usize * 2;
+        u16::from_le_bytes([self.data[offset], self.data[offset + 1]]) & 0x7FFF
+    }
+
+    /// Overwrite colour `color` (0-3) of palette `palette` (0-7).
+    pub fn set_color(&mut self, palette: u8, color: u8, value: Rgb555) {
+        let offset = (palette as usize & 0x07) * 8 + color as usize * 2;
+        self.data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn advance(&mut self) {
+        if self.auto_increment {
+            self.index = (self.index + 1) & INDEX_MASK;
+        }
+    }
+}
+
+impl Snapshot for PaletteRam {
+    fn save_to(&self, state: &mut StateWriter) {
+        state.bytes(&self.data);
+        state.u8(self.index);
+        state.bool(self.auto_increment);
+    }
+
+    fn load_from(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.bytes(&mut self.data)?;
+        self.index = state.u8()? & INDEX_MASK;
+        self.auto_increment = state.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auto_increment_writes_consecutive_bytes() {
+        let mut ram = PaletteRam::new();
+        ram.write_spec(0x80 | 0x3E);
+        assert_eq!(ram.read_spec(), 0xFE);
+        ram.write_data(0x1F); // palette 7, colour 3: pure red
+        ram.write_data(0x00);
+        assert_eq!(ram.read_spec(), 0xC0); // wrapped to index 0
+        assert_eq!(ram.color(7, 3), 0x001F);
+
+        ram.write_spec(0x02);
+        ram.write_data(0xE0);
+        ram.write_data(0x03);
+        assert_eq!(ram.read_spec(), 0x42); // no auto-increment
+        assert_eq!(ram.read_data(), 0x03);
+    }
+
+    #[test]
+    fn test_color_conversion() {
+        assert_eq!(to_rgb888(0x7FFF, ColorCorrection::None), [255, 255, 255]);
+        assert_eq!(to_rgb888(0x001F, ColorCorrection::None), [255, 0, 0]);
+        assert_eq!(to_rgb888(0x7FFF, ColorCorrection::Lcd), [240, 240, 240]);
+        // Pure red bleeds into blue on the LCD
+        let [r, g, b] = to_rgb888(0x001F, ColorCorrection::Lcd);
+        assert!(r > b && b > g);
+    }
+}
@@ -0,0 +1,360 @@
+//! Scanline renderer.
+//!
+//! Each visible line is drawn into the framebuffer when the PPU leaves mode 3:
+//! the background and window from the tile maps, then up to 10 objects
+//! selected from OAM, mixed by the DMG or CGB priority rules. Pixels are
+//! stored as RGB555, with DMG shades mapped to greys (or, in DMG
+//! compatibility mode on CGB, to the first CGB palettes).
+//!
+//! On CGB, each tile map entry has an attribute byte at the same position in
+//! VRAM bank 1 (palette, tile bank, flips and BG-over-OBJ priority), and
+//! LCDC bit 0 becomes a master priority switch instead of disabling the
+//! background.
+//!
+//! Reference: [Pan Docs — Tile Maps](https://gbdev.io/pandocs/Tile_Maps.html),
+//! [Pan Docs — OAM](https://gbdev.io/pandocs/OAM.html)
+
+use super::palette::{PaletteRam, Rgb555, DMG_SHADES};
+use super::{GPU, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// LCDC bits
+const LCDC_BG_ENABLE: u8 = 0b0000_0001; // CGB: BG/window master priority
+const LCDC_OBJ_ENABLE: u8 = 0b0000_0010;
+const LCDC_OBJ_SIZE: u8 = 0b0000_0100;
+const LCDC_BG_MAP: u8 = 0b0000_1000;
+const LCDC_TILE_DATA: u8 = 0b0001_0000;
+const LCDC_WINDOW_ENABLE: u8 = 0b0010_0000;
+const LCDC_WINDOW_MAP: u8 = 0b0100_0000;
+
+// Tile map attribute (CGB) and object attribute bits
+const ATTR_PALETTE: u8 = 0b0000_0111;
+const ATTR_BANK: u8 = 0b0000_1000;
+const ATTR_DMG_PALETTE: u8 = 0b0001_0000;
+const ATTR_X_FLIP: u8 = 0b0010_0000;
+const ATTR_Y_FLIP: u8 = 0b0100_0000;
+const ATTR_PRIORITY: u8 = 0b1000_0000;
+
+const TILE_MAP_LOW: usize = 0x1800;
+const TILE_MAP_HIGH: usize = 0x1C00;
+const MAX_OBJECTS_PER_LINE: usize = 10;
+/// WX is the window's X position plus 7.
+const WINDOW_X_OFFSET: u8 = 7;
+
+#[derive(Clone, Copy, Default)]
+struct BgPixel {
+    color: u8,      // colour index 0-3 before the palette
+    priority: bool, // CGB attribute bit 7: drawn over objects
+}
+
+#[derive(Clone, Copy)]
+struct ObjPixel {
+    rgb: Rgb555,
+    behind_bg: bool, // attribute bit 7: hidden behind BG colours 1-3
+}
+
+impl GPU {
+    /// Draw line LY into the framebuffer.
+    pub(super) fn render_scanline(&mut self) {
+        let y = self.ly as usize;
+        if y >= SCREEN_HEIGHT {
+            return;
+        }
+
+        let mut bg = [BgPixel::default(); SCREEN_WIDTH];
+        let mut bg_rgb = [DMG_SHADES[0]; SCREEN_WIDTH];
+        self.render_background(&mut bg, &mut bg_rgb);
+
+        let mut objects = [None; SCREEN_WIDTH];
+        if self.lcdc & LCDC_OBJ_ENABLE != 0 {
+            self.render_objects(&mut objects);
+        }
+
+        let master_priority = self.lcdc & LCDC_BG_ENABLE != 0;
+        for x in 0..SCREEN_WIDTH {
+            let bg_wins = |obj: ObjPixel| {
+                let bg_opaque = bg[x].color != 0;
+                if self.cgb_mode {
+                    master_priority && bg_opaque && (bg[x].priority || obj.behind_bg)
+                } else {
+                    bg_opaque && obj.behind_bg
+                }
+            };
+            self.framebuffer[y * SCREEN_WIDTH + x] = match objects[x] {
+                Some(obj) if !bg_wins(obj) => obj.rgb,
+                _ => bg_rgb[x],
+            };
+        }
+    }
+
+    fn render_background(&mut self, bg: &mut [BgPixel], bg_rgb: &mut [Rgb555]) {
+        // On DMG, LCDC bit 0 blanks both background and window
+        if !self.cgb_mode && self.lcdc & LCDC_BG_ENABLE == 0 {
+            return;
+        }
+
+        let window_visible = self.lcdc & LCDC_WINDOW_ENABLE != 0
+            && self.ly >= self.wy
+            && (self.wx as usize) < SCREEN_WIDTH + WINDOW_X_OFFSET as usize;
+
+        for x in 0..SCREEN_WIDTH {
+            let in_window = window_visible && x + WINDOW_X_OFFSET as usize >= self.wx as usize;
+            let (map, map_x, map_y) = if in_window {
+                let map = self.tile_map(LCDC_WINDOW_MAP);
+                let map_x = (x + WINDOW_X_OFFSET as usize - self.wx as usize) as u8;
+                (map, map_x, self.window_line)
+            } else {
+                let map = self.tile_map(LCDC_BG_MAP);
+                (
+                    map,
+                    (x as u8).wrapping_add(self.scx),
+                    self.ly.wrapping_add(self.scy),
+                )
+            };
+            let (pixel, rgb) = self.tile_map_pixel(map, map_x, map_y);
+            bg[x] = pixel;
+            bg_rgb[x] = rgb;
+        }
+
+        if window_visible {
+            self.window_line += 1;
+        }
+    }
+
+    fn tile_map(&self, select_bit: u8) -> usize {
+        if self.lcdc & select_bit != 0 {
+            TILE_MAP_HIGH
+        } else {
+            TILE_MAP_LOW
+        }
+    }
+
+    fn tile_map_pixel(&self, map: usize, x: u8, y: u8) -> (BgPixel, Rgb555) {
+        let offset = map + (y as usize / 8) * 32 + x as usize / 8;
+        let tile_number = self.vram[0][offset];
+        let attributes = if self.cgb_mode {
+            self.vram[1][offset]
+        } else {
+            0
+        };
+
+        // LCDC bit 4 clear: tiles 0-127 come from 0x9000 and 128-255 from 0x8800
+        let tile_index = if self.lcdc & LCDC_TILE_DATA != 0 {
+            tile_number as usize
+        } else {
+            (256 + tile_number as i8 as isize) as usize
+        };
+        let color = self.tile_pixel(
+            attributes,
+            tile_index,
+            (y % 8) as usize,
+            (x % 8) as usize,
+            8,
+        );
+
+        let rgb = if self.cgb_mode {
+            self.bg_palettes.color(attributes & ATTR_PALETTE, color)
+        } else {
+            self.dmg_color(&self.bg_palettes, 0, self.bgp, color)
+        };
+        let pixel = BgPixel {
+            color,
+            priority: attributes & ATTR_PRIORITY != 0,
+        };
+        (pixel, rgb)
+    }
+
+    fn render_objects(&self, objects: &mut [Option<ObjPixel>]) {
+        let height = if self.lcdc & LCDC_OBJ_SIZE != 0 {
+            16
+        } else {
+            8
+        };
+        let line = self.ly as usize + 16;
+
+        // OAM scan: the first 10 objects overlapping this line, in OAM order
+        let mut selected: Vec<usize> = (0..40)
+            .map(|index| index * 4)
+            .filter(|&entry| {
+                let top = self.oam[entry] as usize;
+                line >= top && line < top + height
+            })
+            .take(MAX_OBJECTS_PER_LINE)
+            .collect();
+
+        // DMG (and CGB with OPRI bit 0 set) favours the lowest X coordinate;
+        // otherwise OAM order alone decides. The sort is stable, so ties keep
+        // OAM order.
+        if !self.cgb_mode || self.opri & 1 != 0 {
+            selected.sort_by_key(|&entry| self.oam[entry + 1]);
+        }
+
+        for entry in selected {
+            let [top, left, tile, attributes] = [0, 1, 2, 3].map(|i| self.oam[entry + i]);
+            let row = line - top as usize;
+            let tile = if height == 16 { tile & 0xFE } else { tile };
+
+            for col in 0..8 {
+                let screen_x = left as usize + col;
+                if !(8..SCREEN_WIDTH + 8).contains(&screen_x) || objects[screen_x - 8].is_some() {
+                    continue;
+                }
+                let color = self.tile_pixel(attributes, tile as usize, row, col, height);
+                if color == 0 {
+                    continue; // transparent
+                }
+                let rgb = if self.cgb_mode {
+                    self.obj_palettes.color(attributes & ATTR_PALETTE, color)
+                } else if attributes & ATTR_DMG_PALETTE != 0 {
+                    self.dmg_color(&self.obj_palettes, 1, self.obp1, color)
+                } else {
+                    self.dmg_color(&self.obj_palettes, 0, self.obp0, color)
+                };
+                objects[screen_x - 8] = Some(ObjPixel {
+                    rgb,
+                    behind_bg: attributes & ATTR_PRIORITY != 0,
+                });
+            }
+        }
+    }
+
+    /// Colour index of pixel (`row`, `col`) of a `height`-pixel tall tile,
+    /// applying the bank and flip attributes.
+    fn tile_pixel(&self, attributes: u8, tile: usize, row: usize, col: usize, height: usize) -> u8 {
+        let bank = if self.cgb_mode && attributes & ATTR_BANK != 0 {
+            1
+        } else {
+            0
+        };
+        let row = if attributes & ATTR_Y_FLIP != 0 {
+            height - 1 - row
+        } else {
+            row
+        };
+        let col = if attributes & ATTR_X_FLIP != 0 {
+            7 - col
+        } else {
+            col
+        };
+        self.tile_set[bank][tile + row / 8][row % 8][col] as u8
+    }
+
+    /// Colour of `color` through the DMG palette `register`. In compatibility
+    /// mode the shade indexes CGB palette `palette` instead of a grey.
+    fn dmg_color(&self, ram: &PaletteRam, palette: u8, register: u8, color: u8) -> Rgb555 {
+        let shade = (register >> (color * 2)) & 0x03;
+        if self.dmg_compat {
+            ram.color(palette, shade)
+        } else {
+            DMG_SHADES[shade as usize]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interrupts::InterruptController;
+
+    /// Fill tile `tile` of the current VRAM bank with colour index `color`.
+    fn fill_tile(gpu: &mut GPU, tile: usize, color: u8) {
+        let low = if color & 1 != 0 { 0xFF } else { 0x00 };
+        let high = if color & 2 != 0 { 0xFF } else { 0x00 };
+        for row in 0..8 {
+            gpu.write_vram(tile * 16 + row * 2, low);
+            gpu.write_vram(tile * 16 + row * 2 + 1, high);
+        }
+    }
+
+    fn render_line(gpu: &mut GPU, line: u8) {
+        gpu.ly = line;
+        gpu.render_scanline();
+    }
+
+    fn pixel(gpu: &GPU, x: usize, y: usize) -> Rgb555 {
+        gpu.framebuffer()[y * SCREEN_WIDTH + x]
+    }
+
+    #[test]
+    fn test_dmg_background_uses_bgp() {
+        let mut gpu = GPU::new();
+        fill_tile(&mut gpu, 0, 1);
+        gpu.write_register(0xFF47, 0b0000_1100); // colour 1 -> shade 3
+        render_line(&mut gpu, 0);
+        assert_eq!(pixel(&gpu, 0, 0), DMG_SHADES[3]);
+
+        // LCDC bit 0 blanks the background on DMG
+        gpu.write_register(0xFF40, 0x90);
+        render_line(&mut gpu, 1);
+        assert_eq!(pixel(&gpu, 0, 1), DMG_SHADES[0]);
+    }
+
+    #[test]
+    fn test_cgb_attributes_select_palette_and_bank() {
+        let mut gpu = GPU::new();
+        gpu.set_cgb_mode(true);
+        fill_tile(&mut gpu, 0, 1); // bank 0: colour 1
+        gpu.set_vram_bank(1);
+        fill_tile(&mut gpu, 0, 2); // bank 1: colour 2
+        gpu.write_vram(TILE_MAP_LOW + 1, ATTR_BANK | 3); // second tile: bank 1, palette 3
+        gpu.set_vram_bank(0);
+
+        gpu.write_register(0xFF68, 0x80 | (3 * 8 + 2 * 2)); // palette 3, colour 2
+        gpu.write_register(0xFF69, 0x00);
+        gpu.write_register(0xFF69, 0x7C); // pure blue
+        gpu.write_register(0xFF68, 0x80 | 2); // palette 0, colour 1
+        gpu.write_register(0xFF69, 0x1F); // pure red
+        gpu.write_register(0xFF69, 0x00);
+
+        render_line(&mut gpu, 0);
+        assert_eq!(pixel(&gpu, 0, 0), 0x001F);
+        assert_eq!(pixel(&gpu, 8, 0), 0x7C00);
+    }
+
+    #[test]
+    fn test_object_priority() {
+        let mut gpu = GPU::new();
+        fill_tile(&mut gpu, 1, 3);
+        gpu.write_register(0xFF40, 0x93); // objects on
+        gpu.write_register(0xFF47, 0xE4);
+        gpu.write_register(0xFF48, 0b0000_0100); // OBP0: colour 3 -> shade 0
+
+        // Two overlapping objects: the lower X coordinate wins on DMG
+        for (entry, x, palette) in [(0, 12, 0x00), (4, 8, ATTR_DMG_PALETTE)] {
+            gpu.write_oam(entry, 16);
+            gpu.write_oam(entry + 1, x);
+            gpu.write_oam(entry + 2, 1);
+            gpu.write_oam(entry + 3, palette);
+        }
+        gpu.write_register(0xFF49, 0b1100_0000); // OBP1: colour 3 -> shade 3
+        render_line(&mut gpu, 0);
+        assert_eq!(pixel(&gpu, 4, 0), DMG_SHADES[3]);
+
+        // In CGB OAM order mode the first OAM entry wins instead
+        gpu.set_cgb_mode(true);
+        gpu.write_register(0xFF6A, 0x80 | 6); // OBJ palette 0, colour 3
+        gpu.write_register(0xFF6B, 0x1F);
+        gpu.write_register(0xFF6B, 0x00);
+        render_line(&mut gpu, 0);
+        assert_eq!(pixel(&gpu, 4, 0), 0x001F);
+
+        // Behind-BG objects are hidden by non-zero background colours
+        fill_tile(&mut gpu, 0, 1);
+        gpu.write_oam(3, ATTR_PRIORITY);
+        gpu.write_oam(7, ATTR_PRIORITY);
+        render_line(&mut gpu, 0);
+        assert_eq!(pixel(&gpu, 4, 0), gpu.bg_palettes.color(0, 1));
+    }
+
+    #[test]
+    fn test_lines_render_at_end_of_mode_3() {
+        let mut gpu = GPU::new();
+        let mut interrupts = InterruptController::new();
+        fill_tile(&mut gpu, 0, 3);
+        for _ in 0..80 + 171 {
+            gpu.tick(&mut interrupts);
+        }
+        assert_eq!(pixel(&gpu, 0, 0), DMG_SHADES[0]);
+        gpu.tick(&mut interrupts);
+        assert_eq!(pixel(&gpu, 0, 0), DMG_SHADES[3]);
+    }
+}
@@ -0,0 +1,492 @@
+//! Game Boy Printer emulation.
+//!
+//! The printer is a serial peer driven by the Game Boy's internal clock. The
+//! game sends packets of the form:
+//!
+//! ```text
+//! 0x88 0x33 | command | compression | length (LE16) | data | checksum (LE16) | 0x00 0x00
+//! ```
+//!
+//! The printer answers 0x00 to every byte except the two trailing ones, where
+//! it replies with 0x81 (device present) and then its status byte. Image data
+//! arrives as 2bpp tiles in bands of 20x2 tiles (160x16 pixels); a print
+//! command renders the buffered bands with the requested palette and margins.
+//! Each printout is saved as a greyscale PNG once the paper is fed out.
+//!
+//! Reference: [Pan Docs — Game Boy Printer](https://gbdev.io/pandocs/Gameboy_Printer.html)
+
+use crate::serial::SerialPeer;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+const MAGIC_1: u8 = 0x88;
+const MAGIC_2: u8 = 0x33;
+
+const COMMAND_INIT: u8 = 0x01;
+const COMMAND_PRINT: u8 = 0x02;
+const COMMAND_DATA: u8 = 0x04;
+const COMMAND_BREAK: u8 = 0x08;
+const COMMAND_STATUS: u8 = 0x0F;
+
+/// Reply to the first trailing byte of a packet.
+const DEVICE_ID: u8 = 0x81;
+
+// Status byte bits
+const STATUS_CHECKSUM_ERROR: u8 = 0b0000_0001;
+const STATUS_PRINTING: u8 = 0b0000_0010;
+const STATUS_IMAGE_DATA_FULL: u8 = 0b0000_0100;
+const STATUS_UNPROCESSED_DATA: u8 = 0b0000_1000;
+
+pub const PRINTER_WIDTH: usize = 160;
+const TILES_PER_ROW: usize = PRINTER_WIDTH / 8;
+const BYTES_PER_TILE: usize = 16;
+const BAND_HEIGHT: usize = 16;
+const BAND_BYTES: usize = TILES_PER_ROW * 2 * BYTES_PER_TILE; // 640
+/// The printer's buffer holds up to 9 bands (a full 160x144 screen).
+const MAX_BUFFER_BYTES: usize = 9 * BAND_BYTES;
+/// Blank pixel rows fed for each unit of margin in a print command.
+const MARGIN_ROWS_PER_UNIT: usize = BAND_HEIGHT;
+/// Status replies reporting "printing" after each print command.
+const PRINT_BUSY_STATUS_REPLIES: u8 = 4;
+/// A palette byte of 0 is treated as the standard 0xE4 mapping.
+const DEFAULT_PALETTE: u8 = 0xE4;
+
+/// Grey level for each of the four printed shades (white to black).
+const SHADE_LEVELS: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+/// A finished printout: one shade (0-3) per pixel, `PRINTER_WIDTH` pixels wide.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Printout {
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Printout {
+    /// Encode as an 8-bit greyscale PNG.
+    pub fn write_png(&self, path: &Path) -> io::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, PRINTER_WIDTH as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let data: Vec<u8> = self
+            .pixels
+            .iter()
+            .map(|&shade| SHADE_LEVELS[shade as usize])
+            .collect();
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&data))
+            .map_err(io::Error::other)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PacketState {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    DeviceId,
+    Status,
+}
+
+pub struct Printer {
+    output_dir: Option<PathBuf>, // where printouts are saved, if anywhere
+    state: PacketState,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    data: Vec<u8>,
+    checksum: u16,
+    status: u8,
+    busy_replies: u8,
+    buffer: Vec<u8>,          // decompressed tile data awaiting a print command
+    page: Vec<u8>,            // shades of the printout still on the paper feed
+    printouts: Vec<Printout>, // finished printouts, oldest first
+}
+
+impl Printer {
+    /// Create a printer that saves each printout as a PNG in `output_dir`.
+    pub fn new(output_dir: Option<PathBuf>) -> Self {
+        Printer {
+            output_dir,
+            state: PacketState::Magic1,
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            status: 0,
+            busy_replies: 0,
+            buffer: Vec::new(),
+            page: Vec::new(),
+            printouts: Vec::new(),
+        }
+    }
+
+    /// Printouts completed so far.
+    #[allow(dead_code)]
+    pub fn printouts(&self) -> &[Printout] {
+        &self.printouts
+    }
+
+    fn receive(&mut self, byte: u8) {
+        self.state = match self.state {
+            PacketState::Magic1 if byte == MAGIC_1 => PacketState::Magic2,
+            PacketState::Magic1 => PacketState::Magic1,
+            PacketState::Magic2 if byte == MAGIC_2 => PacketState::Command,
+            PacketState::Magic2 if byte == MAGIC_1 => PacketState::Magic2,
+            PacketState::Magic2 => PacketState::Magic1,
+            PacketState::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                PacketState::Compression
+            }
+            PacketState::Compression => {
+                self.compressed = byte & 0x01 != 0;
+                self.add_to_checksum(byte);
+                PacketState::LengthLow
+            }
+            PacketState::LengthLow => {
+                self.length = byte as u16;
+                self.add_to_checksum(byte);
+                PacketState::LengthHigh
+            }
+            PacketState::LengthHigh => {
+                self.length |= (byte as u16) << 8;
+                self.add_to_checksum(byte);
+                self.data.clear();
+                if self.length == 0 {
+                    PacketState::ChecksumLow
+                } else {
+                    PacketState::Data
+                }
+            }
+            PacketState::Data => {
+                self.data.push(byte);
+                self.add_to_checksum(byte);
+                if self.data.len() == self.length as usize {
+                    PacketState::ChecksumLow
+                } else {
+                    PacketState::Data
+                }
+            }
+            PacketState::ChecksumLow => {
+                // Reuse `checksum` as the difference from the received value
+                self.checksum = self.checksum.wrapping_sub(byte as u16);
+                PacketState::ChecksumHigh
+            }
+            PacketState::ChecksumHigh => {
+                let valid = self.checksum.wrapping_sub((byte as u16) << 8) == 0;
+                self.finish_packet(valid);
+                PacketState::DeviceId
+            }
+            PacketState::DeviceId => PacketState::Status,
+            PacketState::Status => PacketState::Magic1,
+        };
+    }
+
+    fn add_to_checksum(&mut self, byte: u8) {
+        self.checksum = self.checksum.wrapping_add(byte as u16);
+    }
+
+    fn reply(&mut self) -> u8 {
+        match self.state {
+            PacketState::DeviceId => DEVICE_ID,
+            PacketState::Status => {
+                let mut status = self.status;
+                if self.busy_replies > 0 {
+                    self.busy_replies -= 1;
+                    status |= STATUS_PRINTING;
+                }
+                if !self.buffer.is_empty() {
+                    status |= STATUS_UNPROCESSED_DATA;
+                }
+                if self.buffer.len() >= MAX_BUFFER_BYTES {
+                    status |= STATUS_IMAGE_DATA_FULL;
+                }
+                status
+            }
+            _ => 0x00,
+        }
+    }
+
+    fn finish_packet(&mut self, valid: bool) {
+        if !valid {
+            self.status |= STATUS_CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !STATUS_CHECKSUM_ERROR;
+
+        match self.command {
+            COMMAND_INIT => {
+                self.buffer.clear();
+                self.status = 0;
+                self.busy_replies = 0;
+            }
+            COMMAND_DATA => self.receive_image_data(),
+            COMMAND_PRINT if self.data.len() >= 4 => self.print(),
+            COMMAND_BREAK => {
+                self.buffer.clear();
+                self.busy_replies = 0;
+            }
+            COMMAND_STATUS => {}
+            _ => {}
+        }
+    }
+
+    fn receive_image_data(&mut self) {
+        let data = if self.compressed {
+            decompress(&self.data)
+        } else {
+            std::mem::take(&mut self.data)
+        };
+        let space = MAX_BUFFER_BYTES.saturating_sub(self.buffer.len());
+        self.buffer.extend(data.into_iter().take(space));
+    }
+
+    // Print command data: [sheets, margins (before << 4 | after), palette, exposure]
+    fn print(&mut self) {
+        let margin_before = (self.data[1] >> 4) as usize;
+        let margin_after = (self.data[1] & 0x0F) as usize;
+        let palette = match self.data[2] {
+            0 => DEFAULT_PALETTE,
+            palette => palette,
+        };
+
+        self.feed(margin_before);
+        let bands = std::mem::take(&mut self.buffer);
+        for band in bands.chunks(BAND_BYTES) {
+            self.render_band(band, palette);
+        }
+        self.feed(margin_after);
+        self.busy_replies = PRINT_BUSY_STATUS_REPLIES;
+
+        // The page stays on the feed until a print ends with a bottom margin,
+        // so multi-part images print as one continuous printout.
+        if margin_after > 0 && !self.page.is_empty() {
+            self.finish_page();
+        }
+    }
+
+    fn feed(&mut self, margin_units: usize) {
+        let rows = margin_units * MARGIN_ROWS_PER_UNIT;
+        self.page.resize(self.page.len() + rows * PRINTER_WIDTH, 0);
+    }
+
+    fn render_band(&mut self, band: &[u8], palette: u8) {
+        let rows = band.len() / (TILES_PER_ROW * BYTES_PER_TILE) * 8;
+        for y in 0..rows {
+            let tile_row = y / 8;
+            let line = y % 8;
+            for x in 0..PRINTER_WIDTH {
+                let tile = tile_row * TILES_PER_ROW + x / 8;
+                let offset = tile * BYTES_PER_TILE + line * 2;
+                let bit = 7 - (x % 8);
+                let low = (band[offset] >> bit) & 1;
+                let high = (band[offset + 1] >> bit) & 1;
+                let colour = (high << 1) | low;
+                self.page.push((palette >> (colour * 2)) & 0x03);
+            }
+        }
+    }
+
+    fn finish_page(&mut self) {
+        let pixels = std::mem::take(&mut self.page);
+        let printout = Printout {
+            height: pixels.len() / PRINTER_WIDTH,
+            pixels,
+        };
+
+        if let Some(dir) = &self.output_dir {
+            let path = dir.join(format!("printout_{:03}.png", self.printouts.len() + 1));
+            match printout.write_png(&path) {
+                Ok(()) => println!("\n Printer: saved {}", path.display()),
+                Err(e) => eprintln!("\n Printer: failed to save {}: {e}", path.display()),
+            }
+        }
+        self.printouts.push(printout);
+    }
+}
+
+impl SerialPeer for Printer {
+    fn exchange(&mut self, outgoing: u8) -> Option<u8> {
+        let reply = self.reply();
+        self.receive(outgoing);
+        Some(reply)
+    }
+
+    fn poll_external(&mut self, _outgoing: u8) -> Option<u8> {
+        // The printer never drives the clock
+        None
+    }
+}
+
+/// Expand printer RLE: a control byte with bit 7 set repeats the next byte
+/// `(n & 0x7F) + 2` times, otherwise `n + 1` literal bytes follow.
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut bytes = data.iter().copied();
+    while let Some(control) = bytes.next() {
+        if control & 0x80 != 0 {
+            let Some(value) = bytes.next() else {
+                break;
+            };
+            let count = (control & 0x7F) as usize + 2;
+            output.extend(std::iter::repeat_n(value, count));
+        } else {
+            output.extend(bytes.by_ref().take(control as usize + 1));
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(command: u8, compressed: bool, data: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![MAGIC_1, MAGIC_2, command, compressed as u8];
+        bytes.extend((data.len() as u16).to_le_bytes());
+        bytes.extend(data);
+        let checksum = bytes[2..]
+            .iter()
+            .fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+        bytes.extend(checksum.to_le_bytes());
+        bytes.extend([0x00, 0x00]);
+        bytes
+    }
+
+    /// Send a packet and return the printer's replies to the two trailing bytes.
+    fn send(printer: &mut Printer, bytes: &[u8]) -> (u8, u8) {
+        let replies: Vec<u8> = bytes
+            .iter()
+            .map(|&b| printer.exchange(b).unwrap())
+            .collect();
+        (replies[replies.len() - 2], replies[replies.len() - 1])
+    }
+
+    /// A band where every pixel has colour `colour`.
+    fn solid_band(colour: u8) -> Vec<u8> {
+        let low = if colour & 1 != 0 { 0xFF } else { 0x00 };
+        let high = if colour & 2 != 0 { 0xFF } else { 0x00 };
+        [low, high].repeat(BAND_BYTES / 2)
+    }
+
+    #[test]
+    fn test_status_packet_replies_device_id_and_status() {
+        let mut printer = Printer::new(None);
+        let replies: Vec<u8> = packet(COMMAND_STATUS, false, &[])
+            .iter()
+            .map(|&b| printer.exchange(b).unwrap())
+            .collect();
+        assert_eq!(replies, vec![0, 0, 0, 0, 0, 0, 0, 0, DEVICE_ID, 0x00]);
+    }
+
+    #[test]
+    fn test_bad_checksum_sets_error_and_is_ignored() {
+        let mut printer = Printer::new(None);
+        let mut bytes = packet(COMMAND_DATA, false, &solid_band(3));
+        let checksum_index = bytes.len() - 4;
+        bytes[checksum_index] ^= 0xFF;
+
+        let (_, status) = send(&mut printer, &bytes);
+
+        assert_eq!(status, STATUS_CHECKSUM_ERROR);
+        assert!(printer.buffer.is_empty());
+    }
+
+    #[test]
+    fn test_data_then_print_renders_with_palette_and_margins() {
+        let mut printer = Printer::new(None);
+        send(&mut printer, &packet(COMMAND_INIT, false, &[]));
+
+        let (_, status) = send(&mut printer, &packet(COMMAND_DATA, false, &solid_band(1)));
+        assert_eq!(status, STATUS_UNPROCESSED_DATA);
+        send(&mut printer, &packet(COMMAND_DATA, false, &[]));
+
+        // One sheet, no margin before, one after, palette mapping colour 1 -> shade 3
+        let (_, status) = send(
+            &mut printer,
+            &packet(COMMAND_PRINT, false, &[0x01, 0x01, 0b0000_1100, 0x40]),
+        );
+        assert_eq!(status, STATUS_PRINTING);
+
+        let printouts = printer.printouts();
+        assert_eq!(printouts.len(), 1);
+        assert_eq!(printouts[0].height, BAND_HEIGHT + MARGIN_ROWS_PER_UNIT);
+        assert!(printouts[0].pixels[..BAND_HEIGHT * PRINTER_WIDTH]
+            .iter()
+            .all(|&shade| shade == 3));
+        assert!(printouts[0].pixels[BAND_HEIGHT * PRINTER_WIDTH..]
+            .iter()
+            .all(|&shade| shade == 0));
+    }
+
+    #[test]
+    fn test_prints_without_bottom_margin_join_one_printout() {
+        let mut printer = Printer::new(None);
+        for _ in 0..2 {
+            send(&mut printer, &packet(COMMAND_DATA, false, &solid_band(2)));
+            send(
+                &mut printer,
+                &packet(COMMAND_PRINT, false, &[0x01, 0x00, 0xE4, 0x40]),
+            );
+        }
+        assert!(printer.printouts().is_empty());
+
+        send(&mut printer, &packet(COMMAND_DATA, false, &solid_band(2)));
+        send(
+            &mut printer,
+            &packet(COMMAND_PRINT, false, &[0x01, 0x03, 0xE4, 0x40]),
+        );
+
+        assert_eq!(printer.printouts().len(), 1);
+        assert_eq!(
+            printer.printouts()[0].height,
+            3 * BAND_HEIGHT + 3 * MARGIN_ROWS_PER_UNIT
+        );
+    }
+
+    #[test]
+    fn test_compressed_data_is_expanded() {
+        // 0x81 0xAA -> 3 x 0xAA; 0x01 0x10 0x20 -> two literal bytes
+        assert_eq!(
+            decompress(&[0x81, 0xAA, 0x01, 0x10, 0x20]),
+            vec![0xAA, 0xAA, 0xAA, 0x10, 0x20]
+        );
+
+        let mut printer = Printer::new(None);
+        // A full band of colour 3 compresses to runs of 0xFF
+        let compressed = [0xFF, 0xFF].repeat(5);
+        send(&mut printer, &packet(COMMAND_DATA, true, &compressed));
+        assert_eq!(printer.buffer, vec![0xFF; 5 * 129]);
+    }
+
+    #[test]
+    fn test_printout_writes_png() {
+        let dir = std::env::temp_dir().join(format!("gb_printer_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut printer = Printer::new(Some(dir.clone()));
+
+        send(&mut printer, &packet(COMMAND_DATA, false, &solid_band(3)));
+        send(
+            &mut printer,
+            &packet(COMMAND_PRINT, false, &[0x01, 0x01, 0xE4, 0x40]),
+        );
+
+        let path = dir.join("printout_001.png");
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let reader = decoder.read_info().unwrap();
+        assert_eq!(reader.info().width, PRINTER_WIDTH as u32);
+        assert_eq!(reader.info().height, 32);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
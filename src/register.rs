@@ -3,6 +3,9 @@
 //! This module defines the 8-bit and 16-bit registers of the Game Boy CPU,
 //! including the flags register (F) with its condition flags.
 
+use crate::model::Model;
+use crate::save_state::{Snapshot, StateError, StateReader, StateWriter};
+
 /// The F register contains 4 flags in the upper nibble (bits 7-4).
 /// Bits 3-0 are always zero on real hardware.
 ///
@@ -81,10 +84,10 @@ pub struct Registers {
 }
 
 impl Registers {
-    /// Creates a new Registers struct with post-boot ROM values.
+    /// Creates a Registers struct with post-boot ROM values for `model`.
     ///
-    /// These are the register values immediately after the boot ROM finishes execution
-    /// on a real DMG-01 Game Boy. The boot ROM:
+    /// These are the register values immediately after the boot ROM finishes execution.
+    /// The boot ROM:
     /// - Scrolls the Nintendo logo
     /// - Plays the startup sound
     /// - Validates the cartridge header
@@ -92,21 +95,38 @@ impl Registers {
     ///
     /// PC starts at 0x0100 (first instruction of the cartridge ROM)
     /// SP starts at 0xFFFE (top of High RAM)
-    pub fn new() -> Registers {
+    pub fn post_boot(model: Model, header_checksum: u8) -> Registers {
+        let [a, f, b, c, d, e, h, l] = model.post_boot_registers(header_checksum);
         Registers {
-            a: 0x01,
-            b: 0x00,
-            c: 0x13,
-            d: 0x00,
-            e: 0xD8,
-            f: FlagsRegister::from_byte(0xC0),
-            h: 0x01,
-            l: 0x4D,
+            a,
+            b,
+            c,
+            d,
+            e,
+            f: FlagsRegister::from_byte(f),
+            h,
+            l,
             sp: 0xFFFE,
             pc: 0x100,
         }
     }
 
+    /// Creates a Registers struct in the power-on state, ready to run a boot ROM from 0x0000.
+    pub fn power_on() -> Registers {
+        Registers {
+            a: 0,
+            b: 0,
+            c: 0,
+            d: 0,
+            e: 0,
+            f: FlagsRegister::default(),
+            h: 0,
+            l: 0,
+            sp: 0,
+            pc: 0,
+        }
+    }
+
     // Combined 16-bit register pairs getters and setters
     /// Gets the 16-bit AF register pair.
     /// The A register forms the high byte, and the F (Flags) register forms the low byte.
@@ -192,3 +212,24 @@ impl Registers {
         }
     }
 }
+
+impl Snapshot for Registers {
+    fn save_to(&self, state: &mut StateWriter) {
+        state.u16(self.get_af());
+        state.u16(self.get_bc());
+        state.u16(self.get_de());
+        state.u16(self.get_hl());
+        state.u16(self.sp);
+        state.u16(self.pc);
+    }
+
+    fn load_from(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.set_af(state.u16()?);
+        self.set_bc(state.u16()?);
+        self.set_de(state.u16()?);
+        self.set_hl(state.u16()?);
+        self.sp = state.u16()?;
+        self.pc = state.u16()?;
+        Ok(())
+    }
+}
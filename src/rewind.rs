@@ -0,0 +1,275 @@
+//! Rewind buffer: recent save states, kept as compressed deltas.
+//!
+//! Every `interval` frames the machine is snapshotted with
+//! [`CPU::save_state`]. Only the newest snapshot is kept whole; each older
+//! one is stored as the XOR of itself and its successor. Between nearby
+//! frames most of the machine is unchanged, so the XOR is mostly zero bytes,
+//! which are run-length encoded away. When the buffer grows past its memory
+//! budget, the oldest deltas are dropped.
+//!
+//! Rewinding restores the newest snapshot and rebuilds the one before it
+//! from its delta, so repeated rewinds walk back one snapshot at a time.
+
+use crate::cpu::CPU;
+use crate::save_state::StateError;
+use std::collections::VecDeque;
+
+/// Default memory budget for the snapshots.
+pub const DEFAULT_MAX_BYTES: usize = 64 << 20;
+
+/// Zero bytes needed to end a literal run; shorter gaps are cheaper to
+/// store as literals than as a new run.
+const MIN_ZERO_RUN: usize = 4;
+
+/// An older snapshot, stored relative to the one after it.
+struct Delta {
+    len: usize,    // length of the older snapshot
+    data: Vec<u8>, // compressed XOR of the two snapshots
+}
+
+pub struct RewindBuffer {
+    interval: u64,
+    max_bytes: usize,
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Delta>, // oldest first
+    delta_bytes: usize,
+    last_capture_frame: Option<u64>,
+}
+
+impl RewindBuffer {
+    /// Snapshot every `interval` frames (at least 1), keeping at most about
+    /// `max_bytes` of snapshots.
+    pub fn new(interval: u64, max_bytes: usize) -> Self {
+        RewindBuffer {
+            interval: interval.max(1),
+            max_bytes,
+            latest: None,
+            deltas: VecDeque::new(),
+            delta_bytes: 0,
+            last_capture_frame: None,
+        }
+    }
+
+    /// Snapshot the machine if `interval` frames have been completed since
+    /// the last snapshot. Call this after every CPU step.
+    pub fn capture_if_due(&mut self, cpu: &CPU) {
+        let frame = cpu.bus.gpu.frame_count();
+        let due = self
+            .last_capture_frame
+            .is_none_or(|last| frame >= last + self.interval);
+        if due {
+            self.capture(cpu);
+        }
+    }
+
+    /// Snapshot the machine now.
+    pub fn capture(&mut self, cpu: &CPU) {
+        let state = cpu.save_state();
+        if let Some(latest) = self.latest.take() {
+            let delta = Delta {
+                len: latest.len(),
+                data: compress(&xor(&latest, &state)),
+            };
+            self.delta_bytes += delta.data.len();
+            self.deltas.push_back(delta);
+        }
+        self.latest = Some(state);
+        self.last_capture_frame = Some(cpu.bus.gpu.frame_count());
+        self.evict();
+    }
+
+    /// Step back to the newest snapshot and remove it from the buffer.
+    ///
+    /// Returns false, leaving the machine alone, when there is nothing left
+    /// to rewind to.
+    pub fn rewind(&mut self, cpu: &mut CPU) -> Result<bool, StateError> {
+        let Some(mut state) = self.latest.take() else {
+            return Ok(false);
+        };
+        cpu.load_state(&state)?;
+        self.last_capture_frame = Some(cpu.bus.gpu.frame_count());
+
+        if let Some(delta) = self.deltas.pop_back() {
+            self.delta_bytes -= delta.data.len();
+            state.resize(state.len().max(delta.len), 0);
+            apply_delta(&mut state, &delta.data);
+            state.truncate(delta.len);
+            self.latest = Some(state);
+        }
+        Ok(true)
+    }
+
+    /// Number of snapshots that can be rewound to.
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.latest.is_some() as usize
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    /// Bytes currently used by the snapshots.
+    pub fn memory_usage(&self) -> usize {
+        self.latest.as_ref().map_or(0, Vec::len) + self.delta_bytes
+    }
+
+    // Drop the oldest deltas until the buffer fits its budget. The newest
+    // snapshot is always kept.
+    fn evict(&mut self) {
+        while self.memory_usage() > self.max_bytes {
+            let Some(delta) = self.deltas.pop_front() else {
+                break;
+            };
+            self.delta_bytes -= delta.data.len();
+        }
+    }
+}
+
+/// XOR of two snapshots, the shorter one padded with zeros.
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut delta = vec![0; a.len().max(b.len())];
+    delta[..a.len()].copy_from_slice(a);
+    for (byte, other) in delta.iter_mut().zip(b) {
+        *byte ^= other;
+    }
+    delta
+}
+
+/// Run-length encode the zero runs of `data`: a sequence of (zero run
+/// length, literal length, literal bytes), lengths as LEB128 varints.
+/// Trailing zeros are left out.
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let zeros = data[pos..].iter().take_while(|&&byte| byte == 0).count();
+        pos += zeros;
+        if pos == data.len() {
+            break;
+        }
+        let start = pos;
+        while pos < data.len() && !starts_zero_run(&data[pos..]) {
+            pos += 1;
+        }
+        write_varint(&mut output, zeros);
+        write_varint(&mut output, pos - start);
+        output.extend_from_slice(&data[start..pos]);
+    }
+    output
+}
+
+fn starts_zero_run(data: &[u8]) -> bool {
+    data.len() >= MIN_ZERO_RUN && data[..MIN_ZERO_RUN].iter().all(|&byte| byte == 0)
+}
+
+/// XOR the delta encoded by [`compress`] into `target`.
+fn apply_delta(target: &mut [u8], mut delta: &[u8]) {
+    let mut pos = 0;
+    while !delta.is_empty() {
+        pos += read_varint(&mut delta);
+        let len = read_varint(&mut delta);
+        for (byte, change) in target[pos..pos + len].iter_mut().zip(&delta[..len]) {
+            *byte ^= change;
+        }
+        pos += len;
+        delta = &delta[len..];
+    }
+}
+
+fn write_varint(output: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        output.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+fn read_varint(input: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    while let Some((&byte, rest)) = input.split_first() {
+        *input = rest;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::cpu_with_program;
+    use crate::model::Model;
+
+    // INC A; LD (HL),A; INC L; JR -5 (writes 0xC000-0xC0FF forever)
+    fn counter_cpu() -> CPU {
+        let mut cpu = cpu_with_program(Model::Dmg, &[0x3C, 0x77, 0x2C, 0x18, 0xFB]);
+        cpu.registers.set_hl(0xC000);
+        cpu
+    }
+
+    fn run_frames(cpu: &mut CPU, buffer: &mut RewindBuffer, frames: u64) {
+        let target = cpu.bus.gpu.frame_count() + frames;
+        while cpu.bus.gpu.frame_count() < target {
+            cpu.step();
+            buffer.capture_if_due(cpu);
+        }
+    }
+
+    #[test]
+    fn test_compressed_delta_round_trip() {
+        let old = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13];
+        let new = [1, 2, 9, 4, 5, 6, 7, 8, 9, 10, 11, 12, 14, 15];
+        let delta = compress(&xor(&old, &new));
+        assert!(delta.len() < old.len());
+
+        let mut restored = new.to_vec();
+        apply_delta(&mut restored, &delta);
+        restored.truncate(old.len());
+        assert_eq!(restored, old);
+    }
+
+    #[test]
+    fn test_rewind_walks_back_one_snapshot_at_a_time() {
+        let mut cpu = counter_cpu();
+        let mut buffer = RewindBuffer::new(2, DEFAULT_MAX_BYTES);
+        let mut states = Vec::new();
+        for _ in 0..4 {
+            run_frames(&mut cpu, &mut buffer, 2);
+            states.push(cpu.save_state());
+        }
+        // One snapshot at frame 0, then one every 2 frames
+        assert_eq!(buffer.len(), 5);
+        assert!(buffer.memory_usage() < 2 * states[0].len());
+
+        for frame in [8, 6, 4] {
+            assert_eq!(buffer.rewind(&mut cpu), Ok(true));
+            assert_eq!(cpu.bus.gpu.frame_count(), frame);
+        }
+        // Snapshots are taken on the step that completes the frame
+        assert_eq!(cpu.save_state(), states[1]);
+
+        // Playing on records new history from the restored point
+        run_frames(&mut cpu, &mut buffer, 2);
+        assert_eq!(cpu.save_state(), states[2]);
+        assert_eq!(buffer.len(), 3);
+    }
+
+    #[test]
+    fn test_oldest_snapshots_are_dropped_to_fit_budget() {
+        let mut cpu = counter_cpu();
+        let state_size = cpu.save_state().len();
+        let mut buffer = RewindBuffer::new(1, state_size + 1);
+        run_frames(&mut cpu, &mut buffer, 10);
+        assert!(buffer.memory_usage() <= state_size + 1);
+        assert!(buffer.len() < 11);
+
+        while buffer.rewind(&mut cpu).unwrap() {}
+        assert!(buffer.is_empty());
+        assert!(cpu.bus.gpu.frame_count() > 0);
+    }
+}
@@ -0,0 +1,412 @@
+//! Save states: snapshots of the whole machine in a versioned binary format.
+//!
+//! A state starts with a header identifying what it belongs to, followed by
+//! the payload that each component appends through [`Snapshot`]:
+//!
+//! | Offset | Size | Contents                                  |
+//! |--------|------|-------------------------------------------|
+//! | 0      | 4    | Magic `GBST`                              |
+//! | 4      | 2    | Format version                            |
+//! | 6      | 1    | Hardware model                            |
+//! | 7      | 4    | CRC-32 of the cartridge ROM               |
+//! | 11     | 4    | Payload length                            |
+//! | 15     | 4    | CRC-32 of the payload                     |
+//! | 19     | ...  | Payload: CPU, then the bus and its devices |
+//!
+//! All integers are little-endian. A state is only loaded into a machine
+//! running the same ROM on the same model. The header and the payload
+//! checksum are checked before any component is touched; values are checked
+//! as each component loads them, and the machine is restored to where it was
+//! when any is rejected, so a rejected state leaves the machine unchanged.
+//! Values that would be out of range for the hardware are masked or rejected
+//! on load.
+//!
+//! Only emulated hardware is saved. Host-side settings (audio output,
+//! mute/solo, the serial link partner) stay as they are. There is no memory
+//! bank controller yet; cartridge RAM is saved with the bus memory.
+
+use crate::model::Model;
+use core::fmt;
+
+const MAGIC: [u8; 4] = *b"GBST";
+/// Bumped whenever the payload layout changes; older states are rejected.
+pub const FORMAT_VERSION: u16 = 1;
+const HEADER_SIZE: usize = 19;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// The data does not start with the save state magic.
+    NotASaveState,
+    /// The state was written by another version of the format.
+    UnsupportedVersion(u16),
+    /// The state was saved on another hardware model.
+    ModelMismatch { state: Model, machine: Model },
+    /// The state was saved while running another ROM.
+    RomMismatch,
+    /// The payload is truncated, fails its checksum or holds invalid values.
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::NotASaveState => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => write!(
+                f,
+                "save state format version {version} is not supported (expected {FORMAT_VERSION})"
+            ),
+            StateError::ModelMismatch { state, machine } => write!(
+                f,
+                "save state is for {state:?}, but the emulator is running {machine:?}"
+            ),
+            StateError::RomMismatch => write!(f, "save state was made with a different ROM"),
+            StateError::Corrupt(reason) => write!(f, "save state is corrupt: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// A component whose state is part of a save state.
+///
+/// `load_from` must read exactly the fields `save_to` wrote, in the same order.
+pub trait Snapshot {
+    fn save_to(&self, state: &mut StateWriter);
+    fn load_from(&mut self, state: &mut StateReader) -> Result<(), StateError>;
+}
+
+/// Appends little-endian values to a payload.
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter { data: Vec::new() }
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Raw bytes of a fixed-size buffer (the reader must know the length).
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    /// A presence flag, followed by the value if there is one.
+    pub fn option<T>(&mut self, value: Option<T>, write: impl FnOnce(&mut Self, T)) {
+        self.bool(value.is_some());
+        if let Some(value) = value {
+            write(self, value);
+        }
+    }
+
+    /// The values written so far, without a save state header.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// Prefix the payload with the header for `model` and `rom_checksum`.
+    pub fn finish(self, model: Model, rom_checksum: u32) -> Vec<u8> {
+        let mut state = Vec::with_capacity(HEADER_SIZE + self.data.len());
+        state.extend_from_slice(&MAGIC);
+        state.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        state.push(model_id(model));
+        state.extend_from_slice(&rom_checksum.to_le_bytes());
+        state.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        state.extend_from_slice(&crc32(&self.data).to_le_bytes());
+        state.extend_from_slice(&self.data);
+        state
+    }
+}
+
+/// Reads back the values of a payload.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    model: Option<Model>, // from the header, when there is one
+}
+
+impl<'a> StateReader<'a> {
+    /// A reader over raw data without a save state header, for other file
+    /// formats built from the same values.
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data, model: None }
+    }
+
+    /// Check the header of `state` against the running machine and return a
+    /// reader over its payload.
+    pub fn open(state: &'a [u8], model: Model, rom_checksum: u32) -> Result<Self, StateError> {
+        let mut header = StateReader::new(state);
+        if header.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err(StateError::NotASaveState);
+        }
+        let version = header.u16()?;
+        if version != FORMAT_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let state_model =
+            model_from_id(header.u8()?).ok_or(StateError::Corrupt("unknown model"))?;
+        if state_model != model {
+            return Err(StateError::ModelMismatch {
+                state: state_model,
+                machine: model,
+            });
+        }
+        if header.u32()? != rom_checksum {
+            return Err(StateError::RomMismatch);
+        }
+        let length = header.u32()? as usize;
+        let checksum = header.u32()?;
+        if header.data.len() != length {
+            return Err(StateError::Corrupt("wrong payload length"));
+        }
+        if crc32(header.data) != checksum {
+            return Err(StateError::Corrupt("payload checksum mismatch"));
+        }
+        header.model = Some(model);
+        Ok(header)
+    }
+
+    /// The hardware model named in the header, for readers made by [`open`].
+    ///
+    /// [`open`]: StateReader::open
+    pub fn model(&self) -> Option<Model> {
+        self.model
+    }
+
+    /// The next `len` bytes.
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Corrupt("payload too short"));
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Corrupt("invalid boolean")),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Fill `buffer` with the next `buffer.len()` bytes.
+    pub fn bytes(&mut self, buffer: &mut [u8]) -> Result<(), StateError> {
+        buffer.copy_from_slice(self.take(buffer.len())?);
+        Ok(())
+    }
+
+    pub fn option<T>(
+        &mut self,
+        read: impl FnOnce(&mut Self) -> Result<T, StateError>,
+    ) -> Result<Option<T>, StateError> {
+        if self.bool()? {
+            read(self).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Fail unless the whole payload has been read.
+    pub fn finish(self) -> Result<(), StateError> {
+        if self.data.is_empty() {
+            Ok(())
+        } else {
+            Err(StateError::Corrupt("unexpected data after payload"))
+        }
+    }
+}
+
+pub const fn model_id(model: Model) -> u8 {
+    match model {
+        Model::Dmg0 => 0,
+        Model::Dmg => 1,
+        Model::Mgb => 2,
+        Model::Sgb => 3,
+        Model::Cgb => 4,
+    }
+}
+
+pub fn model_from_id(id: u8) -> Option<Model> {
+    [Model::Dmg0, Model::Dmg, Model::Mgb, Model::Sgb, Model::Cgb]
+        .into_iter()
+        .find(|&model| model_id(model) == id)
+}
+
+/// CRC-32 (IEEE 802.3, as used by PNG and zip).
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{cpu_with_program, CPU};
+
+    // INC A; LD (HL+),A; JR -4 (loops forever, writing to WRAM and HRAM)
+    const COUNTER_LOOP: [u8; 4] = [0x3C, 0x22, 0x18, 0xFC];
+
+    fn run(cpu: &mut CPU, steps: usize) {
+        for _ in 0..steps {
+            cpu.step();
+        }
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_loaded_state_resumes_identically() {
+        let mut cpu = cpu_with_program(Model::Cgb, &COUNTER_LOOP);
+        cpu.registers.set_hl(0xC000);
+        run(&mut cpu, 10_000);
+        let saved = cpu.save_state();
+
+        run(&mut cpu, 50_000);
+        let expected = cpu.save_state();
+
+        // Into the same machine, and into a fresh one
+        cpu.load_state(&saved).unwrap();
+        run(&mut cpu, 50_000);
+        assert_eq!(cpu.save_state(), expected);
+
+        let mut fresh = cpu_with_program(Model::Cgb, &COUNTER_LOOP);
+        fresh.load_state(&saved).unwrap();
+        run(&mut fresh, 50_000);
+        assert_eq!(fresh.save_state(), expected);
+    }
+
+    #[test]
+    fn test_state_from_other_rom_or_model_is_rejected() {
+        let cpu = cpu_with_program(Model::Dmg, &COUNTER_LOOP);
+        let saved = cpu.save_state();
+
+        let mut other_rom = cpu_with_program(Model::Dmg, &[0x00]);
+        assert_eq!(other_rom.load_state(&saved), Err(StateError::RomMismatch));
+
+        let mut other_model = cpu_with_program(Model::Mgb, &COUNTER_LOOP);
+        assert_eq!(
+            other_model.load_state(&saved),
+            Err(StateError::ModelMismatch {
+                state: Model::Dmg,
+                machine: Model::Mgb
+            })
+        );
+    }
+
+    #[test]
+    fn test_bad_header_or_payload_is_rejected() {
+        let mut cpu = cpu_with_program(Model::Dmg, &COUNTER_LOOP);
+        let saved = cpu.save_state();
+        let pc = cpu.registers.pc;
+
+        assert_eq!(cpu.load_state(b"PNG"), Err(StateError::NotASaveState));
+
+        let mut old_version = saved.clone();
+        old_version[4..6].copy_from_slice(&0u16.to_le_bytes());
+        assert_eq!(
+            cpu.load_state(&old_version),
+            Err(StateError::UnsupportedVersion(0))
+        );
+
+        let mut corrupted = saved.clone();
+        *corrupted.last_mut().unwrap() ^= 0xFF;
+        assert!(matches!(
+            cpu.load_state(&corrupted),
+            Err(StateError::Corrupt(_))
+        ));
+        assert!(matches!(
+            cpu.load_state(&saved[..saved.len() - 1]),
+            Err(StateError::Corrupt(_))
+        ));
+        assert_eq!(cpu.registers.pc, pc);
+    }
+
+    #[test]
+    fn test_value_rejected_late_leaves_machine_unchanged() {
+        let mut cpu = cpu_with_program(Model::Dmg, &COUNTER_LOOP);
+        cpu.registers.set_hl(0xC000);
+        run(&mut cpu, 1_000);
+        let mut other = cpu.save_state();
+        run(&mut cpu, 1_000);
+        let before = cpu.save_state();
+
+        // The last payload byte says an SGB follows, which this machine
+        // lacks; every other component has loaded by the time it is read
+        *other.last_mut().unwrap() = 1;
+        let checksum = crc32(&other[HEADER_SIZE..]);
+        other[15..19].copy_from_slice(&checksum.to_le_bytes());
+
+        assert!(matches!(
+            cpu.load_state(&other),
+            Err(StateError::Corrupt(_))
+        ));
+        assert_eq!(cpu.save_state(), before);
+    }
+
+    #[test]
+    fn test_boot_rom_of_wrong_size_is_rejected() {
+        let mut short = cpu_with_program(Model::Dmg, &COUNTER_LOOP);
+        short.bus.map_boot_rom(Model::Dmg, vec![0; 4]);
+        let saved = short.save_state();
+
+        let mut cpu = cpu_with_program(Model::Dmg, &COUNTER_LOOP);
+        let before = cpu.save_state();
+        assert!(matches!(
+            cpu.load_state(&saved),
+            Err(StateError::Corrupt(_))
+        ));
+        assert_eq!(cpu.save_state(), before);
+
+        short
+            .bus
+            .map_boot_rom(Model::Dmg, vec![0; Model::Dmg.boot_rom_size()]);
+        assert_eq!(cpu.load_state(&short.save_state()), Ok(()));
+    }
+}
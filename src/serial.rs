@@ -0,0 +1,379 @@
+//! Serial port module (SB/SC registers).
+//!
+//! A transfer shifts the 8 bits of SB out MSB first while the partner's bits
+//! are shifted in from the other end. The Game Boy that drives the clock
+//! (SC bit 0 = 1, internal clock) shifts one bit every 512 T-cycles (8192 Hz);
+//! one waiting on an external clock does nothing until its partner starts a
+//! transfer. With no cable attached the input line floats high, so a
+//! completed transfer leaves 0xFF in SB.
+//!
+//! Link partners plug in through the [`SerialPeer`] trait.
+//!
+//! Reference: [Pan Docs — Serial Data Transfer](https://gbdev.io/pandocs/Serial_Data_Transfer_(Link_Cable).html)
+
+use crate::save_state::{Snapshot, StateError, StateReader, StateWriter};
+
+pub const SB_REGISTER: u16 = 0xFF01;
+pub const SC_REGISTER: u16 = 0xFF02;
+
+const SC_TRANSFER_ENABLE: u8 = 0b1000_0000;
+const SC_INTERNAL_CLOCK: u8 = 0b0000_0001;
+const SC_WRITABLE_MASK: u8 = SC_TRANSFER_ENABLE | SC_INTERNAL_CLOCK;
+const SC_UNUSED_BITS: u8 = 0b0111_1110;
+
+/// T-cycles per bit with the internal 8192 Hz clock.
+const INTERNAL_CLOCK_T_CYCLES_PER_BIT: u16 = 512;
+/// Value shifted in when no partner is connected.
+const DISCONNECTED_BYTE: u8 = 0xFF;
+
+/// A device on the other end of the link cable.
+pub trait SerialPeer {
+    /// Exchange a byte for a transfer clocked by this Game Boy.
+    ///
+    /// Called when a transfer starts with the internal clock. Returns the
+    /// byte the partner shifts back, which is then clocked into SB bit by bit,
+    /// or `None` if it is not known yet. The port then asks for it with
+    /// `poll_exchange` and holds the last bit of the transfer until it comes.
+    fn exchange(&mut self, outgoing: u8) -> Option<u8>;
+
+    /// The partner's byte for a transfer whose `exchange` returned `None`,
+    /// once it is known. Called every T-cycle until then.
+    fn poll_exchange(&mut self) -> Option<u8> {
+        None
+    }
+
+    /// Check whether the partner has clocked a transfer.
+    ///
+    /// Called every T-cycle while this Game Boy waits on the external clock,
+    /// with the byte it is offering. Returns the partner's byte once the
+    /// transfer has completed on the partner's side.
+    fn poll_external(&mut self, outgoing: u8) -> Option<u8>;
+}
+
+pub struct Serial {
+    sb: u8,                            // 0xFF01 - transfer data
+    sc: u8,                            // 0xFF02 - transfer control (bits 7 and 0)
+    peer: Option<Box<dyn SerialPeer>>, // connected link partner, if any
+    incoming: u8,                      // partner byte still to be shifted into SB
+    incoming_pending: bool,            // partner byte not known yet; `incoming` is idle
+    bits_remaining: u8,                // bits left in an internally clocked transfer
+    bit_clock: u16,                    // T-cycles until the next bit is shifted
+    output: Vec<u8>,                   // every byte sent, for test ROM output capture
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Serial {
+            sb: 0x00,
+            sc: 0x00,
+            peer: None,
+            incoming: DISCONNECTED_BYTE,
+            incoming_pending: false,
+            bits_remaining: 0,
+            bit_clock: 0,
+            output: Vec::new(),
+        }
+    }
+
+    /// Attach a link partner, replacing any existing one.
+    pub fn connect(&mut self, peer: Box<dyn SerialPeer>) {
+        self.peer = Some(peer);
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            SB_REGISTER => self.sb,
+            SC_REGISTER => self.sc | SC_UNUSED_BITS,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            SB_REGISTER => self.sb = value,
+            SC_REGISTER => self.write_sc(value),
+            _ => {}
+        }
+    }
+
+    /// Advance one T-cycle. Returns true if a Serial interrupt should fire.
+    pub fn tick(&mut self) -> bool {
+        if self.sc & SC_TRANSFER_ENABLE == 0 {
+            return false;
+        }
+
+        if self.sc & SC_INTERNAL_CLOCK == 0 {
+            return self.poll_external_clock();
+        }
+
+        if self.incoming_pending {
+            self.poll_exchange();
+        }
+        self.bit_clock -= 1;
+        if self.bit_clock > 0 {
+            return false;
+        }
+        if self.bits_remaining == 1 && self.incoming_pending {
+            // Hold the last bit until the partner's byte is known
+            self.bit_clock = 1;
+            return false;
+        }
+        self.bit_clock = INTERNAL_CLOCK_T_CYCLES_PER_BIT;
+
+        self.sb = (self.sb << 1) | (self.incoming >> 7);
+        self.incoming <<= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining > 0 {
+            return false;
+        }
+
+        self.sc &= !SC_TRANSFER_ENABLE;
+        true
+    }
+
+    /// Every byte this Game Boy has started sending on the internal clock.
+    ///
+    /// Test ROMs (Blargg) print their results this way.
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    pub fn clear_output(&mut self) {
+        self.output.clear();
+    }
+
+    // SC (0xFF02): setting bit 7 with bit 0 set starts an internally clocked
+    // transfer; with bit 0 clear the port waits for the partner's clock.
+    fn write_sc(&mut self, value: u8) {
+        self.sc = value & SC_WRITABLE_MASK;
+        if self.sc != SC_WRITABLE_MASK {
+            return;
+        }
+
+        self.output.push(self.sb);
+        let incoming = match self.peer.as_mut() {
+            Some(peer) => peer.exchange(self.sb),
+            None => Some(DISCONNECTED_BYTE),
+        };
+        self.incoming = incoming.unwrap_or(DISCONNECTED_BYTE);
+        self.incoming_pending = incoming.is_none();
+        self.bits_remaining = 8;
+        self.bit_clock = INTERNAL_CLOCK_T_CYCLES_PER_BIT;
+    }
+
+    // Take the partner's late byte, replacing the idle bits shifted into SB
+    // in its place so far.
+    fn poll_exchange(&mut self) {
+        let Some(incoming) = self.peer.as_mut().and_then(|peer| peer.poll_exchange()) else {
+            return;
+        };
+        let shifted = 8 - self.bits_remaining as u16;
+        let low_bits = ((1u16 << shifted) - 1) as u8;
+        self.sb = (self.sb & !low_bits) | ((incoming as u16 >> (8 - shifted)) as u8 & low_bits);
+        self.incoming = incoming << shifted;
+        self.incoming_pending = false;
+    }
+
+    fn poll_external_clock(&mut self) -> bool {
+        let Some(peer) = self.peer.as_mut() else {
+            return false;
+        };
+        match peer.poll_external(self.sb) {
+            Some(incoming) => {
+                self.sb = incoming;
+                self.sc &= !SC_TRANSFER_ENABLE;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// The link partner and the captured output are not part of the state, nor
+/// is a byte still awaited from the partner: a loaded transfer completes
+/// with the idle line's bits.
+impl Snapshot for Serial {
+    fn save_to(&self, state: &mut StateWriter) {
+        state.u8(self.sb);
+        state.u8(self.sc);
+        state.u8(self.incoming);
+        state.u8(self.bits_remaining);
+        state.u16(self.bit_clock);
+    }
+
+    fn load_from(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.sb = state.u8()?;
+        self.sc = state.u8()? & SC_WRITABLE_MASK;
+        self.incoming = state.u8()?;
+        self.incoming_pending = false;
+        self.bits_remaining = state.u8()?;
+        self.bit_clock = state.u16()?;
+        // tick() counts both down during an internally clocked transfer
+        if self.sc == SC_WRITABLE_MASK
+            && (!(1..=8).contains(&self.bits_remaining) || self.bit_clock == 0)
+        {
+            return Err(StateError::Corrupt("invalid serial transfer position"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Partner that always answers with a fixed byte and clocks external
+    /// transfers immediately.
+    struct FixedPeer(u8);
+
+    impl SerialPeer for FixedPeer {
+        fn exchange(&mut self, _outgoing: u8) -> Option<u8> {
+            Some(self.0)
+        }
+
+        fn poll_external(&mut self, _outgoing: u8) -> Option<u8> {
+            Some(self.0)
+        }
+    }
+
+    /// Partner whose answer to a transfer becomes known `delay` polls after
+    /// the transfer starts.
+    struct LatePeer {
+        answer: u8,
+        delay: u32,
+    }
+
+    impl SerialPeer for LatePeer {
+        fn exchange(&mut self, _outgoing: u8) -> Option<u8> {
+            None
+        }
+
+        fn poll_exchange(&mut self) -> Option<u8> {
+            self.delay = self.delay.saturating_sub(1);
+            (self.delay == 0).then_some(self.answer)
+        }
+
+        fn poll_external(&mut self, _outgoing: u8) -> Option<u8> {
+            None
+        }
+    }
+
+    fn run(serial: &mut Serial, t_cycles: u32) -> bool {
+        let mut interrupt = false;
+        for _ in 0..t_cycles {
+            interrupt |= serial.tick();
+        }
+        interrupt
+    }
+
+    #[test]
+    fn test_internal_transfer_without_peer_reads_ff() {
+        let mut serial = Serial::new();
+        serial.write(SB_REGISTER, 0x42);
+        serial.write(SC_REGISTER, 0x81);
+        assert_eq!(serial.read(SC_REGISTER), 0xFF);
+
+        assert!(!run(&mut serial, 8 * 512 - 1));
+        assert_eq!(serial.read(SC_REGISTER) & 0x80, 0x80);
+        assert!(serial.tick());
+
+        assert_eq!(serial.read(SB_REGISTER), 0xFF);
+        assert_eq!(serial.read(SC_REGISTER), 0x7F);
+        assert_eq!(serial.output(), &[0x42]);
+    }
+
+    #[test]
+    fn test_internal_transfer_shifts_peer_bits_in_msb_first() {
+        let mut serial = Serial::new();
+        serial.connect(Box::new(FixedPeer(0b1010_0000)));
+        serial.write(SB_REGISTER, 0x00);
+        serial.write(SC_REGISTER, 0x81);
+
+        run(&mut serial, 512);
+        assert_eq!(serial.read(SB_REGISTER), 0b0000_0001);
+        run(&mut serial, 2 * 512);
+        assert_eq!(serial.read(SB_REGISTER), 0b0000_0101);
+        assert!(run(&mut serial, 5 * 512));
+        assert_eq!(serial.read(SB_REGISTER), 0b1010_0000);
+    }
+
+    #[test]
+    fn test_late_answer_replaces_bits_already_shifted_in() {
+        let mut serial = Serial::new();
+        serial.connect(Box::new(LatePeer {
+            answer: 0b1010_0101,
+            delay: 3 * 512 + 1,
+        }));
+        serial.write(SB_REGISTER, 0x00);
+        serial.write(SC_REGISTER, 0x81);
+
+        run(&mut serial, 3 * 512);
+        assert_eq!(serial.read(SB_REGISTER), 0b0000_0111); // idle line so far
+        run(&mut serial, 1);
+        assert_eq!(serial.read(SB_REGISTER), 0b0000_0101);
+        assert!(run(&mut serial, 5 * 512 - 1));
+        assert_eq!(serial.read(SB_REGISTER), 0b1010_0101);
+    }
+
+    #[test]
+    fn test_transfer_is_held_until_the_answer_arrives() {
+        let mut serial = Serial::new();
+        serial.connect(Box::new(LatePeer {
+            answer: 0x5A,
+            delay: 8 * 512 + 100,
+        }));
+        serial.write(SC_REGISTER, 0x81);
+
+        assert!(!run(&mut serial, 8 * 512 + 99));
+        assert_eq!(serial.read(SC_REGISTER) & 0x80, 0x80);
+        assert!(serial.tick());
+        assert_eq!(serial.read(SB_REGISTER), 0x5A);
+    }
+
+    #[test]
+    fn test_external_clock_waits_without_peer() {
+        let mut serial = Serial::new();
+        serial.write(SB_REGISTER, 0x42);
+        serial.write(SC_REGISTER, 0x80);
+
+        assert!(!run(&mut serial, 100_000));
+        assert_eq!(serial.read(SC_REGISTER), 0xFE);
+        assert_eq!(serial.read(SB_REGISTER), 0x42);
+        assert!(serial.output().is_empty());
+    }
+
+    #[test]
+    fn test_external_clock_completes_when_peer_clocks() {
+        let mut serial = Serial::new();
+        serial.connect(Box::new(FixedPeer(0x5A)));
+        serial.write(SB_REGISTER, 0x42);
+        serial.write(SC_REGISTER, 0x80);
+
+        assert!(serial.tick());
+        assert_eq!(serial.read(SB_REGISTER), 0x5A);
+        assert_eq!(serial.read(SC_REGISTER), 0x7E);
+    }
+
+    #[test]
+    fn test_invalid_transfer_position_is_rejected_on_load() {
+        let mut serial = Serial::new();
+        serial.write(SC_REGISTER, 0x81);
+        let mut state = StateWriter::new();
+        serial.save_to(&mut state);
+        let saved = state.into_bytes();
+        let (bits_remaining, bit_clock) = (3, 4);
+
+        let mut loaded = Serial::new();
+        assert!(loaded.load_from(&mut StateReader::new(&saved)).is_ok());
+        let mut bad_states = [saved.clone(), saved.clone(), saved.clone()];
+        bad_states[0][bits_remaining] = 0;
+        bad_states[1][bits_remaining] = 9;
+        bad_states[2][bit_clock..bit_clock + 2].copy_from_slice(&0u16.to_le_bytes());
+        for bad in bad_states {
+            assert!(matches!(
+                loaded.load_from(&mut StateReader::new(&bad)),
+                Err(StateError::Corrupt(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! SGB attribute map: which of the four system palettes colours each 8x8
+//! cell of the game screen, set by the ATTR_BLK, ATTR_LIN, ATTR_DIV and
+//! ATTR_CHR commands.
+//!
+//! Reference: [Pan Docs — SGB Color Palettes Overview](https://gbdev.io/pandocs/SGB_Color_Palettes.html)
+
+use crate::save_state::{Snapshot, StateError, StateReader, StateWriter};
+
+pub const CELLS_WIDE: usize = 20;
+pub const CELLS_HIGH: usize = 18;
+
+const PALETTE_MASK: u8 = 0x03;
+// ATTR_BLK control bits
+const BLK_INSIDE: u8 = 0b001;
+const BLK_BORDER: u8 = 0b010;
+const BLK_OUTSIDE: u8 = 0b100;
+const BLK_DATA_SET_SIZE: usize = 6;
+// ATTR_LIN: bits 0-4 line, 5-6 palette, 7 set for a horizontal line (a row)
+const LIN_LINE_MASK: u8 = 0x1F;
+const LIN_HORIZONTAL: u8 = 0x80;
+// ATTR_DIV bit 6: divide along a horizontal line (above/below)
+const DIV_HORIZONTAL: u8 = 0x40;
+const MAX_CHR_CELLS: usize = CELLS_WIDE * CELLS_HIGH;
+
+pub struct AttributeMap {
+    cells: [u8; CELLS_WIDE * CELLS_HIGH],
+}
+
+impl AttributeMap {
+    pub fn new() -> Self {
+        AttributeMap {
+            cells: [0; CELLS_WIDE * CELLS_HIGH],
+        }
+    }
+
+    /// Palette (0-3) of the cell containing screen pixel (`x`, `y`).
+    pub fn palette_at(&self, x: usize, y: usize) -> u8 {
+        self.cells[(y / 8) * CELLS_WIDE + x / 8]
+    }
+
+    fn set(&mut self, x: usize, y: usize, palette: u8) {
+        if x < CELLS_WIDE && y < CELLS_HIGH {
+            self.cells[y * CELLS_WIDE + x] = palette & PALETTE_MASK;
+        }
+    }
+
+    /// ATTR_BLK: `data` is the packet data after the command byte.
+    ///
+    /// Each data set colours the cells inside, on the border of and outside a
+    /// rectangle. When only the inside or only the outside is changed, the
+    /// border takes the same palette.
+    pub fn attr_blk(&mut self, data: &[u8]) {
+        let count = data.first().copied().unwrap_or(0) as usize;
+        for set in data[1..].chunks_exact(BLK_DATA_SET_SIZE).take(count) {
+            let control = set[0] & 0x07;
+            let inside = set[1] & PALETTE_MASK;
+            let outside = (set[1] >> 4) & PALETTE_MASK;
+            let border = match control {
+                BLK_INSIDE => inside,
+                BLK_OUTSIDE => outside,
+                _ => (set[1] >> 2) & PALETTE_MASK,
+            };
+            let border_enabled =
+                control & BLK_BORDER != 0 || control == BLK_INSIDE || control == BLK_OUTSIDE;
+            let (x1, y1, x2, y2) = (
+                set[2] as usize,
+                set[3] as usize,
+                set[4] as usize,
+                set[5] as usize,
+            );
+
+            for y in 0..CELLS_HIGH {
+                for x in 0..CELLS_WIDE {
+                    let within = (x1..=x2).contains(&x) && (y1..=y2).contains(&y);
+                    let on_edge = within && (x == x1 || x == x2 || y == y1 || y == y2);
+                    let (enabled, palette) = if on_edge {
+                        (border_enabled, border)
+                    } else if within {
+                        (control & BLK_INSIDE != 0, inside)
+                    } else {
+                        (control & BLK_OUTSIDE != 0, outside)
+                    };
+                    if enabled {
+                        self.set(x, y, palette);
+                    }
+                }
+            }
+        }
+    }
+
+    /// ATTR_LIN: colour whole rows or columns.
+    pub fn attr_lin(&mut self, data: &[u8]) {
+        let count = data.first().copied().unwrap_or(0) as usize;
+        for &line in data[1..].iter().take(count) {
+            let index = (line & LIN_LINE_MASK) as usize;
+            let palette = (line >> 5) & PALETTE_MASK;
+            if line & LIN_HORIZONTAL != 0 {
+                (0..CELLS_WIDE).for_each(|x| self.set(x, index, palette));
+            } else {
+                (0..CELLS_HIGH).for_each(|y| self.set(index, y, palette));
+            }
+        }
+    }
+
+    /// ATTR_DIV: split the screen in two along a row or column, which gets
+    /// its own palette.
+    pub fn attr_div(&mut self, data: &[u8]) {
+        let [control, coordinate] = [data[0], data[1]];
+        let after = control & PALETTE_MASK;
+        let before = (control >> 2) & PALETTE_MASK;
+        let on_line = (control >> 4) & PALETTE_MASK;
+        let line = coordinate as usize;
+        for y in 0..CELLS_HIGH {
+            for x in 0..CELLS_WIDE {
+                let position = if control & DIV_HORIZONTAL != 0 { y } else { x };
+                let palette = match position.cmp(&line) {
+                    std::cmp::Ordering::Less => before,
+                    std::cmp::Ordering::Equal => on_line,
+                    std::cmp::Ordering::Greater => after,
+                };
+                self.set(x, y, palette);
+            }
+        }
+    }
+
+    /// ATTR_CHR: palettes for consecutive cells, four per byte (most
+    /// significant bits first), from a start cell left-to-right or
+    /// top-to-bottom.
+    pub fn attr_chr(&mut self, data: &[u8]) {
+        let (mut x, mut y) = (data[0] as usize, data[1] as usize);
+        let count = (u16::from_le_bytes([data[2], data[3]]) as usize).min(MAX_CHR_CELLS);
+        let vertical = data[4] & 0x01 != 0;
+        let palettes = data[5..]
+            .iter()
+            .flat_map(|&byte| [6, 4, 2, 0].map(|shift| (byte >> shift) & PALETTE_MASK));
+
+        for palette in palettes.take(count) {
+            self.set(x, y, palette);
+            if vertical {
+                y += 1;
+                if y == CELLS_HIGH {
+                    y = 0;
+                    x += 1;
+                }
+            } else {
+                x += 1;
+                if x == CELLS_WIDE {
+                    x = 0;
+                    y += 1;
+                }
+            }
+        }
+    }
+}
+
+impl Snapshot for AttributeMap {
+    fn save_to(&self, state: &mut StateWriter) {
+        state.bytes(&self.cells);
+    }
+
+    fn load_from(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.bytes(&mut self.cells)?;
+        for cell in &mut self.cells {
+            *cell &= PALETTE_MASK;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(map: &AttributeMap, x: usize, y: usize) -> u8 {
+        map.palette_at(x * 8, y * 8)
+    }
+
+    #[test]
+    fn test_attr_blk_inside_only_also_colours_border() {
+        let mut map = AttributeMap::new();
+        // One data set: inside only, palette 2, cells (1,1)-(3,3)
+        map.attr_blk(&[1, BLK_INSIDE, 0x02, 1, 1, 3, 3]);
+        assert_eq!(cell(&map, 2, 2), 2);
+        assert_eq!(cell(&map, 1, 1), 2);
+        assert_eq!(cell(&map, 0, 0), 0);
+
+        // Border and outside, leaving the inside alone
+        map.attr_blk(&[1, BLK_BORDER | BLK_OUTSIDE, 0b0011_0100, 1, 1, 3, 3]);
+        assert_eq!(cell(&map, 2, 2), 2);
+        assert_eq!(cell(&map, 3, 1), 1);
+        assert_eq!(cell(&map, 19, 17), 3);
+    }
+
+    #[test]
+    fn test_attr_lin_and_div() {
+        let mut map = AttributeMap::new();
+        map.attr_div(&[0b0010_0111, 9]); // left 1, right 3, on the line 2
+        assert_eq!(
+            [cell(&map, 8, 0), cell(&map, 9, 5), cell(&map, 10, 17)],
+            [1, 2, 3]
+        );
+
+        map.attr_lin(&[2, LIN_HORIZONTAL | 0x20 | 4, 0x60 | 19]);
+        assert_eq!(cell(&map, 0, 4), 1);
+        assert_eq!(cell(&map, 19, 0), 3);
+    }
+
+    #[test]
+    fn test_attr_chr_wraps_to_next_row() {
+        let mut map = AttributeMap::new();
+        map.attr_chr(&[18, 0, 4, 0, 0, 0b0110_1100]);
+        assert_eq!(cell(&map, 18, 0), 1);
+        assert_eq!(cell(&map, 19, 0), 2);
+        assert_eq!(cell(&map, 0, 1), 3);
+        assert_eq!(cell(&map, 1, 1), 0);
+    }
+}
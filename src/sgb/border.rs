@@ -0,0 +1,145 @@
+//! SGB border: a 256x224 SNES background drawn around the game screen.
+//!
+//! CHR_TRN loads 4bpp SNES tiles (128 per transfer, into the lower or upper
+//! half of 256 tiles). PCT_TRN loads the 32x28 tile map and colour palettes
+//! 4-7. Colour 0 of every border palette is transparent, showing the game
+//! screen in the middle and the backdrop colour elsewhere.
+//!
+//! Reference: [Pan Docs — SGB Border](https://gbdev.io/pandocs/SGB_Command_Border.html)
+
+use crate::ppu::palette::Rgb555;
+use crate::save_state::{Snapshot, StateError, StateReader, StateWriter};
+
+pub const BORDER_WIDTH: usize = 256;
+pub const BORDER_HEIGHT: usize = 224;
+
+const TILE_BYTES: usize = 32; // 8 rows x 4 bitplanes
+const TILE_COUNT: usize = 256;
+const MAP_WIDTH: usize = 32;
+const MAP_ENTRIES: usize = MAP_WIDTH * 32;
+const PALETTE_COUNT: usize = 4;
+const COLORS_PER_PALETTE: usize = 16;
+/// First SNES palette available to the border.
+const FIRST_BORDER_PALETTE: u16 = 4;
+
+// Tile map entry bits
+const ENTRY_TILE_MASK: u16 = 0x00FF;
+const ENTRY_X_FLIP: u16 = 0x4000;
+const ENTRY_Y_FLIP: u16 = 0x8000;
+
+pub struct Border {
+    tiles: Vec<u8>,
+    map: [u16; MAP_ENTRIES],
+    palettes: [[Rgb555; COLORS_PER_PALETTE]; PALETTE_COUNT],
+}
+
+impl Border {
+    pub fn new() -> Self {
+        Border {
+            tiles: vec![0; TILE_COUNT * TILE_BYTES],
+            map: [0; MAP_ENTRIES],
+            palettes: [[0; COLORS_PER_PALETTE]; PALETTE_COUNT],
+        }
+    }
+
+    /// CHR_TRN: 4 KiB of tile data for tiles 0-127 (`upper` false) or 128-255.
+    pub fn load_tiles(&mut self, upper: bool, data: &[u8]) {
+        let start = if upper { self.tiles.len() / 2 } else { 0 };
+        let len = data.len().min(self.tiles.len() / 2);
+        self.tiles[start..start + len].copy_from_slice(&data[..len]);
+    }
+
+    /// PCT_TRN: 0x800 bytes of tile map followed by palettes 4-7.
+    pub fn load_map_and_palettes(&mut self, data: &[u8]) {
+        let (map, palettes) = data.split_at(MAP_ENTRIES * 2);
+        for (entry, bytes) in self.map.iter_mut().zip(map.chunks_exact(2)) {
+            *entry = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+        let colors = palettes
+            .chunks_exact(2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]) & 0x7FFF);
+        for (index, color) in colors.take(PALETTE_COUNT * COLORS_PER_PALETTE).enumerate() {
+            self.palettes[index / COLORS_PER_PALETTE][index % COLORS_PER_PALETTE] = color;
+        }
+    }
+
+    /// Colour of border pixel (`x`, `y`), or `None` where it is transparent.
+    pub fn pixel(&self, x: usize, y: usize) -> Option<Rgb555> {
+        let entry = self.map[(y / 8) * MAP_WIDTH + x / 8];
+        let tile = (entry & ENTRY_TILE_MASK) as usize;
+        let palette = ((entry >> 10) & 0x07).wrapping_sub(FIRST_BORDER_PALETTE) as usize;
+        let row = if entry & ENTRY_Y_FLIP != 0 {
+            7 - y % 8
+        } else {
+            y % 8
+        };
+        let col = if entry & ENTRY_X_FLIP != 0 {
+            x % 8
+        } else {
+            7 - x % 8
+        };
+
+        let base = tile * TILE_BYTES + row * 2;
+        let planes = [base, base + 1, base + 16, base + 17];
+        let color = planes
+            .iter()
+            .enumerate()
+            .map(|(plane, &offset)| ((self.tiles[offset] >> col) & 1) << plane)
+            .sum::<u8>() as usize;
+
+        match color {
+            0 => None,
+            _ => self.palettes.get(palette).map(|colors| colors[color]),
+        }
+    }
+}
+
+impl Snapshot for Border {
+    fn save_to(&self, state: &mut StateWriter) {
+        state.bytes(&self.tiles);
+        for &entry in &self.map {
+            state.u16(entry);
+        }
+        for &color in self.palettes.iter().flatten() {
+            state.u16(color);
+        }
+    }
+
+    fn load_from(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.bytes(&mut self.tiles)?;
+        for entry in &mut self.map {
+            *entry = state.u16()?;
+        }
+        for color in self.palettes.iter_mut().flatten() {
+            *color = state.u16()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_border_pixel_decodes_4bpp_tiles() {
+        let mut border = Border::new();
+        // Tile 1, row 0: leftmost pixel colour 0b0101 (planes 0 and 2)
+        let mut tiles = vec![0u8; 0x1000];
+        tiles[TILE_BYTES] = 0x80;
+        tiles[TILE_BYTES + 16] = 0x80;
+        border.load_tiles(false, &tiles);
+
+        let mut pct = vec![0u8; 0x880];
+        // Map entry (1, 0): tile 1, palette 5, X flipped
+        pct[2..4].copy_from_slice(&(1 | 5 << 10 | ENTRY_X_FLIP).to_le_bytes());
+        // Palette 5 colour 5
+        let offset = MAP_ENTRIES * 2 + (COLORS_PER_PALETTE + 5) * 2;
+        pct[offset..offset + 2].copy_from_slice(&0x001Fu16.to_le_bytes());
+        border.load_map_and_palettes(&pct);
+
+        assert_eq!(border.pixel(15, 0), Some(0x001F));
+        assert_eq!(border.pixel(8, 0), None);
+        assert_eq!(border.pixel(0, 0), None);
+    }
+}
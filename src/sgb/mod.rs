@@ -0,0 +1,498 @@
+//! Super Game Boy support.
+//!
+//! SGB-enhanced games talk to the SNES side by bit-banging packets through
+//! the joypad select lines of P1 (0xFF00): a reset pulse (P14 and P15 both
+//! low) starts a packet, then each pulse of P14 low sends a 0 bit and each
+//! pulse of P15 low a 1 bit, every pulse ending with both lines high. A
+//! packet is 16 bytes sent least significant bit first, followed by a 0 stop
+//! bit. The first byte holds the command (bits 3-7) and how many packets the
+//! command spans (bits 0-2).
+//!
+//! Implemented commands: PAL01-PAL23 (system palettes), ATTR_BLK/LIN/DIV/CHR
+//! (attribute map), MASK_EN (screen freeze/blanking), CHR_TRN/PCT_TRN
+//! (border) and MLT_REQ (multiplayer joypad reads). CHR_TRN and PCT_TRN copy
+//! 4 KiB from the next displayed frame, read back as 2bpp tile data.
+//!
+//! The output is 256x224: the game screen, recoloured by the system
+//! palettes and the attribute map, framed by the border.
+//!
+//! Reference: [Pan Docs — Super Game Boy](https://gbdev.io/pandocs/SGB_Functions.html)
+
+mod attributes;
+mod border;
+
+use crate::ppu::palette::{Rgb555, DMG_SHADES};
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::save_state::{Snapshot, StateError, StateReader, StateWriter};
+use attributes::AttributeMap;
+use border::Border;
+pub use border::{BORDER_HEIGHT, BORDER_WIDTH};
+
+const PACKET_BYTES: usize = 16;
+const PACKET_BITS: usize = PACKET_BYTES * 8;
+const MAX_PACKETS: usize = 7;
+const TRANSFER_BYTES: usize = 0x1000;
+const TRANSFER_TILES_WIDE: usize = SCREEN_WIDTH / 8;
+
+// P1 select lines (active-low)
+const P14: u8 = 0b0001_0000;
+const P15: u8 = 0b0010_0000;
+const SELECT_MASK: u8 = P14 | P15;
+
+// Commands
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const MASK_EN: u8 = 0x17;
+
+/// Top-left corner of the game screen inside the border.
+const GAME_X: usize = (BORDER_WIDTH - SCREEN_WIDTH) / 2;
+const GAME_Y: usize = (BORDER_HEIGHT - SCREEN_HEIGHT) / 2;
+
+/// The SGB's default system palette (the same for all four palettes).
+const DEFAULT_PALETTE: [Rgb555; 4] = [0x67BF, 0x265B, 0x10B5, 0x2866];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mask {
+    Cancel,
+    Freeze,
+    Black,
+    Color0,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transfer {
+    BorderTiles { upper: bool },
+    BorderMap,
+}
+
+/// Assembles packets from the P1 select line pulses.
+struct PacketReceiver {
+    receiving: bool,
+    awaiting_release: bool, // a pulse was read; both lines must go high again
+    bits: usize,
+    packet: [u8; PACKET_BYTES],
+}
+
+impl PacketReceiver {
+    fn new() -> Self {
+        PacketReceiver {
+            receiving: false,
+            awaiting_release: false,
+            bits: 0,
+            packet: [0; PACKET_BYTES],
+        }
+    }
+
+    /// Feed a P1 write; returns a packet once its stop bit has arrived.
+    fn write(&mut self, select: u8) -> Option<[u8; PACKET_BYTES]> {
+        let bit = match select {
+            0 => {
+                // Reset pulse: start a new packet
+                *self = PacketReceiver::new();
+                self.receiving = true;
+                self.awaiting_release = true;
+                return None;
+            }
+            SELECT_MASK => {
+                self.awaiting_release = false;
+                return None;
+            }
+            P15 => 0, // P14 low
+            _ => 1,   // P15 low
+        };
+        if !self.receiving || self.awaiting_release {
+            return None;
+        }
+        self.awaiting_release = true;
+
+        if self.bits == PACKET_BITS {
+            // Stop bit: must be 0
+            self.receiving = false;
+            return (bit == 0).then_some(self.packet);
+        }
+        self.packet[self.bits / 8] |= bit << (self.bits % 8);
+        self.bits += 1;
+        None
+    }
+}
+
+pub struct Sgb {
+    receiver: PacketReceiver,
+    command: Vec<u8>, // packets of a multi-packet command received so far
+    palettes: [[Rgb555; 4]; 4],
+    attributes: AttributeMap,
+    border: Border,
+    mask: Mask,
+    pending_transfer: Option<Transfer>,
+    screen: Vec<u8>, // last unfrozen frame as DMG shades 0-3
+    players: u8,     // 1, 2 or 4 (MLT_REQ)
+    player: u8,      // joypad currently read
+    select: u8,      // last P14/P15 write
+}
+
+impl Sgb {
+    pub fn new() -> Self {
+        Sgb {
+            receiver: PacketReceiver::new(),
+            command: Vec::with_capacity(PACKET_BYTES * MAX_PACKETS),
+            palettes: [DEFAULT_PALETTE; 4],
+            attributes: AttributeMap::new(),
+            border: Border::new(),
+            mask: Mask::Cancel,
+            pending_transfer: None,
+            screen: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            players: 1,
+            player: 0,
+            select: SELECT_MASK,
+        }
+    }
+
+    /// Observe a P1 write.
+    ///
+    /// In multiplayer mode, the next joypad is selected each time P15 goes
+    /// high again after being pulled low.
+    pub fn write_joypad(&mut self, value: u8) {
+        let select = value & SELECT_MASK;
+        if self.players > 1 && self.select & P15 == 0 && select & P15 != 0 {
+            self.player = (self.player + 1) % self.players;
+        }
+        self.select = select;
+
+        if let Some(packet) = self.receiver.write(select) {
+            self.receive_packet(packet);
+        }
+    }
+
+    /// Adjust a P1 read for multiplayer mode: with neither row selected the
+    /// low nibble holds 0xF minus the current joypad number, and joypads
+    /// other than the first have no buttons held.
+    pub fn read_joypad(&self, value: u8) -> u8 {
+        if self.players == 1 {
+            value
+        } else if value & SELECT_MASK == SELECT_MASK {
+            (value & 0xF0) | (0x0F - self.player)
+        } else if self.player != 0 {
+            value | 0x0F
+        } else {
+            value
+        }
+    }
+
+    fn receive_packet(&mut self, packet: [u8; PACKET_BYTES]) {
+        self.command.extend_from_slice(&packet);
+        let packets = (self.command[0] & 0x07).max(1) as usize;
+        if self.command.len() >= packets * PACKET_BYTES {
+            let command = std::mem::take(&mut self.command);
+            self.execute(&command);
+        }
+    }
+
+    fn execute(&mut self, command: &[u8]) {
+        let data = &command[1..];
+        match command[0] >> 3 {
+            PAL01 => self.set_palettes(0, 1, data),
+            PAL23 => self.set_palettes(2, 3, data),
+            PAL03 => self.set_palettes(0, 3, data),
+            PAL12 => self.set_palettes(1, 2, data),
+            ATTR_BLK => self.attributes.attr_blk(data),
+            ATTR_LIN => self.attributes.attr_lin(data),
+            ATTR_DIV => self.attributes.attr_div(data),
+            ATTR_CHR => self.attributes.attr_chr(data),
+            MLT_REQ => {
+                self.players = match data[0] & 0x03 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                self.player = 0;
+            }
+            CHR_TRN => {
+                let upper = data[0] & 0x01 != 0;
+                self.pending_transfer = Some(Transfer::BorderTiles { upper });
+            }
+            PCT_TRN => self.pending_transfer = Some(Transfer::BorderMap),
+            MASK_EN => {
+                self.mask = match data[0] & 0x03 {
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    3 => Mask::Color0,
+                    _ => Mask::Cancel,
+                };
+            }
+            _ => {} // not implemented
+        }
+    }
+
+    // PALxy: colour 0 (shared by all palettes), then colours 1-3 of palette
+    // `first` and of palette `second`.
+    fn set_palettes(&mut self, first: usize, second: usize, data: &[u8]) {
+        let colors: Vec<Rgb555> = data[..14]
+            .chunks_exact(2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]) & 0x7FFF)
+            .collect();
+        for palette in &mut self.palettes {
+            palette[0] = colors[0];
+        }
+        self.palettes[first][1..].copy_from_slice(&colors[1..4]);
+        self.palettes[second][1..].copy_from_slice(&colors[4..7]);
+    }
+
+    /// Called with every completed game frame.
+    pub fn on_frame(&mut self, framebuffer: &[Rgb555]) {
+        let shades: Vec<u8> = framebuffer.iter().map(|&color| shade_of(color)).collect();
+        if let Some(transfer) = self.pending_transfer.take() {
+            let data = transfer_data(&shades);
+            match transfer {
+                Transfer::BorderTiles { upper } => self.border.load_tiles(upper, &data),
+                Transfer::BorderMap => self.border.load_map_and_palettes(&data),
+            }
+        }
+        if self.mask != Mask::Freeze {
+            self.screen = shades;
+        }
+    }
+
+    /// The 256x224 SGB output: the recoloured game screen inside the border.
+    pub fn compose(&self) -> Vec<Rgb555> {
+        let backdrop = self.palettes[0][0];
+        let mut output = vec![backdrop; BORDER_WIDTH * BORDER_HEIGHT];
+        for (index, pixel) in output.iter_mut().enumerate() {
+            let (x, y) = (index % BORDER_WIDTH, index / BORDER_WIDTH);
+            if let Some(color) = self.border.pixel(x, y) {
+                *pixel = color;
+            } else if (GAME_X..GAME_X + SCREEN_WIDTH).contains(&x)
+                && (GAME_Y..GAME_Y + SCREEN_HEIGHT).contains(&y)
+            {
+                *pixel = self.game_pixel(x - GAME_X, y - GAME_Y);
+            }
+        }
+        output
+    }
+
+    fn game_pixel(&self, x: usize, y: usize) -> Rgb555 {
+        match self.mask {
+            Mask::Black => 0x0000,
+            Mask::Color0 => self.palettes[0][0],
+            Mask::Cancel | Mask::Freeze => {
+                let shade = self.screen[y * SCREEN_WIDTH + x];
+                self.palettes[self.attributes.palette_at(x, y) as usize][shade as usize]
+            }
+        }
+    }
+}
+
+/// DMG shade (0-3) of a greyscale framebuffer colour.
+fn shade_of(color: Rgb555) -> u8 {
+    DMG_SHADES
+        .iter()
+        .position(|&shade| shade == color)
+        .unwrap_or(0) as u8
+}
+
+/// Read 4 KiB back from a frame showing 256 tiles in order, 20 per row, as
+/// 2bpp tile data.
+fn transfer_data(shades: &[u8]) -> Vec<u8> {
+    (0..TRANSFER_BYTES)
+        .map(|index| {
+            let tile = index / 16;
+            let row = (index % 16) / 2;
+            let plane = index % 2;
+            let y = (tile / TRANSFER_TILES_WIDE) * 8 + row;
+            let x = (tile % TRANSFER_TILES_WIDE) * 8;
+            (0..8).fold(0u8, |byte, col| {
+                let shade = shades.get(y * SCREEN_WIDTH + x + col).copied().unwrap_or(0);
+                byte << 1 | ((shade >> plane) & 1)
+            })
+        })
+        .collect()
+}
+
+impl Snapshot for PacketReceiver {
+    fn save_to(&self, state: &mut StateWriter) {
+        state.bool(self.receiving);
+        state.bool(self.awaiting_release);
+        state.u8(self.bits as u8);
+        state.bytes(&self.packet);
+    }
+
+    fn load_from(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.receiving = state.bool()?;
+        self.awaiting_release = state.bool()?;
+        self.bits = (state.u8()? as usize).min(PACKET_BITS);
+        state.bytes(&mut self.packet)
+    }
+}
+
+impl Snapshot for Sgb {
+    fn save_to(&self, state: &mut StateWriter) {
+        self.receiver.save_to(state);
+        state.u8(self.command.len() as u8);
+        state.bytes(&self.command);
+        for &color in self.palettes.iter().flatten() {
+            state.u16(color);
+        }
+        self.attributes.save_to(state);
+        self.border.save_to(state);
+        state.u8(self.mask as u8);
+        state.u8(match self.pending_transfer {
+            None => 0,
+            Some(Transfer::BorderTiles { upper: false }) => 1,
+            Some(Transfer::BorderTiles { upper: true }) => 2,
+            Some(Transfer::BorderMap) => 3,
+        });
+        state.bytes(&self.screen);
+        state.u8(self.players);
+        state.u8(self.player);
+        state.u8(self.select);
+    }
+
+    fn load_from(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.receiver.load_from(state)?;
+        let command_len = state.u8()? as usize;
+        if command_len >= PACKET_BYTES * MAX_PACKETS {
+            return Err(StateError::Corrupt("SGB command too long"));
+        }
+        self.command.resize(command_len, 0);
+        state.bytes(&mut self.command)?;
+        for color in self.palettes.iter_mut().flatten() {
+            *color = state.u16()?;
+        }
+        self.attributes.load_from(state)?;
+        self.border.load_from(state)?;
+        self.mask = match state.u8()? {
+            0 => Mask::Cancel,
+            1 => Mask::Freeze,
+            2 => Mask::Black,
+            3 => Mask::Color0,
+            _ => return Err(StateError::Corrupt("invalid SGB mask")),
+        };
+        self.pending_transfer = match state.u8()? {
+            0 => None,
+            1 => Some(Transfer::BorderTiles { upper: false }),
+            2 => Some(Transfer::BorderTiles { upper: true }),
+            3 => Some(Transfer::BorderMap),
+            _ => return Err(StateError::Corrupt("invalid SGB transfer")),
+        };
+        state.bytes(&mut self.screen)?;
+        self.players = match state.u8()? {
+            players @ (1 | 2 | 4) => players,
+            _ => return Err(StateError::Corrupt("invalid SGB player count")),
+        };
+        self.player = state.u8()? % self.players;
+        self.select = state.u8()? & SELECT_MASK;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bit-bang `packets` through P1 the way games do.
+    fn send(sgb: &mut Sgb, packets: &[[u8; PACKET_BYTES]]) {
+        for packet in packets {
+            sgb.write_joypad(0x00);
+            sgb.write_joypad(0x30);
+            for bit in 0..PACKET_BITS {
+                let one = packet[bit / 8] >> (bit % 8) & 1 != 0;
+                sgb.write_joypad(if one { 0x10 } else { 0x20 });
+                sgb.write_joypad(0x30);
+            }
+            sgb.write_joypad(0x20);
+            sgb.write_joypad(0x30);
+        }
+    }
+
+    fn packet(bytes: &[u8]) -> [u8; PACKET_BYTES] {
+        let mut packet = [0; PACKET_BYTES];
+        packet[..bytes.len()].copy_from_slice(bytes);
+        packet
+    }
+
+    #[test]
+    fn test_pal01_sets_shared_colour_0() {
+        let mut sgb = Sgb::new();
+        let colors: Vec<u8> = (1..=7u16).flat_map(|c| c.to_le_bytes()).collect();
+        send(
+            &mut sgb,
+            &[packet(&[[PAL01 << 3 | 1].as_slice(), &colors].concat())],
+        );
+        assert_eq!(sgb.palettes[0], [1, 2, 3, 4]);
+        assert_eq!(sgb.palettes[1], [1, 5, 6, 7]);
+        assert_eq!(sgb.palettes[3][0], 1);
+        assert_eq!(sgb.palettes[3][1], DEFAULT_PALETTE[1]);
+    }
+
+    #[test]
+    fn test_packet_without_stop_bit_is_dropped() {
+        let mut sgb = Sgb::new();
+        sgb.write_joypad(0x00);
+        sgb.write_joypad(0x30);
+        for _ in 0..PACKET_BITS + 1 {
+            sgb.write_joypad(0x10); // all ones, including the stop bit
+            sgb.write_joypad(0x30);
+        }
+        assert_eq!(sgb.mask, Mask::Cancel);
+        assert_eq!(sgb.palettes[0], DEFAULT_PALETTE);
+    }
+
+    #[test]
+    fn test_attributes_recolour_game_screen() {
+        let mut sgb = Sgb::new();
+        let colors: Vec<u8> = [0u16, 0, 0, 0, 0x7C00, 0x7C00, 0x7C00]
+            .iter()
+            .flat_map(|c| c.to_le_bytes())
+            .collect();
+        send(
+            &mut sgb,
+            &[packet(&[[PAL01 << 3 | 1].as_slice(), &colors].concat())],
+        );
+        // Right half (from column 10) uses palette 1
+        send(&mut sgb, &[packet(&[ATTR_DIV << 3 | 1, 0b0001_0001, 10])]);
+
+        sgb.on_frame(&vec![DMG_SHADES[3]; SCREEN_WIDTH * SCREEN_HEIGHT]);
+        let output = sgb.compose();
+        let at = |x: usize, y: usize| output[(GAME_Y + y) * BORDER_WIDTH + GAME_X + x];
+        assert_eq!(at(0, 0), 0x0000);
+        assert_eq!(at(159, 143), 0x7C00);
+        assert_eq!(output[0], 0x0000); // backdrop: shared colour 0
+
+        send(&mut sgb, &[packet(&[MASK_EN << 3 | 1, 2])]);
+        assert_eq!(sgb.compose()[GAME_Y * BORDER_WIDTH + GAME_X + 159], 0x0000);
+    }
+
+    #[test]
+    fn test_chr_trn_reads_tiles_from_next_frame() {
+        let mut sgb = Sgb::new();
+        send(&mut sgb, &[packet(&[CHR_TRN << 3 | 1, 0])]);
+        // Screen tile 0, row 0, leftmost pixel shade 3 -> both planes set
+        let mut frame = vec![DMG_SHADES[0]; SCREEN_WIDTH * SCREEN_HEIGHT];
+        frame[0] = DMG_SHADES[3];
+        sgb.on_frame(&frame);
+        let data = transfer_data(&frame.iter().map(|&c| shade_of(c)).collect::<Vec<_>>());
+        assert_eq!(&data[..2], &[0x80, 0x80]);
+        assert_eq!(sgb.pending_transfer, None);
+    }
+
+    #[test]
+    fn test_mlt_req_cycles_joypad_ids() {
+        let mut sgb = Sgb::new();
+        send(&mut sgb, &[packet(&[MLT_REQ << 3 | 1, 1])]);
+        assert_eq!(sgb.read_joypad(0xFF) & 0x0F, 0x0F);
+        sgb.write_joypad(0x10); // P15 low
+        sgb.write_joypad(0x30); // and high again: next joypad
+        assert_eq!(sgb.read_joypad(0xFF) & 0x0F, 0x0E);
+        sgb.write_joypad(0x10);
+        sgb.write_joypad(0x30);
+        assert_eq!(sgb.read_joypad(0xFF) & 0x0F, 0x0F);
+    }
+}
@@ -2,6 +2,8 @@
 //! Implements DIV, TIMA, TMA, and TAC with edge-detection and overflow delay.
 //! Reference: <https://gbdev.io/pandocs/Timer_and_Divider_Registers.html>
 
+use crate::save_state::{Snapshot, StateError, StateReader, StateWriter};
+
 pub const DIV_REGISTER: u16 = 0xFF04;
 pub const TIMA_REGISTER: u16 = 0xFF05;
 pub const TMA_REGISTER: u16 = 0xFF06;
@@ -26,6 +28,7 @@ pub struct Timer {
     tac: u8,                    // timer control (0xFF07)
     prev_timer_bit: bool,       // previous selected DIV bit state
     overflow_delay: Option<u8>, // pending reload delay after overflow
+    just_reloaded: bool,        // TIMA was reloaded from TMA on the current cycle
 }
 
 impl Timer {
@@ -37,11 +40,13 @@ impl Timer {
             tac: 0,
             prev_timer_bit: false,
             overflow_delay: None,
+            just_reloaded: false,
         }
     }
 
     // Advance one T-cycle. Returns true if a timer interrupt should fire.
     pub fn tick(&mut self) -> bool {
+        self.just_reloaded = false;
         self.div = self.div.wrapping_add(1);
         let interrupt = self.update_overflow_delay();
 
@@ -52,6 +57,19 @@ impl Timer {
         interrupt
     }
 
+    /// The full 16-bit internal divider (DIV is its upper byte).
+    ///
+    /// The APU frame sequencer is clocked by falling edges of its bit 12.
+    pub fn div_counter(&self) -> u16 {
+        self.div
+    }
+
+    /// Set the internal divider directly, e.g. to the value a boot ROM leaves.
+    pub fn set_div_counter(&mut self, value: u16) {
+        self.div = value;
+        self.prev_timer_bit = self.calculate_timer_bit();
+    }
+
     pub fn read(&self, addr: u16) -> u8 {
         match addr {
             DIV_REGISTER => self.read_div(),
@@ -101,6 +119,7 @@ impl Timer {
             if delay == 1 {
                 self.tima = self.tma;
                 self.overflow_delay = None;
+                self.just_reloaded = true;
                 return true;
             } else {
                 self.overflow_delay = Some(delay - 1);
@@ -144,15 +163,23 @@ impl Timer {
         self.handle_falling_edge_and_update_prev(current_bit);
     }
 
+    // A write on the cycle TIMA is reloaded from TMA is ignored.
     fn write_tima(&mut self, value: u8) {
+        if self.just_reloaded {
+            return;
+        }
         self.tima = value;
         if self.overflow_delay.is_some() {
             self.overflow_delay = None;
         }
     }
 
+    // A write on the reload cycle also lands in TIMA, since the reload is still in progress.
     fn write_tma(&mut self, value: u8) {
         self.tma = value;
+        if self.just_reloaded {
+            self.tima = value;
+        }
     }
 
     // Update TAC (only bits 0-2 writable), then recompute selected bit and handle glitch edge.
@@ -163,6 +190,35 @@ impl Timer {
     }
 }
 
+impl Snapshot for Timer {
+    fn save_to(&self, state: &mut StateWriter) {
+        state.u16(self.div);
+        state.u8(self.tima);
+        state.u8(self.tma);
+        state.u8(self.tac);
+        state.bool(self.prev_timer_bit);
+        state.option(self.overflow_delay, StateWriter::u8);
+        state.bool(self.just_reloaded);
+    }
+
+    fn load_from(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.div = state.u16()?;
+        self.tima = state.u8()?;
+        self.tma = state.u8()?;
+        self.tac = state.u8()? & 0x07;
+        self.prev_timer_bit = state.bool()?;
+        self.overflow_delay = state.option(StateReader::u8)?;
+        if self
+            .overflow_delay
+            .is_some_and(|delay| !(1..=TIMA_OVERFLOW_RELOAD_DELAY).contains(&delay))
+        {
+            return Err(StateError::Corrupt("invalid TIMA reload delay"));
+        }
+        self.just_reloaded = state.bool()?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -242,4 +298,71 @@ mod tests {
 
         assert_eq!(timer.read(TIMA_REGISTER), 0x25);
     }
+
+    // Tick until the reload from TMA happens.
+    fn run_until_reload(timer: &mut Timer) {
+        for _ in 0..30 {
+            if timer.tick() {
+                return;
+            }
+        }
+        panic!("timer never reloaded");
+    }
+
+    #[test]
+    fn test_tima_write_on_reload_cycle_is_ignored() {
+        let mut timer = Timer::new();
+        timer.write(TAC_REGISTER, 0b101);
+        timer.write(TMA_REGISTER, 0x42);
+        timer.write(TIMA_REGISTER, 0xFF);
+        timer.div = 7;
+        timer.prev_timer_bit = false;
+
+        run_until_reload(&mut timer);
+        timer.write(TIMA_REGISTER, 0x10);
+
+        assert_eq!(timer.read(TIMA_REGISTER), 0x42);
+    }
+
+    #[test]
+    fn test_tma_write_on_reload_cycle_updates_tima() {
+        let mut timer = Timer::new();
+        timer.write(TAC_REGISTER, 0b101);
+        timer.write(TMA_REGISTER, 0x42);
+        timer.write(TIMA_REGISTER, 0xFF);
+        timer.div = 7;
+        timer.prev_timer_bit = false;
+
+        run_until_reload(&mut timer);
+        timer.write(TMA_REGISTER, 0x99);
+
+        assert_eq!(timer.read(TIMA_REGISTER), 0x99);
+
+        // One cycle later TMA writes no longer affect TIMA
+        timer.tick();
+        timer.write(TMA_REGISTER, 0x11);
+        assert_eq!(timer.read(TIMA_REGISTER), 0x99);
+    }
+
+    #[test]
+    fn test_invalid_reload_delay_is_rejected_on_load() {
+        let mut timer = Timer::new();
+        timer.overflow_delay = Some(TIMA_OVERFLOW_RELOAD_DELAY);
+        let mut state = StateWriter::new();
+        timer.save_to(&mut state);
+        let mut saved = state.into_bytes();
+        let (tac, delay) = (4, 7); // after DIV, TIMA and TMA; after the Some flag
+
+        saved[tac] = 0xFF;
+        let mut loaded = Timer::new();
+        assert!(loaded.load_from(&mut StateReader::new(&saved)).is_ok());
+        assert_eq!(loaded.tac, 0x07);
+        for bad_delay in [0, TIMA_OVERFLOW_RELOAD_DELAY + 1] {
+            saved[delay] = bad_delay;
+            assert!(matches!(
+                loaded.load_from(&mut StateReader::new(&saved)),
+                Err(StateError::Corrupt(_))
+            ));
+        }
+    }
 }